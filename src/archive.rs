@@ -0,0 +1,183 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crossterm::style::Color;
+
+use crate::constants::DEFAULT_PNG_SCALE;
+use crate::export::export_png;
+use crate::project::{Project, ProjectMetadata, PROJECT_VERSION};
+use crate::screen::Layer;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+// 1970-01-01 was a thursday, weeks start on monday
+const EPOCH_WEEKDAY: u64 = 3;
+
+// how often a served room canvas is archived and wiped, resets happen at UTC
+// midnight, on mondays for weekly ones
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResetSchedule {
+    Daily,
+    Weekly,
+}
+
+impl ResetSchedule {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec {
+            "daily" => Ok(ResetSchedule::Daily),
+            "weekly" => Ok(ResetSchedule::Weekly),
+            _ => Err(format!(
+                "unknown reset schedule {}, expected daily or weekly",
+                spec
+            )),
+        }
+    }
+
+    // first reset strictly after the unix time now
+    pub fn next_after(&self, now: u64) -> u64 {
+        let midnight: u64 = (now / DAY_SECS + 1) * DAY_SECS;
+        match self {
+            ResetSchedule::Daily => midnight,
+            ResetSchedule::Weekly => {
+                let weekday: u64 = (midnight / DAY_SECS + EPOCH_WEEKDAY) % 7;
+                midnight + ((7 - weekday) % 7) * DAY_SECS
+            }
+        }
+    }
+}
+
+// YYYY-MM-DD of a unix time, the days to civil date conversion by Howard Hinnant
+pub fn date(unix: u64) -> String {
+    let days: i64 = (unix / DAY_SECS) as i64 + 719468;
+    let era: i64 = days.div_euclid(146097);
+    let day_of_era: i64 = days.rem_euclid(146097);
+    let year_of_era: i64 =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index: i64 = (5 * day_of_year + 2) / 153;
+    let day: i64 = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month: i64 = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year: i64 = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// a date as `date` writes them, YYYY-MM-DD
+fn is_date(text: &str) -> bool {
+    let bytes: &[u8] = text.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, byte)| match i {
+            4 | 7 => *byte == b'-',
+            _ => byte.is_ascii_digit(),
+        })
+}
+
+// the outgoing canvas of room as <dir>/<room>-<date>.png and .pxr, returns the
+// written paths, an empty canvas only gets the project file
+pub fn archive_canvas(
+    layers: &[Layer],
+    dir: &str,
+    room: &str,
+    unix: u64,
+    cell_aspect: (usize, usize),
+) -> io::Result<Vec<String>> {
+    fs::create_dir_all(dir)?;
+    let stem = Path::new(dir).join(format!("{}-{}", room, date(unix)));
+    let project_path: String = stem.with_extension("pxr").to_string_lossy().to_string();
+    let mut project = Project {
+        version: PROJECT_VERSION,
+        metadata: ProjectMetadata::new(&project_path),
+        layers: layers.to_vec(),
+        active_layer: 0,
+        color_selected: Color::White,
+        frames: Vec::new(),
+        frame: 0,
+    };
+    project.save(&project_path)?;
+    let mut written: Vec<String> = vec![project_path];
+    if layers.iter().any(|layer| !layer.is_empty()) {
+        let png_path: String = stem.with_extension("png").to_string_lossy().to_string();
+        export_png(layers, &png_path, DEFAULT_PNG_SCALE, cell_aspect)?;
+        written.push(png_path);
+    }
+    Ok(written)
+}
+
+// pieces archived for room in dir, as file stems like `wall-2026-10-16`,
+// oldest first, those of rooms like `wall-b` are not among them
+pub fn archived_pieces(dir: &str, room: &str) -> Vec<String> {
+    let prefix: String = format!("{}-", room);
+    let mut pieces: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "pxr"))
+                .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+                .filter(|stem| stem.strip_prefix(&prefix).is_some_and(is_date))
+                .collect()
+        })
+        .unwrap_or_default();
+    pieces.sort();
+    pieces
+}
+
+// files of an archived piece of room with their contents, pieces not listed
+// by archived_pieces are refused so requests cannot reach other files
+pub fn read_piece(dir: &str, room: &str, piece: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+    if !archived_pieces(dir, room)
+        .iter()
+        .any(|listed| listed == piece)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no archived piece {}", piece),
+        ));
+    }
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+    for extension in ["pxr", "png"] {
+        let name: String = format!("{}.{}", piece, extension);
+        match fs::read(Path::new(dir).join(&name)) {
+            Ok(data) => files.push((name, data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(files)
+}
+
+// a downloaded archive file stored in dir, names that are not plain file
+// names are refused, returns the written path
+pub fn save_download(dir: &str, name: &str, data: &[u8]) -> io::Result<String> {
+    if Path::new(name).file_name().and_then(|file| file.to_str()) != Some(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid archive file name {}", name),
+        ));
+    }
+    fs::create_dir_all(dir)?;
+    let path: String = Path::new(dir).join(name).to_string_lossy().to_string();
+    fs::write(&path, data)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pieces_of_other_rooms_are_not_listed() {
+        let dir = std::env::temp_dir().join(format!("pixelrs-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for stem in ["wall-2026-10-15", "wall-b-2026-10-15", "wall-notes", "wall-2026-10-16"] {
+            fs::write(dir.join(format!("{}.pxr", stem)), b"").unwrap();
+        }
+        let pieces: Vec<String> = archived_pieces(&dir.to_string_lossy(), "wall");
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(pieces, ["wall-2026-10-15", "wall-2026-10-16"]);
+        assert!(is_date(&date(0)));
+    }
+}
@@ -0,0 +1,256 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{ASSETS_TIMEOUT, MAX_ASSET_LEN, MAX_BRUSH_PIXELS};
+use crate::palette::Palette;
+
+const SHA256_ROUNDS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// shape the brush paints at once, pixels as x, y and color code
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StampAsset {
+    pub name: String,
+    pub pixels: Vec<(i32, i32, u8)>,
+}
+
+impl StampAsset {
+    // brush footprint relative to the cursor, pixels are two columns wide
+    pub fn footprint(&self) -> Vec<((i32, i32), Color)> {
+        self.pixels
+            .iter()
+            .map(|&(x, y, code)| ((2 * x, y), Color::AnsiValue(code)))
+            .collect()
+    }
+}
+
+// community palettes and stamps installed together, stored as fetched
+#[derive(Serialize, Deserialize)]
+pub struct AssetPack {
+    pub name: String,
+    #[serde(default)]
+    pub palettes: Vec<Palette>,
+    #[serde(default)]
+    pub stamps: Vec<StampAsset>,
+}
+
+// $PIXELRS_ASSETS, pixelrs in $XDG_DATA_HOME or in ~/.local/share otherwise
+pub fn asset_dir() -> PathBuf {
+    if let Ok(dir) = env::var("PIXELRS_ASSETS") {
+        return PathBuf::from(dir);
+    }
+    let data: PathBuf = env::var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| Path::new(&env::var("HOME").unwrap_or_default()).join(".local/share"));
+    data.join("pixelrs")
+}
+
+fn packs_dir() -> PathBuf {
+    asset_dir().join("packs")
+}
+
+// plain http only as there is no tls around, packs served over https have to
+// be mirrored or downloaded by hand. Redirects are not followed
+fn http_get(url: &str) -> io::Result<Vec<u8>> {
+    let rest: &str = match url.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some((scheme, _)) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is not supported, only http", scheme),
+            ))
+        }
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid url {}", url),
+            ))
+        }
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let address: String = match authority.contains(':') {
+        true => authority.to_string(),
+        false => format!("{}:80", authority),
+    };
+    let mut stream: TcpStream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(ASSETS_TIMEOUT))?;
+    // http/1.0 keeps the body as is, there is no chunked encoding to undo
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: pixelrs/{}\r\n\r\n",
+        path,
+        authority,
+        env!("CARGO_PKG_VERSION")
+    )?;
+    let mut response: Vec<u8> = Vec::new();
+    stream.take(MAX_ASSET_LEN + 1).read_to_end(&mut response)?;
+    if response.len() as u64 > MAX_ASSET_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is bigger than {} bytes", url, MAX_ASSET_LEN),
+        ));
+    }
+    let end: usize = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed http response"))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status: &str = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(io::Error::other(format!("{} answered {}", url, status)));
+    }
+    Ok(response[end + 4..].to_vec())
+}
+
+// lowercase hex digest, packs are small enough to be hashed in one go
+fn sha256_hex(data: &[u8]) -> String {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut message: Vec<u8> = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut schedule: [u32; 64] = [0; 64];
+        for (word, bytes) in schedule.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let (w15, w2) = (schedule[i - 15], schedule[i - 2]);
+            let s0: u32 = w15.rotate_right(7) ^ w15.rotate_right(18) ^ (w15 >> 3);
+            let s1: u32 = w2.rotate_right(17) ^ w2.rotate_right(19) ^ (w2 >> 10);
+            schedule[i] = schedule[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for (round, word) in SHA256_ROUNDS.iter().zip(schedule.iter()) {
+            let s1: u32 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice: u32 = (e & f) ^ (!e & g);
+            let t1: u32 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(*round)
+                .wrapping_add(*word);
+            let s0: u32 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority: u32 = (a & b) ^ (a & c) ^ (b & c);
+            let t2: u32 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+    state.iter().map(|word| format!("{:08x}", word)).collect()
+}
+
+// downloads the pack at url into the asset dir once its sha256 matches the
+// given one, or the one published next to it as <url>.sha256. That one comes
+// from the same server over plain http, it only catches a corrupted download,
+// a pinned --sha256 is what tells the pack is the expected one
+pub fn add(url: &str, sha256: Option<&str>) -> io::Result<AssetPack> {
+    let data: Vec<u8> = http_get(url)?;
+    let expected: String = match sha256 {
+        Some(sum) => sum.to_string(),
+        None => String::from_utf8_lossy(&http_get(&format!("{}.sha256", url))?)
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    };
+    let actual: String = sha256_hex(&data);
+    if !expected.eq_ignore_ascii_case(&actual) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("checksum mismatch, expected {} got {}", expected, actual),
+        ));
+    }
+    let pack: AssetPack =
+        serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    // the name becomes the file name of the pack
+    if Path::new(&pack.name)
+        .file_name()
+        .and_then(|file| file.to_str())
+        != Some(&pack.name)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid pack name {}", pack.name),
+        ));
+    }
+    fs::create_dir_all(packs_dir())?;
+    fs::write(packs_dir().join(format!("{}.json", pack.name)), &data)?;
+    Ok(pack)
+}
+
+// installed packs by name, unreadable ones are skipped, palettes without
+// colors and stamps too big for the brush are left out
+pub fn installed_packs() -> Vec<AssetPack> {
+    let mut packs: Vec<AssetPack> = fs::read_dir(packs_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| fs::read(entry.path()).ok())
+                .filter_map(|data| serde_json::from_slice::<AssetPack>(&data).ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    for pack in packs.iter_mut() {
+        pack.palettes.retain(|palette| !palette.colors.is_empty());
+        pack.stamps
+            .retain(|stamp| !stamp.pixels.is_empty() && stamp.pixels.len() <= MAX_BRUSH_PIXELS);
+    }
+    packs.sort_by(|a, b| a.name.cmp(&b.name));
+    packs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // two blocks once padded
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
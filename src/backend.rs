@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::io::{self, stdout, Stdout, Write};
+use std::rc::Rc;
+
+use crossterm::style::Print;
+use crossterm::terminal::{self as terminal};
+use crossterm::{cursor, Command};
+
+use crate::constants::EMPTY_TERM_CHAR;
+use crate::screen::{rows_to_ansi, TermChar};
+
+// where the frames of a Terminal end up, the real terminal or memory for
+// rendering without one. nothing has to be visible before flush
+pub trait Backend {
+    // a run of cells from col,row to the right, the cursor stays where it was
+    fn write_cells(&mut self, col_row: (u16, u16), cells: &[TermChar]) -> io::Result<()>;
+    fn move_to(&mut self, col_row: (u16, u16)) -> io::Result<()>;
+    fn clear(&mut self) -> io::Result<()>;
+    fn size(&self) -> io::Result<(u16, u16)>;
+    // anything else, cursor visibility, mouse capture or plain text printed at
+    // the cursor
+    fn write_ansi(&mut self, ansi: &str) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+// shared with whoever wants to look at what was drawn, e.g. a test keeping a
+// clone of a MemoryBackend handed to a Screen
+impl<B: Backend> Backend for Rc<RefCell<B>> {
+    fn write_cells(&mut self, col_row: (u16, u16), cells: &[TermChar]) -> io::Result<()> {
+        self.borrow_mut().write_cells(col_row, cells)
+    }
+
+    fn move_to(&mut self, col_row: (u16, u16)) -> io::Result<()> {
+        self.borrow_mut().move_to(col_row)
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.borrow_mut().clear()
+    }
+
+    fn size(&self) -> io::Result<(u16, u16)> {
+        self.borrow().size()
+    }
+
+    fn write_ansi(&mut self, ansi: &str) -> io::Result<()> {
+        self.borrow_mut().write_ansi(ansi)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.borrow_mut().flush()
+    }
+}
+
+// escape sequences of a frame, the queued commands in the order they were
+// written and then the cells with the cursor saved, so it ends up where the
+// commands left it
+#[derive(Default)]
+pub struct FrameAnsi {
+    commands: String,
+    cells: String,
+}
+
+impl FrameAnsi {
+    // formatting into a string never fails
+    pub fn command(&mut self, command: impl Command) {
+        command.write_ansi(&mut self.commands).unwrap();
+    }
+
+    pub fn write_ansi(&mut self, ansi: &str) {
+        self.commands.push_str(ansi);
+    }
+
+    pub fn write_cells(&mut self, (col, row): (u16, u16), cells: &[TermChar]) {
+        cursor::MoveTo(col, row)
+            .write_ansi(&mut self.cells)
+            .unwrap();
+        self.cells.push_str(&rows_to_ansi(&[cells.to_vec()], ""));
+    }
+
+    // everything written since the last take, empty when nothing was
+    pub fn take(&mut self) -> String {
+        let mut frame: String = std::mem::take(&mut self.commands);
+        if !self.cells.is_empty() {
+            cursor::SavePosition.write_ansi(&mut frame).unwrap();
+            Print(std::mem::take(&mut self.cells))
+                .write_ansi(&mut frame)
+                .unwrap();
+            cursor::RestorePosition.write_ansi(&mut frame).unwrap();
+        }
+        frame
+    }
+}
+
+// the terminal pixelrs runs in, everything written during a frame goes out in
+// a single write on flush
+pub struct CrosstermBackend {
+    out: Stdout,
+    frame: FrameAnsi,
+}
+
+impl CrosstermBackend {
+    pub fn new() -> Self {
+        CrosstermBackend {
+            out: stdout(),
+            frame: FrameAnsi::default(),
+        }
+    }
+}
+
+impl Default for CrosstermBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for CrosstermBackend {
+    fn write_cells(&mut self, col_row: (u16, u16), cells: &[TermChar]) -> io::Result<()> {
+        self.frame.write_cells(col_row, cells);
+        Ok(())
+    }
+
+    fn move_to(&mut self, (col, row): (u16, u16)) -> io::Result<()> {
+        self.frame.command(cursor::MoveTo(col, row));
+        Ok(())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.frame
+            .command(terminal::Clear(terminal::ClearType::All));
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<(u16, u16)> {
+        terminal::size()
+    }
+
+    fn write_ansi(&mut self, ansi: &str) -> io::Result<()> {
+        self.frame.write_ansi(ansi);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.write_all(self.frame.take().as_bytes())?;
+        self.out.flush()
+    }
+}
+
+// a terminal kept in memory, for rendering without one, e.g. headless tests
+// comparing what a frame shows with a snapshot
+pub struct MemoryBackend {
+    pub cells: Vec<Vec<TermChar>>,
+    pub cursor: (u16, u16),
+    // escape sequences written so far, plain text lands in cells instead
+    pub commands: Vec<String>,
+    pub flushes: usize,
+}
+
+impl MemoryBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        MemoryBackend {
+            cells: vec![vec![EMPTY_TERM_CHAR; width as usize]; height as usize],
+            cursor: (0, 0),
+            commands: Vec::new(),
+            flushes: 0,
+        }
+    }
+
+    fn put(&mut self, (col, row): (usize, usize), term_char: TermChar) {
+        if let Some(cell) = self.cells.get_mut(row).and_then(|cells| cells.get_mut(col)) {
+            *cell = term_char;
+        }
+    }
+
+    // the characters shown, one line per row with trailing blanks trimmed
+    #[cfg(test)]
+    pub fn text(&self) -> String {
+        self.cells
+            .iter()
+            .map(|row| {
+                let line: String = row.iter().map(|cell| cell.character).collect();
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn write_cells(&mut self, (col, row): (u16, u16), cells: &[TermChar]) -> io::Result<()> {
+        for (x, term_char) in cells.iter().enumerate() {
+            self.put((col as usize + x, row as usize), *term_char);
+        }
+        Ok(())
+    }
+
+    fn move_to(&mut self, col_row: (u16, u16)) -> io::Result<()> {
+        self.cursor = col_row;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        for row in self.cells.iter_mut() {
+            row.fill(EMPTY_TERM_CHAR);
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<(u16, u16)> {
+        let width: usize = self.cells.first().map_or(0, |row| row.len());
+        Ok((width as u16, self.cells.len() as u16))
+    }
+
+    // printed text moves the cursor along, it is not wrapped
+    fn write_ansi(&mut self, ansi: &str) -> io::Result<()> {
+        if ansi.contains('\x1b') {
+            self.commands.push(ansi.to_string());
+            return Ok(());
+        }
+        for character in ansi.chars() {
+            let term_char: TermChar = TermChar {
+                character,
+                ..EMPTY_TERM_CHAR
+            };
+            let (col, row) = self.cursor;
+            self.put((col as usize, row as usize), term_char);
+            self.cursor.0 = col.saturating_add(1);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
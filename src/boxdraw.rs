@@ -0,0 +1,77 @@
+// name of the items box drawing glyphs are stored as, a cell each
+pub const BOX_ITEM: &str = "box";
+
+// sides of a cell a box drawing line leaves through
+pub const UP: u8 = 1;
+pub const RIGHT: u8 = 2;
+pub const DOWN: u8 = 4;
+pub const LEFT: u8 = 8;
+
+pub const SIDES: [u8; 4] = [UP, RIGHT, DOWN, LEFT];
+
+// light glyphs and the sides they join, a line ending in a cell is drawn
+// straight through it
+const GLYPHS: [(u8, char); 11] = [
+    (LEFT | RIGHT, '─'),
+    (UP | DOWN, '│'),
+    (RIGHT | DOWN, '┌'),
+    (LEFT | DOWN, '┐'),
+    (UP | RIGHT, '└'),
+    (UP | LEFT, '┘'),
+    (UP | DOWN | RIGHT, '├'),
+    (UP | DOWN | LEFT, '┤'),
+    (LEFT | RIGHT | DOWN, '┬'),
+    (LEFT | RIGHT | UP, '┴'),
+    (UP | RIGHT | DOWN | LEFT, '┼'),
+];
+
+// sides the glyph joins, none for anything but a box drawing glyph
+pub fn sides(glyph: char) -> u8 {
+    GLYPHS
+        .iter()
+        .find(|(_, other)| *other == glyph)
+        .map_or(0, |(sides, _)| *sides)
+}
+
+pub fn glyph(sides: u8) -> Option<char> {
+    let sides: u8 = match sides {
+        UP | DOWN => UP | DOWN,
+        LEFT | RIGHT => LEFT | RIGHT,
+        sides => sides,
+    };
+    GLYPHS
+        .iter()
+        .find(|(other, _)| *other == sides)
+        .map(|(_, glyph)| *glyph)
+}
+
+pub fn opposite(side: u8) -> u8 {
+    match side {
+        UP => DOWN,
+        RIGHT => LEFT,
+        DOWN => UP,
+        _ => RIGHT,
+    }
+}
+
+// cell next to x,y across the side
+pub fn neighbour((x, y): (i32, i32), side: u8) -> (i32, i32) {
+    match side {
+        UP => (x, y - 1),
+        RIGHT => (x + 1, y),
+        DOWN => (x, y + 1),
+        _ => (x - 1, y),
+    }
+}
+
+// side of from that leads one step closer to to, columns first and then
+// rows, none once there
+pub fn toward(from: (i32, i32), to: (i32, i32)) -> Option<u8> {
+    match (to.0 - from.0, to.1 - from.1) {
+        (0, 0) => None,
+        (dx, _) if dx > 0 => Some(RIGHT),
+        (dx, _) if dx < 0 => Some(LEFT),
+        (_, dy) if dy > 0 => Some(DOWN),
+        _ => Some(UP),
+    }
+}
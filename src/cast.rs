@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crossterm::{cursor, terminal};
+use serde_json::json;
+
+use crate::backend::{Backend, FrameAnsi};
+use crate::project::now;
+use crate::screen::TermChar;
+
+// a backend drawing on another one while every frame it flushes is also
+// appended to an asciinema v2 cast, a header line and then one
+// [seconds, "o", output] line per frame, terminal resizes as "r" events
+pub struct CastBackend {
+    inner: Box<dyn Backend>,
+    file: File,
+    frame: FrameAnsi,
+    start: Instant,
+    size: (u16, u16),
+}
+
+impl CastBackend {
+    // inner is handed back when the cast cannot be written
+    pub fn create(
+        path: &str,
+        inner: Box<dyn Backend>,
+    ) -> Result<CastBackend, (io::Error, Box<dyn Backend>)> {
+        let size: (u16, u16) = inner.size().unwrap_or((80, 25));
+        match cast_file(path, size) {
+            Ok(file) => Ok(CastBackend {
+                inner,
+                file,
+                frame: FrameAnsi::default(),
+                start: Instant::now(),
+                size,
+            }),
+            Err(e) => Err((e, inner)),
+        }
+    }
+
+    // frames reach the terminal whether or not the cast takes them, a full
+    // disk only cuts the cast short
+    fn event(&mut self, kind: &str, data: &str) {
+        let seconds: f64 = self.start.elapsed().as_secs_f64();
+        let _ = writeln!(self.file, "{}", json!([seconds, kind, data]));
+    }
+}
+
+// the cast with its header line written
+fn cast_file(path: &str, (width, height): (u16, u16)) -> io::Result<File> {
+    let mut file: File = File::create(path)?;
+    let header = json!({
+        "version": 2,
+        "width": width,
+        "height": height,
+        "timestamp": now(),
+        "env": {"TERM": std::env::var("TERM").unwrap_or_default()},
+    });
+    writeln!(file, "{}", header)?;
+    Ok(file)
+}
+
+impl Backend for CastBackend {
+    fn write_cells(&mut self, col_row: (u16, u16), cells: &[TermChar]) -> io::Result<()> {
+        self.frame.write_cells(col_row, cells);
+        self.inner.write_cells(col_row, cells)
+    }
+
+    fn move_to(&mut self, (col, row): (u16, u16)) -> io::Result<()> {
+        self.frame.command(cursor::MoveTo(col, row));
+        self.inner.move_to((col, row))
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.frame
+            .command(terminal::Clear(terminal::ClearType::All));
+        self.inner.clear()
+    }
+
+    fn size(&self) -> io::Result<(u16, u16)> {
+        self.inner.size()
+    }
+
+    fn write_ansi(&mut self, ansi: &str) -> io::Result<()> {
+        self.frame.write_ansi(ansi);
+        self.inner.write_ansi(ansi)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Ok(size) = self.inner.size() {
+            if size != self.size {
+                self.size = size;
+                self.event("r", &format!("{}x{}", size.0, size.1));
+            }
+        }
+        let output: String = self.frame.take();
+        if !output.is_empty() {
+            self.event("o", &output);
+        }
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,292 @@
+use std::process;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use pixelrs::{archive, constants, regions, screen, storage};
+
+use crate::hooks;
+
+// options before or after the command apply to every command, the ones of a
+// command are refused anywhere else
+#[derive(Parser, Debug)]
+#[command(
+    name = "pixelrs",
+    about = "draw in the terminal, alone or with others",
+    after_help = "defaults for tool, color, canvas, server, autosave, render and key bindings
+are read from ~/.config/pixelrs/config.toml ($XDG_CONFIG_HOME or
+$PIXELRS_CONFIG when set)"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// sign shared strokes, $USER otherwise
+    #[arg(long, global = true)]
+    pub name: Option<String>,
+
+    /// room of the shared session
+    #[arg(long, global = true, default_value = constants::DEFAULT_ROOM, value_parser = parse_room)]
+    pub room: String,
+
+    /// session password
+    #[arg(long, global = true, env = "PIXELRS_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+
+    /// join shared sessions over websocket
+    #[arg(long, global = true)]
+    pub websocket: bool,
+
+    /// replay every stroke made before joining
+    #[arg(long, global = true)]
+    pub replay_history: bool,
+
+    /// dump the session traffic
+    #[arg(long, global = true, value_name = "FILE")]
+    pub record: Option<String>,
+
+    /// save every key, mouse and resize event
+    #[arg(long, global = true, value_name = "FILE")]
+    pub record_input: Option<String>,
+
+    /// feed the editor a recorded input
+    #[arg(long, global = true, value_name = "FILE")]
+    pub replay: Option<String>,
+
+    /// record the screen as an asciinema cast
+    #[arg(long, global = true, value_name = "FILE.cast")]
+    pub cast: Option<String>,
+
+    /// keep the undo history next to the project
+    #[arg(long, global = true)]
+    pub history: bool,
+
+    /// run command on saved, exported, ...
+    #[arg(long, global = true, value_name = "EVENT=COMMAND", value_parser = parse_hook)]
+    pub hook: Vec<String>,
+
+    /// how the screen is repainted, full or diff
+    #[arg(long, global = true, value_parser = screen::RenderMode::parse)]
+    pub render: Option<screen::RenderMode>,
+
+    /// terminal cell shape for image exports
+    #[arg(long, global = true, value_name = "W:H", value_parser = parse_cell_aspect)]
+    pub cell_aspect: Option<(usize, usize)>,
+
+    /// start with the pressed keys overlay on
+    #[arg(long, global = true)]
+    pub overlay: bool,
+
+    /// start drawing with the keys, no mouse needed
+    #[arg(long, global = true)]
+    pub keyboard: bool,
+
+    /// quit without asking about unsaved changes
+    #[arg(long, global = true)]
+    pub no_confirm: bool,
+
+    /// start with the canvas fitted to the terminal
+    #[arg(long, global = true)]
+    pub fit: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// open a project
+    Open { file: String },
+    /// start with an image or ANSI art on its own layer
+    Import {
+        file: String,
+        /// dither the image
+        #[arg(long)]
+        dither: bool,
+        /// trace the image in a color
+        #[arg(long, value_name = "CODE")]
+        outline: Option<u8>,
+    },
+    /// host a shared session and join it
+    Serve {
+        #[arg(requires = "port")]
+        host: Option<String>,
+        port: Option<u16>,
+        /// archive and wipe the served canvas, daily or weekly
+        #[arg(long, value_parser = archive::ResetSchedule::parse)]
+        reset: Option<archive::ResetSchedule>,
+        /// where resets archive to
+        #[arg(long, value_name = "DIR", default_value = constants::DEFAULT_ARCHIVE_DIR)]
+        archive: String,
+        /// home region of a participant
+        #[arg(long, value_name = "NAME=X,Y,WIDTH,HEIGHT", value_parser = regions::Region::parse)]
+        region: Vec<regions::Region>,
+        /// keep the rooms across restarts, in a sqlite database for .db paths
+        /// or a directory of logs
+        #[arg(long, value_name = "PATH")]
+        store: Option<String>,
+    },
+    /// join a shared session, at the configured server when no address is given
+    Connect {
+        #[arg(requires = "port")]
+        host: Option<String>,
+        port: Option<u16>,
+    },
+    /// render a project without the editor
+    Export {
+        /// the project
+        #[arg(required_unless_present = "list", value_name = "FILE.pxr")]
+        file: Option<String>,
+        /// output, its extension tells the format, or a format name
+        #[arg(required_unless_present = "list", value_name = "OUT|FORMAT")]
+        target: Option<String>,
+        /// output when a format name was given
+        out: Option<String>,
+        /// export format, png, ans, svg, txt, ...
+        #[arg(long)]
+        format: Option<String>,
+        /// show the export formats
+        #[arg(long, conflicts_with_all = ["file", "format"])]
+        list: bool,
+    },
+    /// tile every project of a directory in a png
+    Montage {
+        dir: String,
+        /// montage columns
+        #[arg(long, default_value_t = constants::DEFAULT_MONTAGE_COLUMNS)]
+        cols: usize,
+        /// montage output
+        #[arg(long, value_name = "FILE.png", default_value = "montage.png")]
+        out: String,
+    },
+    /// render where the edits of a history went
+    Heatmap { history: String, out: String },
+    /// print an image in the terminal
+    Ansify {
+        image: String,
+        /// dither the image
+        #[arg(long)]
+        dither: bool,
+    },
+    /// summarize recorded session traffic
+    Analyze { traffic: String },
+    /// install or list packs of palettes and stamps
+    #[command(subcommand)]
+    Assets(Assets),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Assets {
+    /// install a pack of palettes and stamps
+    Add {
+        url: String,
+        /// checksum given by the pack author, the published one only catches corruption
+        #[arg(long)]
+        sha256: Option<String>,
+    },
+    /// show the installed packs
+    List,
+}
+
+fn parse_room(room: &str) -> Result<String, String> {
+    storage::check_room_name(room).map_err(|e| e.to_string())?;
+    Ok(room.to_string())
+}
+
+fn parse_hook(spec: &str) -> Result<String, String> {
+    hooks::Hooks::default().add(spec)?;
+    Ok(spec.to_string())
+}
+
+fn parse_cell_aspect(aspect: &str) -> Result<(usize, usize), String> {
+    let parse = |v: &str| v.parse().map_err(|_| "expected W:H".to_string());
+    match aspect.split_once(':') {
+        Some((w, h)) => Ok((parse(w)?, parse(h)?)),
+        None => Err("expected W:H".into()),
+    }
+}
+
+// prints what is wrong with the invocation and the usage, then exits
+pub fn fail(message: &str) -> ! {
+    Cli::command()
+        .error(ErrorKind::InvalidValue, message)
+        .exit()
+}
+
+// for errors that are not about the invocation, e.g. a broken config file
+pub fn abort(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("pixelrs").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn definition_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn global_options_go_anywhere() {
+        let cli: Cli = parse(&[
+            "serve",
+            "0.0.0.0",
+            "7777",
+            "--name",
+            "ana",
+            "--cell-aspect",
+            "1:3",
+        ])
+        .unwrap();
+        assert_eq!(cli.name.as_deref(), Some("ana"));
+        assert_eq!(cli.cell_aspect, Some((1, 3)));
+        assert_eq!(cli.room, constants::DEFAULT_ROOM);
+        match cli.command {
+            Some(Command::Serve { host, port, .. }) => {
+                assert_eq!(host.as_deref(), Some("0.0.0.0"));
+                assert_eq!(port, Some(7777));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_command_starts_the_editor() {
+        let cli: Cli = parse(&["--keyboard", "--fit"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.keyboard && cli.fit);
+    }
+
+    #[test]
+    fn regions_are_repeated() {
+        let cli: Cli = parse(&["serve", "--region", "a=0,0,4,4", "--region", "b=4,0,4,4"]).unwrap();
+        let Some(Command::Serve { region, .. }) = cli.command else {
+            panic!("expected serve");
+        };
+        assert_eq!(region.len(), 2);
+    }
+
+    #[test]
+    fn export_forms() {
+        assert!(parse(&["export", "--list"]).is_ok());
+        assert!(parse(&["export", "a.pxr", "a.png"]).is_ok());
+        assert!(parse(&["export", "a.pxr", "svg", "out.svg"]).is_ok());
+        assert!(parse(&["export", "a.pxr"]).is_err());
+        assert!(parse(&["export", "a.pxr", "a.png", "--list"]).is_err());
+    }
+
+    #[test]
+    fn invalid_invocations_are_refused() {
+        assert!(parse(&["serve", "--dither"]).is_err());
+        assert!(parse(&["import", "a.png", "--reset", "daily"]).is_err());
+        assert!(parse(&["connect", "localhost"]).is_err());
+        assert!(parse(&["connect", "localhost", "99999"]).is_err());
+        assert!(parse(&["--room", "../etc"]).is_err());
+        assert!(parse(&["--cell-aspect", "12"]).is_err());
+        assert!(parse(&["heatmap", "a.history"]).is_err());
+        assert!(parse(&["paint"]).is_err());
+        assert!(parse(&["assets", "add"]).is_err());
+    }
+}
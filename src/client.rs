@@ -0,0 +1,741 @@
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::constants::{
+    AUTH_HANDSHAKE, CHECKSUM_INTERVAL, HANDSHAKE_ACCEPTED, HANDSHAKE_REJECTED, HANDSHAKE_TIMEOUT,
+    HEARTBEAT_INTERVAL, NETWORK_POLL_INTERVAL,
+    PROTOCOL_VERSION, RECONNECT_MAX_BACKOFF, RECONNECT_MIN_BACKOFF, RECONNECT_TIMEOUT,
+    ROOM_HANDSHAKE, STROKE_FLUSH_INTERVAL, SYNC_LOG_FILE,
+};
+use crate::crdt::{CellRegisters, Stamp};
+use crate::error::{self, Error};
+use crate::protocol::{
+    drain_messages, encode_message, encode_update, SerializableHello,
+    SerializablePing, SerializableStroke, SerializableTermChar, Update,
+};
+use crate::traffic::{Direction, TrafficRecorder};
+use crate::websocket::{
+    self, Frame, OPCODE_BINARY, OPCODE_CLOSE, OPCODE_PING, OPCODE_PONG, OPCODE_TEXT,
+};
+
+// shared session state shown next to the cursor info
+#[derive(PartialEq, Clone, Copy)]
+pub enum SessionStatus {
+    // joining, the attempt made so far out of how many
+    Connecting { attempt: u32, of: u32 },
+    Reconnecting,
+    // live but no peer answered the last ping, e.g. alone in the room
+    Connected,
+    Latency(Duration),
+}
+
+// what the ui asks of the network thread
+enum Command {
+    Publish(Update),
+    Hello(SerializableHello),
+    Pong(SerializablePing),
+    Record(TrafficRecorder),
+}
+
+// how a join started with Client::join is going
+pub enum JoinProgress {
+    Attempt { attempt: u32, of: u32 },
+    Joined(Client),
+    Failed(Error),
+}
+
+// what the network thread reports back to the ui
+pub enum NetworkEvent {
+    Received(Vec<u8>),
+    Status(SessionStatus),
+}
+
+// ui side of a shared session, the connection itself lives on its own thread
+// so a slow or unreachable server never stalls rendering or input
+pub struct Client {
+    pub _addr: String,
+    pub room: String,
+    commands: Sender<Command>,
+    events: Receiver<NetworkEvent>,
+    last_checksum: Instant,
+    registers: CellRegisters,
+    // id the server knows this client by, kept across reconnections
+    participant: Arc<Mutex<Option<u64>>>,
+}
+
+impl Client {
+    // connects and joins room on a thread of its own so the editor never
+    // freezes, progress is read from the receiver until the join ends either
+    // way, fails when the server turns the handshake down
+    pub fn join(addr: &str, room: &str, password: &str, websocket: bool) -> Receiver<JoinProgress> {
+        let (progress, receiver) = mpsc::channel::<JoinProgress>();
+        let (addr, room, password) = (addr.to_string(), room.to_string(), password.to_string());
+        thread::spawn(move || {
+            let attempts: Sender<JoinProgress> = progress.clone();
+            let on_attempt = move |attempt: u32, of: u32| {
+                let _ = attempts.send(JoinProgress::Attempt { attempt, of });
+            };
+            let joined = Client::start(Connection::new(
+                &addr,
+                &room,
+                &password,
+                websocket,
+                &on_attempt,
+            ));
+            let _ = progress.send(match joined {
+                Ok(client) => JoinProgress::Joined(client),
+                Err(e) => JoinProgress::Failed(e),
+            });
+        });
+        receiver
+    }
+
+    // the connection is handed to its own thread
+    fn start(connection: error::Result<Connection>) -> error::Result<Self> {
+        let connection: Connection = connection?;
+        let (addr, room) = (connection._addr.clone(), connection.room.clone());
+        let participant: Arc<Mutex<Option<u64>>> = Arc::clone(&connection.participant);
+        // writes are stamped with the participant id so a session knows its own
+        // in the history the server replays, unique in the room as well
+        let joined: Option<u64> = *participant.lock().unwrap_or_else(PoisonError::into_inner);
+        let (commands, commands_rx) = mpsc::channel::<Command>();
+        let (events_tx, events) = mpsc::channel::<NetworkEvent>();
+        thread::spawn(move || connection.run(commands_rx, events_tx));
+        Ok(Client {
+            _addr: addr,
+            room,
+            commands,
+            events,
+            last_checksum: Instant::now(),
+            registers: joined.map_or_else(CellRegisters::new, CellRegisters::with_replica),
+            participant,
+        })
+    }
+
+    // a gone network thread has nothing left to send, updates are dropped
+    pub fn publish(&mut self, mut update: Update) {
+        self.stamp(&mut update);
+        let _ = self.commands.send(Command::Publish(update));
+    }
+
+    // writes to the shared layer get a fresh stamp, syncs carry the stamps of
+    // the writes they replay
+    pub fn stamp(&mut self, update: &mut Update) {
+        match update {
+            Update::TermChar(tc) => tc.stamp = self.registers.write((tc.abs_x, tc.abs_y)),
+            Update::Stroke(stroke) => {
+                for tc in stroke.cells.iter_mut() {
+                    tc.stamp = self.registers.write((tc.abs_x, tc.abs_y));
+                }
+            }
+            Update::Erase(erase) => erase.stamp = self.registers.write((erase.abs_x, erase.abs_y)),
+            Update::Text(text) => text.stamp = self.registers.write((text.abs_x, text.abs_y)),
+            Update::Sync(sync) => {
+                for tc in sync.items.iter_mut() {
+                    tc.stamp = self.registers.get((tc.abs_x, tc.abs_y));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // cells of the shared layer written so far, by anyone, with their stamps
+    pub fn written(&self) -> Vec<((i32, i32), Stamp)> {
+        self.registers.cells().collect()
+    }
+
+    // true when a remote write to cell wins over the last one seen there
+    pub fn accepts(&mut self, cell: (i32, i32), stamp: Stamp) -> bool {
+        self.registers.merge(cell, stamp)
+    }
+
+    pub fn participant(&self) -> Option<u64> {
+        *self.participant.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn on_hello(&self, hello: SerializableHello) {
+        let _ = self.commands.send(Command::Hello(hello));
+    }
+
+    pub fn on_pong(&self, pong: SerializablePing) {
+        let _ = self.commands.send(Command::Pong(pong));
+    }
+
+    // the file is opened here so a failure reaches the ui
+    pub fn record_to(&self, path: &str) -> std::io::Result<()> {
+        let recorder: TrafficRecorder = TrafficRecorder::new(path)?;
+        let _ = self.commands.send(Command::Record(recorder));
+        Ok(())
+    }
+
+    // everything the network thread reported since the last call
+    pub fn poll_events(&self) -> Vec<NetworkEvent> {
+        self.events.try_iter().collect()
+    }
+
+    // true once every CHECKSUM_INTERVAL, the caller is expected to publish the checksum
+    pub fn checksum_due(&mut self) -> bool {
+        if self.last_checksum.elapsed() < CHECKSUM_INTERVAL {
+            return false;
+        }
+        self.last_checksum = Instant::now();
+        true
+    }
+
+    pub fn log_divergence(&self, message: &str) {
+        log_divergence(&self._addr, message);
+    }
+}
+
+// divergences are appended to SYNC_LOG_FILE, the terminal belongs to the canvas
+fn log_divergence(addr: &str, message: &str) {
+    if let Ok(mut file) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(SYNC_LOG_FILE)
+    {
+        let _ = writeln!(file, "[{}] {}", addr, message);
+    }
+}
+
+// an update framed for the socket and how much of the frame was written,
+// websocket pongs have no update
+struct Outgoing {
+    update: Option<Vec<u8>>,
+    frame: Vec<u8>,
+    written: usize,
+}
+
+struct Connection {
+    client: TcpStream,
+    participant: Arc<Mutex<Option<u64>>>,
+    // given by the server with the participant id, proves it when rejoining
+    secret: Option<u64>,
+    _addr: String,
+    room: String,
+    password: String,
+    // false once the connection dropped, updates keep queueing until it is back
+    live: bool,
+    backoff: Duration,
+    next_attempt: Instant,
+    pubsub: VecDeque<Vec<u8>>,
+    // the update the socket took part of, the rest goes out before any other
+    outgoing: Option<Outgoing>,
+    // websocket pongs, framed, they go out between updates
+    pongs: VecDeque<Vec<u8>>,
+    recorder: Option<TrafficRecorder>,
+    // updates go out as binary frames while every peer that said hello
+    // announced support for them
+    binary: bool,
+    // lowest protocol announced by a peer since joining
+    lowest_protocol: Option<u8>,
+    // traffic wrapped in websocket frames, or else behind its length, inbox
+    // keeps what is split across reads
+    websocket: bool,
+    inbox: Vec<u8>,
+    // pixels waiting to go out as one stroke, since when
+    stroke: Vec<SerializableTermChar>,
+    stroke_started: Instant,
+    last_ping: Instant,
+    // nonce and send time of the ping waiting for its pong
+    pending_ping: Option<(u64, Instant)>,
+    latency: Option<Duration>,
+}
+
+impl Connection {
+    // create and connect non blocking to the addr specified, joining room,
+    // fails when the server is unreachable or turns the handshake down
+    // on_attempt is told of every connection attempt before it is made, the
+    // first one is made at once and the next ones back off as reconnections do
+    fn new(
+        addr: &str,
+        room: &str,
+        password: &str,
+        websocket: bool,
+        on_attempt: &dyn Fn(u32, u32),
+    ) -> error::Result<Self> {
+        let max_attempts: u32 = 5;
+        let mut attempts: u32 = 0;
+        let mut backoff: Duration = RECONNECT_MIN_BACKOFF;
+        let socket_client: TcpStream = loop {
+            attempts += 1;
+            on_attempt(attempts, max_attempts);
+            let stream: Option<TcpStream> = addr
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .and_then(|addr| TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT).ok());
+            match stream {
+                Some(stream) => break stream,
+                None if attempts >= max_attempts => {
+                    return Err(Error::Unreachable {
+                        addr: addr.to_string(),
+                        attempts,
+                    });
+                }
+                None => {
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF);
+                }
+            }
+        };
+
+        let admission: Option<(u64, Option<u64>)> = match websocket {
+            true => join_room_websocket(&socket_client, addr, room, password, None)?,
+            false => join_room(&socket_client, room, password, None)?,
+        };
+        socket_client.set_nonblocking(true)?;
+        let participant: Option<u64> = admission.map(|(participant, _)| participant);
+
+        let mut connection = Connection {
+            participant: Arc::new(Mutex::new(participant)),
+            secret: admission.and_then(|(_, secret)| secret),
+            client: socket_client,
+            _addr: addr.to_string(),
+            room: room.to_string(),
+            password: password.to_string(),
+            live: true,
+            backoff: RECONNECT_MIN_BACKOFF,
+            next_attempt: Instant::now(),
+            pubsub: VecDeque::new(),
+            outgoing: None,
+            pongs: VecDeque::new(),
+            recorder: None,
+            binary: false,
+            lowest_protocol: None,
+            websocket,
+            inbox: Vec::new(),
+            stroke: Vec::new(),
+            stroke_started: Instant::now(),
+            last_ping: Instant::now(),
+            pending_ping: None,
+            latency: None,
+        };
+        connection.publish(Update::Hello(SerializableHello::new(false, participant)));
+        Ok(connection)
+    }
+
+    // serves the ui until its Client is dropped, queued updates are sent one
+    // last time before leaving
+    fn run(mut self, commands: Receiver<Command>, events: Sender<NetworkEvent>) {
+        let mut status: Option<SessionStatus> = None;
+        loop {
+            loop {
+                match commands.try_recv() {
+                    Ok(Command::Publish(update)) => self.publish(update),
+                    Ok(Command::Hello(hello)) => self.on_hello(hello),
+                    Ok(Command::Pong(pong)) => self.on_pong(pong),
+                    Ok(Command::Record(recorder)) => self.recorder = Some(recorder),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.flush_stroke();
+                        self.broadcast_client_updates();
+                        return;
+                    }
+                }
+            }
+            self.reconnect();
+            let received: Option<Vec<u8>> = self.read_server_update();
+            let idle: bool = received.is_none();
+            self.heartbeat();
+            self.broadcast_client_updates();
+
+            let current: SessionStatus = self.status();
+            let mut reports: Vec<NetworkEvent> =
+                received.map(NetworkEvent::Received).into_iter().collect();
+            if status != Some(current) {
+                status = Some(current);
+                reports.push(NetworkEvent::Status(current));
+            }
+            // the ui is gone without saying so, nothing left to do
+            if reports
+                .into_iter()
+                .any(|report| events.send(report).is_err())
+            {
+                return;
+            }
+            if idle {
+                thread::sleep(NETWORK_POLL_INTERVAL);
+            }
+        }
+    }
+
+    // peers speaking PROTOCOL_VERSION switch this client to binary frames,
+    // one older peer in the room keeps everyone on json, a first hello is
+    // answered so the peer can switch as well, or learn it has to upgrade
+    fn on_hello(&mut self, hello: SerializableHello) {
+        let lowest: u8 = self
+            .lowest_protocol
+            .map_or(hello.protocol, |lowest| lowest.min(hello.protocol));
+        self.lowest_protocol = Some(lowest);
+        self.binary = lowest == PROTOCOL_VERSION;
+        if !hello.reply {
+            let participant: Option<u64> =
+                *self.participant.lock().unwrap_or_else(PoisonError::into_inner);
+            self.publish(Update::Hello(SerializableHello::new(true, participant)));
+        }
+    }
+
+    // pings the peers every HEARTBEAT_INTERVAL while live, a ping left
+    // unanswered by then means the latency is unknown again
+    fn heartbeat(&mut self) {
+        if !self.live || self.last_ping.elapsed() < HEARTBEAT_INTERVAL {
+            return;
+        }
+        if self.pending_ping.is_some() {
+            self.latency = None;
+        }
+        let nonce: u64 = rand::random();
+        self.last_ping = Instant::now();
+        self.pending_ping = Some((nonce, self.last_ping));
+        self.publish(Update::Ping(SerializablePing { nonce }));
+    }
+
+    // the first pong to the pending ping gives the round trip, later ones and
+    // pongs to other peers are ignored
+    fn on_pong(&mut self, pong: SerializablePing) {
+        if let Some((nonce, sent)) = self.pending_ping {
+            if nonce == pong.nonce {
+                self.latency = Some(sent.elapsed());
+                self.pending_ping = None;
+            }
+        }
+    }
+
+    fn status(&self) -> SessionStatus {
+        match (self.live, self.latency) {
+            (false, _) => SessionStatus::Reconnecting,
+            (true, None) => SessionStatus::Connected,
+            (true, Some(latency)) => SessionStatus::Latency(latency),
+        }
+    }
+
+    fn disconnect(&mut self) {
+        if self.live {
+            log_divergence(&self._addr, "connection lost, reconnecting");
+        }
+        self.live = false;
+        // a frame cut short is sent whole on the next connection
+        if let Some(update) = self.outgoing.take().and_then(|outgoing| outgoing.update) {
+            self.pubsub.push_front(update);
+        }
+        self.pongs.clear();
+        self.binary = false;
+        self.lowest_protocol = None;
+        self.pending_ping = None;
+        self.latency = None;
+    }
+
+    // one attempt per call, spaced with an exponential backoff, the hello sent
+    // first makes the peers send a fresh sync of the canvas
+    fn reconnect(&mut self) {
+        if self.live || Instant::now() < self.next_attempt {
+            return;
+        }
+        let stream: Option<TcpStream> = self
+            ._addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .and_then(|addr| TcpStream::connect_timeout(&addr, RECONNECT_TIMEOUT).ok());
+        let (addr, room, password) = (&self._addr, &self.room, &self.password);
+        let websocket: bool = self.websocket;
+        let mut participant = self
+            .participant
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let rejoin: Option<(u64, u64)> = participant.zip(self.secret);
+        let rejoined = stream.and_then(|stream| {
+            let joined = match websocket {
+                true => join_room_websocket(&stream, addr, room, password, rejoin),
+                false => join_room(&stream, room, password, rejoin),
+            };
+            let joined: Option<(u64, Option<u64>)> = joined.ok()?;
+            stream.set_nonblocking(true).ok()?;
+            Some((stream, joined))
+        });
+        let Some((stream, admission)) = rejoined else {
+            drop(participant);
+            self.backoff = (self.backoff * 2).min(RECONNECT_MAX_BACKOFF);
+            self.next_attempt = Instant::now() + self.backoff;
+            return;
+        };
+        let joined: Option<u64> = admission.map(|(participant, _)| participant);
+        *participant = joined;
+        drop(participant);
+        self.secret = admission.and_then(|(_, secret)| secret);
+        self.client = stream;
+        self.inbox.clear();
+        self.live = true;
+        self.backoff = RECONNECT_MIN_BACKOFF;
+        log_divergence(&self._addr, "reconnected");
+        let hello: Update = Update::Hello(SerializableHello::new(false, joined));
+        if let Some(hello) = self.encode(&hello, false) {
+            self.pubsub.push_front(hello);
+        }
+    }
+
+    // plain bytes return from other clients in the shared session
+    fn read_server_update(&mut self) -> Option<Vec<u8>> {
+        if !self.live {
+            return None;
+        }
+        let mut server_buff: Vec<u8> = vec![0; 1024];
+        match self.client.read(&mut server_buff) {
+            // end of stream, the server closed the connection
+            Ok(0) => {
+                self.disconnect();
+                None
+            }
+            Err(e) if e.kind() != ErrorKind::WouldBlock => {
+                self.disconnect();
+                None
+            }
+            Ok(n) => {
+                server_buff.truncate(n);
+                server_buff = match self.websocket {
+                    true => self.unwrap_frames(&server_buff)?,
+                    false => self.unwrap_messages(&server_buff)?,
+                };
+                if let (Some(recorder), false) = (&mut self.recorder, server_buff.is_empty()) {
+                    recorder.record(Direction::Received, &server_buff);
+                }
+                Some(server_buff)
+            }
+            Err(_) => None,
+        }
+    }
+
+    // payloads of the complete messages read so far
+    fn unwrap_messages(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        self.inbox.extend_from_slice(bytes);
+        let Ok(messages) = drain_messages(&mut self.inbox) else {
+            self.disconnect();
+            return None;
+        };
+        let data: Vec<u8> = messages.concat();
+        (!data.is_empty()).then_some(data)
+    }
+
+    // payloads of the complete frames read so far, pings are answered between
+    // updates and a close frame ends the connection, a refused frame is
+    // answered with a close frame when nothing else is being written
+    fn unwrap_frames(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        self.inbox.extend_from_slice(bytes);
+        let frames: Vec<Frame> = match websocket::drain_frames(&mut self.inbox) {
+            Ok(frames) => frames,
+            Err(e) => {
+                // not in the middle of a frame the socket took part of
+                if self.outgoing.is_none() {
+                    let refusal: Vec<u8> = websocket::refusal(&e);
+                    let close: Vec<u8> = websocket::encode_frame(OPCODE_CLOSE, &refusal, true);
+                    let _ = self.client.write_all(&close);
+                }
+                self.disconnect();
+                return None;
+            }
+        };
+        if frames.iter().any(|frame| frame.opcode == OPCODE_CLOSE) {
+            self.disconnect();
+            return None;
+        }
+        for ping in frames.iter().filter(|frame| frame.opcode == OPCODE_PING) {
+            let pong: Vec<u8> = websocket::encode_frame(OPCODE_PONG, &ping.payload, true);
+            self.pongs.push_back(pong);
+        }
+        let data: Vec<u8> = frames
+            .iter()
+            .filter_map(Frame::data)
+            .flatten()
+            .copied()
+            .collect();
+        (!data.is_empty()).then_some(data)
+    }
+
+    // write to server queued updates from current client, in order. the
+    // socket does not block, a frame it takes only part of is finished on
+    // the next run before anything else goes out
+    fn broadcast_client_updates(&mut self) {
+        if !self.live {
+            return;
+        }
+        if self.stroke_started.elapsed() >= STROKE_FLUSH_INTERVAL {
+            self.flush_stroke();
+        }
+        loop {
+            if self.outgoing.is_none() {
+                let outgoing: Outgoing = match self.pongs.pop_front() {
+                    Some(pong) => Outgoing {
+                        update: None,
+                        frame: pong,
+                        written: 0,
+                    },
+                    None => {
+                        let Some(update) = self.pubsub.pop_front() else {
+                            return;
+                        };
+                        let frame: Vec<u8> = match self.websocket {
+                            true => websocket::encode_frame(OPCODE_BINARY, &update, true),
+                            false => encode_message(&update),
+                        };
+                        Outgoing {
+                            update: Some(update),
+                            frame,
+                            written: 0,
+                        }
+                    }
+                };
+                self.outgoing = Some(outgoing);
+            }
+            let Some(outgoing) = self.outgoing.as_mut() else {
+                return;
+            };
+            match self.client.write(&outgoing.frame[outgoing.written..]) {
+                Ok(0) => return self.disconnect(),
+                Ok(written) => {
+                    outgoing.written += written;
+                    if outgoing.written < outgoing.frame.len() {
+                        continue;
+                    }
+                    let sent: Option<Vec<u8>> = self.outgoing.take().and_then(|sent| sent.update);
+                    if let (Some(recorder), Some(sent)) = (&mut self.recorder, sent) {
+                        recorder.record(Direction::Sent, &sent);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return self.disconnect(),
+            }
+        }
+    }
+
+    // publish serialized update the client pubsub queue
+    // the update event is going to be serialized and pushed to the queue
+    // for later processing, pixels are held back to travel as a stroke and
+    // anything else flushes them first so peers apply updates in order
+    fn publish(&mut self, update: Update) {
+        if let Update::TermChar(cell) = update {
+            if self.stroke.is_empty() {
+                self.stroke_started = Instant::now();
+            }
+            self.stroke.push(cell);
+            return;
+        }
+        self.flush_stroke();
+        if let Some(serialized) = self.encode(&update, self.binary) {
+            self.pubsub.push_back(serialized);
+        }
+    }
+
+    // an update that cannot be serialized is logged and left out
+    fn encode(&self, update: &Update, binary: bool) -> Option<Vec<u8>> {
+        encode_update(update, binary)
+            .map_err(|e| log_divergence(&self._addr, &format!("unserializable update: {}", e)))
+            .ok()
+    }
+
+    fn flush_stroke(&mut self) {
+        if self.stroke.is_empty() {
+            return;
+        }
+        let cells: Vec<SerializableTermChar> = std::mem::take(&mut self.stroke);
+        let update: Update = match <[SerializableTermChar; 1]>::try_from(cells) {
+            Ok([cell]) => Update::TermChar(cell),
+            Err(cells) => Update::Stroke(SerializableStroke { cells }),
+        };
+        if let Some(serialized) = self.encode(&update, self.binary) {
+            self.pubsub.push_back(serialized);
+        }
+    }
+}
+
+// the first line sent on a connection picks the room, the server only relays
+// between peers of the same room, and presents the participant id and secret
+// of a client rejoining, the second one carries the session password. The answer is read
+// a byte at a time, updates relayed right after it are left on the stream for
+// the session
+fn join_room(
+    mut stream: &TcpStream,
+    room: &str,
+    password: &str,
+    rejoin: Option<(u64, u64)>,
+) -> std::io::Result<Option<(u64, Option<u64>)>> {
+    let room: String = match rejoin {
+        Some((participant, secret)) => format!("{} {} {}", room, participant, secret),
+        None => room.to_string(),
+    };
+    let handshake: String = format!(
+        "{}{}\n{}{}\n",
+        ROOM_HANDSHAKE, room, AUTH_HANDSHAKE, password
+    );
+    stream.write_all(handshake.as_bytes())?;
+
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut answer: Vec<u8> = Vec::new();
+    let mut byte: [u8; 1] = [0];
+    while byte[0] != b'\n' {
+        if stream.read(&mut byte)? == 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::ConnectionAborted,
+                "server closed the connection during the handshake",
+            ));
+        }
+        answer.push(byte[0]);
+    }
+    stream.set_read_timeout(None)?;
+    check_handshake_answer(&String::from_utf8_lossy(&answer))
+}
+
+// over websocket the room, participant id and secret go in the upgrade path and the
+// password in the first message, urls end up in logs. The answer comes as a
+// text frame, or as a close frame carrying the rejection reason
+fn join_room_websocket(
+    mut stream: &TcpStream,
+    host: &str,
+    room: &str,
+    password: &str,
+    rejoin: Option<(u64, u64)>,
+) -> std::io::Result<Option<(u64, Option<u64>)>> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let path: String = match rejoin {
+        Some((participant, secret)) => format!("/{}?peer={}&secret={}", room, participant, secret),
+        None => format!("/{}", room),
+    };
+    websocket::connect(&mut stream, host, &path)?;
+    let auth: String = format!("{}{}", AUTH_HANDSHAKE, password);
+    stream.write_all(&websocket::encode_frame(OPCODE_TEXT, auth.as_bytes(), true))?;
+    let frame: Frame = websocket::read_frame(&mut stream, websocket::MAX_FRAME_LEN)?;
+    stream.set_read_timeout(None)?;
+    match frame.opcode {
+        OPCODE_CLOSE => {
+            let reason = String::from_utf8_lossy(frame.payload.get(2..).unwrap_or_default());
+            check_handshake_answer(&format!("{}{}", HANDSHAKE_REJECTED, reason))
+        }
+        _ => check_handshake_answer(&String::from_utf8_lossy(&frame.payload)),
+    }
+}
+
+// the participant id given by the server and the secret to rejoin with it,
+// older servers give no secret or neither
+fn check_handshake_answer(answer: &str) -> std::io::Result<Option<(u64, Option<u64>)>> {
+    let answer: &str = answer.trim_end();
+    if let Some(admission) = answer.strip_prefix(HANDSHAKE_ACCEPTED) {
+        if admission.is_empty() || admission.starts_with(' ') {
+            let mut words = admission.split_whitespace().map(|word| word.parse().ok());
+            let participant: Option<u64> = words.next().flatten();
+            let secret: Option<u64> = words.next().flatten();
+            return Ok(participant.map(|participant| (participant, secret)));
+        }
+    }
+    let reason: &str = answer.strip_prefix(HANDSHAKE_REJECTED).unwrap_or(answer);
+    Err(std::io::Error::new(
+        ErrorKind::PermissionDenied,
+        format!("server rejected the connection: {}", reason),
+    ))
+}
@@ -0,0 +1,250 @@
+use std::fs;
+use std::path::Path;
+
+use pixelrs::transform::Transform;
+
+// what the `:` prompt understands, name and arguments as the help shows them
+pub const COMMANDS: [(&str, &str); 12] = [
+    ("save", "[file.pxr]"),
+    ("export", "<format> [out]"),
+    ("resize", "<width> <height>"),
+    ("layer", "new [name]"),
+    ("connect", "<host:port> [room]"),
+    ("glyph", "[character] [background 0-255]"),
+    ("sprite", "save <name>"),
+    ("flip", "<h|v>"),
+    ("rotate", "[cw|ccw]"),
+    ("grid", "[spacing]"),
+    ("frame", "<number|delete>"),
+    ("script", "<file.rhai>"),
+];
+
+const LAYER_COMMANDS: [&str; 1] = ["new"];
+const SPRITE_COMMANDS: [&str; 1] = ["save"];
+const FRAME_COMMANDS: [&str; 1] = ["delete"];
+const FLIPS: [&str; 2] = ["h", "v"];
+const ROTATIONS: [&str; 2] = ["cw", "ccw"];
+
+#[derive(PartialEq, Debug)]
+pub enum Command {
+    Save(Option<String>),
+    Export { format: String, out: Option<String> },
+    Resize(i32, i32),
+    NewLayer(Option<String>),
+    Connect { addr: String, room: Option<String> },
+    // character the brush paints and its background, none for pixels
+    Glyph(Option<(char, Option<u8>)>),
+    SaveSprite(String),
+    // of the clone tool sample
+    Transform(Transform),
+    // pixels between grid lines, none toggles the grid
+    Grid(Option<i32>),
+    // animation frame to show, counted from 1
+    ShowFrame(usize),
+    DeleteFrame,
+    Script(String),
+}
+
+fn usage(name: &str) -> String {
+    let (_, arguments) = COMMANDS
+        .iter()
+        .find(|(command, _)| *command == name)
+        .expect("every command has a usage");
+    format!("usage: {} {}", name, arguments)
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((&name, arguments)) = words.split_first() else {
+        return Err("no command".to_string());
+    };
+    let owned = |word: Option<&&str>| word.map(|word| word.to_string());
+    match (name, arguments) {
+        ("save", [] | [_]) => Ok(Command::Save(owned(arguments.first()))),
+        ("export", [format] | [format, _]) => Ok(Command::Export {
+            format: format.to_string(),
+            out: owned(arguments.get(1)),
+        }),
+        ("resize", [width, height]) => match (width.parse::<i32>(), height.parse::<i32>()) {
+            (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+                Ok(Command::Resize(width, height))
+            }
+            _ => Err(usage(name)),
+        },
+        ("layer", ["new", rest @ ..]) if rest.len() <= 1 => {
+            Ok(Command::NewLayer(owned(rest.first())))
+        }
+        ("connect", [addr] | [addr, _]) if addr.contains(':') => Ok(Command::Connect {
+            addr: addr.to_string(),
+            room: owned(arguments.get(1)),
+        }),
+        ("sprite", ["save", name]) => Ok(Command::SaveSprite(name.to_string())),
+        ("flip", ["h"]) => Ok(Command::Transform(Transform::FlipHorizontal)),
+        ("flip", ["v"]) => Ok(Command::Transform(Transform::FlipVertical)),
+        ("rotate", [] | ["cw"]) => Ok(Command::Transform(Transform::RotateClockwise)),
+        ("rotate", ["ccw"]) => Ok(Command::Transform(Transform::RotateCounterClockwise)),
+        ("grid", []) => Ok(Command::Grid(None)),
+        ("grid", [spacing]) => match spacing.parse::<i32>() {
+            Ok(spacing) if spacing > 0 => Ok(Command::Grid(Some(spacing))),
+            _ => Err(usage(name)),
+        },
+        ("script", [path]) => Ok(Command::Script(path.to_string())),
+        ("frame", ["delete"]) => Ok(Command::DeleteFrame),
+        ("frame", [number]) => match number.parse::<usize>() {
+            Ok(number) if number > 0 => Ok(Command::ShowFrame(number)),
+            _ => Err(usage(name)),
+        },
+        ("glyph", []) => Ok(Command::Glyph(None)),
+        ("glyph", [glyph] | [glyph, _]) if glyph.chars().count() == 1 => {
+            let glyph: char = glyph.chars().next().unwrap_or(' ');
+            match arguments.get(1).map(|background| background.parse::<u8>()) {
+                None => Ok(Command::Glyph(Some((glyph, None)))),
+                Some(Ok(code)) => Ok(Command::Glyph(Some((glyph, Some(code))))),
+                Some(Err(_)) => Err(usage(name)),
+            }
+        }
+        _ if COMMANDS.iter().any(|(command, _)| *command == name) => Err(usage(name)),
+        _ => Err(format!("unknown command {}", name)),
+    }
+}
+
+// the line with its last word completed as far as every candidate agrees,
+// and the candidates when more than one is left. commands, export formats,
+// the arguments of layer, sprite, frame, flip and rotate and the files of
+// save, export and script are completed
+pub fn complete(line: &str, formats: &[&str]) -> (String, Vec<String>) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (head, last): (&[&str], &str) = match line.ends_with(' ') || words.is_empty() {
+        true => (&words, ""),
+        false => (&words[..words.len() - 1], words[words.len() - 1]),
+    };
+    let options: Vec<String> = match head {
+        [] => COMMANDS.iter().map(|(name, _)| name.to_string()).collect(),
+        ["export"] => formats.iter().map(|format| format.to_string()).collect(),
+        ["layer"] => LAYER_COMMANDS.iter().map(|name| name.to_string()).collect(),
+        ["sprite"] => SPRITE_COMMANDS
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        ["frame"] => FRAME_COMMANDS.iter().map(|name| name.to_string()).collect(),
+        ["flip"] => FLIPS.iter().map(|name| name.to_string()).collect(),
+        ["rotate"] => ROTATIONS.iter().map(|name| name.to_string()).collect(),
+        ["save"] | ["export", _] | ["script"] => files(last),
+        _ => Vec::new(),
+    };
+    let candidates: Vec<String> = options
+        .into_iter()
+        .filter(|option| option.starts_with(last))
+        .collect();
+    let Some(first) = candidates.first() else {
+        return (line.to_string(), candidates);
+    };
+    let common: usize = candidates.iter().fold(first.len(), |common, candidate| {
+        first
+            .chars()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(common)
+    });
+    let mut completed: String = head.iter().map(|word| format!("{} ", word)).collect();
+    completed.push_str(&first.chars().take(common).collect::<String>());
+    if candidates.len() == 1 && !first.ends_with('/') {
+        completed.push(' ');
+        return (completed, Vec::new());
+    }
+    (completed, candidates)
+}
+
+// entries of the directory partial points into, directories end with a slash
+fn files(partial: &str) -> Vec<String> {
+    let (dir, shown) = match partial.rfind('/') {
+        Some(slash) => (&partial[..=slash], &partial[..=slash]),
+        None => (".", ""),
+    };
+    let Ok(entries) = fs::read_dir(Path::new(dir)) else {
+        return Vec::new();
+    };
+    let mut files: Vec<String> = entries
+        .flatten()
+        .map(|entry| {
+            let name: String = entry.file_name().to_string_lossy().to_string();
+            match entry.path().is_dir() {
+                true => format!("{}{}/", shown, name),
+                false => format!("{}{}", shown, name),
+            }
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed_with_their_arguments() {
+        assert_eq!(parse("save"), Ok(Command::Save(None)));
+        assert_eq!(
+            parse("  save  art.pxr "),
+            Ok(Command::Save(Some("art.pxr".to_string())))
+        );
+        assert_eq!(
+            parse("export png out.png"),
+            Ok(Command::Export {
+                format: "png".to_string(),
+                out: Some("out.png".to_string()),
+            })
+        );
+        assert_eq!(parse("resize 200 60"), Ok(Command::Resize(200, 60)));
+        assert_eq!(parse("layer new"), Ok(Command::NewLayer(None)));
+        assert_eq!(
+            parse("connect 10.0.0.1:8080 lobby"),
+            Ok(Command::Connect {
+                addr: "10.0.0.1:8080".to_string(),
+                room: Some("lobby".to_string()),
+            })
+        );
+        assert_eq!(parse("glyph # 21"), Ok(Command::Glyph(Some(('#', Some(21))))));
+        assert_eq!(
+            parse("rotate"),
+            Ok(Command::Transform(Transform::RotateClockwise))
+        );
+        assert_eq!(parse("grid 8"), Ok(Command::Grid(Some(8))));
+        assert_eq!(parse("frame 2"), Ok(Command::ShowFrame(2)));
+        assert_eq!(parse("frame delete"), Ok(Command::DeleteFrame));
+    }
+
+    #[test]
+    fn bad_arguments_show_the_usage() {
+        assert_eq!(parse(""), Err("no command".to_string()));
+        assert_eq!(parse("paint"), Err("unknown command paint".to_string()));
+        assert_eq!(
+            parse("resize 0 60"),
+            Err("usage: resize <width> <height>".to_string())
+        );
+        assert_eq!(parse("connect localhost"), Err(usage("connect")));
+        assert_eq!(parse("glyph ab"), Err(usage("glyph")));
+        assert_eq!(parse("glyph # 300"), Err(usage("glyph")));
+        assert_eq!(parse("frame 0"), Err(usage("frame")));
+        assert_eq!(parse("flip x"), Err(usage("flip")));
+    }
+
+    #[test]
+    fn words_are_completed_as_far_as_the_candidates_agree() {
+        let formats: [&str; 4] = ["ansi", "text", "html", "png"];
+        assert_eq!(complete("ex", &formats), ("export ".to_string(), Vec::new()));
+        assert_eq!(
+            complete("s", &formats),
+            (
+                "s".to_string(),
+                vec!["save".to_string(), "sprite".to_string(), "script".to_string()]
+            )
+        );
+        assert_eq!(complete("export p", &formats), ("export png ".to_string(), Vec::new()));
+        assert_eq!(complete("rotate c", &formats).1, vec!["cw", "ccw"]);
+        assert_eq!(complete("layer ", &formats), ("layer new ".to_string(), Vec::new()));
+        assert_eq!(complete("resize 2", &formats), ("resize 2".to_string(), Vec::new()));
+    }
+}
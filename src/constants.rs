@@ -1,7 +1,103 @@
 use crate::screen::TermChar;
 use crossterm::style::Color;
+use std::time::Duration;
 
-pub const MAX_FAILED_SENT_ON_QUEUE: usize = 16;
+// longest message a terminal client and the server exchange, a whole canvas
+// sync fits well within it
+pub const MAX_MESSAGE_LEN: usize = 32 << 20;
+// how often the shared canvas hash is exchanged with the server
+pub const CHECKSUM_INTERVAL: Duration = Duration::from_secs(5);
+// pixels painted meanwhile go out together as a single stroke message, which
+// caps drags to one message per interval
+pub const STROKE_FLUSH_INTERVAL: Duration = Duration::from_millis(30);
+// how often peers are pinged to measure the session latency
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+// reconnection attempts start at the min backoff and double up to the max
+pub const RECONNECT_MIN_BACKOFF: Duration = Duration::from_millis(500);
+pub const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+// attempts block the network thread, they are kept short
+pub const RECONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+// a server not answering the handshake by then is taken as gone
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// the network thread naps this long when there was nothing to read
+pub const NETWORK_POLL_INTERVAL: Duration = Duration::from_millis(5);
+// asset downloads stalling longer than this fail
+pub const ASSETS_TIMEOUT: Duration = Duration::from_secs(10);
+// asset downloads bigger than this fail, packs are a few palettes and stamps
+pub const MAX_ASSET_LEN: u64 = 8 * 1024 * 1024;
+// writes to the shared layer of a room the server keeps for late joiners
+pub const JOURNAL_LIMIT: usize = 65536;
+// where scheduled resets archive the outgoing canvas
+pub const DEFAULT_ARCHIVE_DIR: &str = "archive";
+pub const SYNC_LOG_FILE: &str = "pixelrs-sync.log";
+// remote updates received meanwhile are applied together and followed by a
+// single redraw, bursts from peers cannot starve local input
+pub const REMOTE_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+// the ui loop wakes up at least this often for network updates and timers,
+// input arriving meanwhile is handled at once and presented with the frame
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+// a burst of input is handled over several frames past this many events, so
+// what it draws keeps being presented
+pub const MAX_EVENTS_PER_FRAME: usize = 256;
+// time without input or network updates before the document is compacted
+pub const IDLE_COMPACTION_DELAY: Duration = Duration::from_secs(2);
+// pauses between inputs longer than this are not counted as time spent on a piece
+pub const ACTIVE_TIME_IDLE_LIMIT: Duration = Duration::from_secs(60);
+// drag smoothing levels cycled with `s`, 0.0 follows the pointer as is
+pub const DRAG_SMOOTHING_LEVELS: [f32; 4] = [0.0, 0.25, 0.5, 0.75];
+// pixels between grid lines until :grid sets another spacing
+pub const DEFAULT_GRID_SPACING: i32 = 8;
+// how far onion skins of the neighbouring frames are darkened, 0 to 1
+pub const ONION_DIMMING: f32 = 0.6;
+// steps a script may take before it is stopped, loops that never end included
+pub const MAX_SCRIPT_OPERATIONS: u64 = 50_000_000;
+// pixels a script may paint, native calls such as fill_rect count each one
+pub const MAX_SCRIPT_PIXELS: usize = 1_000_000;
+// image pixels per canvas cell side on png export
+pub const DEFAULT_PNG_SCALE: usize = 8;
+// rows of cells rasterized at once by the png export, bounds its memory
+pub const EXPORT_BAND_ROWS: usize = 64;
+// width:height of a terminal cell, most monospace fonts are about twice as tall
+pub const DEFAULT_CELL_ASPECT: (usize, usize) = (1, 2);
+pub const DEFAULT_MONTAGE_COLUMNS: usize = 4;
+// colors extracted from imported images, as many as the picker fits
+pub const PALETTE_SIZE: usize = 16;
+// the shade tool darkens this many pixels in from the edge of a region, the
+// edge by SHADE_STRENGTH and less so further in
+pub const SHADE_STEPS: u32 = 4;
+pub const SHADE_STRENGTH: f32 = 0.6;
+// shapes bigger than this are not taken as a brush, every cell of a stroke
+// paints the whole footprint
+pub const MAX_BRUSH_PIXELS: usize = 256;
+// pixels the view moves on every pan key press, rows down and two cells each
+// across
+pub const PAN_STEP: i32 = 4;
+// view zoom levels, see Screen::zoom
+pub const MIN_ZOOM: i8 = -2;
+pub const MAX_ZOOM: i8 = 3;
+// last pressed keys shown by the recording overlay
+pub const OVERLAY_KEYS: usize = 6;
+// wire protocol spoken by this client, 1 is plain JSON, 2 adds binary frames,
+// 3 stamps shared layer writes, 4 lets the host lock the canvas, 5 signs
+// erases so regions hold against them, 6 sends items whole and 7 sends the
+// erased cells along with a sync
+pub const PROTOCOL_VERSION: u8 = 7;
+// shared sessions without --room all land in the same room
+pub const DEFAULT_ROOM: &str = "default";
+// prefix of the line a client sends right after connecting, followed by the room
+// and, when rejoining, by the participant id and secret the server gave it
+pub const ROOM_HANDSHAKE: &str = "ROOM ";
+// prefix of the second handshake line, followed by the session password if any
+pub const AUTH_HANDSHAKE: &str = "AUTH ";
+// the server answers the handshake with this line followed by the participant
+// id of the client and a secret to rejoin with it, or with HANDSHAKE_REJECTED followed by the reason before
+// closing the connection
+pub const HANDSHAKE_ACCEPTED: &str = "OK";
+pub const HANDSHAKE_REJECTED: &str = "ERR ";
+// kicked participants are turned away this long, banned ones for good
+pub const KICK_COOLDOWN: Duration = Duration::from_secs(60);
+// first byte of a binary frame, JSON messages never start with it
+pub const BINARY_FRAME_TAG: u8 = 0;
 pub const EMPTY_TERM_CHAR: TermChar = TermChar {
     character: ' ',
     foreground_color: Color::Reset,
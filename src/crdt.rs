@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+// lamport timestamp of a write, the replica breaks ties between writes made
+// with the same clock so every peer picks the same winner
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Stamp {
+    pub clock: u64,
+    pub replica: u64,
+}
+
+impl Stamp {
+    // older peers send no stamps, their writes keep the arrival order
+    pub fn is_unset(&self) -> bool {
+        self.clock == 0
+    }
+}
+
+// one last-write-wins register per cell of the shared layer, painting and
+// erasing are both writes so concurrent edits of a cell converge everywhere
+pub struct CellRegisters {
+    clock: u64,
+    replica: u64,
+    stamps: HashMap<(i32, i32), Stamp>,
+}
+
+impl Default for CellRegisters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CellRegisters {
+    pub fn new() -> Self {
+        CellRegisters {
+            clock: 0,
+            // zero is left for unset stamps
+            replica: rand::random::<u64>().max(1),
+            stamps: HashMap::new(),
+        }
+    }
+
+    // replica is never zero, that is left for unset stamps
+    pub fn with_replica(replica: u64) -> Self {
+        CellRegisters {
+            replica: replica.max(1),
+            ..Self::new()
+        }
+    }
+
+    // stamp of a local write to cell, newer than anything seen so far
+    pub fn write(&mut self, cell: (i32, i32)) -> Stamp {
+        self.clock += 1;
+        let stamp: Stamp = Stamp {
+            clock: self.clock,
+            replica: self.replica,
+        };
+        self.stamps.insert(cell, stamp);
+        stamp
+    }
+
+    // stamp of the write currently held by cell, unset when never written
+    pub fn get(&self, cell: (i32, i32)) -> Stamp {
+        self.stamps.get(&cell).copied().unwrap_or_default()
+    }
+
+    // every cell written so far with the stamp it holds
+    pub fn cells(&self) -> impl Iterator<Item = ((i32, i32), Stamp)> + '_ {
+        self.stamps.iter().map(|(cell, stamp)| (*cell, *stamp))
+    }
+
+    // true when the remote write wins over the one held by cell and has to be
+    // applied, unset stamps always are
+    pub fn merge(&mut self, cell: (i32, i32), stamp: Stamp) -> bool {
+        if stamp.is_unset() {
+            return true;
+        }
+        self.clock = self.clock.max(stamp.clock);
+        if stamp <= self.get(cell) {
+            return false;
+        }
+        self.stamps.insert(cell, stamp);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_converge_whatever_the_order() {
+        let stamps: Vec<((i32, i32), Stamp)> = vec![
+            ((0, 0), Stamp { clock: 1, replica: 2 }),
+            ((0, 0), Stamp { clock: 2, replica: 1 }),
+            ((0, 0), Stamp { clock: 2, replica: 3 }),
+            ((1, 0), Stamp { clock: 5, replica: 1 }),
+            ((1, 0), Stamp { clock: 4, replica: 9 }),
+        ];
+        let mut forward: CellRegisters = CellRegisters::with_replica(7);
+        let mut backward: CellRegisters = CellRegisters::with_replica(8);
+        for &(cell, stamp) in stamps.iter() {
+            forward.merge(cell, stamp);
+        }
+        for &(cell, stamp) in stamps.iter().rev() {
+            backward.merge(cell, stamp);
+        }
+        for cell in [(0, 0), (1, 0)] {
+            assert_eq!(forward.get(cell), backward.get(cell));
+        }
+        assert_eq!(forward.get((0, 0)), Stamp { clock: 2, replica: 3 });
+        assert_eq!(forward.get((1, 0)), Stamp { clock: 5, replica: 1 });
+        // a stamp already held, or an older one, is not applied again
+        assert!(!forward.merge((0, 0), Stamp { clock: 2, replica: 3 }));
+        assert!(!forward.merge((1, 0), Stamp { clock: 4, replica: 9 }));
+        // local writes come after everything seen
+        assert!(forward.write((0, 0)) > Stamp { clock: 5, replica: 1 });
+    }
+
+    #[test]
+    fn unset_stamps_are_always_applied_and_lose_to_any_set_one() {
+        let mut registers: CellRegisters = CellRegisters::with_replica(0);
+        assert!(registers.write((0, 0)) > Stamp::default());
+        assert!(registers.merge((0, 0), Stamp::default()));
+        assert!(registers.merge((1, 1), Stamp::default()));
+        // they are not held, the cell keeps its last stamped write
+        assert_eq!(registers.get((1, 1)), Stamp::default());
+        assert_eq!(registers.get((0, 0)), Stamp { clock: 1, replica: 1 });
+        assert!(Stamp { clock: 1, replica: 0 } > Stamp::default());
+    }
+}
@@ -1,218 +1,553 @@
-use std::collections::VecDeque;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::thread;
-use std::time::Duration;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
 
 use crossterm::cursor;
-use crossterm::cursor::MoveTo;
 use crossterm::event::{
-    self as event, KeyCode, KeyEvent, KeyEventKind, MouseButton, MouseEvent, MouseEventKind,
+    self as event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+    MouseEventKind,
 };
 use crossterm::style::Color;
 use crossterm::terminal;
-use crossterm::ExecutableCommand;
-use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
 
-use crate::constants::{EMPTY_TERM_CHAR, MAX_FAILED_SENT_ON_QUEUE};
-use crate::screen::TermChar;
-use crate::screen::{Item, Layer, Pixel, Screen};
+use crate::command_line::{self, Command};
+#[cfg(feature = "gamepad")]
+use crate::gamepad::{Gamepad, GamepadAction};
+use crate::hooks::{HookEvent, Hooks};
+use crate::input::Input;
+use crate::keymap::{Action, Keymap, Mode};
+use pixelrs::archive::{archive_canvas, archived_pieces, read_piece, save_download, ResetSchedule};
+use pixelrs::assets::{AssetPack, StampAsset};
+use pixelrs::boxdraw::{self, BOX_ITEM};
+use pixelrs::cast::CastBackend;
+use pixelrs::client::{Client, JoinProgress, NetworkEvent, SessionStatus};
+use pixelrs::constants::{
+    ACTIVE_TIME_IDLE_LIMIT, DEFAULT_ARCHIVE_DIR, DEFAULT_CELL_ASPECT, DEFAULT_GRID_SPACING,
+    DEFAULT_PNG_SCALE, DRAG_SMOOTHING_LEVELS, EMPTY_TERM_CHAR, FRAME_INTERVAL,
+    IDLE_COMPACTION_DELAY, MAX_BRUSH_PIXELS, MAX_EVENTS_PER_FRAME, MAX_ZOOM, MIN_ZOOM,
+    OVERLAY_KEYS, PALETTE_SIZE, PAN_STEP, REMOTE_FRAME_INTERVAL, SHADE_STEPS, SHADE_STRENGTH,
+};
+use pixelrs::crdt::Stamp;
+use pixelrs::error::{self, Context, Error};
+use pixelrs::export::{export_ansi, export_html, export_png, export_text, Exporters};
+use pixelrs::figlet::{self, Font};
+use pixelrs::history::{FrameChange, History, LocalOperation, MemoryHistory, SqliteHistory};
+use pixelrs::import::{extract_palette, import_ansi, import_image, import_outline};
+use pixelrs::palette::{harmony_suggestions, Palette};
+use pixelrs::project::{
+    now, Project, ProjectLock, ProjectMetadata, DEFAULT_PROJECT_PATH, PROJECT_VERSION,
+};
+use pixelrs::protocol::{
+    decode_updates, item_update, text_item, SerializableArchiveFile,
+    SerializableArchiveList, SerializableArchiveRequest, SerializableChecksum, SerializableErase,
+    SerializableHistoryRequest, SerializableTermChar, SerializableText,
+    SerializableViewport, SerializableWrite, SerializebleSync, Update,
+};
+use pixelrs::regions::{frame, Region, Regions};
+use pixelrs::screen::TermChar;
+use pixelrs::screen::{Item, Layer, Pixel, RenderMode, Screen, TerminalGuard};
+use pixelrs::script::{self, ScriptCanvas};
+use pixelrs::server::{Host, PeerInfo};
+use pixelrs::shade::feathered_fill;
+use pixelrs::sprites::{self, Sprite};
+use pixelrs::text::{is_text, TextEdit, GLYPH_ITEM};
+use pixelrs::transform::Transform;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 enum Tool {
     Brush,
     Erase,
     Ink,
     Move,
     Text,
+    Shade,
+    Box,
+    Clone,
 }
 
-#[derive(PartialEq)]
-enum Config {
-    None,
-    ColorSelection,
-    Connection,
-}
+// numeric tool bar order, key `1` selects the first tool
+const TOOL_BAR: [Tool; 8] = [
+    Tool::Brush,
+    Tool::Erase,
+    Tool::Ink,
+    Tool::Move,
+    Tool::Text,
+    Tool::Shade,
+    Tool::Box,
+    Tool::Clone,
+];
 
-pub struct DrawTerm {
-    screen: Screen,
-    tool: Tool,
-    config: Config,
-    cursor: Item,
-    cursor_info: Item,
-    resized: bool,
-    typing: bool,
-    color_selected: Color,
-    last_cursor_position: (u16, u16),
+impl Tool {
+    fn letter(&self) -> char {
+        match self {
+            Tool::Brush => 'B',
+            Tool::Erase => 'E',
+            Tool::Ink => 'I',
+            Tool::Move => 'M',
+            Tool::Text => 'T',
+            Tool::Shade => 'V',
+            Tool::Box => 'L',
+            Tool::Clone => 'C',
+        }
+    }
+
+    fn parse(name: &str) -> Option<Tool> {
+        TOOL_BAR.into_iter().find(|tool| tool.name() == name)
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Tool::Brush => "brush",
+            Tool::Erase => "erase",
+            Tool::Ink => "ink",
+            Tool::Move => "move",
+            Tool::Text => "text",
+            Tool::Shade => "shade",
+            Tool::Box => "box",
+            Tool::Clone => "clone",
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-enum Update {
-    TermChar(SerializableTermChar),
-    Erase(SerializableErase),
-    Sync(SerializebleSync),
+// human readable key for the overlay, e.g. Ctrl+s or Tab
+fn key_label(event: &KeyEvent) -> String {
+    let mut label: String = String::new();
+    if event.modifiers.contains(KeyModifiers::CONTROL) {
+        label.push_str("Ctrl+");
+    }
+    if event.modifiers.contains(KeyModifiers::ALT) {
+        label.push_str("Alt+");
+    }
+    match event.code {
+        KeyCode::Char(' ') => label.push_str("Space"),
+        KeyCode::Char(c) => label.push(c),
+        code => label.push_str(&format!("{:?}", code)),
+    }
+    label
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
-struct SerializableErase {
-    abs_x: i32,
-    abs_y: i32,
+// what keyboard drawing feeds on_mouse_event, a left button at the cursor
+fn keyboard_pointer(kind: MouseEventKind, (column, row): (u16, u16)) -> MouseEvent {
+    MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers: KeyModifiers::NONE,
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy)]
-struct SerializableTermChar {
-    abs_x: i32,
-    abs_y: i32,
-    character: char,
-    foreground_color: u8,
-    background_color: u8,
-    empty: bool,
+// help entries laid out in as many columns as it takes to fit rows, the
+// names used in the config file are only shown when there is room for them
+// and descriptions are cut short when even that is not enough
+fn help_columns(entries: &[(String, &str, &str)], width: usize, rows: usize) -> Vec<String> {
+    let rows: usize = rows.max(1);
+    let columns: usize = entries.len().div_ceil(rows);
+    let key_width: usize = entries
+        .iter()
+        .map(|(key, _, _)| key.len())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let named: Vec<String> = entries
+        .iter()
+        .map(|(key, description, name)| match name.is_empty() {
+            true => format!("{:<2$}{}", key, description, key_width),
+            false => format!("{:<3$}{} ({})", key, description, name, key_width),
+        })
+        .collect();
+    let plain: Vec<String> = entries
+        .iter()
+        .map(|(key, description, _)| format!("{:<2$}{}", key, description, key_width))
+        .collect();
+    let widest = |cells: &[String]| cells.iter().map(|cell| cell.len()).max().unwrap_or(0) + 2;
+    let cells: Vec<String> = match widest(&named) * columns <= width {
+        true => named,
+        false => plain,
+    };
+    let column_width: usize = widest(&cells).min(width / columns.max(1));
+    (0..rows.min(entries.len()))
+        .map(|row| {
+            (0..columns)
+                .filter_map(|column| cells.get(column * rows + row))
+                .map(|cell| {
+                    let cell: String = cell.chars().take(column_width.saturating_sub(2)).collect();
+                    format!("{:<1$}", cell, column_width)
+                })
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        })
+        .collect()
 }
 
-impl SerializableTermChar {
-    fn from_pixel(pixel: Item, x: i32, y: i32) -> Self {
-        let color = pixel.chars[0][0].background_color;
-        let mut color_code: u8 = 0;
+// peers erase the topmost item at its offset, as the whole item they have
+fn publish_erase(client: &mut Client, item: &Item, author: &str) {
+    client.publish(Update::Erase(SerializableErase {
+        abs_x: item.offset.0,
+        abs_y: item.offset.1,
+        stamp: Stamp::default(),
+        author: author.to_string(),
+    }));
+}
 
-        if let Color::AnsiValue(c) = color {
-            color_code = c;
-        }
+// peers see a moved item go and come back at its new offset
+fn publish_moved(client: &mut Client, before: &Item, after: &Item, author: &str) {
+    publish_erase(client, before, author);
+    client.publish(item_update(after, author));
+}
 
-        SerializableTermChar {
-            abs_x: x,
-            abs_y: y,
-            character: ' ',
-            foreground_color: color_code,
-            background_color: color_code,
-            empty: false,
-        }
+// a message across the bottom row of the screen in item, an empty one takes
+// the row back
+fn draw_bottom_row(screen: &mut Screen, item: &mut Item, message: &str, background: Color) {
+    let (x0, y0) = item.offset;
+    for col in 0..item.chars[0].len() {
+        screen.redraw_cell((x0 + col as i32, y0));
+    }
+    item.chars = vec![vec![]];
+    if message.is_empty() {
+        return;
     }
+    let width: usize = screen.width as usize;
+    item.offset = (0, screen.height as i32 - 1);
+    item.chars = vec![format!(" {:<width$}", message)
+        .chars()
+        .take(width)
+        .map(|c| TermChar {
+            character: c,
+            foreground_color: Color::White,
+            background_color: background,
+            empty: false,
+        })
+        .collect()];
+    item.redraw(&mut screen.term, (0, 0), screen.width, screen.height);
 }
 
-#[derive(Serialize, Deserialize)]
-struct SerializebleSync {
-    items: Vec<SerializableTermChar>,
+// options a tool had when it was last used, restored when switching back to it
+#[derive(Clone, Copy)]
+struct ToolSettings {
+    color: Color,
+    smoothing: f32,
 }
 
-pub struct Client {
-    client: TcpStream,
-    _addr: String,
-    _live: bool,
-    pubsub: VecDeque<Vec<u8>>,
+// offsets of every canvas layer and the cursor position
+type View = ((i32, i32), (u16, u16));
+
+// where to join from the connection screen while offline, typed keys go to
+// the focused field
+struct ConnectForm {
+    fields: [String; 3],
+    focus: usize,
+    // how the last join went, or is going
+    status: String,
 }
 
-impl Client {
-    // create and connect non blocking to the addr specified
-    pub fn new(addr: &String) -> Self {
-        let mut attempts = 0;
-        let max_attempts = 5;
-        let socket_client: TcpStream;
+const CONNECT_FIELDS: [&str; 3] = ["host", "port", "room"];
 
-        loop {
-            attempts += 1;
-            println!(
-                "Attempting to connect to {}... (Attempt {}/{})\n",
-                addr, attempts, max_attempts
-            );
-            thread::sleep(Duration::from_secs(1));
+impl ConnectForm {
+    fn addr(&self) -> String {
+        format!("{}:{}", self.fields[0], self.fields[1])
+    }
+}
 
-            match TcpStream::connect(addr) {
-                Ok(stream) => {
-                    socket_client = stream;
-                    break;
-                }
-                Err(e) => {
-                    println!("Failed to connect: {}. Attempt: {}\n", e, attempts);
-                    if attempts >= max_attempts {
-                        panic!("Failed to connect after {} attempts\n", max_attempts);
-                    } else {
-                        thread::sleep(Duration::from_secs(1));
-                    }
+// what the clone tool stamps, pixels and items relative to the top left
+// corner of the region sampled
+struct CloneSample {
+    size: (i32, i32),
+    pixels: Vec<((i32, i32), Color)>,
+    items: Vec<Item>,
+    // layer and layer cell the region was sampled at, none for sprites
+    at: Option<(usize, (i32, i32))>,
+}
+
+impl CloneSample {
+    // pixels and items are moved within the sample, pixels of the items are
+    // laid out again
+    fn transform(&mut self, transform: Transform) {
+        let size: (i32, i32) = ((self.size.0 + 1) / 2, self.size.1);
+        for (offset, _) in self.pixels.iter_mut() {
+            let (x, y) = transform.origin((offset.0.div_euclid(2), offset.1, 1, 1), size);
+            *offset = (2 * x, y);
+        }
+        for item in self.items.iter_mut() {
+            // items of text typed a character per cell may start on any column
+            let mut chars: Vec<Vec<TermChar>> = item.chars.clone();
+            if item.offset.0.rem_euclid(2) == 1 {
+                for row in chars.iter_mut() {
+                    row.insert(0, EMPTY_TERM_CHAR);
                 }
             }
+            let width: usize = chars.iter().map(|row| row.len()).max().unwrap_or(0);
+            let rect = (
+                item.offset.0.div_euclid(2),
+                item.offset.1,
+                width.div_ceil(2) as i32,
+                chars.len() as i32,
+            );
+            if Transform::paired(&chars) {
+                let (x, y) = transform.origin(rect, size);
+                item.offset = (2 * x, y);
+                item.chars = transform.chars(&chars);
+                continue;
+            }
+            // single width characters are turned a cell at a time, flipped on
+            // the cells of the sample, turned from where their pixels go
+            item.offset = match transform {
+                Transform::FlipHorizontal | Transform::FlipVertical => {
+                    let width: usize = item.chars.iter().map(|row| row.len()).max().unwrap_or(0);
+                    let rect = (item.offset.0, item.offset.1, width as i32, rect.3);
+                    transform.origin(rect, (2 * size.0, size.1))
+                }
+                _ => {
+                    let (x, y) = transform.origin(rect, size);
+                    (2 * x, y)
+                }
+            };
+            item.chars = transform.cells(&item.chars);
         }
-
-        socket_client
-            .set_nonblocking(true)
-            .expect("Failed to set non-blocking");
-
-        println!("Successfully connected to {}", addr);
-
-        Client {
-            client: socket_client,
-            _addr: addr.clone(),
-            _live: true,
-            pubsub: VecDeque::new(),
-        }
-    }
-
-    // plain bytes return from other clients in the shared session
-    fn read_server_update(&mut self) -> Option<Vec<u8>> {
-        let mut server_buff: Vec<u8> = vec![0; 1024];
-        match self.client.read(&mut server_buff) {
-            Ok(n) => {
-                server_buff.truncate(n);
-                Some(server_buff)
+        let (width, height) = transform.size(size);
+        self.size = (2 * width, height);
+        // turned single width art may reach past the turned region
+        for item in &self.items {
+            for (x, y) in item.get_filled_indexes((0, 0)) {
+                self.size = (self.size.0.max(x + 1), self.size.1.max(y + 1));
             }
-            Err(_) => None,
         }
     }
 
-    // write to server queued updates from current client
-    // failed sents are pushed back for next run
-    fn broadcast_client_updates(&mut self) {
-        let mut failed: VecDeque<Vec<u8>> = VecDeque::new();
-        while !self.pubsub.is_empty() {
-            let update = self.pubsub.pop_front();
-            if let Some(update) = update {
-                match self.client.write_all(&update) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Failed to send update to server: {}", e);
-                        failed.push_back(update);
-                        break;
-                    }
-                }
+    // the sample as a single block of characters, items over pixels
+    fn to_chars(&self) -> Vec<Vec<TermChar>> {
+        let (width, height) = self.size;
+        let mut chars: Vec<Vec<TermChar>> =
+            vec![vec![EMPTY_TERM_CHAR; width as usize]; height as usize];
+        for &((x, y), color) in &self.pixels {
+            for (dx, term_char) in Pixel::new(color).to_chars()[0].iter().enumerate() {
+                chars[y as usize][x as usize + dx] = *term_char;
             }
         }
-        while !failed.is_empty() {
-            self.pubsub.push_back(failed.pop_front().unwrap().to_vec());
+        for item in &self.items {
+            for (x, y) in item.get_filled_indexes((0, 0)) {
+                let (col, row) = ((x - item.offset.0) as usize, (y - item.offset.1) as usize);
+                chars[y as usize][x as usize] = item.chars[row][col];
+            }
         }
+        chars
+    }
+}
 
-        let remove_n: usize = MAX_FAILED_SENT_ON_QUEUE - failed.len();
-        for _ in 0..remove_n {
-            failed.pop_front();
+// offsets of an item the move tool drags, where it was and where it is now,
+// and the cell it was grabbed at
+struct MovingItem {
+    // as it was grabbed, at from
+    item: Item,
+    from: (i32, i32),
+    at: (i32, i32),
+    grab: (i32, i32),
+}
+
+impl MovingItem {
+    // the item as it is on the layer while dragged
+    fn dragged(&self) -> Item {
+        Item {
+            offset: self.at,
+            ..self.item.clone()
         }
     }
+}
 
-    // publish serialized update the client pubsub queue
-    // the update event is going to be serialized and pushed to the queue
-    // for later processing
-    fn publish(&mut self, update: Update) {
-        let serialized: Vec<u8> = match update {
-            Update::TermChar(tc) => to_string(&Update::TermChar(tc))
-                .expect("failed to deserialize term char")
-                .into_bytes(),
-            Update::Erase(erase) => to_string(&Update::Erase(erase))
-                .expect("failed to serialize erase")
-                .into_bytes(),
-            Update::Sync(s) => to_string(&Update::Sync(s))
-                .expect("failed to serialize sync")
-                .into_bytes(),
-        };
-        self.pubsub.push_back(serialized);
-    }
+// how the text tool sets what is typed, banners in one of the embedded fonts
+#[derive(PartialEq, Clone, Copy)]
+enum TextMode {
+    Wide,
+    Narrow,
+    Banner(usize),
+}
+
+#[derive(PartialEq)]
+enum Config {
+    None,
+    ColorSelection,
+    SpritePicker,
+    Connection,
+    Help,
+}
+
+pub struct DrawTerm {
+    screen: Screen,
+    tool: Tool,
+    previous_tool: Tool,
+    tool_settings: HashMap<Tool, ToolSettings>,
+    config: Config,
+    cursor: Item,
+    cursor_info: Item,
+    tool_bar: Item,
+    // clickable column of tools on the left edge, see draw_toolbox
+    toolbox: bool,
+    resized: bool,
+    // text tool edit in progress, see on_text_key
+    text_edit: Option<TextEdit>,
+    text_mode: TextMode,
+    fonts: Vec<Font>,
+    // layer cell the box drawing line being dragged is at
+    box_from: Option<(i32, i32)>,
+    // the move tool drags single items instead of the whole layer
+    move_items: bool,
+    // item being dragged, see grab_item
+    moving_item: Option<MovingItem>,
+    // pixels between grid lines, and whether the brush and item moves snap
+    // to them, shown or not
+    grid_spacing: i32,
+    snap: bool,
+    // corner the clone tool region is dragged from, and what it sampled
+    clone_from: Option<(i32, i32)>,
+    clone_sample: Option<CloneSample>,
+    // built in and saved ones, picked to be stamped by the clone tool
+    sprites: Vec<Sprite>,
+    // index of the layer receiving tool events, the last layer is reserved for the ui
+    active_layer: usize,
+    color_selected: Color,
+    last_cursor_position: (u16, u16),
+    // exponential smoothing applied to left button drags, see DRAG_SMOOTHING_LEVELS
+    smoothing: f32,
+    smoothed_position: Option<(f32, f32)>,
+    // name this session draws under, writes to the shared layer carry it
+    user: String,
+    // pixels painted by the brush relative to the cursor, None for a single pixel
+    brush_footprint: Option<Vec<((i32, i32), Color)>>,
+    // character and background the brush paints instead of pixels, the
+    // selected color is its foreground
+    brush_glyph: Option<TermChar>,
+    // stamps of the installed asset packs and the one taken as brush
+    stamps: Vec<StampAsset>,
+    stamp_index: Option<usize>,
+    // active layer to go back to when leaving the scratch layer
+    layer_before_scratch: usize,
+    // presented when joining a shared session started with --password
+    password: Option<String>,
+    // join shared sessions over websocket, like browsers do
+    websocket: bool,
+    // served room canvas archived to the dir and wiped on schedule, and the
+    // unix time of the next reset
+    reset_schedule: Option<(ResetSchedule, String)>,
+    next_reset: u64,
+    // the writes made before joining are asked to the host, see replay_history
+    replay_on_join: bool,
+    pending_replay: Option<u64>,
+    // participant ids of the room heard in hellos, the lowest one, the host
+    // when there is one, sends joiners the canvas
+    room_peers: BTreeSet<u64>,
+    // archived pieces of the room listed on the connection screen, and the one
+    // being downloaded
+    archives: Vec<String>,
+    pending_download: Option<String>,
+    // workshop home regions on the shared layer, assigned by the host
+    regions: Regions,
+    // size in pixels of the frame guiding the drawing, from the config file
+    canvas: Option<(i32, i32)>,
+    // opened projects are saved this often when something changed
+    autosave: Option<Duration>,
+    keymap: Keymap,
+    // server started by this session, its peers are listed on the connection
+    // screen with the one selected for kicks and bans
+    host: Option<Host>,
+    host_selection: usize,
+    // who drew each item of the shared layer, keyed by item offset
+    authors: HashMap<(i32, i32), String>,
+    // shared layer tinted by author with a legend, editing is paused meanwhile
+    attribution: bool,
+    // parked viewport origin and cursor position, see park and jump_to_parked
+    parked: Option<View>,
+    // last shared layer offsets of the participants, the one whose view this
+    // session follows and the own offset the peers last heard of
+    viewports: BTreeMap<String, (i32, i32)>,
+    following: Option<String>,
+    published_viewport: Option<(i32, i32)>,
+    // last position where the hover preview was drawn
+    hover_position: Option<(u16, u16)>,
+    // file the project was opened from, saves go there
+    project_path: Option<String>,
+    project_metadata: Option<ProjectMetadata>,
+    // lock on the opened project, saves are refused when another instance has it
+    project_lock: Option<ProjectLock>,
+    read_only: bool,
+    // shell commands run on document events
+    hooks: Hooks,
+    // terminal cell width:height, image exports keep what is drawn undistorted
+    cell_aspect: (usize, usize),
+    // local edits for undo, in memory unless persisted next to the project
+    history: Box<dyn History>,
+    // shared session state shown next to the cursor info, None outside sessions
+    session_status: Option<SessionStatus>,
+    // why a peer of the session cannot be understood, shown instead of the status
+    incompatible_peer: Option<String>,
+    // the last thing that failed, e.g. a save, shown in red instead of the
+    // status until the next key press
+    error: Option<String>,
+    // time spent and strokes on the piece, carried over in the project metadata
+    time_spent: Duration,
+    strokes: u64,
+    last_input: Instant,
+    stats: bool,
+    stats_item: Item,
+    // corner overlay with the active tool and last pressed keys, for recordings
+    overlay: bool,
+    overlay_item: Item,
+    pressed_keys: VecDeque<String>,
+    // for terminals without mouse reporting, arrows and hjkl move the cursor
+    // and space stamps, see on_keyboard_drawing
+    keyboard: bool,
+    // a run of shifted moves is one stroke, ended by any other key
+    keyboard_stroke: bool,
+    // quitting with unsaved changes asks first, see quit, the row also asks
+    // before a frame is deleted
+    confirm_quit: bool,
+    quit_prompt: Item,
+    deleting_frame: bool,
+    // typed after `:` until enter runs it, see on_command_line_key
+    command_line: Option<String>,
+    command_item: Item,
+    // palettes offered by the color picker, imports add their own
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    // the terminal or a replayed recording of it
+    input: Input,
+    connect_form: ConnectForm,
+    // join started from the connection screen, see poll_join
+    joining: Option<Receiver<JoinProgress>>,
+    // where --record dumps the traffic of the session once joined
+    record: Option<String>,
+    #[cfg(feature = "gamepad")]
+    gamepad: Option<Gamepad>,
+}
+
+// what incoming updates left to repaint, cells are offsets of shared layer items
+#[derive(PartialEq)]
+pub enum Repaint {
+    Nothing,
+    Cells(Vec<(i32, i32)>),
+    Everything,
+}
+
+// every user gets a stable color of the 6x6x6 cube derived from their name
+fn user_color(name: &str) -> Color {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    Color::AnsiValue(16 + (hasher.finish() % 216) as u8)
 }
 
 impl DrawTerm {
     pub fn new() -> Self {
-        let (width, height): (u16, u16) = terminal::size().unwrap();
+        let (width, height): (u16, u16) = terminal::size().unwrap_or((80, 25));
         let foreground: Layer = Layer::new_empty("foreground".to_string(), width, height, (0, 0));
         let background: Layer = Layer::new_empty("background".to_string(), width, height, (0, 0));
-        let screen: Screen = Screen::new(vec![background, foreground]);
+        let mut screen: Screen = Screen::new(vec![background, foreground]);
+        screen.mark_saved(1);
         let tool: Tool = Tool::Brush;
+        let previous_tool: Tool = Tool::Erase;
         let config: Config = Config::None;
+        let tool_settings: HashMap<Tool, ToolSettings> = HashMap::new();
 
         let cursor: Item = Item {
             name: "cursor".to_string(),
@@ -224,134 +559,3531 @@ impl DrawTerm {
             offset: (width as i32 - 9, height as i32 - 1),
             chars: vec![vec![EMPTY_TERM_CHAR]],
         };
+        let tool_bar: Item = Item {
+            name: "tool_bar".to_string(),
+            offset: (width as i32 - 2 - 2 * TOOL_BAR.len() as i32, 0),
+            chars: vec![vec![EMPTY_TERM_CHAR]],
+        };
         let color_selected: Color = Color::AnsiValue(0);
         let last_cursor_position: (u16, u16) = (0, 0);
         let resized: bool = false;
-        let typing: bool = false;
+        let parked: Option<View> = None;
+        let user: String = std::env::var("USER").unwrap_or_else(|_| "anonymous".to_string());
+        let authors: HashMap<(i32, i32), String> = HashMap::new();
+        let attribution: bool = false;
+        let active_layer: usize = 0;
+        let smoothing: f32 = DRAG_SMOOTHING_LEVELS[0];
+        let smoothed_position: Option<(f32, f32)> = None;
+        let hover_position: Option<(u16, u16)> = None;
+        let project_path: Option<String> = None;
+        let project_metadata: Option<ProjectMetadata> = None;
+        let history: Box<dyn History> = Box::new(MemoryHistory::default());
+        let project_lock: Option<ProjectLock> = None;
+        let read_only: bool = false;
+        let cell_aspect: (usize, usize) = DEFAULT_CELL_ASPECT;
+        let hooks: Hooks = Hooks::default();
+        let session_status: Option<SessionStatus> = None;
+        let stats_item: Item = Item {
+            name: "stats".to_string(),
+            offset: (0, 0),
+            chars: vec![vec![]],
+        };
+        let overlay: bool = false;
+        let overlay_item: Item = Item {
+            name: "overlay".to_string(),
+            offset: (0, 0),
+            chars: vec![vec![]],
+        };
+        let pressed_keys: VecDeque<String> = VecDeque::new();
+        let palettes: Vec<Palette> = vec![Palette::system()];
+        let palette_index: usize = 0;
         DrawTerm {
             screen,
             tool,
+            previous_tool,
+            tool_settings,
             config,
             cursor,
             cursor_info,
+            tool_bar,
+            toolbox: true,
             resized,
-            typing,
+            user,
+            brush_footprint: None,
+            brush_glyph: None,
+            stamps: Vec::new(),
+            stamp_index: None,
+            layer_before_scratch: 0,
+            password: None,
+            websocket: false,
+            reset_schedule: None,
+            next_reset: 0,
+            archives: Vec::new(),
+            pending_download: None,
+            regions: Regions::default(),
+            authors,
+            attribution,
+            parked,
+            viewports: BTreeMap::new(),
+            following: None,
+            published_viewport: None,
+            text_edit: None,
+            text_mode: TextMode::Wide,
+            fonts: figlet::embedded(),
+            box_from: None,
+            move_items: false,
+            moving_item: None,
+            grid_spacing: DEFAULT_GRID_SPACING,
+            snap: false,
+            clone_from: None,
+            clone_sample: None,
+            sprites: sprites::builtin(),
+            active_layer,
             color_selected,
             last_cursor_position,
+            smoothing,
+            smoothed_position,
+            hover_position,
+            project_path,
+            project_metadata,
+            project_lock,
+            read_only,
+            hooks,
+            cell_aspect,
+            history,
+            session_status,
+            incompatible_peer: None,
+            error: None,
+            host: None,
+            host_selection: 0,
+            canvas: None,
+            autosave: None,
+            keymap: Keymap::default(),
+            replay_on_join: false,
+            pending_replay: None,
+            room_peers: BTreeSet::new(),
+            overlay,
+            overlay_item,
+            keyboard: false,
+            keyboard_stroke: false,
+            confirm_quit: true,
+            deleting_frame: false,
+            quit_prompt: Item {
+                name: "quit_prompt".to_string(),
+                offset: (0, 0),
+                chars: vec![vec![]],
+            },
+            command_line: None,
+            command_item: Item {
+                name: "command_line".to_string(),
+                offset: (0, 0),
+                chars: vec![vec![]],
+            },
+            time_spent: Duration::ZERO,
+            strokes: 0,
+            last_input: Instant::now(),
+            stats: false,
+            stats_item,
+            pressed_keys,
+            palettes,
+            palette_index,
+            input: Input::new(),
+            connect_form: ConnectForm {
+                fields: ["127.0.0.1".to_string(), String::new(), String::new()],
+                focus: 0,
+                status: String::new(),
+            },
+            joining: None,
+            record: None,
+            #[cfg(feature = "gamepad")]
+            gamepad: Gamepad::new(),
         }
     }
 
-    pub fn run(&mut self, addr: Option<String>) {
-        self._enter();
-        let mut exit = false;
+    pub fn set_password(&mut self, password: &str) {
+        self.password = Some(password.to_string());
+    }
 
-        let mut client: Option<Client> = None;
-        if let Some(addr) = addr {
-            client = Some(Client::new(&addr));
+    pub fn set_render_mode(&mut self, render: RenderMode) {
+        self.screen.render = render;
+    }
+
+    pub fn use_websocket(&mut self) {
+        self.websocket = true;
+    }
+
+    pub fn replay_history_on_join(&mut self) {
+        self.replay_on_join = true;
+    }
+
+    // writes made before joining, in order, so attribution ends up as the
+    // earlier participants have it and own writes made before a reconnection
+    // can be undone again. Cells the snapshot already brought are left as they
+    // are
+    fn replay_history(&mut self, writes: Vec<SerializableWrite>, client: &mut Option<Client>) {
+        // own writes carry the participant id of this session in their stamps
+        let participant: Option<u64> = client.as_ref().and_then(Client::participant);
+        let own = |stamp: Stamp| !stamp.is_unset() && Some(stamp.replica) == participant;
+        for write in writes {
+            match write {
+                SerializableWrite::Pixel(tc) => {
+                    let (cell, own) = ((tc.abs_x, tc.abs_y), own(tc.stamp));
+                    if !self.regions.may_draw(&tc.author, cell) {
+                        continue;
+                    }
+                    if accepted(client, cell, tc.stamp) {
+                        let operation: LocalOperation = self.add_network_pixel(tc);
+                        if own {
+                            self.history.push(operation);
+                        }
+                    }
+                }
+                SerializableWrite::Text(text) => {
+                    let (cell, own) = ((text.abs_x, text.abs_y), own(text.stamp));
+                    if !self.regions.may_draw(&text.author, cell) {
+                        continue;
+                    }
+                    let item: Item = text_item(&text);
+                    if accepted(client, cell, text.stamp) {
+                        self.add_network_text(text);
+                        if own {
+                            self.history.push(LocalOperation::Add { layer: 0, item });
+                        }
+                    }
+                }
+                SerializableWrite::Erase(erase) => {
+                    let cell: (i32, i32) = (erase.abs_x, erase.abs_y);
+                    if !self.regions.may_draw(&erase.author, cell) {
+                        continue;
+                    }
+                    if accepted(client, cell, erase.stamp) {
+                        let at =
+                            self.screen.layers[0].absolute_position((erase.abs_x, erase.abs_y));
+                        self.erase_network_cell(at);
+                    }
+                }
+            }
+        }
+    }
+
+    // only the session archiving the room answers, requests for pieces it
+    // does not have are ignored
+    fn answer_archive_request(
+        &mut self,
+        request: SerializableArchiveRequest,
+        client: &mut Option<Client>,
+    ) {
+        let (Some((_, archive_dir)), Some(client)) = (&self.reset_schedule, client.as_mut()) else {
+            return;
+        };
+        let Some(piece) = request.piece else {
+            let pieces: Vec<String> = archived_pieces(archive_dir, &client.room);
+            client.publish(Update::ArchiveList(SerializableArchiveList { pieces }));
+            return;
+        };
+        if let Ok(files) = read_piece(archive_dir, &client.room, &piece) {
+            for (name, data) in files {
+                client.publish(Update::ArchiveFile(SerializableArchiveFile { name, data }));
+            }
+        }
+    }
+
+    // the connection screen lists the archived pieces below the server address
+    fn draw_archives(&mut self) {
+        let mut lines: Vec<String> = match self.archives.is_empty() {
+            true => vec!["no archived pieces yet".to_string()],
+            false => vec!["archived pieces, press a number to download".to_string()],
+        };
+        for (i, piece) in self.archives.iter().take(9).enumerate() {
+            lines.push(format!("{} {}", i + 1, piece));
+        }
+        for (row, line) in lines.iter().enumerate() {
+            self.screen.term.move_to(0, row as u16 + 2);
+            self.screen
+                .term
+                .queue(terminal::Clear(terminal::ClearType::CurrentLine));
+            self.screen.term.print(line);
+        }
+    }
+
+    fn download_archive(&mut self, index: usize, client: &mut Option<Client>) {
+        let (Some(piece), Some(client)) = (self.archives.get(index), client.as_mut()) else {
+            return;
+        };
+        self.pending_download = Some(piece.clone());
+        client.publish(Update::ArchiveRequest(SerializableArchiveRequest {
+            piece: Some(piece.clone()),
+        }));
+    }
+
+    // files of the piece being downloaded land in DEFAULT_ARCHIVE_DIR, files
+    // requested by other peers are ignored
+    fn receive_archive_file(&mut self, file: SerializableArchiveFile) {
+        let Some(piece) = &self.pending_download else {
+            return;
+        };
+        if Path::new(&file.name)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            != Some(piece)
+        {
+            return;
+        }
+        let message: String = match save_download(DEFAULT_ARCHIVE_DIR, &file.name, &file.data) {
+            Ok(path) => format!("downloaded {}", path),
+            Err(e) => format!("Failed to download {}: {}", file.name, e),
+        };
+        if self.config == Config::Connection {
+            let row: u16 = self.archives.len().min(9) as u16 + 4;
+            self.screen.term.move_to(0, row);
+            self.screen
+                .term
+                .queue(terminal::Clear(terminal::ClearType::CurrentLine));
+            self.screen.term.print(&message);
+        }
+    }
+
+    pub fn host_session(&mut self, host: Host) {
+        self.host = Some(host);
+    }
+
+    // hosts see the connected peers below the archived pieces and the download
+    // status, as many as the terminal fits around the selected one, own is the
+    // participant id of this session, which is never turned away
+    fn draw_host_controls(&mut self, own: Option<u64>, message: &str) {
+        let Some(host) = &self.host else {
+            return;
+        };
+        let peers: Vec<PeerInfo> = host.peers();
+        self.host_selection = self.host_selection.min(peers.len().saturating_sub(1));
+        let lock: &str = match self.regions.locked {
+            true => "unlock",
+            false => "lock",
+        };
+        let mut lines: Vec<String> = vec![format!(
+            "connected peers, up/down to select, k kick, b ban, l {} the canvas",
+            lock
+        )];
+        let top: u16 = self.archives.len().min(9) as u16 + 6;
+        let height: usize = self.screen.term.size().map_or(25, |(_, height)| height as usize);
+        // the title and message rows are always shown
+        let rows: usize = height.saturating_sub(top as usize + 2).max(1);
+        let first: usize = (self.host_selection + 1).saturating_sub(rows);
+        for (i, peer) in peers.iter().enumerate().skip(first).take(rows) {
+            let selected: char = if i == self.host_selection { '>' } else { ' ' };
+            let you: &str = if Some(peer.id) == own { " (you)" } else { "" };
+            lines.push(format!(
+                "{} {} {} {}{}",
+                selected, peer.id, peer.addr, peer.room, you
+            ));
+        }
+        lines.push(message.to_string());
+        self.screen.term.move_to(0, top);
+        self.screen
+            .term
+            .queue(terminal::Clear(terminal::ClearType::FromCursorDown));
+        for (row, line) in lines.iter().enumerate() {
+            self.screen.term.move_to(0, top + row as u16);
+            self.screen.term.print(line);
+        }
+    }
+
+    fn select_peer(&mut self, down: bool, client: &Option<Client>) {
+        self.host_selection = match down {
+            true => self.host_selection + 1,
+            false => self.host_selection.saturating_sub(1),
+        };
+        self.draw_host_controls(client.as_ref().and_then(Client::participant), "");
+    }
+
+    // kicked peers may come back after KICK_COOLDOWN, banned ones never, both
+    // go by participant id so peers sharing an address are told apart, new
+    // ids from the address are turned away along with it
+    fn turn_away_selected(&mut self, ban: bool, client: &Option<Client>) {
+        let Some(host) = self.host.clone() else {
+            return;
+        };
+        let own: Option<u64> = client.as_ref().and_then(Client::participant);
+        let Some(peer) = host.peers().into_iter().nth(self.host_selection) else {
+            return;
+        };
+        let message: String = if own == Some(peer.id) {
+            "that is this session".to_string()
+        } else {
+            let (action, result) = match ban {
+                true => ("banned", host.ban(peer.id)),
+                false => ("kicked", host.kick(peer.id)),
+            };
+            match result {
+                Ok(addr) => format!("{} {}", action, addr),
+                Err(e) => format!("Failed to turn away {}: {}", peer.addr, e),
+            }
+        };
+        self.draw_host_controls(own, &message);
+    }
+
+    // while locked only the host draws on the shared layer, the relay drops
+    // what the others draw and peers get the lock along with the regions
+    fn toggle_canvas_lock(&mut self, client: &mut Option<Client>) {
+        let (Some(host), Some(client)) = (&self.host, client.as_mut()) else {
+            return;
+        };
+        let Some(own) = client.participant() else {
+            return;
+        };
+        self.regions.locked = !self.regions.locked;
+        match self.regions.locked {
+            true => host.lock(&client.room, own),
+            false => host.unlock(&client.room),
+        }
+        client.publish(Update::Regions(self.regions.clone()));
+        let message: &str = match self.regions.locked {
+            true => "canvas locked, only you can draw",
+            false => "canvas unlocked",
+        };
+        self.draw_host_controls(Some(own), message);
+    }
+
+    pub fn assign_region(&mut self, region: Region) {
+        self.regions.regions.push(region);
+    }
+
+    // the canvas frame and region borders follow the shared layer as guides
+    fn refresh_guides(&mut self) {
+        self.screen.guides = self
+            .canvas
+            .map(|(width, height)| frame("canvas", (0, 0, width, height), Color::DarkGrey))
+            .into_iter()
+            .chain(
+                self.regions
+                    .regions
+                    .iter()
+                    .map(|region| region.border(user_color(&region.owner))),
+            )
+            .collect();
+        self.screen.invalidate();
+    }
+
+    // the frame goes one cell around the canvas, the view is nudged so its
+    // top and left sides are on screen
+    pub fn set_canvas(&mut self, size: (i32, i32)) {
+        self.canvas = Some(size);
+        self.screen.pan((-2, -1));
+        self.refresh_guides();
+    }
+
+    pub fn set_start_tool(&mut self, name: &str) -> Result<(), String> {
+        let tool: Tool = Tool::parse(name).ok_or_else(|| format!("unknown tool {}", name))?;
+        self.set_tool(tool);
+        Ok(())
+    }
+
+    // every tool starts with it, they remember their own from then on
+    pub fn set_start_color(&mut self, code: u8) {
+        self.color_selected = Color::AnsiValue(code);
+        self.tool_settings.clear();
+    }
+
+    pub fn set_autosave(&mut self, interval: Duration) {
+        self.autosave = Some(interval);
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    // every action with the key it is bound to right now, any key goes back
+    fn toggle_help(&mut self) {
+        if self.config == Config::Help {
+            self.config = Config::None;
+            self.clear_screen();
+            self.screen.redraw();
+            self.draw_stats();
+            self.draw_overlay();
+            self.draw_cursor_info(self.last_cursor_position);
+            return;
         }
+        if self.config == Config::ColorSelection {
+            self.erase_ansi_colors();
+        }
+        if self.config == Config::SpritePicker {
+            self.erase_sprite_picker();
+        }
+        self.config = Config::Help;
         self.clear_screen();
+        let tools: Vec<String> = TOOL_BAR
+            .iter()
+            .enumerate()
+            .map(|(slot, tool)| {
+                let key: String = self
+                    .keymap
+                    .key_label(Action::ToolSlot(slot))
+                    .unwrap_or_default();
+                format!("{} {}", key, tool.name())
+            })
+            .collect();
+        let mut lines: Vec<String> = vec![
+            "key bindings, press any key to go back".to_string(),
+            format!("tools  {}", tools.join("  ")),
+            String::new(),
+        ];
+        let mut entries: Vec<(String, &str, &str)> = self.keymap.help();
+        entries.push(("1-9".to_string(), "download an archive (connection)", ""));
+        entries.push(("arrows".to_string(), "move, shifted to draw (keyboard)", ""));
+        let (width, height) = (self.screen.width as usize, self.screen.height as usize);
+        lines.extend(help_columns(
+            &entries,
+            width,
+            height.saturating_sub(lines.len()),
+        ));
+        for (row, line) in lines.iter().enumerate().take(height) {
+            self.screen.term.move_to(0, row as u16);
+            self.screen.term.print(line);
+        }
+    }
 
-        let mut updates: VecDeque<Vec<u8>> = VecDeque::new();
-        while !exit {
-            // network session client handler
-            if let Some(client) = &mut client {
-                let server_update = client.read_server_update();
-                if let Some(server_update) = server_update {
-                    updates.push_back(server_update);
+    // what a bound key does, true quits
+    fn perform(&mut self, action: Action, client: &mut Option<Client>) -> bool {
+        match action {
+            Action::Quit => return self.quit(),
+            Action::Help => self.toggle_help(),
+            Action::CommandLine => {
+                self.command_line = Some(String::new());
+                self.draw_command_line(&[]);
+            }
+            Action::Save => {
+                if let Err(e) = self.save_project().context("saving the project") {
+                    self.report(e);
                 }
-                client.broadcast_client_updates();
             }
-
-            let must_update: bool = !updates.is_empty();
-            self.on_netowrk_update_events(&mut updates, &mut client);
-            if must_update {
-                self.screen.layers[0].draw_buffer(
-                    &mut self.screen.term,
-                    self.screen.width,
-                    self.screen.height,
-                );
+            Action::ExportAnsi => {
+                let path: String = self.export_path("ans");
+                match export_ansi(self.document_layers(), &path).context(&path) {
+                    Ok(_) => self.exported(&path, "ans"),
+                    Err(e) => self.report(e),
+                }
+            }
+            Action::ExportText => {
+                let path: String = self.export_path("txt");
+                match export_text(self.document_layers(), &path).context(&path) {
+                    Ok(_) => self.exported(&path, "txt"),
+                    Err(e) => self.report(e),
+                }
+            }
+            Action::ExportHtml => {
+                let path: String = self.export_path("html");
+                match export_html(self.document_layers(), &path).context(&path) {
+                    Ok(_) => self.exported(&path, "html"),
+                    Err(e) => self.report(e),
+                }
+            }
+            Action::ExportPng => {
+                let path: String = self.export_path("png");
+                let layers: &[Layer] = self.document_layers();
+                match export_png(layers, &path, DEFAULT_PNG_SCALE, self.cell_aspect).context(&path)
+                {
+                    Ok(_) => self.exported(&path, "png"),
+                    Err(e) => self.report(e),
+                }
+            }
+            Action::Undo => self.undo(client),
+            Action::SwapTool => self.swap_tool(),
+            Action::ToolSlot(slot) => self.set_tool(TOOL_BAR[slot]),
+            Action::Brush => self.set_tool(Tool::Brush),
+            Action::Erase => self.set_tool(Tool::Erase),
+            Action::Ink => self.set_tool(Tool::Ink),
+            Action::Shade => self.set_tool(Tool::Shade),
+            // once more switches between moving the layer and single items
+            Action::Move => {
+                if self.tool == Tool::Move {
+                    self.move_items = !self.move_items;
+                }
+                self.set_tool(Tool::Move);
+                self.draw_cursor_info(self.last_cursor_position);
+            }
+            Action::Text => self.set_tool(Tool::Text),
+            Action::BoxDraw => self.set_tool(Tool::Box),
+            // once more drops the sample for taking another
+            Action::Clone => {
+                if self.tool == Tool::Clone {
+                    self.clone_sample = None;
+                }
+                self.set_tool(Tool::Clone);
+                self.draw_cursor_info(self.last_cursor_position);
+            }
+            Action::TextMode => {
+                self.cycle_text_mode();
+                self.set_tool(Tool::Text);
+                self.draw_cursor_info(self.last_cursor_position);
+            }
+            Action::Grid => self.toggle_grid(),
+            Action::Snap => {
+                self.snap = !self.snap;
+                self.draw_cursor_info(self.last_cursor_position);
+            }
+            Action::NewFrame => self.new_frame(client),
+            Action::PreviousFrame => {
+                if let Some(index) = self.screen.frame.checked_sub(1) {
+                    self.show_frame(index, client);
+                }
+            }
+            Action::NextFrame => self.show_frame(self.screen.frame + 1, client),
+            Action::FrameEarlier => {
+                if let Some(index) = self.screen.frame.checked_sub(1) {
+                    self.move_frame(index, client);
+                }
+            }
+            Action::FrameLater => self.move_frame(self.screen.frame + 1, client),
+            Action::OnionSkin => {
+                self.screen.onion = !self.screen.onion;
+                self.screen.refresh();
+                self.draw_tool_indicators();
             }
+            Action::CaptureBrush => self.capture_brush(),
+            Action::Toolbox => {
+                self.toolbox = !self.toolbox;
+                self.draw_toolbox();
+            }
+            Action::Sprites => match self.config {
+                Config::SpritePicker => self.erase_sprite_picker(),
+                Config::None => self.draw_sprite_picker(),
+                _ => {}
+            },
+            Action::Colors => match self.config {
+                Config::ColorSelection => self.erase_ansi_colors(),
+                Config::Connection | Config::Help | Config::SpritePicker => {}
+                Config::None => {
+                    if self.tool == Tool::Erase {
+                        self.set_tool(Tool::Brush)
+                    };
+                    self.draw_ansi_colors();
+                }
+            },
+            Action::CyclePalette => self.cycle_palette(),
+            Action::DuplicateLayer => self.duplicate_active_layer(),
+            Action::MergeDown => self.merge_down_active_layer(client),
+            Action::PreviousLayer => self.select_layer(self.active_layer.saturating_sub(1)),
+            Action::NextLayer => self.select_layer(self.active_layer + 1),
+            Action::LockLayer => self.screen.layers[self.active_layer].toggle_lock(),
+            Action::ScratchLayer => self.toggle_scratch_layer(),
+            Action::Smoothing => self.cycle_smoothing(),
+            Action::Overlay => self.toggle_overlay(),
+            Action::Fit => self.toggle_fit(),
+            Action::Attribution => self.toggle_attribution(),
+            Action::Stats => self.toggle_stats(),
+            Action::RotateClockwise => self.rotate_view(1),
+            Action::RotateCounterClockwise => self.rotate_view(3),
+            Action::Park => self.park(),
+            Action::JumpToParked => self.jump_to_parked(),
+            Action::PanLeft => self.pan((-2 * PAN_STEP, 0)),
+            Action::PanRight => self.pan((2 * PAN_STEP, 0)),
+            Action::PanUp => self.pan((0, -PAN_STEP)),
+            Action::PanDown => self.pan((0, PAN_STEP)),
+            Action::ZoomIn => self.zoom(1),
+            Action::ZoomOut => self.zoom(-1),
+            Action::Follow => self.follow_next(),
+            Action::CycleStamp => self.cycle_stamp(),
+            Action::KeyboardDrawing => self.toggle_keyboard(),
+            Action::Connection => self.toggle_connection_screen(client),
+            Action::SelectPrevious => self.select_peer(false, client),
+            Action::SelectNext => self.select_peer(true, client),
+            Action::Kick => self.turn_away_selected(false, client),
+            Action::Ban => self.turn_away_selected(true, client),
+            Action::LockCanvas => self.toggle_canvas_lock(client),
+            // handled by on_keyboard_drawing
+            Action::CursorLeft
+            | Action::CursorRight
+            | Action::CursorUp
+            | Action::CursorDown
+            | Action::DrawLeft
+            | Action::DrawRight
+            | Action::DrawUp
+            | Action::DrawDown
+            | Action::Stamp
+            | Action::LeaveKeyboard => {}
+        }
+        false
+    }
 
-            // local client event handler
-            if event::poll(Duration::ZERO).unwrap() {
-                match event::read().unwrap() {
-                    event::Event::Key(event) => exit = self.on_key_event(event, &client),
-                    event::Event::Mouse(event) => exit = self.on_mouse_event(event, &mut client),
-                    event::Event::Resize(width, height) => {
-                        exit = self.on_resize_event(width, height)
+    fn toggle_connection_screen(&mut self, client: &mut Option<Client>) {
+        match self.config {
+            Config::Connection => {
+                self.config = Config::None;
+                self.clear_screen();
+                self.screen.term.queue(event::EnableMouseCapture);
+                self.screen.redraw();
+            }
+            _ => {
+                self.config = Config::Connection;
+                self.clear_screen();
+                self.screen.term.move_to(0, 0);
+                match client {
+                    Some(client) => {
+                        self.screen.term.print(&client._addr);
+                        self.archives.clear();
+                        self.pending_download = None;
+                        self.draw_archives();
+                        self.draw_host_controls(client.participant(), "");
+                        client.publish(Update::ArchiveRequest(SerializableArchiveRequest {
+                            piece: None,
+                        }));
+                    }
+                    None => self.draw_connect_form(),
+                }
+            }
+        }
+    }
+
+    // a session was just joined, from the command line or the connection screen
+    fn joined(&mut self, client: &mut Client) {
+        let context = [("ADDR", client._addr.as_str()), ("ROOM", &client.room)];
+        self.hooks.run(HookEvent::SessionJoined, &context);
+        self.room_peers.clear();
+        // the session that started the server hosts it
+        if self.host.is_some() {
+            self.regions.host = self.user.clone();
+            self.refresh_guides();
+        }
+        if let Some(path) = self.record.clone() {
+            // the session goes on unrecorded
+            if let Err(e) = client.record_to(&path).context(&path) {
+                self.report(e);
+            }
+        }
+        if self.replay_on_join {
+            let nonce: u64 = rand::random();
+            self.pending_replay = Some(nonce);
+            client.publish(Update::HistoryRequest(SerializableHistoryRequest { nonce }));
+        }
+    }
+
+    fn draw_connect_form(&mut self) {
+        let mut lines: Vec<String> = vec![
+            "not connected, tab moves between fields, enter joins, esc goes back".to_string(),
+            String::new(),
+        ];
+        for (i, (label, value)) in CONNECT_FIELDS
+            .iter()
+            .zip(&self.connect_form.fields)
+            .enumerate()
+        {
+            let focus: &str = match i == self.connect_form.focus {
+                true => ">",
+                false => " ",
+            };
+            lines.push(format!("{} {:<5} {}", focus, label, value));
+        }
+        lines.push(String::new());
+        lines.push(self.connect_form.status.clone());
+        for (row, line) in lines.iter().enumerate() {
+            self.screen.term.move_to(0, row as u16);
+            self.screen
+                .term
+                .queue(terminal::Clear(terminal::ClearType::CurrentLine));
+            self.screen.term.print(line);
+        }
+    }
+
+    fn on_connect_form_key(&mut self, event: &KeyEvent, client: &mut Option<Client>) {
+        let form: &mut ConnectForm = &mut self.connect_form;
+        match event.code {
+            KeyCode::Esc => return self.toggle_connection_screen(client),
+            KeyCode::Tab | KeyCode::Down => form.focus = (form.focus + 1) % CONNECT_FIELDS.len(),
+            KeyCode::BackTab | KeyCode::Up => {
+                form.focus = (form.focus + CONNECT_FIELDS.len() - 1) % CONNECT_FIELDS.len()
+            }
+            KeyCode::Backspace => {
+                form.fields[form.focus].pop();
+            }
+            KeyCode::Enter if self.joining.is_none() => self.start_join(),
+            KeyCode::Char(c) if !event.modifiers.contains(KeyModifiers::CONTROL) => {
+                form.fields[form.focus].push(c)
+            }
+            _ => {}
+        }
+        self.draw_connect_form();
+    }
+
+    fn start_join(&mut self) {
+        let form: &mut ConnectForm = &mut self.connect_form;
+        if form.fields[1].parse::<u16>().is_err() {
+            form.status = format!("invalid port {}", form.fields[1]);
+            return;
+        }
+        let addr: String = form.addr();
+        form.status = format!("connecting to {}", addr);
+        let password: &str = self.password.as_deref().unwrap_or_default();
+        let room: &str = &self.connect_form.fields[2];
+        self.joining = Some(Client::join(&addr, room, password, self.websocket));
+    }
+
+    // joins are followed without blocking, on the connection screen below
+    // the form and elsewhere in the cursor info, once through the connection
+    // screen switches to the session
+    fn poll_join(&mut self, client: &mut Option<Client>) {
+        loop {
+            let progress: JoinProgress = match self.joining.as_ref().map(|j| j.try_recv()) {
+                Some(Ok(progress)) => progress,
+                Some(Err(TryRecvError::Disconnected)) => {
+                    self.joining = None;
+                    return;
+                }
+                Some(Err(TryRecvError::Empty)) | None => return,
+            };
+            let addr: String = self.connect_form.addr();
+            match progress {
+                JoinProgress::Attempt { attempt, of } => {
+                    self.connect_form.status =
+                        format!("connecting to {}, attempt {}/{}", addr, attempt, of);
+                    self.set_session_status(SessionStatus::Connecting { attempt, of });
+                }
+                JoinProgress::Failed(e) => {
+                    self.joining = None;
+                    self.session_status = None;
+                    self.connect_form.status = format!("joining {}: {}", addr, e);
+                    // joins started with :connect have no form to show it
+                    if self.config != Config::Connection {
+                        self.error = Some(self.connect_form.status.clone());
+                        self.draw_cursor_info(self.last_cursor_position);
+                    }
+                }
+                JoinProgress::Joined(mut new_client) => {
+                    self.joining = None;
+                    self.connect_form.status.clear();
+                    self.set_session_status(SessionStatus::Connected);
+                    self.joined(&mut new_client);
+                    *client = Some(new_client);
+                    if self.config == Config::Connection {
+                        self.config = Config::None;
+                        self.toggle_connection_screen(client);
+                    }
+                    return;
+                }
+            }
+            if self.config == Config::Connection {
+                self.draw_connect_form();
+            }
+        }
+    }
+
+    // cell relative to layer, only the shared layer has regions
+    fn may_draw(&self, layer: usize, cell: (i32, i32)) -> bool {
+        layer != 0 || self.regions.may_draw(&self.user, cell)
+    }
+
+    pub fn schedule_resets(&mut self, schedule: ResetSchedule, archive_dir: &str) {
+        self.next_reset = schedule.next_after(now());
+        self.reset_schedule = Some((schedule, archive_dir.to_string()));
+    }
+
+    // once the scheduled time passed the shared layer is archived and wiped for
+    // every peer of the room, a failed archive leaves the canvas untouched
+    fn reset_if_due(&mut self, room: &str, client: &mut Option<Client>) {
+        let Some((schedule, archive_dir)) = self.reset_schedule.clone() else {
+            return;
+        };
+        let now: u64 = now();
+        if now < self.next_reset {
+            return;
+        }
+        // named after the last day of the period being closed
+        let closed: u64 = self.next_reset - 1;
+        self.next_reset = schedule.next_after(now);
+        let layers: &[Layer] = &self.screen.layers[..1];
+        let archived = archive_canvas(layers, &archive_dir, room, closed, self.cell_aspect)
+            .context("archiving the canvas");
+        match archived {
+            Ok(paths) => {
+                for path in paths.iter() {
+                    let format: &str = Path::new(path)
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .unwrap_or_default();
+                    self.exported(path, format);
+                }
+            }
+            Err(e) => {
+                self.report(e);
+                return;
+            }
+        }
+        if self.screen.layers[0].is_empty() {
+            return;
+        }
+        let items: Vec<Item> = self.screen.layers[0].items.clone();
+        self.screen.layers[0].retain_items(|_| false);
+        let pixels: Vec<((i32, i32), Color)> = self.screen.layers[0].clear_pixels();
+        if let Some(client) = client.as_mut() {
+            let offsets = pixels.iter().map(|(offset, _)| *offset);
+            for offset in offsets.chain(items.iter().map(|item| item.offset)) {
+                client.publish(Update::Erase(SerializableErase {
+                    abs_x: offset.0,
+                    abs_y: offset.1,
+                    stamp: Stamp::default(),
+                    author: self.user.clone(),
+                }));
+            }
+        }
+        // the wipe is the room's, not an edit of this session to undo
+        self.authors.clear();
+        self.screen.refresh();
+    }
+
+    pub fn set_user(&mut self, user: &str) {
+        self.user = user.to_string();
+    }
+
+    // updates from older peers carry no author, their items stay unattributed
+    fn attribute(&mut self, offset: (i32, i32), author: String) {
+        match author.is_empty() {
+            true => self.authors.remove(&offset),
+            false => self.authors.insert(offset, author),
+        };
+    }
+
+    // tints and legend are rebuilt from the pixels and items of the shared
+    // layer, so authors of erased ones drop out on their own
+    fn refresh_attribution(&mut self) {
+        let ui = self.ui_layer();
+        self.screen.layers[ui].retain_items(|item| item.name != "attribution_legend");
+        if !self.attribution {
+            self.screen.tint = None;
+            return;
+        }
+        let mut tint: HashMap<(i32, i32), Color> = HashMap::new();
+        let mut cells: BTreeMap<&str, usize> = BTreeMap::new();
+        let layer: &Layer = &self.screen.layers[0];
+        let offsets = layer.pixels.iter().map(|(offset, _)| offset);
+        for offset in offsets.chain(layer.items.iter().map(|item| item.offset)) {
+            let author: &str = self.authors.get(&offset).map_or("unknown", |a| a);
+            let color: Color = match self.authors.get(&offset) {
+                Some(author) => user_color(author),
+                None => Color::DarkGrey,
+            };
+            tint.insert(offset, color);
+            *cells.entry(author).or_default() += 1;
+        }
+        let legend: Vec<Item> = cells
+            .iter()
+            .enumerate()
+            .map(|(row, (author, count))| {
+                let color: Color = match *author {
+                    "unknown" => Color::DarkGrey,
+                    author => user_color(author),
+                };
+                let line: String = format!("{} {}", author, count);
+                let mut chars: Vec<TermChar> = vec![
+                    TermChar {
+                        character: ' ',
+                        foreground_color: color,
+                        background_color: color,
+                        empty: false,
+                    };
+                    2
+                ];
+                chars.extend(format!(" {}", line).chars().map(|c| TermChar {
+                    character: c,
+                    foreground_color: Color::Reset,
+                    background_color: Color::Reset,
+                    empty: false,
+                }));
+                Item {
+                    name: "attribution_legend".to_string(),
+                    offset: (0, 1 + row as i32),
+                    chars: vec![chars],
+                }
+            })
+            .collect();
+        for item in legend {
+            self.screen.layers[ui].add_item(item);
+        }
+        self.screen.tint = Some(tint);
+    }
+
+    pub fn toggle_attribution(&mut self) {
+        self.attribution = !self.attribution;
+        self.refresh_attribution();
+        self.clear_screen();
+        self.screen.redraw();
+        self.draw_tool_indicators();
+    }
+
+    fn view(&self) -> View {
+        (self.screen.viewport, self.last_cursor_position)
+    }
+
+    pub fn park(&mut self) {
+        self.parked = Some(self.view());
+    }
+
+    // the current view takes the place of the parked one, jumping again goes back
+    pub fn jump_to_parked(&mut self) {
+        let Some((viewport, cursor_position)) = self.parked.take() else {
+            return;
+        };
+        self.following = None;
+        self.parked = Some(self.view());
+        self.screen.viewport = viewport;
+        self.last_cursor_position = cursor_position;
+        self.clear_screen();
+        self.screen.redraw();
+        self.draw_tool_indicators();
+        self.draw_cursor_info(cursor_position);
+    }
+
+    // follows the next participant by name, after the last one stops following
+    fn follow_next(&mut self) {
+        let next: Option<String> = self
+            .viewports
+            .keys()
+            .filter(|user| **user != self.user)
+            .find(|user| {
+                self.following
+                    .as_ref()
+                    .is_none_or(|following| *user > following)
+            })
+            .cloned();
+        self.following = next;
+        if let Some(&offset) = self
+            .following
+            .as_ref()
+            .and_then(|user| self.viewports.get(user))
+        {
+            self.show_shared_layer_at(offset);
+            self.screen.redraw();
+        }
+        self.draw_cursor_info(self.last_cursor_position);
+    }
+
+    // peers exchange where the shared layer sits on their screen, which is
+    // what the offset of the layer used to be before the view could pan
+    fn shared_layer_on_screen(&self) -> (i32, i32) {
+        let (x, y) = self.screen.layers[0].offset;
+        let (view_x, view_y) = self.screen.viewport;
+        (x - view_x, y - view_y)
+    }
+
+    fn show_shared_layer_at(&mut self, (x, y): (i32, i32)) {
+        let (layer_x, layer_y) = self.screen.layers[0].offset;
+        self.screen.viewport = (layer_x - x, layer_y - y);
+    }
+
+    // the view moves over the canvas, whatever was followed stays behind
+    fn pan(&mut self, delta: (i32, i32)) {
+        self.following = None;
+        self.screen.pan(delta);
+        self.screen.redraw();
+        self.draw_cursor_info(self.last_cursor_position);
+    }
+
+    // zooms around the cursor, the pixel under it stays put
+    fn zoom(&mut self, step: i8) {
+        let zoom: i8 = (self.screen.zoom + step).clamp(MIN_ZOOM, MAX_ZOOM);
+        let (col, row) = self.last_cursor_position;
+        self.screen.set_zoom(zoom, (col as i32, row as i32));
+        self.screen.redraw();
+        self.draw_cursor_info(self.last_cursor_position);
+    }
+
+    // peers learn the view of this session whenever the shared layer pans
+    fn publish_viewport(&mut self, client: &mut Option<Client>) {
+        let offset: (i32, i32) = self.shared_layer_on_screen();
+        let Some(client) = client.as_mut() else {
+            return;
+        };
+        if self.published_viewport == Some(offset) {
+            return;
+        }
+        self.published_viewport = Some(offset);
+        client.publish(Update::Viewport(SerializableViewport {
+            user: self.user.clone(),
+            x: offset.0,
+            y: offset.1,
+        }));
+    }
+
+    pub fn set_fit(&mut self, fit: bool) {
+        self.screen.fit = fit;
+    }
+
+    // clockwise quarter turns of the view, the canvas itself is left untouched
+    pub fn rotate_view(&mut self, turns: u8) {
+        self.screen.rotation = (self.screen.rotation + turns) % 4;
+        self.clear_screen();
+        self.screen.redraw();
+        self.draw_tool_indicators();
+    }
+
+    pub fn toggle_fit(&mut self) {
+        self.screen.fit = !self.screen.fit;
+        self.clear_screen();
+        self.screen.redraw();
+        self.draw_tool_indicators();
+    }
+
+    // time between inputs adds up unless the artist stepped away
+    fn count_active_time(&mut self) {
+        let elapsed: Duration = self.last_input.elapsed();
+        if elapsed <= ACTIVE_TIME_IDLE_LIMIT {
+            self.time_spent += elapsed;
+        }
+        self.last_input = Instant::now();
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.stats = !self.stats;
+        self.draw_stats();
+    }
+
+    // bottom left corner, cells under the previous panel are restored first
+    fn draw_stats(&mut self) {
+        let (x0, y0) = self.stats_item.offset;
+        for col in 0..self.stats_item.chars[0].len() {
+            self.screen.redraw_cell((x0 + col as i32, y0));
+        }
+        self.stats_item.chars = vec![vec![]];
+        if !self.stats || matches!(self.config, Config::Connection | Config::Help) {
+            return;
+        }
+        let minutes: u64 = self.time_spent.as_secs() / 60;
+        let stats_str: String = format!(
+            " {}h {:02}m | {} strokes ",
+            minutes / 60,
+            minutes % 60,
+            self.strokes
+        );
+        self.stats_item.offset = (0, self.screen.height as i32 - 1);
+        self.stats_item.chars = vec![stats_str
+            .chars()
+            .map(|c| TermChar {
+                character: c,
+                foreground_color: Color::Black,
+                background_color: Color::White,
+                empty: false,
+            })
+            .collect()];
+        self.stats_item.redraw(
+            &mut self.screen.term,
+            (0, 0),
+            self.screen.width,
+            self.screen.height,
+        );
+    }
+
+    // the terminal cursor shows where the keys draw
+    pub fn toggle_keyboard(&mut self) {
+        self.keyboard = !self.keyboard;
+        match self.keyboard {
+            true => self.screen.term.queue(cursor::Show),
+            false => self.screen.term.queue(cursor::Hide),
+        };
+        if self.keyboard {
+            let (col, row) = self.last_cursor_position;
+            self.screen.term.move_to(col, row);
+        }
+        self.draw_cursor_info(self.last_cursor_position);
+    }
+
+    // the cursor moves a pixel, the draw moves paint on the way as a drag
+    // would, stamp uses the current tool once and the arrows move, shifted
+    // they draw. moves and stamps go through on_mouse_event so every tool,
+    // lock and region works as with a pointer, false for the keys it leaves
+    // alone
+    fn on_keyboard_drawing(&mut self, event: &KeyEvent, client: &mut Option<Client>) -> bool {
+        let shift: bool = event.modifiers.contains(KeyModifiers::SHIFT);
+        let action: Option<Action> = match event.code {
+            KeyCode::Left if shift => Some(Action::DrawLeft),
+            KeyCode::Right if shift => Some(Action::DrawRight),
+            KeyCode::Up if shift => Some(Action::DrawUp),
+            KeyCode::Down if shift => Some(Action::DrawDown),
+            KeyCode::Left => Some(Action::CursorLeft),
+            KeyCode::Right => Some(Action::CursorRight),
+            KeyCode::Up => Some(Action::CursorUp),
+            KeyCode::Down => Some(Action::CursorDown),
+            _ => self.keymap.action(event, Mode::Keyboard),
+        };
+        let draws: bool = matches!(
+            action,
+            Some(Action::DrawLeft | Action::DrawRight | Action::DrawUp | Action::DrawDown)
+        );
+        if !draws {
+            self.end_keyboard_stroke(client);
+        }
+        if self.config != Config::None
+            || self.screen.fit
+            || self.screen.zoom < 0
+            || self.attribution
+        {
+            return false;
+        }
+        let (width, height) = self.screen.pixel_size();
+        let step: Option<(i32, i32)> = match action {
+            Some(Action::CursorLeft | Action::DrawLeft) => Some((-width, 0)),
+            Some(Action::CursorRight | Action::DrawRight) => Some((width, 0)),
+            Some(Action::CursorUp | Action::DrawUp) => Some((0, -height)),
+            Some(Action::CursorDown | Action::DrawDown) => Some((0, height)),
+            _ => None,
+        };
+        let position: (u16, u16) = self.last_cursor_position;
+        match (action, step) {
+            (_, Some((dx, dy))) => {
+                let col = (position.0 as i32 + dx).clamp(0, self.screen.width as i32 - width);
+                let row = (position.1 as i32 + dy).clamp(0, self.screen.height as i32 - height);
+                let to: (u16, u16) = (col as u16, row as u16);
+                // the first draw move presses the button where the cursor is
+                if draws && !self.keyboard_stroke {
+                    self.keyboard_stroke = true;
+                    let down = MouseEventKind::Down(MouseButton::Left);
+                    self.on_mouse_event(keyboard_pointer(down, position), client);
+                }
+                let kind = match draws {
+                    true => MouseEventKind::Drag(MouseButton::Left),
+                    false => MouseEventKind::Moved,
+                };
+                self.on_mouse_event(keyboard_pointer(kind, to), client);
+            }
+            (Some(Action::Stamp), None) => {
+                let down = MouseEventKind::Down(MouseButton::Left);
+                let up = MouseEventKind::Up(MouseButton::Left);
+                self.on_mouse_event(keyboard_pointer(down, position), client);
+                self.on_mouse_event(keyboard_pointer(up, position), client);
+            }
+            (Some(Action::LeaveKeyboard), None) => self.toggle_keyboard(),
+            _ => return false,
+        }
+        if self.text_edit.is_none() {
+            let (col, row) = self.last_cursor_position;
+            self.screen.term.move_to(col, row);
+        }
+        true
+    }
+
+    // the keyboard drawing keys are looked up in on_keyboard_drawing first
+    fn key_mode(&self) -> Mode {
+        match self.config {
+            Config::Connection => Mode::Connection,
+            _ => Mode::Canvas,
+        }
+    }
+
+    // releases the button the draw moves hold down
+    fn end_keyboard_stroke(&mut self, client: &mut Option<Client>) {
+        if !self.keyboard_stroke {
+            return;
+        }
+        self.keyboard_stroke = false;
+        let up = MouseEventKind::Up(MouseButton::Left);
+        self.on_mouse_event(keyboard_pointer(up, self.last_cursor_position), client);
+    }
+
+    pub fn toggle_grid(&mut self) {
+        self.screen.grid = match self.screen.grid {
+            Some(_) => None,
+            None => Some(self.grid_spacing),
+        };
+        self.screen.refresh();
+        self.draw_tool_indicators();
+    }
+
+    // the canvas cell moved onto the closest grid line, the brush follows the
+    // lines while snapping
+    fn snap_to_line(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        if !self.snap {
+            return (x, y);
+        }
+        let (px, py) = self.grid_pixel((x, y));
+        let (sx, sy) = (self.nearest_line(px), self.nearest_line(py));
+        match (px - sx).abs() <= (py - sy).abs() {
+            true => self.grid_cell((sx, py)),
+            false => self.grid_cell((px, sy)),
+        }
+    }
+
+    // the canvas cell moved onto the closest place grid lines meet, items
+    // land there while snapping
+    fn snap_to_corner(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        if !self.snap {
+            return (x, y);
+        }
+        let (px, py) = self.grid_pixel((x, y));
+        self.grid_cell((self.nearest_line(px), self.nearest_line(py)))
+    }
+
+    // grid lines run from the layer 0 origin, a pixel apart per unit
+    fn grid_pixel(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        let origin: (i32, i32) = self.screen.layers[0].offset;
+        ((x - origin.0).div_euclid(2), y - origin.1)
+    }
+
+    fn grid_cell(&self, (px, py): (i32, i32)) -> (i32, i32) {
+        let origin: (i32, i32) = self.screen.layers[0].offset;
+        (origin.0 + 2 * px, origin.1 + py)
+    }
+
+    fn nearest_line(&self, pixel: i32) -> i32 {
+        let spacing: i32 = self.grid_spacing;
+        (pixel + spacing / 2).div_euclid(spacing) * spacing
+    }
+
+    pub fn toggle_overlay(&mut self) {
+        self.overlay = !self.overlay;
+        self.draw_overlay();
+    }
+
+    // cells under the previous overlay are restored before drawing the new one
+    fn draw_overlay(&mut self) {
+        let (x0, y0) = self.overlay_item.offset;
+        for col in 0..self.overlay_item.chars[0].len() {
+            self.screen.redraw_cell((x0 + col as i32, y0));
+        }
+        self.overlay_item.chars = vec![vec![]];
+        if !self.overlay || matches!(self.config, Config::Connection | Config::Help) {
+            return;
+        }
+        let keys: Vec<String> = self.pressed_keys.iter().cloned().collect();
+        let overlay_str: String = format!(" {} | {} ", self.tool.name(), keys.join(" "));
+        self.overlay_item.chars = vec![overlay_str
+            .chars()
+            .map(|c| TermChar {
+                character: c,
+                foreground_color: Color::Black,
+                background_color: Color::White,
+                empty: false,
+            })
+            .collect()];
+        self.overlay_item.redraw(
+            &mut self.screen.term,
+            (0, 0),
+            self.screen.width,
+            self.screen.height,
+        );
+    }
+
+    // every frame drawn from now on is also written to an asciinema cast
+    pub fn record_cast(&mut self, path: &str) -> error::Result<()> {
+        self.screen
+            .term
+            .wrap_backend(|backend| {
+                let cast: CastBackend = CastBackend::create(path, backend)?;
+                Ok(Box::new(cast))
+            })
+            .context(path)?;
+        Ok(())
+    }
+
+    // history is kept in a sqlite database next to the project from now on,
+    // whatever it held from previous sessions becomes undoable
+    pub fn persist_history(&mut self) -> error::Result<()> {
+        let project_path: &str = self.project_path.as_deref().unwrap_or(DEFAULT_PROJECT_PATH);
+        let path: String = format!("{}.history", project_path);
+        self.history = Box::new(SqliteHistory::new(&path).context(&path)?);
+        Ok(())
+    }
+
+    // operations on layers that no longer exist are dropped, on locked layers
+    // or outside the regions the user may draw in they are kept
+    pub fn undo(&mut self, client: &mut Option<Client>) {
+        let Some(operation) = self.history.pop() else {
+            return;
+        };
+        if let LocalOperation::Frame { change } = operation {
+            return self.undo_frame_change(change, client);
+        }
+        let operations: Vec<LocalOperation> = operation.clone().into_operations();
+        let ui: usize = self.ui_layer();
+        if operations
+            .iter()
+            .any(|operation| operation.layer().is_none_or(|layer| layer >= ui))
+        {
+            return;
+        }
+        let kept: bool = operations.iter().any(|operation| {
+            let layer: usize = operation.layer().unwrap_or(0);
+            self.screen.layers[layer].locked
+                || layer == 0
+                    && operation
+                        .cells()
+                        .into_iter()
+                        .any(|cell| !self.regions.may_draw(&self.user, cell))
+        });
+        if kept {
+            self.history.push(operation);
+            return;
+        }
+        for operation in operations.into_iter().rev() {
+            self.undo_operation(operation, client);
+        }
+        self.screen.refresh();
+    }
+
+    // the inverse of a single operation is applied
+    fn undo_operation(&mut self, operation: LocalOperation, client: &mut Option<Client>) {
+        let Some(layer) = operation.layer() else {
+            return;
+        };
+        match operation {
+            LocalOperation::Add { item, .. } => {
+                // pixels of histories older than the grid were added as items
+                if self.screen.layers[layer]
+                    .remove_last_at(item.offset)
+                    .is_none()
+                {
+                    self.screen.layers[layer].clear_pixel(item.offset);
+                }
+                if let (Some(client), 0) = (client.as_mut(), layer) {
+                    publish_erase(client, &item, &self.user);
+                }
+            }
+            LocalOperation::Remove { items, .. } => {
+                for item in items {
+                    if layer == 0 {
+                        self.authors.insert(item.offset, self.user.clone());
+                    }
+                    if let (Some(client), 0) = (client.as_mut(), layer) {
+                        client.publish(item_update(&item, &self.user));
+                    }
+                    self.screen.layers[layer].add_item(item);
+                }
+            }
+            LocalOperation::Paint {
+                offset,
+                replaced,
+                authors,
+                ..
+            } => {
+                self.screen.layers[layer].clear_pixel(offset);
+                if let (Some(client), 0) = (client.as_mut(), layer) {
+                    client.publish(Update::Erase(SerializableErase {
+                        abs_x: offset.0,
+                        abs_y: offset.1,
+                        stamp: Stamp::default(),
+                        author: self.user.clone(),
+                    }));
+                }
+                self.restore_pixels(layer, replaced, authors, client);
+            }
+            LocalOperation::Clear {
+                pixels, authors, ..
+            } => self.restore_pixels(layer, pixels, authors, client),
+            LocalOperation::Move { item, from, .. } => {
+                // the item may have been moved or removed since, by a peer too
+                if self.screen.layers[layer].take_item(&item).is_none() {
+                    return;
+                }
+                let back: Item = Item {
+                    offset: from,
+                    ..item.clone()
+                };
+                if let (Some(client), 0) = (client.as_mut(), layer) {
+                    publish_moved(client, &item, &back, &self.user);
+                }
+                self.screen.layers[layer].add_item(back);
+            }
+            LocalOperation::AddLayer { .. } => {
+                self.screen.layers.remove(layer);
+                if self.active_layer > layer || self.active_layer == self.document_end() {
+                    self.active_layer -= 1;
+                }
+                self.clear_screen();
+            }
+            LocalOperation::MergeDown { upper, .. } => {
+                self.screen.layers.insert(layer + 1, *upper);
+                if self.active_layer >= layer {
+                    self.active_layer += 1;
+                }
+                self.clear_screen();
+            }
+            LocalOperation::Group { .. } | LocalOperation::Frame { .. } => {}
+        }
+    }
+
+    // pixels taken back by an undo are painted again as their authors had
+    // them, the user's own when the history does not know
+    fn restore_pixels(
+        &mut self,
+        layer: usize,
+        pixels: Vec<((i32, i32), Color)>,
+        authors: Vec<((i32, i32), String)>,
+        client: &mut Option<Client>,
+    ) {
+        let authors: HashMap<(i32, i32), String> = authors.into_iter().collect();
+        for (offset, color) in pixels {
+            self.screen.layers[layer].paint(offset, color);
+            let author: String = authors.get(&offset).unwrap_or(&self.user).clone();
+            if let (Some(client), 0) = (client.as_mut(), layer) {
+                client.publish(item_update(&Pixel::new(color).to_item(offset), &author));
+            }
+            if layer == 0 {
+                self.authors.insert(offset, author);
+            }
+        }
+    }
+
+    // authors of the shared layer pixels, taken before they are replaced so an
+    // undo gives the pixels back to them
+    fn authors_of(
+        &self,
+        layer: usize,
+        pixels: &[((i32, i32), Color)],
+    ) -> Vec<((i32, i32), String)> {
+        if layer != 0 {
+            return Vec::new();
+        }
+        pixels
+            .iter()
+            .filter_map(|(offset, _)| Some((*offset, self.authors.get(offset)?.clone())))
+            .collect()
+    }
+
+    pub fn compact(&mut self) {
+        let ui = self.ui_layer();
+        for layer in self.screen.layers[..ui].iter_mut() {
+            layer.compact();
+        }
+    }
+
+    // imports land on a new layer right above the active one
+    fn insert_layer_above(&mut self, mut layer: Layer) {
+        layer.width = self.screen.width;
+        layer.height = self.screen.height;
+        // the scratch layer stays on top of the document
+        self.active_layer = (self.active_layer + 1).min(self.document_end());
+        self.screen.layers.insert(self.active_layer, layer);
+        let layer: usize = self.active_layer;
+        self.history.push(LocalOperation::AddLayer { layer });
+    }
+
+    // the image is fitted to the screen
+    // with an outline color the traced edges land on a layer above the image,
+    // as line art to start from
+    pub fn import_image(
+        &mut self,
+        path: &str,
+        dither: bool,
+        outline: Option<u8>,
+    ) -> error::Result<()> {
+        let (width, height) = (self.screen.width, self.screen.height);
+        let layer: Layer = import_image(path, width, height, dither).context(path)?;
+        // the picker starts on the image palette so tracing it is immediate
+        self.palettes.push(extract_palette(&layer, PALETTE_SIZE));
+        self.palette_index = self.palettes.len() - 1;
+        self.insert_layer_above(layer);
+        if let Some(code) = outline {
+            let outline: Layer = import_outline(path, width, height, code).context(path)?;
+            self.insert_layer_above(outline);
+        }
+        Ok(())
+    }
+
+    pub fn import_ansi(&mut self, path: &str) -> error::Result<()> {
+        let layer: Layer = import_ansi(path).context(path)?;
+        self.insert_layer_above(layer);
+        Ok(())
+    }
+
+    // exports are written next to the project with the extension swapped
+    fn export_path(&self, extension: &str) -> String {
+        let project_path: &str = self.project_path.as_deref().unwrap_or(DEFAULT_PROJECT_PATH);
+        Path::new(project_path)
+            .with_extension(extension)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    // canvas layers replace the current ones, the ui layer is kept on top
+    pub fn open_project(&mut self, path: &str) -> error::Result<()> {
+        let project: Project = Project::load(path).context(path)?;
+        let ui: Layer = self.screen.layers.remove(self.ui_layer());
+        self.screen.layers = project.layers;
+        if self.screen.layers.is_empty() {
+            let (width, height) = (self.screen.width, self.screen.height);
+            let background = Layer::new_empty("background".to_string(), width, height, (0, 0));
+            self.screen.layers.push(background);
+        }
+        self.screen.layers.push(ui);
+        self.screen.frame = project.frame.min(project.frames.len());
+        self.screen.frames = project.frames;
+        self.active_layer = project.active_layer.min(self.ui_layer() - 1);
+        self.color_selected = project.color_selected;
+        self.project_path = Some(path.to_string());
+        self.time_spent = Duration::from_secs(project.metadata.time_spent);
+        self.strokes = project.metadata.strokes;
+        self.project_metadata = Some(project.metadata);
+        self.screen.mark_saved(self.document_end());
+        Ok(())
+    }
+
+    pub fn skip_quit_confirmation(&mut self) {
+        self.confirm_quit = false;
+    }
+
+    // true when it is fine to exit right away, otherwise the prompt is shown
+    // and answer_quit decides
+    fn quit(&mut self) -> bool {
+        if !self.confirm_quit || !self.screen.dirty(self.document_end()) {
+            return true;
+        }
+        self.draw_quit_prompt("unsaved changes, s save and quit, q quit, any other key goes back");
+        false
+    }
+
+    fn answer_quit(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Char('s') => match self.save_project() {
+                Ok(_) => true,
+                Err(e) => {
+                    let message: String = format!("Failed to save project: {}, q quits anyway", e);
+                    self.draw_quit_prompt(&message);
+                    false
+                }
+            },
+            KeyCode::Char('q') => true,
+            _ => {
+                self.draw_quit_prompt("");
+                self.draw_stats();
+                self.draw_overlay();
+                self.draw_cursor_info(self.last_cursor_position);
+                false
+            }
+        }
+    }
+
+    // bottom row over the stats and cursor info, an empty message takes it away
+    fn draw_quit_prompt(&mut self, message: &str) {
+        draw_bottom_row(
+            &mut self.screen,
+            &mut self.quit_prompt,
+            message,
+            Color::DarkRed,
+        );
+    }
+
+    // the command being typed, with the completions tab found for it
+    fn draw_command_line(&mut self, candidates: &[String]) {
+        let message: String = match &self.command_line {
+            Some(line) if candidates.is_empty() => format!(":{}", line),
+            Some(line) => format!(":{}   {}", line, candidates.join(" ")),
+            None => String::new(),
+        };
+        draw_bottom_row(
+            &mut self.screen,
+            &mut self.command_item,
+            &message,
+            Color::DarkBlue,
+        );
+    }
+
+    // esc leaves, tab completes and enter runs the command
+    fn on_command_line_key(&mut self, event: &KeyEvent, client: &mut Option<Client>) {
+        let Some(line) = self.command_line.as_mut() else {
+            return;
+        };
+        let mut candidates: Vec<String> = Vec::new();
+        match event.code {
+            KeyCode::Esc => self.command_line = None,
+            KeyCode::Enter => {
+                let line: String = line.clone();
+                self.command_line = None;
+                self.draw_command_line(&[]);
+                return self.run_command(&line, client);
+            }
+            KeyCode::Tab => {
+                let exporters: Exporters = Exporters::new(self.cell_aspect);
+                (*line, candidates) = command_line::complete(line, &exporters.names());
+            }
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Char(c) if !event.modifiers.contains(KeyModifiers::CONTROL) => line.push(c),
+            _ => {}
+        }
+        self.draw_command_line(&candidates);
+        if self.command_line.is_none() {
+            self.draw_cursor_info(self.last_cursor_position);
+        }
+    }
+
+    fn run_command(&mut self, line: &str, client: &mut Option<Client>) {
+        let command: Command = match command_line::parse(line) {
+            Ok(command) => command,
+            Err(message) => {
+                self.error = Some(message);
+                self.draw_cursor_info(self.last_cursor_position);
+                return;
+            }
+        };
+        match command {
+            Command::Save(path) => {
+                let saved = match path {
+                    Some(path) => self.save_project_to(path),
+                    None => self.save_project(),
+                };
+                if let Err(e) = saved.context("saving the project") {
+                    self.report(e);
+                }
+            }
+            Command::Export { format, out } => self.export_as(&format, out),
+            Command::Resize(width, height) => {
+                self.canvas = Some((width, height));
+                self.refresh_guides();
+                self.screen.redraw();
+            }
+            Command::NewLayer(name) => self.new_layer(name),
+            Command::Script(path) => self.run_script(&path, client),
+            Command::ShowFrame(number) => self.show_frame(number - 1, client),
+            Command::DeleteFrame if self.screen.frame_count() > 1 && client.is_none() => {
+                self.deleting_frame = true;
+                let message: String = format!(
+                    "delete frame {}/{}? y deletes it, any other key keeps it",
+                    self.screen.frame + 1,
+                    self.screen.frame_count()
+                );
+                self.draw_quit_prompt(&message);
+            }
+            Command::DeleteFrame => self.delete_frame(client),
+            Command::Grid(None) => self.toggle_grid(),
+            Command::Grid(Some(spacing)) => {
+                self.grid_spacing = spacing;
+                self.screen.grid = Some(spacing);
+                self.screen.refresh();
+                self.draw_tool_indicators();
+            }
+            Command::Transform(transform) => self.transform_sample(transform, client),
+            Command::SaveSprite(name) => {
+                let Some(sample) = &self.clone_sample else {
+                    self.error =
+                        Some("nothing to save, sample a region with the clone tool".to_string());
+                    self.draw_cursor_info(self.last_cursor_position);
+                    return;
+                };
+                let sprite: Sprite = Sprite {
+                    name,
+                    chars: sample.to_chars(),
+                };
+                match sprite.save().context("saving the sprite") {
+                    Ok(_) => {
+                        self.sprites.retain(|other| other.name != sprite.name);
+                        self.sprites.push(sprite);
+                    }
+                    Err(e) => self.report(e),
+                }
+            }
+            Command::Glyph(glyph) => {
+                self.brush_glyph = glyph.map(|(character, background)| TermChar {
+                    character,
+                    foreground_color: self.color_selected,
+                    background_color: background.map_or(Color::Reset, Color::AnsiValue),
+                    empty: false,
+                });
+                if self.brush_glyph.is_some() {
+                    self.brush_footprint = None;
+                    self.stamp_index = None;
+                }
+                self.set_tool(Tool::Brush);
+            }
+            Command::Connect { addr, room } => {
+                if client.is_some() {
+                    self.error = Some("already in a session".to_string());
+                    self.draw_cursor_info(self.last_cursor_position);
+                    return;
+                }
+                self.set_server(&addr);
+                if let Some(room) = room {
+                    self.connect_form.fields[2] = room;
+                }
+                self.start_join();
+            }
+        }
+        self.draw_cursor_info(self.last_cursor_position);
+    }
+
+    // format is an exporter name or an extension, out defaults to the project
+    // path with the extension of the format
+    fn export_as(&mut self, format: &str, out: Option<String>) {
+        let exporters: Exporters = Exporters::new(self.cell_aspect);
+        let Some(exporter) = exporters
+            .get(format)
+            .or_else(|| exporters.for_extension(format))
+        else {
+            self.error = Some(format!("unknown format {}", format));
+            return;
+        };
+        let path: String = out.unwrap_or_else(|| self.export_path(exporter.extension()));
+        match exporter
+            .export(self.document_layers(), &path)
+            .context(&path)
+        {
+            Ok(_) => self.exported(&path, exporter.extension()),
+            Err(e) => self.report(e),
+        }
+    }
+
+    // host:port the connection screen offers to join
+    pub fn set_server(&mut self, server: &str) {
+        if let Some((host, port)) = server.rsplit_once(':') {
+            self.connect_form.fields[0] = host.to_string();
+            self.connect_form.fields[1] = port.to_string();
+        }
+    }
+
+    pub fn set_input(&mut self, input: Input) {
+        self.input = input;
+    }
+
+    pub fn hold_lock(&mut self, lock: ProjectLock) {
+        self.project_lock = Some(lock);
+    }
+
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    fn exported(&self, path: &str, format: &str) {
+        self.hooks
+            .run(HookEvent::Exported, &[("PATH", path), ("FORMAT", format)]);
+    }
+
+    pub fn set_cell_aspect(&mut self, cell_aspect: (usize, usize)) {
+        self.cell_aspect = cell_aspect;
+    }
+
+    pub fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+
+    pub fn save_project(&mut self) -> std::io::Result<()> {
+        let path: String = self
+            .project_path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_PROJECT_PATH.to_string());
+        self.save_project_to(path)
+    }
+
+    // the project is only known by path once it was saved there
+    fn save_project_to(&mut self, path: String) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "project is open read-only",
+            ));
+        }
+        let mut metadata: ProjectMetadata = self
+            .project_metadata
+            .take()
+            .unwrap_or_else(|| ProjectMetadata::new(&path));
+        metadata.time_spent = self.time_spent.as_secs();
+        metadata.strokes = self.strokes;
+        let mut project = Project {
+            version: PROJECT_VERSION,
+            metadata,
+            layers: self.document_layers().to_vec(),
+            active_layer: self.active_layer.min(self.document_end() - 1),
+            color_selected: self.color_selected,
+            frames: self.screen.frames.clone(),
+            frame: self.screen.frame,
+        };
+        let saved = project.save(&path);
+        if saved.is_ok() {
+            self.screen.mark_saved(self.document_end());
+            self.hooks.run(HookEvent::Saved, &[("PATH", &path)]);
+            self.project_path = Some(path);
+        }
+        self.project_metadata = Some(project.metadata);
+        saved
+    }
+
+    // fails when the terminal does, the session ends and the error is
+    // printed once the terminal is restored
+    pub fn run(
+        &mut self,
+        addr: Option<String>,
+        room: &str,
+        record: Option<String>,
+    ) -> error::Result<()> {
+        // the join goes on while drawing, its attempts are shown in the cursor
+        // info and one that fails leaves the session offline with the reason,
+        // the drawing is not lost to a typo in the address
+        let mut client: Option<Client> = None;
+        self.record = record;
+        self.connect_form.fields[2] = room.to_string();
+        if let Some(addr) = addr {
+            self.set_server(&addr);
+            self.start_join();
+            if self.joining.is_none() {
+                self.error = Some(self.connect_form.status.clone());
+            }
+        }
+        let _guard: TerminalGuard = TerminalGuard::install();
+        self._enter()?;
+        let mut exit = false;
+        self.clear_screen();
+        self.screen.redraw();
+        self.draw_tool_indicators();
+
+        let mut updates: VecDeque<Vec<u8>> = VecDeque::new();
+        let mut last_activity: Instant = Instant::now();
+        let mut compacted: bool = true;
+        let mut last_remote_frame: Instant = Instant::now();
+        let mut last_autosave: Instant = Instant::now();
+        let mut last_frame: Instant = Instant::now();
+        self.input.start();
+        while !exit {
+            self.poll_join(&mut client);
+
+            // network session client handler
+            if let Some(client) = &mut client {
+                for event in client.poll_events() {
+                    match event {
+                        NetworkEvent::Received(server_update) => updates.push_back(server_update),
+                        NetworkEvent::Status(status) => self.set_session_status(status),
+                    }
+                }
+                if client.checksum_due() {
+                    let (hash, cells) = self.network_checksum();
+                    client.publish(Update::Checksum(SerializableChecksum { hash, cells }));
+                }
+            }
+
+            // remote updates are batched per frame, a burst costs one redraw
+            if !updates.is_empty() && last_remote_frame.elapsed() >= REMOTE_FRAME_INTERVAL {
+                last_remote_frame = Instant::now();
+                let repaint: Repaint = self.on_netowrk_update_events(&mut updates, &mut client);
+                if repaint != Repaint::Nothing {
+                    last_activity = Instant::now();
+                    compacted = false;
+                }
+                self.repaint(repaint);
+            }
+
+            self.reset_if_due(room, &mut client);
+
+            // only projects with a file are autosaved, a fresh canvas is not
+            // written to untitled.pxr behind the user's back, nor is a project
+            // showing what was last saved
+            if let Some(interval) = self.autosave {
+                if last_autosave.elapsed() >= interval {
+                    let dirty: bool = self.screen.dirty(self.document_end());
+                    if dirty && self.project_path.is_some() && !self.read_only {
+                        if let Err(e) = self.save_project().context("autosaving the project") {
+                            self.report(e);
+                        }
+                    }
+                    last_autosave = Instant::now();
+                }
+            }
+            self.publish_viewport(&mut client);
+
+            // idle frames are used to keep the document small
+            if !compacted && last_activity.elapsed() >= IDLE_COMPACTION_DELAY {
+                self.compact();
+                compacted = true;
+            }
+
+            #[cfg(feature = "gamepad")]
+            self.on_gamepad_events(&mut client);
+
+            // local client event handler, input is waited for until the next
+            // frame is due instead of spinning, what arrives meanwhile is handled
+            // and presented together, up to MAX_EVENTS_PER_FRAME
+            let deadline: Instant = last_frame + FRAME_INTERVAL;
+            let mut handled: usize = 0;
+            while !exit
+                && handled < MAX_EVENTS_PER_FRAME
+                && self
+                    .input
+                    .poll(deadline.saturating_duration_since(Instant::now()))?
+            {
+                handled += 1;
+                last_activity = Instant::now();
+                self.count_active_time();
+                compacted = false;
+                match self.input.read()? {
+                    event::Event::Key(event) => exit = self.on_key_event(event, &mut client),
+                    event::Event::Mouse(event) => exit = self.on_mouse_event(event, &mut client),
+                    event::Event::Resize(width, height) => {
+                        exit = self.on_resize_event(width, height)
+                    }
+                    _ => {}
+                }
+            }
+
+            self.screen.present()?;
+            last_frame = Instant::now();
+        }
+        self._exit()?;
+        Ok(())
+    }
+
+    fn _enter(&mut self) -> std::io::Result<()> {
+        terminal::enable_raw_mode()?;
+        self.screen.term.queue(event::EnableMouseCapture);
+        self.screen.term.queue(cursor::Hide);
+        self.clear_screen();
+        if self.keyboard {
+            self.screen.term.queue(cursor::Show);
+        }
+        Ok(())
+    }
+
+    fn _exit(&mut self) -> std::io::Result<()> {
+        self.screen.term.move_to(0, self.screen.height);
+        self.screen.term.queue(event::DisableMouseCapture);
+        self.screen.term.queue(cursor::Show);
+        self.screen.present()?;
+        terminal::disable_raw_mode()
+    }
+
+    pub fn clear_screen(&mut self) {
+        self.screen.term.clear();
+        self.screen.invalidate();
+    }
+
+    pub fn draw_ansi_colors(&mut self) {
+        self.config = Config::ColorSelection;
+        let palette: &Palette = &self.palettes[self.palette_index];
+        let colors: Vec<u8> = palette.colors.clone();
+        // the palette name follows the swatches
+        let label: Item = Item {
+            name: "color_selection_label".to_string(),
+            offset: (2 * colors.len() as i32 + 1, self.screen.height as i32 - 1),
+            chars: vec![palette
+                .name
+                .chars()
+                .map(|c| TermChar {
+                    character: c,
+                    foreground_color: Color::White,
+                    background_color: Color::Reset,
+                    empty: false,
+                })
+                .collect()],
+        };
+        let ui = self.ui_layer();
+        self.screen.layers[ui].add_item(label.clone());
+        label.draw(
+            &mut self.screen.term,
+            label.offset,
+            self.screen.width,
+            self.screen.height,
+        );
+        for (c, code) in colors.into_iter().enumerate() {
+            let c = c as i32;
+            let color_pixel: Item = Item {
+                name: "color_selection_pixels".to_string(),
+                offset: (2 * c, self.screen.height as i32 - 1),
+                chars: Pixel {
+                    color: Color::AnsiValue(code),
+                }
+                .to_chars(),
+            };
+            let ui = self.ui_layer();
+            self.screen.layers[ui].add_item(color_pixel.clone());
+            color_pixel.draw(
+                &mut self.screen.term,
+                (2 * c, self.screen.height as i32 - 1),
+                self.screen.width,
+                self.screen.height,
+            );
+        }
+        self.draw_harmony_suggestions();
+    }
+
+    // companions of the selected color from the current palette on the row
+    // above the swatches, picked like any other swatch
+    fn draw_harmony_suggestions(&mut self) {
+        let colors: &[u8] = &self.palettes[self.palette_index].colors;
+        let suggestions = harmony_suggestions(self.color_selected, colors);
+        let row: i32 = self.screen.height as i32 - 2;
+        let mut col: i32 = 0;
+        let mut items: Vec<Item> = Vec::new();
+        for (name, codes) in suggestions {
+            for code in codes {
+                items.push(Item {
+                    name: "color_selection_pixels".to_string(),
+                    offset: (col, row),
+                    chars: Pixel {
+                        color: Color::AnsiValue(code),
+                    }
+                    .to_chars(),
+                });
+                col += 2;
+            }
+            // the first letter of the harmony follows its swatches
+            let initial: char = name.chars().next().unwrap_or(' ');
+            items.push(Item {
+                name: "color_selection_label".to_string(),
+                offset: (col, row),
+                chars: vec![[initial, ' ']
+                    .iter()
+                    .map(|&c| TermChar {
+                        character: c,
+                        foreground_color: Color::White,
+                        background_color: Color::Reset,
+                        empty: false,
+                    })
+                    .collect()],
+            });
+            col += 2;
+        }
+        let ui = self.ui_layer();
+        for item in items {
+            item.draw(
+                &mut self.screen.term,
+                item.offset,
+                self.screen.width,
+                self.screen.height,
+            );
+            self.screen.layers[ui].add_item(item);
+        }
+    }
+
+    // steps through the colors of the current palette, wrapping around
+    #[cfg(feature = "gamepad")]
+    fn cycle_color(&mut self, step: isize) {
+        let colors: &[u8] = &self.palettes[self.palette_index].colors;
+        if colors.is_empty() {
+            return;
+        }
+        let current: isize = colors
+            .iter()
+            .position(|&code| Color::AnsiValue(code) == self.color_selected)
+            .map_or(-step, |i| i as isize);
+        let next: usize = (current + step).rem_euclid(colors.len() as isize) as usize;
+        self.color_selected = Color::AnsiValue(colors[next]);
+    }
+
+    // the gamepad drives the same cursor the mouse does
+    #[cfg(feature = "gamepad")]
+    fn on_gamepad_events(&mut self, client: &mut Option<Client>) {
+        let Some(gamepad) = self.gamepad.as_mut() else {
+            return;
+        };
+        let actions: Vec<GamepadAction> = gamepad.poll();
+        if actions.is_empty()
+            || self.screen.fit
+            || self.screen.zoom < 0
+            || matches!(self.config, Config::Connection | Config::Help)
+        {
+            return;
+        }
+        for action in actions {
+            let position: (u16, u16) = self.last_cursor_position;
+            // a locked layer is left alone like with the mouse
+            let locked: bool = self.screen.layers[self.active_layer].locked;
+            match action {
+                GamepadAction::Move(dx, dy) => {
+                    // a pixel at a time, however big the zoom draws it
+                    let (width, height) = self.screen.pixel_size();
+                    let (dx, dy) = (dx / 2 * width, dy * height);
+                    let col = (position.0 as i32 + dx).clamp(0, self.screen.width as i32 - width);
+                    let row = (position.1 as i32 + dy).clamp(0, self.screen.height as i32 - height);
+                    self.last_cursor_position = (col as u16, row as u16);
+                }
+                GamepadAction::Paint | GamepadAction::Erase if locked => {}
+                GamepadAction::Paint => self.paint_at(position, client),
+                GamepadAction::Erase => self.erase_at(position, client),
+                GamepadAction::NextColor => self.cycle_color(1),
+                GamepadAction::PreviousColor => self.cycle_color(-1),
+            }
+        }
+        let position: (u16, u16) = self.last_cursor_position;
+        self.draw_hover_preview(MouseEventKind::Moved, position);
+        self.draw_tool_indicators();
+        self.draw_cursor_info(position);
+    }
+
+    // only while the picker is open, it is redrawn with the next palette
+    pub fn cycle_palette(&mut self) {
+        if self.config != Config::ColorSelection {
+            return;
+        }
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+        self.erase_ansi_colors();
+        self.draw_ansi_colors();
+    }
+
+    pub fn load_sprites(&mut self, sprites: Vec<Sprite>) {
+        self.sprites.extend(sprites);
+    }
+
+    // every sprite along the bottom of the screen, in rows going up when they
+    // do not fit the width
+    fn draw_sprite_picker(&mut self) {
+        self.config = Config::SpritePicker;
+        let ui = self.ui_layer();
+        let (width, height) = (self.screen.width as i32, self.screen.height as i32);
+        let label: Item = Item {
+            name: "sprite_picker_label".to_string(),
+            offset: (0, height - 1),
+            chars: vec!["sprites, click one to stamp it with the clone tool"
+                .chars()
+                .map(|c| TermChar {
+                    character: c,
+                    foreground_color: Color::White,
+                    background_color: Color::Reset,
+                    empty: false,
+                })
+                .collect()],
+        };
+        let mut items: Vec<Item> = vec![label];
+        let (mut x, mut bottom, mut shelf) = (0, height - 2, 0);
+        for (index, sprite) in self.sprites.iter().enumerate() {
+            let mut item: Item = sprite.to_item(self.color_selected);
+            item.name = format!("sprite_picker_{}", index);
+            let sprite_width: i32 =
+                item.chars.iter().map(|row| row.len()).max().unwrap_or(0) as i32;
+            if x > 0 && x + sprite_width > width {
+                (x, bottom, shelf) = (0, bottom - shelf - 1, 0);
+            }
+            item.offset = (x, bottom - item.chars.len() as i32 + 1);
+            x += sprite_width + 2;
+            shelf = shelf.max(item.chars.len() as i32);
+            items.push(item);
+        }
+        for item in items {
+            item.draw(
+                &mut self.screen.term,
+                item.offset,
+                self.screen.width,
+                self.screen.height,
+            );
+            self.screen.layers[ui].add_item(item);
+        }
+    }
+
+    fn erase_sprite_picker(&mut self) {
+        self.config = Config::None;
+        let ui = self.ui_layer();
+        let cells: Vec<(i32, i32)> = self.screen.layers[ui]
+            .items
+            .iter()
+            .filter(|item| item.name.starts_with("sprite_picker"))
+            .flat_map(|item| item.get_filled_indexes((0, 0)))
+            .collect();
+        self.screen.layers[ui].retain_items(|item| !item.name.starts_with("sprite_picker"));
+        for cell in cells {
+            self.screen.redraw_cell(cell);
+        }
+    }
+
+    // the sprite in the selected color becomes what the clone tool stamps
+    fn pick_sprite(&mut self, index: usize) {
+        let Some(sprite) = self.sprites.get(index) else {
+            return;
+        };
+        let item: Item = sprite.to_item(self.color_selected);
+        let width: usize = item.chars.iter().map(|row| row.len()).max().unwrap_or(0);
+        self.clone_sample = Some(CloneSample {
+            size: (width as i32, item.chars.len() as i32),
+            pixels: Vec::new(),
+            items: vec![item],
+            at: None,
+        });
+        self.erase_sprite_picker();
+        self.set_tool(Tool::Clone);
+    }
+
+    pub fn erase_ansi_colors(&mut self) {
+        self.config = Config::None;
+        let ui = self.ui_layer();
+        let cells: Vec<(i32, i32)> = self.screen.layers[ui]
+            .items
+            .iter()
+            .filter(|item| item.name.starts_with("color_selection"))
+            .flat_map(|item| item.get_filled_indexes((0, 0)))
+            .collect();
+        self.screen.layers[ui].retain_items(|item| !item.name.starts_with("color_selection"));
+        for cell in cells {
+            self.screen.redraw_cell(cell);
+        }
+    }
+
+    // typing starts where clicked, on a text item of the active layer it is
+    // edited from the character clicked
+    fn start_text_edit(&mut self, (col, row): (u16, u16)) {
+        let layer: usize = self.active_layer;
+        let cell: (i32, i32) = self
+            .screen
+            .relative_position(layer, (col as i32, row as i32));
+        if self.screen.layers[layer].locked || !self.may_draw(layer, cell) {
+            return;
+        }
+        let at: (i32, i32) = self.screen.layers[layer].absolute_position(cell);
+        // banners are pixels once done, there is no text to open
+        let banner: bool = matches!(self.text_mode, TextMode::Banner(_));
+        let text: Option<(i32, i32)> = self.screen.layers[layer]
+            .get_item_at_absolute(at)
+            .filter(|item| is_text(item) && !banner)
+            .map(|item| item.offset);
+        let edit: TextEdit = match text {
+            Some(offset) => {
+                let item: Item = self.screen.layers[layer]
+                    .remove_last_at(offset)
+                    .expect("the item was just found");
+                TextEdit::open(layer, item, cell)
+            }
+            None => {
+                let font: Option<Font> = match self.text_mode {
+                    TextMode::Banner(font) => Some(self.fonts[font].clone()),
+                    _ => None,
+                };
+                TextEdit::new(layer, cell, self.text_mode == TextMode::Narrow, font)
+            }
+        };
+        self.text_edit = Some(edit);
+        self.screen.term.queue(cursor::Show);
+        self.show_text_edit();
+    }
+
+    // the text being typed is a layer item of its own, put back on every key
+    fn show_text_edit(&mut self) {
+        let Some(edit) = &self.text_edit else {
+            return;
+        };
+        let layer: &mut Layer = &mut self.screen.layers[edit.layer];
+        layer.retain_items(|item| !edit.shown_as(item));
+        layer.add_item(edit.to_item());
+        let at: (i32, i32) = layer.absolute_position(edit.cursor_cell());
+        self.screen.refresh();
+        if let Some(&(col, row)) = self.screen.to_view(at).first() {
+            let (col, row) = (col.max(0) as u16, row.max(0) as u16);
+            self.last_cursor_position = (col, row);
+            self.screen.term.move_to(col, row);
+        }
+    }
+
+    // arrows move the cursor through the text, enter starts a line below and
+    // esc ends the text
+    fn on_text_key(&mut self, event: &KeyEvent, client: &mut Option<Client>) {
+        let color: Color = self.color_selected;
+        let Some(edit) = self.text_edit.as_mut() else {
+            return;
+        };
+        match event.code {
+            KeyCode::Char(c) if !event.modifiers.contains(KeyModifiers::CONTROL) => {
+                edit.insert(c, color)
+            }
+            KeyCode::Enter => edit.split_line(),
+            KeyCode::Backspace => edit.backspace(),
+            KeyCode::Delete => edit.delete(),
+            KeyCode::Left => edit.move_cursor((-1, 0)),
+            KeyCode::Right => edit.move_cursor((1, 0)),
+            KeyCode::Up => edit.move_cursor((0, -1)),
+            KeyCode::Down => edit.move_cursor((0, 1)),
+            KeyCode::Home => edit.home(),
+            KeyCode::End => edit.end(),
+            KeyCode::Esc => {
+                self.commit_text(client);
+                self.set_tool(Tool::Brush);
+                return;
+            }
+            _ => {}
+        }
+        self.show_text_edit();
+    }
+
+    // the whole text lands in the history and reaches the peers as one
+    // change, editing an existing text replaces it
+    fn commit_text(&mut self, client: &mut Option<Client>) {
+        let Some(edit) = self.text_edit.take() else {
+            return;
+        };
+        if !self.keyboard {
+            self.screen.term.queue(cursor::Hide);
+        }
+        let layer: usize = edit.layer;
+        self.screen.layers[layer].retain_items(|item| !edit.shown_as(item));
+        let mut operations: Vec<LocalOperation> = Vec::new();
+        if let Some(original) = edit.original.clone() {
+            if let (Some(client), 0) = (client.as_mut(), layer) {
+                publish_erase(client, &original, &self.user);
+            }
+            operations.push(LocalOperation::Remove {
+                layer,
+                items: vec![original],
+            });
+        }
+        if edit.font.is_some() {
+            operations.extend(self.paint_pixels(layer, edit.banner_pixels(), client));
+        } else if !edit.is_empty() {
+            let item: Item = edit.to_item();
+            if layer == 0 {
+                for cell in item.get_filled_indexes((0, 0)) {
+                    self.authors.insert(cell, self.user.clone());
+                }
+            }
+            if let (Some(client), 0) = (client.as_mut(), layer) {
+                client.publish(item_update(&item, &self.user));
+            }
+            self.screen.layers[layer].add_item(item.clone());
+            operations.push(LocalOperation::Add { layer, item });
+        }
+        // an edited text or a banner is a single undo step
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+        self.screen.refresh();
+    }
+
+    // one ui layer item per tool of the tool bar down the left edge, clicking
+    // one switches to it, the active tool is highlighted
+    fn draw_toolbox(&mut self) {
+        let ui = self.ui_layer();
+        let mut cells: Vec<(i32, i32)> = self.screen.layers[ui]
+            .items
+            .iter()
+            .filter(|item| item.name.starts_with("toolbox_"))
+            .flat_map(|item| item.get_filled_indexes((0, 0)))
+            .collect();
+        self.screen.layers[ui].retain_items(|item| !item.name.starts_with("toolbox_"));
+        if self.toolbox && !matches!(self.config, Config::Connection | Config::Help) {
+            for (i, tool) in TOOL_BAR.iter().enumerate() {
+                let (fg, bg) = match *tool == self.tool {
+                    true => (Color::Black, Color::White),
+                    false => (Color::White, Color::DarkGrey),
+                };
+                let item: Item = Item {
+                    name: format!("toolbox_{}", tool.name()),
+                    offset: (0, 1 + i as i32),
+                    chars: vec![[tool.letter(), ' ']
+                        .into_iter()
+                        .map(|character| TermChar {
+                            character,
+                            foreground_color: fg,
+                            background_color: bg,
+                            empty: false,
+                        })
+                        .collect()],
+                };
+                cells.extend(item.get_filled_indexes((0, 0)));
+                self.screen.layers[ui].add_item(item);
+            }
+        }
+        for cell in cells {
+            self.screen.redraw_cell(cell);
+        }
+    }
+
+    fn ui_layer(&self) -> usize {
+        self.screen.layers.len() - 1
+    }
+
+    // the scratch layer, when there is one, sits right below the ui layer and
+    // everything under it is the document
+    fn document_end(&self) -> usize {
+        match self.screen.layers[self.ui_layer() - 1].scratch {
+            true => self.ui_layer() - 1,
+            false => self.ui_layer(),
+        }
+    }
+
+    // what is saved and exported
+    fn document_layers(&self) -> &[Layer] {
+        &self.screen.layers[..self.document_end()]
+    }
+
+    // jumps to the scratch layer, created on first use, and back to the
+    // layer that was active before
+    pub fn toggle_scratch_layer(&mut self) {
+        if self.screen.layers[self.active_layer].scratch {
+            self.active_layer = self.layer_before_scratch.min(self.document_end() - 1);
+            return;
+        }
+        if self.document_end() == self.ui_layer() {
+            let mut scratch: Layer = Layer::new_empty(
+                "scratch".to_string(),
+                self.screen.width,
+                self.screen.height,
+                (0, 0),
+            );
+            scratch.scratch = true;
+            self.screen.layers.insert(self.ui_layer(), scratch);
+        }
+        self.layer_before_scratch = self.active_layer;
+        self.active_layer = self.document_end();
+    }
+
+    pub fn select_layer(&mut self, index: usize) {
+        if index < self.ui_layer() {
+            self.active_layer = index;
+        }
+    }
+
+    // an empty layer right above the active one, which it becomes
+    pub fn new_layer(&mut self, name: Option<String>) {
+        if self.screen.layers[self.active_layer].scratch {
+            return;
+        }
+        let name: String = name.unwrap_or_else(|| format!("layer {}", self.document_end()));
+        let (width, height) = (self.screen.width, self.screen.height);
+        let layer: Layer = Layer::new_empty(name, width, height, (0, 0));
+        self.screen.layers.insert(self.active_layer + 1, layer);
+        self.active_layer += 1;
+        let layer: usize = self.active_layer;
+        self.history.push(LocalOperation::AddLayer { layer });
+        self.screen.redraw();
+    }
+
+    pub fn duplicate_active_layer(&mut self) {
+        if self.screen.layers[self.active_layer].scratch {
+            return;
+        }
+        self.screen.duplicate_layer(self.active_layer);
+        self.active_layer += 1;
+        let layer: usize = self.active_layer;
+        self.history.push(LocalOperation::AddLayer { layer });
+        self.clear_screen();
+        self.screen.redraw();
+    }
+
+    // the history keeps the upper layer and the lower cells the merge changed,
+    // undo puts back only those. what is merged into the shared layer is
+    // shared like brush strokes, unless some of it lies in a region of
+    // someone else
+    pub fn merge_down_active_layer(&mut self, client: &mut Option<Client>) {
+        let upper: usize = self.active_layer;
+        if self.screen.layers[upper].scratch || upper == 0 {
+            return;
+        }
+        let lower: usize = upper - 1;
+        let upper_layer: Box<Layer> = Box::new(self.screen.layers[upper].clone());
+        // what the upper layer has, rebased into the lower layer
+        let (dx, dy) = (
+            upper_layer.offset.0 - self.screen.layers[lower].offset.0,
+            upper_layer.offset.1 - self.screen.layers[lower].offset.1,
+        );
+        let pixels: Vec<((i32, i32), Color)> = upper_layer
+            .pixels
+            .iter()
+            .map(|((x, y), color)| ((x + dx, y + dy), color))
+            .collect();
+        let items: Vec<Item> = upper_layer
+            .items
+            .iter()
+            .map(|item| Item {
+                offset: (item.offset.0 + dx, item.offset.1 + dy),
+                ..item.clone()
+            })
+            .collect();
+        if pixels
+            .iter()
+            .map(|(cell, _)| *cell)
+            .chain(items.iter().flat_map(|item| item.get_filled_indexes((0, 0))))
+            .any(|cell| !self.may_draw(lower, cell))
+        {
+            self.error = Some("the layer reaches into a region of someone else".to_string());
+            self.draw_cursor_info(self.last_cursor_position);
+            return;
+        }
+        let before: Vec<Item> = self.screen.layers[lower].items.clone();
+        let paints: Vec<LocalOperation> = pixels
+            .iter()
+            .map(|&(offset, color)| {
+                let replaced: Vec<((i32, i32), Color)> = self.screen.layers[lower]
+                    .pixel_color(offset)
+                    .map(|replaced| vec![(offset, replaced)])
+                    .unwrap_or_default();
+                LocalOperation::Paint {
+                    layer: lower,
+                    offset,
+                    color,
+                    authors: self.authors_of(lower, &replaced),
+                    replaced,
+                }
+            })
+            .collect();
+        if !self.screen.merge_down(upper) {
+            return;
+        }
+        self.active_layer -= 1;
+        // items covered by the merged ones are gone for the peers too
+        let removed: Vec<Item> = before
+            .into_iter()
+            .filter(|item| !self.screen.layers[lower].items.contains(item))
+            .collect();
+        if let (Some(client), 0) = (client.as_mut(), lower) {
+            for item in removed.iter() {
+                publish_erase(client, item, &self.user);
+            }
+        }
+        for (offset, color) in pixels {
+            if lower == 0 {
+                self.authors.insert(offset, self.user.clone());
+            }
+            if let (Some(client), 0) = (client.as_mut(), lower) {
+                client.publish(item_update(&Pixel::new(color).to_item(offset), &self.user));
+            }
+        }
+        for item in items.iter() {
+            if lower == 0 {
+                for cell in item.get_filled_indexes((0, 0)) {
+                    self.authors.insert(cell, self.user.clone());
+                }
+            }
+            if let (Some(client), 0) = (client.as_mut(), lower) {
+                client.publish(item_update(item, &self.user));
+            }
+        }
+        // undone last to first, the upper layer comes back once its cells
+        // are taken out of the lower one
+        let mut operations: Vec<LocalOperation> = vec![LocalOperation::MergeDown {
+            layer: lower,
+            upper: upper_layer,
+        }];
+        if !removed.is_empty() {
+            operations.push(LocalOperation::Remove {
+                layer: lower,
+                items: removed,
+            });
+        }
+        operations.extend(paints);
+        operations.extend(items.into_iter().map(|item| LocalOperation::Add { layer: lower, item }));
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+        self.clear_screen();
+        self.screen.redraw();
+    }
+
+    pub fn new_frame(&mut self, client: &Option<Client>) {
+        self.change_frame(client, |screen, document_end| {
+            let from: usize = screen.frame;
+            screen.duplicate_frame(document_end);
+            Some(FrameChange::Duplicate { from })
+        });
+    }
+
+    pub fn show_frame(&mut self, index: usize, client: &Option<Client>) {
+        self.change_frame(client, |screen, document_end| {
+            let from: usize = screen.frame;
+            screen
+                .show_frame(index, document_end)
+                .then_some(FrameChange::Show { from, to: index })
+        });
+    }
+
+    // onion skins show the new neighbours
+    pub fn move_frame(&mut self, index: usize, client: &Option<Client>) {
+        self.change_frame(client, |screen, _| {
+            let from: usize = screen.frame;
+            screen
+                .move_frame(index)
+                .then_some(FrameChange::Move { from, to: index })
+        });
+    }
+
+    fn delete_frame(&mut self, client: &Option<Client>) {
+        self.change_frame(client, |screen, document_end| {
+            let index: usize = screen.frame;
+            screen
+                .delete_frame(document_end)
+                .map(|layers| FrameChange::Delete { index, layers })
+        });
+    }
+
+    fn answer_frame_delete(&mut self, code: KeyCode, client: &Option<Client>) {
+        self.deleting_frame = false;
+        self.draw_quit_prompt("");
+        self.draw_stats();
+        self.draw_overlay();
+        self.draw_cursor_info(self.last_cursor_position);
+        if code == KeyCode::Char('y') {
+            self.delete_frame(client);
+        }
+    }
+
+    // frame changes are undone like any edit, the frame shown before comes
+    // back so the edits made on it can be undone next
+    fn undo_frame_change(&mut self, change: FrameChange, client: &Option<Client>) {
+        let undone: Option<()> =
+            self.apply_frame_change(client, |screen, document_end| match change.clone() {
+                FrameChange::Show { from, .. } => screen.show_frame(from, document_end).then_some(()),
+                FrameChange::Duplicate { from } => {
+                    screen.drop_frame(from, document_end).map(|_| ())
+                }
+                FrameChange::Move { from, .. } => screen.move_frame(from).then_some(()),
+                FrameChange::Delete { index, layers } => {
+                    screen.insert_frame(index, layers, document_end).then_some(())
+                }
+            });
+        // undone once the session is left
+        if undone.is_none() && client.is_some() {
+            self.history.push(LocalOperation::Frame { change });
+        }
+    }
+
+    // a script draws on copies of the document layers, what it painted is
+    // then painted for real, shared like brush strokes
+    fn run_script(&mut self, path: &str, client: &mut Option<Client>) {
+        let source: String = match std::fs::read_to_string(path).context(path) {
+            Ok(source) => source,
+            Err(e) => return self.report(e),
+        };
+        let size: (i32, i32) = self
+            .canvas
+            .unwrap_or((self.screen.width as i32 / 2, self.screen.height as i32));
+        let canvas: ScriptCanvas = ScriptCanvas::new(
+            self.document_layers().to_vec(),
+            self.active_layer.min(self.document_end() - 1),
+            size,
+            self.color_selected,
+        );
+        let drawn: ScriptCanvas = match script::run(&source, canvas) {
+            Ok(drawn) => drawn,
+            Err(message) => {
+                self.error = Some(format!("{}: {}", path, message));
+                self.draw_cursor_info(self.last_cursor_position);
+                return;
+            }
+        };
+        let mut operations: Vec<LocalOperation> = Vec::new();
+        for name in drawn.added {
+            let (width, height) = (self.screen.width, self.screen.height);
+            let layer: Layer = Layer::new_empty(name, width, height, (0, 0));
+            let index: usize = self.document_end();
+            self.screen.layers.insert(index, layer);
+            operations.push(LocalOperation::AddLayer { layer: index });
+        }
+        for layer in 0..self.document_end() {
+            let pixels: Vec<((i32, i32), Color)> = drawn
+                .paints
+                .iter()
+                .filter(|(index, _, _)| *index == layer)
+                .map(|&(_, cell, color)| (cell, color))
+                .collect();
+            if !pixels.is_empty() {
+                operations.extend(self.paint_pixels(layer, pixels, client));
+            }
+        }
+        // the whole run is undone at once, added layers included
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+        self.screen.refresh();
+    }
+
+    // frames swap the whole document under the shared layer, peers would not
+    // follow, so they only change offline. frames may have a different number
+    // of layers, the active one stays in range and on the scratch layer if it
+    // was there
+    fn change_frame(
+        &mut self,
+        client: &Option<Client>,
+        change: impl FnOnce(&mut Screen, usize) -> Option<FrameChange>,
+    ) {
+        if let Some(change) = self.apply_frame_change(client, change) {
+            self.history.push(LocalOperation::Frame { change });
+        }
+    }
+
+    fn apply_frame_change<T>(
+        &mut self,
+        client: &Option<Client>,
+        change: impl FnOnce(&mut Screen, usize) -> Option<T>,
+    ) -> Option<T> {
+        if client.is_some() {
+            self.error = Some("frames are not shared, leave the session first".to_string());
+            self.draw_cursor_info(self.last_cursor_position);
+            return None;
+        }
+        let scratch: bool = self.screen.layers[self.active_layer].scratch;
+        let document_end: usize = self.document_end();
+        let changed: T = change(&mut self.screen, document_end)?;
+        self.active_layer = match scratch {
+            true => self.document_end(),
+            false => self.active_layer.min(self.document_end() - 1),
+        };
+        self.clear_screen();
+        self.screen.redraw();
+        self.draw_tool_indicators();
+        self.draw_cursor_info(self.last_cursor_position);
+        Some(changed)
+    }
+
+    // wide text, narrow text and then a banner in every embedded font
+    fn cycle_text_mode(&mut self) {
+        self.text_mode = match self.text_mode {
+            TextMode::Wide => TextMode::Narrow,
+            TextMode::Narrow if !self.fonts.is_empty() => TextMode::Banner(0),
+            TextMode::Banner(font) if font + 1 < self.fonts.len() => TextMode::Banner(font + 1),
+            _ => TextMode::Wide,
+        };
+    }
+
+    pub fn cycle_smoothing(&mut self) {
+        let current = DRAG_SMOOTHING_LEVELS
+            .iter()
+            .position(|&level| level == self.smoothing)
+            .unwrap_or(0);
+        self.smoothing = DRAG_SMOOTHING_LEVELS[(current + 1) % DRAG_SMOOTHING_LEVELS.len()];
+    }
+
+    // graphics tablets report jittery positions, drags are pulled towards the
+    // pointer by 1 - smoothing of the distance on every event
+    fn smooth_position(&mut self, kind: MouseEventKind, (col, row): (u16, u16)) -> (u16, u16) {
+        let (x, y) = match (kind, self.smoothed_position) {
+            (MouseEventKind::Drag(MouseButton::Left), Some((sx, sy))) => (
+                sx + (1.0 - self.smoothing) * (col as f32 - sx),
+                sy + (1.0 - self.smoothing) * (row as f32 - sy),
+            ),
+            _ => (col as f32, row as f32),
+        };
+        self.smoothed_position = Some((x, y));
+        (x.round() as u16, y.round() as u16)
+    }
+
+    // restores the cells below the previous preview and draws the preview of the
+    // current tool where the pointer hovers without buttons pressed
+    fn draw_hover_preview(&mut self, kind: MouseEventKind, (col, row): (u16, u16)) {
+        if let Some((hover_col, hover_row)) = self.hover_position.take() {
+            for c in 0..2 {
+                let cell = self
+                    .screen
+                    .rotate_cell((hover_col as i32 + c, hover_row as i32));
+                self.screen.redraw_cell(cell);
+            }
+        }
+        if kind != MouseEventKind::Moved || !matches!(self.tool, Tool::Brush | Tool::Erase) {
+            return;
+        }
+        let mut preview: TermChar = self.cursor_term_char();
+        preview.background_color = Color::Reset;
+        for (c, character) in ['[', ']'].into_iter().enumerate() {
+            preview.character = character;
+            let cell: (i32, i32) = self.screen.rotate_cell((col as i32 + c as i32, row as i32));
+            preview.draw(
+                &mut self.screen.term,
+                cell,
+                self.screen.width,
+                self.screen.height,
+            );
+        }
+        self.hover_position = Some((col, row));
+    }
+
+    // the brush paints its footprint around the cell, a single pixel of the
+    // selected color unless a shape was captured, or a glyph
+    fn paint_at(&mut self, (col, row): (u16, u16), client: &mut Option<Client>) {
+        let footprint: Vec<((i32, i32), Color)> = match &self.brush_footprint {
+            Some(footprint) => footprint.clone(),
+            None => vec![((0, 0), self.color_selected)],
+        };
+        let (x, y) = self.snap_to_line(self.screen.to_canvas((col as i32, row as i32)));
+        if let Some(glyph) = self.brush_glyph {
+            self.paint_glyph((x, y), glyph, client);
+            return;
+        }
+        for ((dx, dy), color) in footprint {
+            self.paint_pixel((x + dx, y + dy), color, client);
+        }
+    }
+
+    // glyphs are items on top of the pixels, one painted over another
+    // replaces it
+    fn paint_glyph(&mut self, (x, y): (i32, i32), glyph: TermChar, client: &mut Option<Client>) {
+        let layer: usize = self.active_layer;
+        let layer_offset: (i32, i32) = self.screen.layers[layer].offset;
+        let offset: (i32, i32) = (x - layer_offset.0, y - layer_offset.1);
+        if !self.may_draw(layer, offset) {
+            return;
+        }
+        let term_char: TermChar = TermChar {
+            foreground_color: self.color_selected,
+            ..glyph
+        };
+        let item: Item = Item {
+            name: GLYPH_ITEM.to_string(),
+            offset,
+            chars: vec![vec![term_char, term_char]],
+        };
+        let replaced: Option<Item> = self.screen.layers[layer]
+            .get_item_at_absolute((x, y))
+            .filter(|other| other.name == GLYPH_ITEM && other.offset == offset)
+            .cloned();
+        let mut operations: Vec<LocalOperation> = Vec::new();
+        if let Some(replaced) = replaced {
+            // a drag over the same cell paints it again and again
+            if replaced.chars == item.chars {
+                return;
+            }
+            self.screen.layers[layer].remove_last_at(offset);
+            operations.push(LocalOperation::Remove {
+                layer,
+                items: vec![replaced],
+            });
+        }
+        if layer == 0 {
+            self.authors.insert(offset, self.user.clone());
+        }
+        if let (Some(client), 0) = (client.as_mut(), layer) {
+            client.publish(item_update(&item, &self.user));
+        }
+        self.screen.layers[layer].add_item(item.clone());
+        operations.push(LocalOperation::Add { layer, item });
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+        for dx in 0..2 {
+            self.screen.redraw_canvas_cell((x + dx, y));
+        }
+    }
+
+    // x,y is a canvas cell, pixels are kept relative to the layer wherever the
+    // view is
+    fn paint_pixel(&mut self, (x, y): (i32, i32), color: Color, client: &mut Option<Client>) {
+        let layer_offset: (i32, i32) = self.screen.layers[self.active_layer].offset;
+        let (abs_x, abs_y) = (x - layer_offset.0, y - layer_offset.1);
+        if !self.may_draw(self.active_layer, (abs_x, abs_y)) {
+            return;
+        }
+        let pixel: Item = Pixel::new(color).to_item((abs_x, abs_y));
+
+        let replaced = self.screen.layers[self.active_layer].paint(pixel.offset, color);
+        self.history.push(LocalOperation::Paint {
+            layer: self.active_layer,
+            offset: pixel.offset,
+            color,
+            authors: self.authors_of(self.active_layer, &replaced),
+            replaced,
+        });
+
+        if self.active_layer == 0 {
+            self.authors.insert(pixel.offset, self.user.clone());
+        }
+        // only the shared layer travels, other layers are private to each peer
+        if let (Some(client), 0) = (client, self.active_layer) {
+            client.publish(item_update(&pixel, &self.user));
+        }
+
+        // items of the layer stay on top, and a replaced neighbour leaves its
+        // other half uncovered
+        for dx in -1..3 {
+            self.screen.redraw_canvas_cell((x + dx, y));
+        }
+    }
+
+    // palettes join the color picker and stamps the ones cycled with `y`
+    pub fn load_assets(&mut self, packs: Vec<AssetPack>) {
+        for pack in packs {
+            self.palettes.extend(pack.palettes);
+            self.stamps.extend(pack.stamps);
+        }
+    }
+
+    // takes the next stamp as brush, after the last one it goes back to a
+    // single pixel
+    fn cycle_stamp(&mut self) {
+        self.stamp_index = match self.stamp_index {
+            None if !self.stamps.is_empty() => Some(0),
+            Some(i) if i + 1 < self.stamps.len() => Some(i + 1),
+            _ => None,
+        };
+        self.brush_footprint = self.stamp_index.map(|i| self.stamps[i].footprint());
+        self.brush_glyph = None;
+        self.set_tool(Tool::Brush);
+    }
+
+    // the shape under the cursor, every color of it, becomes the brush anchored
+    // at the hovered pixel, an empty spot goes back to the single pixel brush
+    fn capture_brush(&mut self) {
+        let (col, row) = self.last_cursor_position;
+        let layer: &Layer = &self.screen.layers[self.active_layer];
+        let at: (i32, i32) = self.screen.to_canvas((col as i32, row as i32));
+        let footprint = layer.pixel_at(at).map(|start| {
+            let shape = layer.connected_pixels(start, |_| true);
+            shape
+                .into_iter()
+                .filter_map(|(x, y)| {
+                    let color: Color = layer.pixel_color((x, y))?;
+                    Some(((x - start.0, y - start.1), color))
+                })
+                .collect::<Vec<((i32, i32), Color)>>()
+        });
+        // a background would make every stroke paint the whole canvas
+        if footprint
+            .as_ref()
+            .is_some_and(|footprint| footprint.len() > MAX_BRUSH_PIXELS)
+        {
+            return;
+        }
+        self.brush_footprint = footprint;
+        self.stamp_index = None;
+        self.brush_glyph = None;
+        self.set_tool(Tool::Brush);
+    }
+
+    // vignette of the same colored region under the cursor, added as pixels on
+    // top so it is undone and shared like a brush stroke
+    fn shade_at(&mut self, (col, row): (u16, u16), client: &mut Option<Client>) {
+        let layer: &Layer = &self.screen.layers[self.active_layer];
+        let at: (i32, i32) = self.screen.to_canvas((col as i32, row as i32));
+        let Some(start) = layer.pixel_at(at) else {
+            return;
+        };
+        let pixels = feathered_fill(layer, start, SHADE_STEPS, SHADE_STRENGTH);
+        let operations: Vec<LocalOperation> = self.paint_pixels(self.active_layer, pixels, client);
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+    }
+
+    // the line goes on from where the drag was to cell, a column or a row at a
+    // time so it never runs diagonally
+    fn draw_box_line(&mut self, cell: (i32, i32), client: &mut Option<Client>) {
+        let Some(mut from) = self.box_from else {
+            return;
+        };
+        while let Some(side) = boxdraw::toward(from, cell) {
+            let next: (i32, i32) = boxdraw::neighbour(from, side);
+            self.join_box(from, side, client);
+            self.join_box(next, boxdraw::opposite(side), client);
+            from = next;
+        }
+        self.box_from = Some(cell);
+        self.screen.refresh();
+    }
+
+    // sides of the box drawing glyph at the layer cell, none for anything else
+    fn box_sides(&self, cell: (i32, i32)) -> u8 {
+        let layer: &Layer = &self.screen.layers[self.active_layer];
+        layer
+            .get_item_at_absolute(layer.absolute_position(cell))
+            .filter(|item| item.offset == cell)
+            .map_or(0, |item| boxdraw::sides(item.chars[0][0].character))
+    }
+
+    // the glyph at cell joins side too. sides of the glyph no neighbour joins
+    // back are dropped first, a line drawn straight through its end turns
+    // the corner there instead of crossing it
+    fn join_box(&mut self, cell: (i32, i32), side: u8, client: &mut Option<Client>) {
+        if !self.may_draw(self.active_layer, cell) {
+            return;
+        }
+        let current: u8 = self.box_sides(cell);
+        let joined: u8 = boxdraw::SIDES
+            .into_iter()
+            .filter(|&other| current & other != 0)
+            .filter(|&other| {
+                let neighbour: (i32, i32) = boxdraw::neighbour(cell, other);
+                self.box_sides(neighbour) & boxdraw::opposite(other) != 0
+            })
+            .fold(side, |joined, other| joined | other);
+        let Some(glyph) = boxdraw::glyph(joined) else {
+            return;
+        };
+        if current != 0 && boxdraw::glyph(current) == Some(glyph) {
+            return;
+        }
+        let layer: usize = self.active_layer;
+        let mut operations: Vec<LocalOperation> = Vec::new();
+        if current != 0 {
+            if let Some(replaced) = self.screen.layers[layer].remove_last_at(cell) {
+                operations.push(LocalOperation::Remove {
+                    layer,
+                    items: vec![replaced],
+                });
+            }
+        }
+        let item: Item = Item {
+            name: BOX_ITEM.to_string(),
+            offset: cell,
+            chars: vec![vec![TermChar {
+                character: glyph,
+                foreground_color: self.color_selected,
+                background_color: Color::Reset,
+                empty: false,
+            }]],
+        };
+        if layer == 0 {
+            self.authors.insert(cell, self.user.clone());
+        }
+        if let (Some(client), 0) = (client.as_mut(), layer) {
+            client.publish(item_update(&item, &self.user));
+        }
+        self.screen.layers[layer].add_item(item.clone());
+        operations.push(LocalOperation::Add { layer, item });
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+    }
+
+    // the topmost item of the active layer at the layer cell follows the drag
+    // items inside a region of someone else stay where they are
+    fn grab_item(&mut self, cell: (i32, i32)) {
+        let layer: &Layer = &self.screen.layers[self.active_layer];
+        self.moving_item = layer
+            .get_item_at_absolute(layer.absolute_position(cell))
+            .filter(|item| self.may_draw(self.active_layer, item.offset))
+            .map(|item| MovingItem {
+                item: item.clone(),
+                from: item.offset,
+                at: item.offset,
+                grab: cell,
+            });
+    }
+
+    fn drag_item(&mut self, cell: (i32, i32)) {
+        let Some(moving) = self.moving_item.as_ref() else {
+            return;
+        };
+        let to: (i32, i32) = (
+            moving.from.0 + cell.0 - moving.grab.0,
+            moving.from.1 + cell.1 - moving.grab.1,
+        );
+        let layer_offset: (i32, i32) = self.screen.layers[self.active_layer].offset;
+        let (x, y) = self.snap_to_corner((to.0 + layer_offset.0, to.1 + layer_offset.1));
+        let to: (i32, i32) = (x - layer_offset.0, y - layer_offset.1);
+        let Some(moving) = self.moving_item.as_mut() else {
+            return;
+        };
+        if to == moving.at {
+            return;
+        }
+        let dragged: Item = moving.dragged();
+        moving.at = to;
+        let layer: &mut Layer = &mut self.screen.layers[self.active_layer];
+        if let Some(mut item) = layer.take_item(&dragged) {
+            item.offset = to;
+            layer.add_item(item);
+        }
+        self.screen.refresh();
+    }
+
+    // the item stays where it was dropped, unless it may not be drawn there
+    fn drop_item(&mut self, client: &mut Option<Client>) {
+        let Some(moving) = self.moving_item.take() else {
+            return;
+        };
+        if moving.at == moving.from {
+            return;
+        }
+        let layer: usize = self.active_layer;
+        let Some(item) = self.screen.layers[layer].take_item(&moving.dragged()) else {
+            return;
+        };
+        let before: Item = Item {
+            offset: moving.from,
+            ..item.clone()
+        };
+        if !self.may_draw(layer, item.offset) {
+            self.screen.layers[layer].add_item(before);
+            self.screen.refresh();
+            return;
+        }
+        if layer == 0 {
+            for cell in item.get_filled_indexes((0, 0)) {
+                self.authors.insert(cell, self.user.clone());
+            }
+        }
+        if let (Some(client), 0) = (client.as_mut(), layer) {
+            publish_moved(client, &before, &item, &self.user);
+        }
+        self.screen.layers[layer].add_item(item.clone());
+        self.history.push(LocalOperation::Move {
+            layer,
+            item,
+            from: moving.from,
+        });
+    }
+
+    // pixels and whole items of the region between the corner the drag
+    // started at and cell, both taken in
+    fn sample_clone(&mut self, cell: (i32, i32)) {
+        let Some(from) = self.clone_from.take() else {
+            return;
+        };
+        let (left, top) = (from.0.min(cell.0), from.1.min(cell.1));
+        // pixels are two cells wide
+        let (right, bottom) = (from.0.max(cell.0) + 1, from.1.max(cell.1));
+        let inside = |(x, y): (i32, i32)| x >= left && x <= right && y >= top && y <= bottom;
+        let layer: &Layer = &self.screen.layers[self.active_layer];
+        let mut pixels: Vec<((i32, i32), Color)> = Vec::new();
+        for y in top..=bottom {
+            for x in left..right {
+                if let Some(color) = layer.pixel_color((x, y)) {
+                    pixels.push(((x - left, y - top), color));
+                }
+            }
+        }
+        let items: Vec<Item> = layer
+            .items
+            .iter()
+            .filter(|item| item.get_filled_indexes((0, 0)).into_iter().all(inside))
+            .map(|item| Item {
+                offset: (item.offset.0 - left, item.offset.1 - top),
+                ..item.clone()
+            })
+            .collect();
+        if pixels.is_empty() && items.is_empty() {
+            return;
+        }
+        self.clone_sample = Some(CloneSample {
+            size: (right - left + 1, bottom - top + 1),
+            pixels,
+            items,
+            at: Some((self.active_layer, (left, top))),
+        });
+    }
+
+    // the sample is flipped or turned, and so is the region it was taken from
+    // when it is on the active layer, as a single undo
+    fn transform_sample(&mut self, transform: Transform, client: &mut Option<Client>) {
+        let Some(mut sample) = self.clone_sample.take() else {
+            self.error = Some("nothing to turn, sample a region with the clone tool".to_string());
+            return;
+        };
+        let at: Option<(i32, i32)> = match sample.at {
+            Some((layer, at)) if layer == self.active_layer => Some(at),
+            _ => None,
+        };
+        let Some((x, y)) = at else {
+            sample.transform(transform);
+            self.clone_sample = Some(sample);
+            return;
+        };
+        let layer: usize = self.active_layer;
+        let items: Vec<Item> = sample
+            .items
+            .iter()
+            .map(|item| Item {
+                offset: (x + item.offset.0, y + item.offset.1),
+                ..item.clone()
+            })
+            .filter(|item| self.may_draw(layer, item.offset))
+            .collect();
+        // pixels painted over since the sample was taken stay
+        let pixels: Vec<((i32, i32), Color)> = sample
+            .pixels
+            .iter()
+            .map(|&((dx, dy), color)| ((x + dx, y + dy), color))
+            .filter(|&(offset, color)| {
+                self.may_draw(layer, offset)
+                    && self.screen.layers[layer].pixel_color(offset) == Some(color)
+            })
+            .collect();
+        sample.transform(transform);
+        self.clone_sample = Some(sample);
+        let removed: Vec<Item> = items
+            .iter()
+            .filter_map(|item| self.screen.layers[layer].take_item(item))
+            .collect();
+        for (offset, _) in &pixels {
+            self.screen.layers[layer].clear_pixel(*offset);
+        }
+        if let (Some(client), 0) = (client.as_mut(), layer) {
+            let offsets = pixels.iter().map(|(offset, _)| *offset);
+            for offset in offsets.chain(removed.iter().map(|item| item.offset)) {
+                client.publish(Update::Erase(SerializableErase {
+                    abs_x: offset.0,
+                    abs_y: offset.1,
+                    stamp: Stamp::default(),
+                    author: self.user.clone(),
+                }));
+            }
+        }
+        let mut operations: Vec<LocalOperation> = Vec::new();
+        if !pixels.is_empty() {
+            operations.push(LocalOperation::Clear {
+                layer,
+                authors: self.authors_of(layer, &pixels),
+                pixels,
+            });
+        }
+        if !removed.is_empty() {
+            operations.push(LocalOperation::Remove {
+                layer,
+                items: removed,
+            });
+        }
+        operations.extend(self.place_sample((x, y), client));
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+        self.screen.refresh();
+    }
+
+    // a copy of the sample with its top left corner at the layer cell
+    fn stamp_clone(&mut self, at: (i32, i32), client: &mut Option<Client>) {
+        let operations: Vec<LocalOperation> = self.place_sample(at, client);
+        if let Some(operation) = LocalOperation::group(operations) {
+            self.history.push(operation);
+        }
+        self.screen.refresh();
+    }
+
+    // the sample painted on the active layer, returns what the history needs
+    // to undo it
+    fn place_sample(
+        &mut self,
+        (x, y): (i32, i32),
+        client: &mut Option<Client>,
+    ) -> Vec<LocalOperation> {
+        let Some(sample) = &self.clone_sample else {
+            return Vec::new();
+        };
+        let pixels: Vec<((i32, i32), Color)> = sample
+            .pixels
+            .iter()
+            .map(|&((dx, dy), color)| ((x + dx, y + dy), color))
+            .collect();
+        let items: Vec<Item> = sample
+            .items
+            .iter()
+            .map(|item| Item {
+                offset: (x + item.offset.0, y + item.offset.1),
+                ..item.clone()
+            })
+            .collect();
+        let layer: usize = self.active_layer;
+        let mut operations: Vec<LocalOperation> = self.paint_pixels(layer, pixels, client);
+        for item in items {
+            if !self.may_draw(layer, item.offset) {
+                continue;
+            }
+            if layer == 0 {
+                for cell in item.get_filled_indexes((0, 0)) {
+                    self.authors.insert(cell, self.user.clone());
+                }
+            }
+            if let (Some(client), 0) = (client.as_mut(), layer) {
+                client.publish(item_update(&item, &self.user));
+            }
+            self.screen.layers[layer].add_item(item.clone());
+            operations.push(LocalOperation::Add { layer, item });
+        }
+        operations
+    }
+
+    // pixels at layer cells, shared like a brush stroke, returns what the
+    // history needs to undo them. locked layers are left as they are
+    fn paint_pixels(
+        &mut self,
+        layer: usize,
+        pixels: Vec<((i32, i32), Color)>,
+        client: &mut Option<Client>,
+    ) -> Vec<LocalOperation> {
+        let mut operations: Vec<LocalOperation> = Vec::new();
+        if self.screen.layers[layer].locked {
+            return operations;
+        }
+        for (offset, color) in pixels {
+            if !self.may_draw(layer, offset) {
+                continue;
+            }
+            let replaced = self.screen.layers[layer].paint(offset, color);
+            let authors: Vec<((i32, i32), String)> = self.authors_of(layer, &replaced);
+            if layer == 0 {
+                self.authors.insert(offset, self.user.clone());
+            }
+            if let (Some(client), 0) = (client.as_mut(), layer) {
+                client.publish(item_update(&Pixel::new(color).to_item(offset), &self.user));
+            }
+            operations.push(LocalOperation::Paint {
+                layer,
+                offset,
+                color,
+                replaced,
+                authors,
+            });
+        }
+        self.screen.refresh();
+        operations
+    }
+
+    fn erase_at(&mut self, (col, row): (u16, u16), client: &mut Option<Client>) {
+        let cell: (i32, i32) = self
+            .screen
+            .relative_position(self.active_layer, (col as i32, row as i32));
+        if !self.may_draw(self.active_layer, cell) {
+            return;
+        }
+        // items lie on top of the pixels, they go first
+        let at: (i32, i32) = self.screen.to_canvas((col as i32, row as i32));
+        let removed: Vec<Item> = self.screen.erase_item_at(self.active_layer, at);
+        let erased: Option<(i32, i32)> = match removed.last() {
+            Some(item) => Some(item.offset),
+            None => self
+                .screen
+                .erase_pixel_at(self.active_layer, at)
+                .map(|(offset, color)| {
+                    let pixels: Vec<((i32, i32), Color)> = vec![(offset, color)];
+                    self.history.push(LocalOperation::Clear {
+                        layer: self.active_layer,
+                        authors: self.authors_of(self.active_layer, &pixels),
+                        pixels,
+                    });
+                    offset
+                }),
+        };
+        if let (Some(offset), Some(client), 0) = (erased, client, self.active_layer) {
+            client.publish(Update::Erase(SerializableErase {
+                abs_x: offset.0,
+                abs_y: offset.1,
+                stamp: Stamp::default(),
+                author: self.user.clone(),
+            }));
+        }
+        if !removed.is_empty() {
+            self.history.push(LocalOperation::Remove {
+                layer: self.active_layer,
+                items: removed,
+            });
+        }
+    }
+
+    // whatever is topmost at the absolute position of the shared layer, an
+    // item or else a pixel
+    fn erase_network_cell(&mut self, at: (i32, i32)) {
+        if self.screen.erase_item_at(0, at).is_empty() {
+            self.screen.erase_pixel_at(0, at);
+        }
+    }
+
+    // cells of the shared layer as they travel over the network, keyed by their
+    // layer relative position, only pixels are shared so text is left out
+    fn network_cells(&self) -> BTreeMap<(i32, i32), SerializableTermChar> {
+        let mut cells: BTreeMap<(i32, i32), SerializableTermChar> = BTreeMap::new();
+        for (offset, color) in self.screen.layers[0].pixels.iter() {
+            if let Color::AnsiValue(_) = color {
+                cells.insert(
+                    offset,
+                    SerializableTermChar {
+                        author: self.authors.get(&offset).cloned().unwrap_or_default(),
+                        ..SerializableTermChar::from_pixel(
+                            Pixel::new(color).to_item(offset),
+                            offset.0,
+                            offset.1,
+                        )
+                    },
+                );
+            }
+        }
+        cells
+    }
+
+    // what a sync sends, the shared pixels and the cells written but left
+    // empty since, which were erased
+    fn snapshot(&self, client: &Client) -> SerializebleSync {
+        let cells: BTreeMap<(i32, i32), SerializableTermChar> = self.network_cells();
+        let layer: &Layer = &self.screen.layers[0];
+        let erased: Vec<((i32, i32), Stamp)> = client
+            .written()
+            .into_iter()
+            .filter(|(cell, _)| {
+                let at: (i32, i32) = layer.absolute_position(*cell);
+                !cells.contains_key(cell)
+                    && layer.pixel_at(at).is_none()
+                    && layer.get_item_at_absolute(at).is_none()
+            })
+            .collect();
+        SerializebleSync {
+            items: cells.into_values().collect(),
+            erased,
+        }
+    }
+
+    // pixels go on the grid under the items as local strokes do, so a pixel
+    // never takes text away on one side only. Returns the write as its author
+    // has it in the history
+    fn add_network_pixel(&mut self, tc: SerializableTermChar) -> LocalOperation {
+        let offset: (i32, i32) = (tc.abs_x, tc.abs_y);
+        let color: Color = Color::AnsiValue(tc.background_color);
+        let replaced = self.screen.layers[0].paint(offset, color);
+        let authors: Vec<((i32, i32), String)> = self.authors_of(0, &replaced);
+        self.attribute(offset, tc.author);
+        LocalOperation::Paint {
+            layer: 0,
+            offset,
+            color,
+            replaced,
+            authors,
+        }
+    }
+
+    // text replaces the text at its cell, stacking would let the arrival order
+    // decide what peers see, pixels under it are left alone
+    // returns the cells of the text and of the one it replaced, to be redrawn
+    fn add_network_text(&mut self, text: SerializableText) -> Vec<(i32, i32)> {
+        let item: Item = text_item(&text);
+        let mut cells: Vec<(i32, i32)> = item.get_filled_indexes((0, 0));
+        for other in self.screen.layers[0].items.iter() {
+            if other.offset == item.offset {
+                cells.extend(other.get_filled_indexes((0, 0)));
+            }
+        }
+        self.screen.layers[0].retain_items(|other| other.offset != item.offset);
+        for cell in item.get_filled_indexes((0, 0)) {
+            self.attribute(cell, text.author.clone());
+        }
+        self.screen.layers[0].add_item(item);
+        cells
+    }
+
+    // only the cells remote updates touched are drawn again, a full redraw of
+    // the buffer on every update flickers with many collaborators
+    fn repaint(&mut self, repaint: Repaint) {
+        match repaint {
+            Repaint::Nothing => {}
+            Repaint::Everything => self.screen.refresh(),
+            Repaint::Cells(offsets) => {
+                // items of the shared layer are two columns wide, pixels and chars
+                for (x, y) in offsets {
+                    for dx in 0..2 {
+                        let at = self.screen.layers[0].absolute_position((x + dx, y));
+                        self.screen.redraw_canvas_cell(at);
                     }
-                    _ => {}
                 }
             }
         }
-        self._exit();
     }
 
-    fn _enter(&mut self) {
-        terminal::enable_raw_mode().unwrap();
-        self.screen.term.execute(event::EnableMouseCapture).unwrap();
-        self.screen.term.execute(cursor::Hide).unwrap();
-        self.clear_screen();
+    fn network_checksum(&self) -> (u64, usize) {
+        let cells = self.network_cells();
+        let mut hasher = DefaultHasher::new();
+        for ((x, y), tc) in cells.iter() {
+            (x, y, tc.background_color).hash(&mut hasher);
+        }
+        (hasher.finish(), cells.len())
     }
 
-    fn _exit(&mut self) {
-        self.screen
-            .term
-            .execute(MoveTo(0, self.screen.height))
-            .unwrap();
-        self.screen
-            .term
-            .execute(event::DisableMouseCapture)
-            .unwrap();
-        self.screen.term.execute(cursor::Show).unwrap();
-        terminal::disable_raw_mode().unwrap();
+    // switching to the current tool keeps the previous one untouched so
+    // the quick-switch always alternates between two different tools
+    fn set_tool(&mut self, tool: Tool) {
+        if self.tool != tool {
+            self.previous_tool = self.tool;
+            self.switch_to(tool);
+        }
     }
 
-    pub fn clear_screen(&mut self) {
-        self.screen
-            .term
-            .execute(terminal::Clear(terminal::ClearType::All))
-            .unwrap();
-        self.screen.term.flush().unwrap();
+    fn swap_tool(&mut self) {
+        let previous: Tool = self.previous_tool;
+        self.previous_tool = self.tool;
+        self.switch_to(previous);
     }
 
-    pub fn draw_ansi_colors(&mut self) {
-        self.config = Config::ColorSelection;
-        for c in 0..16 {
-            let color_pixel: Item = Item {
-                name: "color_selection_pixels".to_string(),
-                offset: (2 * c, self.screen.height as i32 - 1),
-                chars: Pixel {
-                    color: Color::AnsiValue(c as u8),
-                }
-                .to_chars(),
-            };
-            self.screen.layers[1].add_item(color_pixel.clone());
-            color_pixel.draw(
-                &mut self.screen.term,
-                (2 * c, self.screen.height as i32 - 1),
-                self.screen.width,
-                self.screen.height,
-            );
-        }
+    // settings of the tool in use are kept aside and the ones the next tool had
+    // are restored, tools never used before take over the current ones
+    fn switch_to(&mut self, tool: Tool) {
+        let current = ToolSettings {
+            color: self.color_selected,
+            smoothing: self.smoothing,
+        };
+        self.tool_settings.insert(self.tool, current);
+        let next = self.tool_settings.get(&tool).copied().unwrap_or(current);
+        self.color_selected = next.color;
+        self.smoothing = next.smoothing;
+        self.tool = tool;
     }
 
-    pub fn erase_ansi_colors(&mut self) {
-        self.config = Config::None;
-        self.screen.layers[1]
-            .items
-            .retain(|item| item.name != "color_selection_pixels");
-        for c in 0..32 {
-            EMPTY_TERM_CHAR.draw(
-                &mut self.screen.term,
-                (c, self.screen.height as i32 - 1),
-                self.screen.width,
-                self.screen.height,
-            );
+    pub fn cursor_term_char(&self) -> TermChar {
+        let mut term_char = self.tool_term_char();
+        // locked canvas is signaled by painting the tool indicator red
+        if self.screen.layers[self.active_layer].locked {
+            term_char.foreground_color = Color::Red;
         }
+        term_char
     }
 
-    pub fn cursor_term_char(&self) -> TermChar {
+    fn tool_term_char(&self) -> TermChar {
         match self.tool {
             Tool::Brush => {
                 let mut fg_color = self.color_selected;
@@ -359,7 +4091,7 @@ impl DrawTerm {
                     fg_color = Color::White
                 };
                 TermChar {
-                    character: 'B',
+                    character: self.brush_glyph.map_or('B', |glyph| glyph.character),
                     foreground_color: fg_color,
                     background_color: Color::Reset,
                     empty: false,
@@ -389,300 +4121,409 @@ impl DrawTerm {
                 background_color: Color::Reset,
                 empty: false,
             },
+            Tool::Shade => TermChar {
+                character: 'V',
+                foreground_color: Color::White,
+                background_color: Color::Reset,
+                empty: false,
+            },
+            Tool::Box => TermChar {
+                character: 'L',
+                foreground_color: Color::White,
+                background_color: Color::Reset,
+                empty: false,
+            },
+            Tool::Clone => TermChar {
+                character: 'C',
+                foreground_color: Color::White,
+                background_color: Color::Reset,
+                empty: false,
+            },
         }
     }
+    fn draw_cursor_info(&mut self, (col, row): (u16, u16)) {
+        self.screen.uncover(&self.cursor_info);
+        self.cursor_info.chars = self.create_cursor_info_chars(
+            self.screen
+                .relative_position(self.active_layer, (col as i32, row as i32)),
+        );
+        self.cursor_info.offset = (
+            self.screen.width as i32 - self.cursor_info.chars[0].len() as i32,
+            self.screen.height as i32 - 1,
+        );
+        self.cursor_info.redraw(
+            &mut self.screen.term,
+            (0, 0),
+            self.screen.width,
+            self.screen.height,
+        );
+    }
+
+    fn set_session_status(&mut self, status: SessionStatus) {
+        if self.session_status != Some(status) {
+            self.session_status = Some(status);
+            self.draw_cursor_info(self.last_cursor_position);
+        }
+    }
+
+    fn set_incompatible_peer(&mut self, message: &str) {
+        self.incompatible_peer = Some(message.to_string());
+        self.draw_cursor_info(self.last_cursor_position);
+    }
+
+    // failures while drawing are shown, the terminal belongs to the canvas
+    fn report(&mut self, error: Error) {
+        self.error = Some(error.to_string());
+        self.draw_cursor_info(self.last_cursor_position);
+    }
+
     pub fn create_cursor_info_chars(&self, (col, row): (i32, i32)) -> Vec<Vec<TermChar>> {
         // make col and row //2 values
         let col = col / 2;
-        let cursor_info_str: String = format!("{:04} {:04}", col, row);
+        let problem: Option<&String> = self.error.as_ref().or(self.incompatible_peer.as_ref());
+        let (status, color) = match (problem, self.session_status) {
+            (Some(problem), _) => (format!("{} ", problem), Color::Red),
+            (None, None) => (String::new(), Color::Reset),
+            (None, Some(SessionStatus::Connecting { attempt, of })) => {
+                (format!("connecting {}/{} ", attempt, of), Color::Reset)
+            }
+            (None, Some(SessionStatus::Reconnecting)) => ("reconnecting ".to_string(), Color::Red),
+            (None, Some(SessionStatus::Connected)) => ("connected ".to_string(), Color::Reset),
+            (None, Some(SessionStatus::Latency(latency))) => {
+                (format!("{}ms ", latency.as_millis()), Color::Green)
+            }
+        };
+        let layer: String = match self.screen.layers[self.active_layer].scratch {
+            true => "S".to_string(),
+            false => self.active_layer.to_string(),
+        };
+        let following: String = self
+            .following
+            .as_ref()
+            .map(|user| format!("following {} ", user))
+            .unwrap_or_default();
+        let keyboard: &str = if self.keyboard { "KB " } else { "" };
+        let text_mode: String = match (self.tool, self.text_mode) {
+            (Tool::Text, TextMode::Narrow) => "narrow ".to_string(),
+            (Tool::Text, TextMode::Banner(font)) => format!("banner {} ", self.fonts[font].name),
+            (Tool::Move, _) if self.move_items => "items ".to_string(),
+            (Tool::Clone, _) => match &self.clone_sample {
+                Some(sample) => format!("clone {}x{} ", sample.size.0 / 2, sample.size.1),
+                None => "sample ".to_string(),
+            },
+            _ => String::new(),
+        };
+        let snap: &str = if self.snap { "snap " } else { "" };
+        let frame: String = match self.screen.frame_count() {
+            1 => String::new(),
+            count => format!("F{}/{} ", self.screen.frame + 1, count),
+        };
+        let zoom: String = match self.screen.zoom {
+            0 => String::new(),
+            zoom => format!("Z{:+} ", zoom),
+        };
+        // where the view sits on the canvas, in pixels
+        let (view_x, view_y) = self.screen.viewport;
+        let cursor_info_str: String = format!(
+            "{}{}{}{}{}{}{}@{},{} L{} {:04} {:04}",
+            status,
+            following,
+            keyboard,
+            text_mode,
+            snap,
+            frame,
+            zoom,
+            view_x.div_euclid(2),
+            view_y,
+            layer,
+            col,
+            row
+        );
         let mut chars: Vec<TermChar> = Vec::new();
         for c in cursor_info_str.chars() {
             chars.push(TermChar {
                 character: c,
-                foreground_color: Color::Reset,
+                foreground_color: color,
                 background_color: Color::Reset,
                 empty: false,
             });
         }
         vec![chars]
     }
+
+    pub fn create_tool_bar_chars(&self) -> Vec<Vec<TermChar>> {
+        let mut chars: Vec<TermChar> = Vec::new();
+        for (i, tool) in TOOL_BAR.iter().enumerate() {
+            let (fg, bg) = match *tool == self.tool {
+                true => (Color::Black, Color::White),
+                false => (Color::Reset, Color::Reset),
+            };
+            for c in [char::from(b'1' + i as u8), tool.letter()] {
+                chars.push(TermChar {
+                    character: c,
+                    foreground_color: fg,
+                    background_color: bg,
+                    empty: false,
+                });
+            }
+        }
+        vec![chars]
+    }
+
+    // tool indicator on the top right corner and the numeric tool bar next to it
+    pub fn draw_tool_indicators(&mut self) {
+        if matches!(self.config, Config::Connection | Config::Help) {
+            return;
+        }
+        for item in [&self.cursor, &self.tool_bar] {
+            self.screen.uncover(item);
+        }
+        self.cursor.chars = vec![vec![self.cursor_term_char()]];
+        self.tool_bar.chars = self.create_tool_bar_chars();
+        for item in [&self.cursor, &self.tool_bar] {
+            item.redraw(
+                &mut self.screen.term,
+                (0, 0),
+                self.screen.width,
+                self.screen.height,
+            );
+        }
+        self.draw_toolbox();
+        self.draw_overlay();
+        self.draw_stats();
+    }
 }
 
 pub trait EventHandlers {
     // event handlers must return bool | null
-    fn on_key_event(&mut self, event: KeyEvent, client: &Option<Client>) -> bool;
+    fn on_key_event(&mut self, event: KeyEvent, client: &mut Option<Client>) -> bool;
     fn on_mouse_event(&mut self, event: MouseEvent, client: &mut Option<Client>) -> bool;
     fn on_resize_event(&mut self, width: u16, height: u16) -> bool;
     fn on_netowrk_update_events(
         &mut self,
         updates: &mut VecDeque<Vec<u8>>,
         client: &mut Option<Client>,
-    );
+    ) -> Repaint;
 }
 
 impl EventHandlers for DrawTerm {
-    fn on_key_event(&mut self, event: KeyEvent, client: &Option<Client>) -> bool {
-        if self.typing {
-            match event.code {
-                KeyCode::Char(c) => {
-                    let char: Item = Item {
-                        name: "char".to_string(),
-                        offset: self.screen.layers[0].relative_position(
-                            self.last_cursor_position.0,
-                            self.last_cursor_position.1,
-                        ),
-                        chars: vec![vec![
-                            TermChar {
-                                character: c,
-                                foreground_color: self.color_selected,
-                                background_color: Color::Reset,
-                                empty: false,
-                            },
-                            EMPTY_TERM_CHAR,
-                        ]],
-                    };
-                    self.screen.layers[0].add_item(char.clone());
-                    char.draw(
-                        &mut self.screen.term,
-                        (
-                            self.last_cursor_position.0 as i32,
-                            self.last_cursor_position.1 as i32,
-                        ),
-                        self.screen.width,
-                        self.screen.height,
-                    );
-                    self.last_cursor_position =
-                        (self.last_cursor_position.0 + 2, self.last_cursor_position.1);
-                    self.screen
-                        .term
-                        .execute(MoveTo(
-                            self.last_cursor_position.0,
-                            self.last_cursor_position.1,
-                        ))
-                        .unwrap();
-                }
-                KeyCode::Enter | KeyCode::Esc => {
-                    self.typing = false;
-                    self.tool = Tool::Brush;
-                    self.screen.term.execute(cursor::Hide).unwrap();
-                }
-                KeyCode::Backspace => {
-                    let item: Option<&Item> = self.screen.layers[0].get_item_at_absolute((
-                        (self.last_cursor_position.0 - 2) as i32,
-                        self.last_cursor_position.1 as i32,
-                    ));
-                    if let Some(item) = item {
-                        item.erase(
-                            &mut self.screen.term,
-                            self.screen.layers[0].offset,
-                            self.screen.width,
-                            self.screen.height,
-                        );
-                        let items: Vec<Item> = self.screen.layers[0].items.clone();
-                        self.screen.layers[0].items = items
-                            .into_iter()
-                            .filter(|i| i.offset != item.offset)
-                            .collect();
-                        self.last_cursor_position =
-                            (self.last_cursor_position.0 - 2, self.last_cursor_position.1);
-                        self.screen
-                            .term
-                            .execute(MoveTo(
-                                self.last_cursor_position.0,
-                                self.last_cursor_position.1,
-                            ))
-                            .unwrap();
-                    }
-                }
-                _ => {}
+    fn on_key_event(&mut self, event: KeyEvent, client: &mut Option<Client>) -> bool {
+        if self.error.take().is_some() {
+            self.draw_cursor_info(self.last_cursor_position);
+        }
+        if self.overlay && event.kind == KeyEventKind::Press {
+            self.pressed_keys.push_back(key_label(&event));
+            if self.pressed_keys.len() > OVERLAY_KEYS {
+                self.pressed_keys.pop_front();
             }
+        }
+        if self.text_edit.is_some() {
+            self.on_text_key(&event, client);
+            self.draw_overlay();
             return false;
         }
-        match event.kind {
+        let exit = match event.kind {
+            KeyEventKind::Press if self.deleting_frame => {
+                self.answer_frame_delete(event.code, client);
+                false
+            }
+            KeyEventKind::Press if !self.quit_prompt.chars[0].is_empty() => {
+                self.answer_quit(event.code)
+            }
+            KeyEventKind::Press if self.command_line.is_some() => {
+                self.on_command_line_key(&event, client);
+                false
+            }
+            KeyEventKind::Press if self.config == Config::Help => {
+                self.toggle_help();
+                false
+            }
+            KeyEventKind::Press if self.config == Config::Connection && client.is_none() => {
+                self.on_connect_form_key(&event, client);
+                false
+            }
+            KeyEventKind::Press if self.keyboard && self.on_keyboard_drawing(&event, client) => {
+                false
+            }
             KeyEventKind::Press => match event.code {
-                KeyCode::Char(c) => match c {
-                    'q' => true,
-                    'e' => {
-                        self.tool = Tool::Erase;
-                        false
-                    }
-                    'b' => {
-                        self.tool = Tool::Brush;
-                        false
-                    }
-                    'i' => {
-                        self.tool = Tool::Ink;
-                        false
-                    }
-                    'c' => {
-                        match self.config {
-                            Config::ColorSelection => {
-                                self.erase_ansi_colors();
-                                return false;
-                            }
-                            Config::Connection => {
-                                return false;
-                            }
-                            _ => {}
-                        }
-                        if self.tool == Tool::Erase {
-                            self.tool = Tool::Brush
-                        };
-                        self.draw_ansi_colors();
-                        false
-                    }
-                    'm' => {
-                        self.tool = Tool::Move;
-                        false
-                    }
-                    'a' => {
-                        self.tool = Tool::Text;
-                        false
-                    }
-                    'x' => {
-                        match self.config {
-                            Config::Connection => {
-                                self.config = Config::None;
-                                self.clear_screen();
-                                self.screen.term.execute(event::EnableMouseCapture).unwrap();
-                                self.screen.layers[0].draw_buffer(
-                                    &mut self.screen.term,
-                                    self.screen.width,
-                                    self.screen.height,
-                                );
-                            }
-                            _ => {
-                                self.config = Config::Connection;
-                                self.clear_screen();
-                                self.screen.term.execute(MoveTo(0, 0)).unwrap();
-                                match client {
-                                    Some(ref client) => {
-                                        println!("{}", client._addr);
-                                    }
-                                    None => {
-                                        println!(
-                                            "No server available. Rerun with host port options"
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                        false
-                    }
-                    _ => false,
+                // archives are picked by their position in the list
+                KeyCode::Char(c @ '1'..='9') if self.config == Config::Connection => {
+                    self.download_archive(c as usize - '1' as usize, client);
+                    false
+                }
+                _ => match self.keymap.action(&event, self.key_mode()) {
+                    Some(action) => self.perform(action, client),
+                    None => false,
                 },
-                _ => false,
             },
             _ => false,
-        }
+        };
+        self.draw_tool_indicators();
+        exit
     }
 
-    fn on_mouse_event(&mut self, event: MouseEvent, mut client: &mut Option<Client>) -> bool {
-        // dont use mouse events when creating connections or monitoring them
-        if self.config == Config::Connection {
+    fn on_mouse_event(&mut self, event: MouseEvent, client: &mut Option<Client>) -> bool {
+        // dont use mouse events when creating connections or monitoring them,
+        // nor while the key bindings are listed
+        if matches!(self.config, Config::Connection | Config::Help) {
             return false;
         };
 
-        let (col, row) = (event.column & !(event.column % 2), event.row);
-        self.screen.term.execute(MoveTo(col, row)).unwrap();
+        // the fitted view is for showing the piece, cells do not map to the canvas,
+        // and the attribution view is for reviewing it
+        if self.screen.fit || self.attribution {
+            return false;
+        }
+
+        // clicking anywhere ends the text being typed, with the text tool it
+        // starts another one where clicked
+        if self.text_edit.is_some() && event.kind == MouseEventKind::Down(MouseButton::Left) {
+            self.commit_text(client);
+        }
+
+        let (col, row) = self.smooth_position(event.kind, (event.column, event.row));
+        // narrow text and box drawing lines take any column
+        let text_col: u16 = col;
+        let (col, row) = (col & !(col % 2), row);
+        self.screen.term.move_to(col, row);
 
         if self.resized {
             self.resized = false;
-            self.screen.layers[0].redraw(
-                &mut self.screen.term,
-                self.screen.width,
-                self.screen.height,
-            );
-            self.screen.layers[1].redraw(
-                &mut self.screen.term,
-                self.screen.width,
-                self.screen.height,
-            );
+            self.screen.redraw();
         }
 
+        // ui items are never rotated, they are hit in view coordinates
+        let ui = self.ui_layer();
         let item_on_foreground =
-            self.screen.layers[1].get_item_at_absolute((col as i32, row as i32));
+            self.screen.layers[ui].get_item_at_absolute((col as i32, row as i32));
+        let pressed = matches!(
+            event.kind,
+            MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left)
+        );
+        if let (Some(item_on_fg), true) = (item_on_foreground, pressed) {
+            let clicked_tool: Option<Tool> = match event.kind {
+                MouseEventKind::Down(_) => item_on_fg
+                    .name
+                    .strip_prefix("toolbox_")
+                    .and_then(Tool::parse),
+                _ => None,
+            };
+            if let Some(tool) = clicked_tool {
+                self.set_tool(tool);
+                self.draw_tool_indicators();
+            } else if let Some(index) = item_on_fg
+                .name
+                .strip_prefix("sprite_picker_")
+                .and_then(|index| index.parse::<usize>().ok())
+            {
+                self.pick_sprite(index);
+            } else if item_on_fg.name == "color_selection_pixels" {
+                // given that items are represented by 2D matrix of TermChar
+                // the only way to get the color is by checking the first element
+                // grabbing it and ressetting the color menu
+                self.color_selected = item_on_fg.chars[0][0].background_color;
+                self.erase_ansi_colors();
+            }
+            return false;
+        }
+
+        // tools work on the canvas cell under the pointer, wherever the view
+        // rotation brought it from, on the first cell of a zoomed in pixel
+        let (col, row) = self.screen.unrotate_cell((col as i32, row as i32));
+        let (width, height) = self.screen.pixel_size();
+        let (col, row) = (col - col.rem_euclid(width), row - row.rem_euclid(height));
+        let (Ok(col), Ok(row)) = (u16::try_from(col), u16::try_from(row)) else {
+            return false;
+        };
+        self.draw_hover_preview(event.kind, (col, row));
 
         match event.kind {
             event::MouseEventKind::Down(MouseButton::Left)
             | MouseEventKind::Drag(event::MouseButton::Left) => {
-                if item_on_foreground.is_some() {
-                    let item_on_fg = item_on_foreground.unwrap();
-                    if item_on_fg.name == "color_selection_pixels" {
-                        // given that items are represented by 2D matrix of TermChar
-                        // the only way to get the color is by checking the first element
-                        // grabbing it and ressetting the color menu
-                        self.color_selected = item_on_fg.chars[0][0].background_color;
-                        self.erase_ansi_colors();
-                    }
-                    return false;
-                };
+                // locked canvas layer ignores every tool that would modify it
+                let locked = self.screen.layers[self.active_layer].locked
+                    && matches!(
+                        self.tool,
+                        Tool::Brush
+                            | Tool::Erase
+                            | Tool::Move
+                            | Tool::Shade
+                            | Tool::Box
+                            | Tool::Clone
+                    );
+
+                let starts_stroke = matches!(event.kind, MouseEventKind::Down(_))
+                    && matches!(
+                        self.tool,
+                        Tool::Brush | Tool::Erase | Tool::Shade | Tool::Box
+                    );
+                if starts_stroke && !locked {
+                    self.strokes += 1;
+                }
 
                 match self.tool {
-                    Tool::Brush => {
-                        // the x,y are absolute, because there is no compounding of
-                        // layers one on top of the other. Just (screen(bg_layer(item)))
-                        let (abs_x, abs_y) = self.screen.layers[0].relative_position(col, row);
-                        let pixel: Item = Item {
-                            name: "P".to_string(),
-                            offset: (abs_x, abs_y),
-                            chars: Pixel {
-                                color: self.color_selected,
-                            }
-                            .to_chars(),
+                    _ if locked => {}
+                    // zoomed out views are for finding the way around, a cell
+                    // holds more than a pixel
+                    _ if self.screen.zoom < 0 => {}
+                    Tool::Brush | Tool::Erase => {
+                        // fast strokes skip cells, fill the gap from the previous position
+                        let from = match event.kind {
+                            MouseEventKind::Drag(_) => self.last_cursor_position,
+                            _ => (col, row),
                         };
-
-                        self.screen.layers[0].add_item(pixel.clone());
-
-                        if let Some(client) = &mut client {
-                            client.publish(Update::TermChar(SerializableTermChar::from_pixel(
-                                pixel.clone(),
-                                abs_x,
-                                abs_y,
-                            )));
-                        }
-
-                        pixel.draw(
-                            &mut self.screen.term,
-                            (col as i32, row as i32),
-                            self.screen.width,
-                            self.screen.height,
-                        );
-                    }
-                    Tool::Erase => {
-                        let item: Option<&Item> =
-                            self.screen.layers[0].get_item_at_absolute((col as i32, row as i32));
-                        if let Some(item) = item {
-                            item.erase(
-                                &mut self.screen.term,
-                                self.screen.layers[0].offset,
-                                self.screen.width,
-                                self.screen.height,
-                            );
-                            let items: Vec<Item> = self.screen.layers[0].items.clone();
-
-                            if let Some(client) = &mut client {
-                                client.publish(Update::Erase(SerializableErase {
-                                    abs_x: item.offset.0,
-                                    abs_y: item.offset.1,
-                                }));
+                        for cell in line_cells(from, (col, row)) {
+                            match self.tool {
+                                Tool::Brush => self.paint_at(cell, client),
+                                _ => self.erase_at(cell, client),
                             }
-
-                            self.screen.layers[0].items = items
-                                .into_iter()
-                                .filter(|i| i.offset != item.offset)
-                                .collect();
                         }
                     }
                     Tool::Ink => {
-                        let item: Option<&Item> =
-                            self.screen.layers[0].get_item_at_absolute((col as i32, row as i32));
-                        match item {
-                            Some(item) => {
-                                self.color_selected = item.chars[0][0].background_color;
-                                self.tool = Tool::Brush;
+                        let picked: Option<TermChar> =
+                            self.screen.layers[self.active_layer].char_at((col as i32, row as i32));
+                        match picked {
+                            // a character is picked up whole, the brush paints it
+                            Some(term_char) if term_char.character != ' ' => {
+                                self.set_tool(Tool::Brush);
+                                self.brush_glyph = Some(term_char);
+                                self.brush_footprint = None;
+                                self.stamp_index = None;
+                                self.color_selected = term_char.foreground_color;
                             }
-                            None => self.tool = Tool::Erase,
+                            Some(term_char) => {
+                                // the picked color belongs to the brush
+                                let color: Color = term_char.background_color;
+                                self.set_tool(Tool::Brush);
+                                self.brush_glyph = None;
+                                self.color_selected = color;
+                            }
+                            None => self.set_tool(Tool::Erase),
+                        }
+                    }
+                    // a drag would shade the already shaded edge over and over
+                    Tool::Shade if matches!(event.kind, MouseEventKind::Down(_)) => {
+                        self.shade_at((col, row), client);
+                    }
+                    Tool::Shade => {}
+                    // without a sample the drag picks the region, on release
+                    Tool::Clone => {
+                        let cell: (i32, i32) = self
+                            .screen
+                            .relative_position(self.active_layer, (col as i32, row as i32));
+                        match (event.kind, &self.clone_sample) {
+                            (MouseEventKind::Down(_), Some(_)) => self.stamp_clone(cell, client),
+                            (MouseEventKind::Down(_), None) => self.clone_from = Some(cell),
+                            _ => {}
+                        }
+                    }
+                    Tool::Move if self.move_items => {
+                        let cell: (i32, i32) = self
+                            .screen
+                            .relative_position(self.active_layer, (col as i32, row as i32));
+                        match event.kind {
+                            MouseEventKind::Down(_) => self.grab_item(cell),
+                            _ => self.drag_item(cell),
                         }
                     }
                     Tool::Move => {
@@ -690,128 +4531,336 @@ impl EventHandlers for DrawTerm {
                             (col as i32 - self.last_cursor_position.0 as i32),
                             row as i32 - self.last_cursor_position.1 as i32,
                         );
-                        self.screen.layers[0].move_layer(distance_to_move);
-                        self.screen.layers[0].draw_buffer(
-                            &mut self.screen.term,
-                            self.screen.width,
-                            self.screen.height,
-                        );
+                        self.screen.layers[self.active_layer].move_layer(distance_to_move);
+                        // panning by hand leaves the followed view
+                        if self.active_layer == 0 {
+                            self.following = None;
+                        }
+                        self.screen.redraw();
+                    }
+                    Tool::Box => {
+                        let cell: (i32, i32) = self
+                            .screen
+                            .relative_position(self.active_layer, (text_col as i32, row as i32));
+                        match event.kind {
+                            MouseEventKind::Down(_) => self.box_from = Some(cell),
+                            _ => self.draw_box_line(cell, client),
+                        }
                     }
                     Tool::Text => {
-                        if !self.typing {
-                            self.typing = true;
-                            self.last_cursor_position = (col, row);
-                            self.screen.term.execute(cursor::Show).unwrap();
-                            self.screen.term.execute(MoveTo(col, row)).unwrap();
+                        if self.text_edit.is_none() {
+                            let col: u16 = match self.text_mode {
+                                TextMode::Narrow => text_col,
+                                _ => col,
+                            };
+                            self.start_text_edit((col, row));
                         }
                     }
                 }
             }
+            MouseEventKind::Up(MouseButton::Left) if self.moving_item.is_some() => {
+                self.drop_item(client);
+            }
+            MouseEventKind::Up(MouseButton::Left) if self.clone_from.is_some() => {
+                let cell: (i32, i32) = self
+                    .screen
+                    .relative_position(self.active_layer, (col as i32, row as i32));
+                self.sample_clone(cell);
+            }
+            // the canvas follows the pointer, whatever the tool
+            MouseEventKind::Drag(MouseButton::Middle) => {
+                let (last_col, last_row) = self.last_cursor_position;
+                let (from_x, from_y) = self.screen.to_canvas((last_col as i32, last_row as i32));
+                let (to_x, to_y) = self.screen.to_canvas((col as i32, row as i32));
+                self.pan((from_x - to_x, from_y - to_y));
+            }
             _ => {}
         }
 
-        self.cursor.erase(
-            &mut self.screen.term,
-            (0, 0),
-            self.screen.width,
-            self.screen.height,
-        );
-        self.cursor.chars = vec![vec![self.cursor_term_char()]];
-        self.cursor.redraw(
-            &mut self.screen.term,
-            (0, 0),
-            self.screen.width,
-            self.screen.height,
-        );
-
-        self.cursor_info.erase(
-            &mut self.screen.term,
-            (0, 0),
-            self.screen.width,
-            self.screen.height,
-        );
-        self.cursor_info.chars = self.create_cursor_info_chars((
-            col as i32 - self.screen.layers[0].offset.0,
-            row as i32 - self.screen.layers[0].offset.1,
-        ));
-        self.cursor_info.redraw(
-            &mut self.screen.term,
-            (0, 0),
-            self.screen.width,
-            self.screen.height,
-        );
+        self.draw_tool_indicators();
+        self.draw_cursor_info((col, row));
 
-        if !self.typing {
+        if self.text_edit.is_none() {
             self.last_cursor_position = (col, row);
         }
         false
     }
     fn on_resize_event(&mut self, width: u16, height: u16) -> bool {
+        self.screen.resize(width, height);
         self.clear_screen();
 
-        self.screen.width = width;
-        self.screen.height = height;
         self.cursor_info.offset = (width as i32 - 9, height as i32 - 1);
         self.cursor.offset = (width as i32 - 1, 0);
+        self.tool_bar.offset = (width as i32 - 2 - 2 * TOOL_BAR.len() as i32, 0);
         self.resized = true;
+        // laid out again for the new size
+        if self.config == Config::Help {
+            self.config = Config::None;
+            self.toggle_help();
+        }
+        // no mouse event is coming to trigger the redraw in the fitted view
+        if self.screen.fit {
+            self.screen.redraw();
+            self.draw_tool_indicators();
+        }
 
         false
     }
 
+    // true when an update touched the canvas, heartbeats alone don't
     fn on_netowrk_update_events(
         &mut self,
         updates: &mut VecDeque<Vec<u8>>,
-        _client: &mut Option<Client>,
-    ) {
-        while !updates.is_empty() {
-            let update_serialized_bytes = updates.pop_front().unwrap();
-            let update_serialized: String = String::from_utf8(update_serialized_bytes).unwrap();
-
-            let update: Update = match from_str(&update_serialized) {
-                Ok(u) => u,
-                Err(e) => {
-                    println!("Failed to deserialize update: {}", e);
-                    continue;
-                }
-            };
+        client: &mut Option<Client>,
+    ) -> Repaint {
+        // reads do not respect message boundaries, updates are parsed as a stream
+        // and a trailing incomplete message is kept for the next round
+        let mut bytes: Vec<u8> = updates.drain(..).flatten().collect();
+        let (parsed, consumed, errors) = decode_updates(&bytes);
+        if consumed < bytes.len() {
+            updates.push_back(bytes.split_off(consumed));
+        }
+        // unreadable updates come from peers speaking another protocol, they
+        // are logged and reported next to the cursor info, never printed over
+        // the canvas
+        if let (Some(client), false) = (client.as_ref(), errors.is_empty()) {
+            for error in errors.iter() {
+                client.log_divergence(&format!("unreadable update: {}", error));
+            }
+            if self.incompatible_peer.is_none() {
+                self.set_incompatible_peer("unreadable updates, a peer may need to upgrade");
+            }
+        }
 
+        // offsets of the shared layer items written, erased cells are redrawn as
+        // they go and moves or new regions shift the whole view
+        let mut written: Vec<(i32, i32)> = Vec::new();
+        let mut erased: bool = false;
+        let mut everything: bool = false;
+        for update in parsed {
             match update {
+                Update::Ping(ping) => {
+                    if let Some(client) = client.as_mut() {
+                        client.publish(Update::Pong(ping));
+                    }
+                }
+                Update::Pong(pong) => {
+                    if let Some(client) = client.as_mut() {
+                        client.on_pong(pong);
+                    }
+                }
+                Update::ArchiveRequest(request) => self.answer_archive_request(request, client),
+                Update::ArchiveList(list) => {
+                    if self.config == Config::Connection {
+                        self.archives = list.pieces;
+                        self.draw_archives();
+                    }
+                }
+                Update::ArchiveFile(file) => self.receive_archive_file(file),
+                Update::Regions(regions) => {
+                    self.regions = regions;
+                    self.refresh_guides();
+                    everything = true;
+                }
+                // the server answers them
+                Update::HistoryRequest(_) => {}
+                Update::History(history) => {
+                    if self.pending_replay == Some(history.nonce) {
+                        self.pending_replay = None;
+                        self.replay_history(history.writes, client);
+                        everything = true;
+                    }
+                }
+                Update::Viewport(viewport) => {
+                    let offset: (i32, i32) = (viewport.x, viewport.y);
+                    if self.following.as_ref() == Some(&viewport.user) {
+                        self.show_shared_layer_at(offset);
+                        everything = true;
+                    }
+                    self.viewports.insert(viewport.user, offset);
+                }
+                // writes inside regions their author may not draw in are dropped
                 Update::TermChar(tc) => {
-                    let pixel_char = TermChar {
-                        character: tc.character,
-                        foreground_color: Color::AnsiValue(tc.foreground_color),
-                        background_color: Color::AnsiValue(tc.background_color),
-                        empty: tc.empty,
-                    };
-
-                    let item: Item = Item {
-                        name: "pixel".to_string(),
-                        offset: (tc.abs_x, tc.abs_y),
-                        chars: vec![vec![pixel_char, pixel_char]],
-                    };
-
-                    self.screen.layers[0].add_item(item.clone());
+                    let cell: (i32, i32) = (tc.abs_x, tc.abs_y);
+                    if self.regions.may_draw(&tc.author, cell) && accepted(client, cell, tc.stamp) {
+                        self.add_network_pixel(tc);
+                        written.push(cell);
+                    }
+                }
+                Update::Stroke(stroke) => {
+                    for tc in stroke.cells {
+                        let cell: (i32, i32) = (tc.abs_x, tc.abs_y);
+                        if self.regions.may_draw(&tc.author, cell)
+                            && accepted(client, cell, tc.stamp)
+                        {
+                            self.add_network_pixel(tc);
+                            written.push(cell);
+                        }
+                    }
                 }
                 Update::Erase(erase) => {
-                    let (offx, offy) = self.screen.layers[0].offset;
-                    let item: Option<&Item> = self.screen.layers[0]
-                        .get_item_at_absolute((erase.abs_x + offx, erase.abs_y + offy));
-                    if let Some(item) = item {
-                        item.erase(
-                            &mut self.screen.term,
-                            self.screen.layers[0].offset,
-                            self.screen.width,
-                            self.screen.height,
-                        );
-                        let items: Vec<Item> = self.screen.layers[0].items.clone();
-                        self.screen.layers[0].items = items
-                            .into_iter()
-                            .filter(|i| i.offset != item.offset)
-                            .collect();
+                    let cell: (i32, i32) = (erase.abs_x, erase.abs_y);
+                    if self.regions.may_draw(&erase.author, cell)
+                        && accepted(client, cell, erase.stamp)
+                    {
+                        let at = self.screen.layers[0].absolute_position(cell);
+                        self.erase_network_cell(at);
+                        erased = true;
+                    }
+                }
+                Update::Text(text) => {
+                    let cell: (i32, i32) = (text.abs_x, text.abs_y);
+                    if !self.regions.may_draw(&text.author, cell)
+                        || !accepted(client, cell, text.stamp)
+                    {
+                        continue;
+                    }
+                    written.extend(self.add_network_text(text));
+                }
+                // older peers still send their view moves, the view is their own
+                Update::Move(_) => {}
+                Update::Checksum(remote) => {
+                    let (hash, cells) = self.network_checksum();
+                    if let (Some(client), true) = (client.as_mut(), hash != remote.hash) {
+                        client.log_divergence(&format!(
+                            "checksum mismatch: local {:016x} ({} cells), remote {:016x} ({} cells)",
+                            hash, cells, remote.hash, remote.cells
+                        ));
+                        let sync: SerializebleSync = self.snapshot(client);
+                        client.publish(Update::Sync(sync));
+                    }
+                }
+                Update::Hello(hello) => {
+                    // a first hello means a peer just joined, the peer elected
+                    // sends it the whole canvas so it does not start from an
+                    // empty screen, ids of peers gone keep the others quiet
+                    // until the checksums tell the canvases apart
+                    let own: Option<u64> = client.as_ref().and_then(Client::participant);
+                    let elected: bool = match own {
+                        Some(own) => self
+                            .room_peers
+                            .iter()
+                            .all(|&id| id > own || Some(id) == hello.participant),
+                        None => true,
+                    };
+                    if let Some(id) = hello.participant {
+                        self.room_peers.insert(id);
+                    }
+                    let snapshot: bool = !hello.reply && elected;
+                    if let Some(mismatch) = hello.mismatch() {
+                        self.set_incompatible_peer(&mismatch);
+                    }
+                    // whoever joins learns the view of this session as well
+                    if !hello.reply {
+                        self.published_viewport = None;
+                    }
+                    if let Some(client) = client.as_mut() {
+                        if !hello.reply {
+                            let context = [("ADDR", client._addr.as_str()), ("ROOM", &client.room)];
+                            self.hooks.run(HookEvent::ParticipantJoined, &context);
+                        }
+                        let joined: bool = !hello.reply;
+                        client.on_hello(hello);
+                        if snapshot {
+                            let sync: SerializebleSync = self.snapshot(client);
+                            if !sync.items.is_empty() || !sync.erased.is_empty() {
+                                client.publish(Update::Sync(sync));
+                            }
+                        }
+                        // the host hands the regions and the lock to whoever joins
+                        let hosting: bool = self.regions.host == self.user;
+                        let ruled: bool = !self.regions.regions.is_empty() || self.regions.locked;
+                        if joined && hosting && ruled {
+                            client.publish(Update::Regions(self.regions.clone()));
+                        }
+                        if joined && self.config == Config::Connection {
+                            self.draw_host_controls(client.participant(), "");
+                        }
+                    }
+                }
+                Update::Sync(sync) => {
+                    // incremental, cells written later than the local ones win,
+                    // unstamped cells of older peers are only taken when missing
+                    // and erases newer than the local write drop the cell, cells
+                    // the sender never heard of are left alone
+                    let local = self.network_cells();
+                    let mut added: usize = 0;
+                    for tc in sync.items {
+                        let cell: (i32, i32) = (tc.abs_x, tc.abs_y);
+                        let take: bool = match tc.stamp.is_unset() {
+                            true => !local.contains_key(&cell),
+                            false => accepted(client, cell, tc.stamp),
+                        };
+                        if take {
+                            self.add_network_pixel(tc);
+                            written.push(cell);
+                            added += 1;
+                        }
+                    }
+                    let mut dropped: usize = 0;
+                    for (cell, stamp) in sync.erased {
+                        if !stamp.is_unset() && accepted(client, cell, stamp) {
+                            let at: (i32, i32) = self.screen.layers[0].absolute_position(cell);
+                            self.erase_network_cell(at);
+                            dropped += 1;
+                        }
+                    }
+                    erased |= dropped > 0;
+                    if let (Some(client), true) = (client.as_ref(), added + dropped > 0) {
+                        client.log_divergence(&format!(
+                            "resync took {} newer cells and dropped {} stale ones",
+                            added, dropped
+                        ));
                     }
                 }
-                _ => (),
             }
         }
+        if self.attribution {
+            self.refresh_attribution();
+        }
+        // tints and the fitted view do not map items to single cells
+        match (
+            everything || erased || !written.is_empty(),
+            everything || self.attribution || self.screen.fit,
+        ) {
+            (false, _) => Repaint::Nothing,
+            (true, true) => Repaint::Everything,
+            (true, false) => Repaint::Cells(written),
+        }
+    }
+}
+
+// remote writes only apply when they win the cell register, there is nothing
+// to arbitrate without a session
+fn accepted(client: &mut Option<Client>, cell: (i32, i32), stamp: Stamp) -> bool {
+    client
+        .as_mut()
+        .is_none_or(|client| client.accepts(cell, stamp))
+}
+
+// cells crossed by the segment between two pointer positions, pixels are two
+// columns wide so the walk happens on the halved column grid
+fn line_cells(from: (u16, u16), to: (u16, u16)) -> Vec<(u16, u16)> {
+    let (x0, y0) = (from.0 as i32 / 2, from.1 as i32);
+    let (x1, y1) = (to.0 as i32 / 2, to.1 as i32);
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+    let mut cells: Vec<(u16, u16)> = Vec::new();
+    loop {
+        cells.push(((2 * x) as u16, y as u16));
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
     }
+    cells
 }
@@ -0,0 +1,55 @@
+use std::fmt;
+use std::io;
+
+// whatever goes wrong past the command line, reported to the user instead of
+// panicking
+#[derive(Debug)]
+pub enum Error {
+    // the terminal, a file or a socket failed
+    Io(io::Error),
+    // no connection attempt to the server went through
+    Unreachable { addr: String, attempts: u32 },
+    // what was being done when the error happened, e.g. opening a project
+    Context(String, Box<Error>),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Unreachable { addr, attempts } => {
+                write!(f, "could not reach {} after {} attempts", addr, attempts)
+            }
+            Error::Context(what, e) => write!(f, "{}: {}", what, e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Unreachable { .. } => None,
+            Error::Context(_, e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+// names what failed, e.g. `Project::load(path).context(path)?`
+pub trait Context<T> {
+    fn context(self, what: &str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context(self, what: &str) -> Result<T> {
+        self.map_err(|e| Error::Context(what.to_string(), Box::new(e.into())))
+    }
+}
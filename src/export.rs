@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crossterm::style::Color;
+
+use crate::constants::{DEFAULT_PNG_SCALE, EXPORT_BAND_ROWS};
+use crate::history::{History, LocalOperation};
+use crate::palette::{color_to_rgb, nearest_system};
+use crate::project::Project;
+use crate::screen::{composite_layers, layers_bounds, rows_to_ansi, Item, Layer, Pixel, TermChar};
+
+// pixels are blank chars painted through their background, plain text has no
+// colors so they are rendered as full blocks instead
+const TEXT_PIXEL: char = '█';
+// operations read from the history at once while counting edits
+const HEATMAP_PAGE_SIZE: usize = 1024;
+
+// an output format for the canvas, built-in formats and the ones registered
+// from outside the core share the export command list
+pub trait Exporter {
+    fn name(&self) -> &str;
+    fn extension(&self) -> &str;
+    fn export(&self, layers: &[Layer], path: &str) -> io::Result<()>;
+}
+
+struct FnExporter {
+    name: &'static str,
+    extension: &'static str,
+    export: fn(&[Layer], &str) -> io::Result<()>,
+}
+
+struct PngExporter {
+    cell_aspect: (usize, usize),
+}
+
+impl Exporter for PngExporter {
+    fn name(&self) -> &str {
+        "png"
+    }
+
+    fn extension(&self) -> &str {
+        "png"
+    }
+
+    fn export(&self, layers: &[Layer], path: &str) -> io::Result<()> {
+        export_png(layers, path, DEFAULT_PNG_SCALE, self.cell_aspect)
+    }
+}
+
+struct SvgExporter {
+    cell_aspect: (usize, usize),
+}
+
+impl Exporter for SvgExporter {
+    fn name(&self) -> &str {
+        "svg"
+    }
+
+    fn extension(&self) -> &str {
+        "svg"
+    }
+
+    fn export(&self, layers: &[Layer], path: &str) -> io::Result<()> {
+        fs::write(path, to_svg(layers, DEFAULT_PNG_SCALE, self.cell_aspect))
+    }
+}
+
+impl Exporter for FnExporter {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn extension(&self) -> &str {
+        self.extension
+    }
+
+    fn export(&self, layers: &[Layer], path: &str) -> io::Result<()> {
+        (self.export)(layers, path)
+    }
+}
+
+pub struct Exporters {
+    exporters: Vec<Box<dyn Exporter>>,
+}
+
+impl Exporters {
+    pub fn new(cell_aspect: (usize, usize)) -> Self {
+        let builtin: [FnExporter; 4] = [
+            FnExporter {
+                name: "ansi",
+                extension: "ans",
+                export: export_ansi,
+            },
+            FnExporter {
+                name: "ansi-safe",
+                extension: "ans",
+                export: export_safe_ansi,
+            },
+            FnExporter {
+                name: "text",
+                extension: "txt",
+                export: export_text,
+            },
+            FnExporter {
+                name: "html",
+                extension: "html",
+                export: export_html,
+            },
+        ];
+        let mut exporters = Exporters {
+            exporters: Vec::new(),
+        };
+        for exporter in builtin {
+            exporters.register(Box::new(exporter));
+        }
+        exporters.register(Box::new(PngExporter { cell_aspect }));
+        exporters.register(Box::new(SvgExporter { cell_aspect }));
+        exporters
+    }
+
+    // a later registration with the same name replaces the earlier one
+    pub fn register(&mut self, exporter: Box<dyn Exporter>) {
+        self.exporters
+            .retain(|registered| registered.name() != exporter.name());
+        self.exporters.push(exporter);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Exporter> {
+        self.exporters
+            .iter()
+            .find(|exporter| exporter.name() == name)
+            .map(|exporter| exporter.as_ref())
+    }
+
+    // the first exporter writing files with extension, e.g. ansi for ans
+    pub fn for_extension(&self, extension: &str) -> Option<&dyn Exporter> {
+        self.exporters
+            .iter()
+            .find(|exporter| exporter.extension() == extension)
+            .map(|exporter| exporter.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.exporters
+            .iter()
+            .map(|exporter| exporter.name())
+            .collect()
+    }
+
+    pub fn list(&self) -> String {
+        self.exporters
+            .iter()
+            .map(|exporter| format!("{:<10} .{}\n", exporter.name(), exporter.extension()))
+            .collect()
+    }
+}
+
+// the canvas cropped to the bounding box of everything drawn on it
+pub fn canvas_rows(layers: &[Layer]) -> Vec<Vec<TermChar>> {
+    match layers_bounds(layers) {
+        Some(((min_x, min_y), (max_x, max_y))) => composite_layers(
+            layers,
+            (min_x, min_y),
+            (max_x - min_x + 1) as usize,
+            (max_y - min_y + 1) as usize,
+        ),
+        None => Vec::new(),
+    }
+}
+
+// standalone ANSI escape file, CRLF line endings as classic .ans files expect
+pub fn to_ansi(layers: &[Layer]) -> String {
+    rows_to_ansi(&canvas_rows(layers), "\r\n")
+}
+
+pub fn to_text(layers: &[Layer]) -> String {
+    let mut text: String = String::new();
+    for row in canvas_rows(layers).iter() {
+        let line: String = row
+            .iter()
+            .map(
+                |term_char| match (term_char.character, term_char.background_color) {
+                    (' ', Color::Reset) => ' ',
+                    (' ', _) if !term_char.empty => TEXT_PIXEL,
+                    (c, _) => c,
+                },
+            )
+            .collect();
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+fn css_color(color: Color) -> Option<String> {
+    color_to_rgb(color).map(|(r, g, b)| format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+fn html_escape(c: char) -> String {
+    match c {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        c => c.to_string(),
+    }
+}
+
+// a single <pre> block meant to be embedded, runs of chars sharing colors are
+// grouped in one span and default colors are left to the surrounding page
+pub fn to_html(layers: &[Layer]) -> String {
+    let mut html: String = String::from("<pre style=\"font-family: monospace; line-height: 1;\">");
+    for row in canvas_rows(layers).iter() {
+        let mut i: usize = 0;
+        while i < row.len() {
+            let colors = (row[i].foreground_color, row[i].background_color);
+            let mut run: String = String::new();
+            while i < row.len() && (row[i].foreground_color, row[i].background_color) == colors {
+                run.push_str(&html_escape(row[i].character));
+                i += 1;
+            }
+            let mut style: Vec<String> = Vec::new();
+            if let Some(fg) = css_color(colors.0) {
+                style.push(format!("color: {}", fg));
+            }
+            if let Some(bg) = css_color(colors.1) {
+                style.push(format!("background-color: {}", bg));
+            }
+            match style.is_empty() {
+                true => html.push_str(&run),
+                false => html.push_str(&format!(
+                    "<span style=\"{}\">{}</span>",
+                    style.join("; "),
+                    run
+                )),
+            }
+        }
+        html.push('\n');
+    }
+    html.push_str("</pre>\n");
+    html
+}
+
+pub fn export_html(layers: &[Layer], path: &str) -> io::Result<()> {
+    fs::write(path, to_html(layers))
+}
+
+pub fn export_ansi(layers: &[Layer], path: &str) -> io::Result<()> {
+    fs::write(path, to_ansi(layers))
+}
+
+pub fn export_safe_ansi(layers: &[Layer], path: &str) -> io::Result<()> {
+    fs::write(path, to_safe_ansi(layers))
+}
+
+// SGR parameters of a color as foreground, background ones are 10 higher,
+// colors are snapped to the 16 system ones and defaults are kept
+fn safe_sgr(color: Color) -> u8 {
+    match color_to_rgb(color).map(nearest_system) {
+        None => 39,
+        Some(code @ 0..=7) => 30 + code,
+        Some(code) => 90 + code - 8,
+    }
+}
+
+// ANSI art that survives tmux and screen passthrough and legacy terminals, no
+// 256 colors nor truecolor and no cursor movement, only plain SGR colors, CRLF
+// line ends and a reset at the end of every line
+pub fn to_safe_ansi(layers: &[Layer]) -> String {
+    let mut ansi: String = String::new();
+    for row in canvas_rows(layers).iter() {
+        let mut colors: Option<(u8, u8)> = None;
+        for term_char in row.iter() {
+            let fg: u8 = safe_sgr(term_char.foreground_color);
+            let bg: u8 = safe_sgr(term_char.background_color) + 10;
+            if colors != Some((fg, bg)) {
+                ansi.push_str(&format!("\x1b[0;{};{}m", fg, bg));
+                colors = Some((fg, bg));
+            }
+            ansi.push(term_char.character);
+        }
+        ansi.push_str("\x1b[0m\r\n");
+    }
+    ansi
+}
+
+pub fn export_text(layers: &[Layer], path: &str) -> io::Result<()> {
+    fs::write(path, to_text(layers))
+}
+
+// pixels and half blocks become rects merged along runs of the same color,
+// other chars are drawn as text over their background, cells have the same
+// size as in png exports and default colors are left transparent
+pub fn to_svg(layers: &[Layer], scale: usize, cell_aspect: (usize, usize)) -> String {
+    let (cell_w, cell_h) = cell_size(scale, cell_aspect);
+    let rows: Vec<Vec<TermChar>> = canvas_rows(layers);
+    let width: usize = rows.first().map_or(0, |row| row.len()) * cell_w;
+    let height: usize = rows.len() * cell_h;
+    let mut svg: String = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+         viewBox=\"0 0 {w} {h}\" shape-rendering=\"crispEdges\">\n",
+        w = width,
+        h = height
+    );
+    let halves = |term_char: &TermChar| match term_char.character {
+        '▀' => (term_char.foreground_color, term_char.background_color),
+        '▄' => (term_char.background_color, term_char.foreground_color),
+        _ => (term_char.background_color, term_char.background_color),
+    };
+    for (y, row) in rows.iter().enumerate() {
+        let top: Vec<Color> = row.iter().map(|term_char| halves(term_char).0).collect();
+        let bottom: Vec<Color> = row.iter().map(|term_char| halves(term_char).1).collect();
+        let top_h: usize = cell_h / 2;
+        for (colors, y0, h) in [
+            (top, y * cell_h, top_h),
+            (bottom, y * cell_h + top_h, cell_h - top_h),
+        ] {
+            let mut x: usize = 0;
+            while x < colors.len() {
+                let start: usize = x;
+                while x < colors.len() && colors[x] == colors[start] {
+                    x += 1;
+                }
+                if let Some(fill) = css_color(colors[start]) {
+                    svg.push_str(&format!(
+                        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+                        start * cell_w,
+                        y0,
+                        (x - start) * cell_w,
+                        h,
+                        fill
+                    ));
+                }
+            }
+        }
+        for (x, term_char) in row.iter().enumerate() {
+            if matches!(term_char.character, ' ' | '▀' | '▄') {
+                continue;
+            }
+            let fill: String = css_color(term_char.foreground_color).unwrap_or("#000000".into());
+            svg.push_str(&format!(
+                "<text x=\"{}\" y=\"{}\" font-family=\"monospace\" font-size=\"{}\" fill=\"{}\">{}</text>\n",
+                x * cell_w,
+                y * cell_h + cell_h * 4 / 5,
+                cell_h,
+                fill,
+                html_escape(term_char.character)
+            ));
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// terminal cells are taller than wide, cell_aspect is their width:height ratio
+// and every cell becomes a block of scale times that size, so what looks square
+// on screen stays square in the image
+fn cell_size(scale: usize, (aspect_w, aspect_h): (usize, usize)) -> (usize, usize) {
+    (
+        scale.max(1) * aspect_w.max(1),
+        scale.max(1) * aspect_h.max(1),
+    )
+}
+
+// half blocks split every cell in a top and a bottom half, default colors are
+// left transparent
+pub fn to_rgba(
+    layers: &[Layer],
+    scale: usize,
+    cell_aspect: (usize, usize),
+) -> (usize, usize, Vec<u8>) {
+    let (cell_w, cell_h) = cell_size(scale, cell_aspect);
+    let rows: Vec<Vec<TermChar>> = canvas_rows(layers);
+    let height: usize = rows.len() * cell_h;
+    let width: usize = rows.first().map_or(0, |row| row.len()) * cell_w;
+    let mut rgba: Vec<u8> = vec![0; width * height * 4];
+    if width > 0 {
+        for (row_vec, pixels) in rows.iter().zip(rgba.chunks_mut(width * cell_h * 4)) {
+            rasterize_row(row_vec, (cell_w, cell_h), pixels);
+        }
+    }
+    (width, height, rgba)
+}
+
+// the cell_h pixel rows of a row of cells, pixels holds exactly them
+fn rasterize_row(row: &[TermChar], (cell_w, cell_h): (usize, usize), pixels: &mut [u8]) {
+    let width: usize = row.len() * cell_w;
+    for (col, term_char) in row.iter().enumerate() {
+        let (top, bottom) = match term_char.character {
+            ' ' => (term_char.background_color, term_char.background_color),
+            '▀' => (term_char.foreground_color, term_char.background_color),
+            '▄' => (term_char.background_color, term_char.foreground_color),
+            _ => (term_char.foreground_color, term_char.foreground_color),
+        };
+        for y in 0..cell_h {
+            let color = match y < cell_h / 2 {
+                true => top,
+                false => bottom,
+            };
+            let Some((r, g, b)) = color_to_rgb(color) else {
+                continue;
+            };
+            for x in 0..cell_w {
+                let i = (y * width + col * cell_w + x) * 4;
+                pixels[i..i + 4].copy_from_slice(&[r, g, b, 255]);
+            }
+        }
+    }
+}
+
+// the canvas is composited and rasterized EXPORT_BAND_ROWS rows of cells at a
+// time and streamed to the encoder, so huge canvases are never held as a
+// whole bitmap
+pub fn export_png(
+    layers: &[Layer],
+    path: &str,
+    scale: usize,
+    cell_aspect: (usize, usize),
+) -> io::Result<()> {
+    let (cell_w, cell_h) = cell_size(scale, cell_aspect);
+    let Some(((min_x, min_y), (max_x, max_y))) = layers_bounds(layers) else {
+        return Err(empty_canvas());
+    };
+    let columns: usize = (max_x - min_x + 1) as usize;
+    let rows: usize = (max_y - min_y + 1) as usize;
+    let width: usize = columns * cell_w;
+    let mut stream = png_encoder(path, width, rows * cell_h)?
+        .write_header()?
+        .into_stream_writer()?;
+    for band_start in (0..rows).step_by(EXPORT_BAND_ROWS) {
+        let band_rows: usize = EXPORT_BAND_ROWS.min(rows - band_start);
+        let origin: (i32, i32) = (min_x, min_y + band_start as i32);
+        let band: Vec<Vec<TermChar>> = composite_layers(layers, origin, columns, band_rows);
+        let mut rgba: Vec<u8> = vec![0; width * band_rows * cell_h * 4];
+        for (row_vec, pixels) in band.iter().zip(rgba.chunks_mut(width * cell_h * 4)) {
+            rasterize_row(row_vec, (cell_w, cell_h), pixels);
+        }
+        stream.write_all(&rgba)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+fn empty_canvas() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "canvas is empty")
+}
+
+fn png_encoder(
+    path: &str,
+    width: usize,
+    height: usize,
+) -> io::Result<png::Encoder<'static, BufWriter<File>>> {
+    if width == 0 || height == 0 {
+        return Err(empty_canvas());
+    }
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    Ok(encoder)
+}
+
+fn write_png(path: &str, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    png_encoder(path, width, height)?
+        .write_header()?
+        .write_image_data(rgba)?;
+    Ok(())
+}
+
+// every project in dir rasterized like the png export and laid out in a grid
+// of cols columns, tiles are as big as the biggest project and one cell apart
+pub fn export_montage(
+    dir: &str,
+    cols: usize,
+    path: &str,
+    scale: usize,
+    cell_aspect: (usize, usize),
+) -> io::Result<()> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pxr"))
+        .collect();
+    paths.sort();
+    let mut tiles: Vec<(usize, usize, Vec<u8>)> = Vec::new();
+    for project_path in paths.iter() {
+        let project: Project = Project::load(&project_path.to_string_lossy())?;
+        tiles.push(to_rgba(&project.layers, scale, cell_aspect));
+    }
+    if tiles.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no projects found"));
+    }
+    let cols: usize = cols.clamp(1, tiles.len());
+    let rows: usize = tiles.len().div_ceil(cols);
+    let gap: usize = cell_size(scale, cell_aspect).0;
+    let tile_width: usize = tiles.iter().map(|tile| tile.0).max().unwrap_or(0) + gap;
+    let tile_height: usize = tiles.iter().map(|tile| tile.1).max().unwrap_or(0) + gap;
+    let (width, height) = (cols * tile_width - gap, rows * tile_height - gap);
+    let mut rgba: Vec<u8> = vec![0; width * height * 4];
+    for (i, (tile_w, tile_h, tile)) in tiles.iter().enumerate() {
+        let (x0, y0) = ((i % cols) * tile_width, (i / cols) * tile_height);
+        for y in 0..*tile_h {
+            let src = y * tile_w * 4;
+            let dst = ((y0 + y) * width + x0) * 4;
+            rgba[dst..dst + tile_w * 4].copy_from_slice(&tile[src..src + tile_w * 4]);
+        }
+    }
+    write_png(path, width, height, &rgba)
+}
+
+// black through red and yellow up to white as heat goes from 0.0 to 1.0
+fn heat_color(heat: f32) -> [u8; 4] {
+    let channel = |start: f32| (((heat - start) * 3.0).clamp(0.0, 1.0) * 255.0) as u8;
+    [channel(0.0), channel(1.0 / 3.0), channel(2.0 / 3.0), 255]
+}
+
+// number of edits every cell received, taken from the history of local operations
+pub fn edit_counts(history: &dyn History) -> HashMap<(i32, i32), usize> {
+    let mut counts: HashMap<(i32, i32), usize> = HashMap::new();
+    let mut offset: usize = 0;
+    loop {
+        let page: Vec<LocalOperation> = history.page(offset, HEATMAP_PAGE_SIZE);
+        if page.is_empty() {
+            break;
+        }
+        offset += page.len();
+        for operation in page.into_iter().flat_map(LocalOperation::into_operations) {
+            let items: Vec<Item> = match operation {
+                LocalOperation::Add { item, .. } | LocalOperation::Move { item, .. } => vec![item],
+                LocalOperation::Remove { items, .. } => items,
+                LocalOperation::Paint { offset, color, .. } => {
+                    vec![Pixel::new(color).to_item(offset)]
+                }
+                LocalOperation::Clear { pixels, .. } => pixels
+                    .into_iter()
+                    .map(|(offset, color)| Pixel::new(color).to_item(offset))
+                    .collect(),
+                LocalOperation::AddLayer { .. }
+                | LocalOperation::MergeDown { .. }
+                | LocalOperation::Group { .. }
+                | LocalOperation::Frame { .. } => Vec::new(),
+            };
+            for cell in items
+                .iter()
+                .flat_map(|item| item.get_filled_indexes((0, 0)))
+            {
+                *counts.entry(cell).or_default() += 1;
+            }
+        }
+    }
+    counts
+}
+
+// cells are scale x scale blocks colored by how often they were edited relative
+// to the most edited one, cells never edited are left transparent
+pub fn export_heatmap(
+    counts: &HashMap<(i32, i32), usize>,
+    path: &str,
+    scale: usize,
+    cell_aspect: (usize, usize),
+) -> io::Result<()> {
+    let (cell_w, cell_h) = cell_size(scale, cell_aspect);
+    let min_x: i32 = counts.keys().map(|cell| cell.0).min().unwrap_or(0);
+    let min_y: i32 = counts.keys().map(|cell| cell.1).min().unwrap_or(0);
+    let max_x: i32 = counts.keys().map(|cell| cell.0).max().unwrap_or(-1);
+    let max_y: i32 = counts.keys().map(|cell| cell.1).max().unwrap_or(-1);
+    let most_edited: usize = counts.values().copied().max().unwrap_or(1);
+    let width: usize = (max_x - min_x + 1) as usize * cell_w;
+    let height: usize = (max_y - min_y + 1) as usize * cell_h;
+    let mut rgba: Vec<u8> = vec![0; width * height * 4];
+    for (&(x, y), &count) in counts.iter() {
+        let color: [u8; 4] = heat_color(count as f32 / most_edited as f32);
+        let (col, row) = ((x - min_x) as usize, (y - min_y) as usize);
+        for dy in 0..cell_h {
+            for dx in 0..cell_w {
+                let i = ((row * cell_h + dy) * width + col * cell_w + dx) * 4;
+                rgba[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    }
+    write_png(path, width, height, &rgba)
+}
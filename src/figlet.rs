@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+// fonts shipped with pixelrs, name and FIGlet source
+const EMBEDDED: [(&str, &str); 2] = [
+    ("block", include_str!("../assets/fonts/block.flf")),
+    ("small", include_str!("../assets/fonts/small.flf")),
+];
+
+// characters every font has, space to tilde, the deutsch ones that follow
+// are not used
+const ASCII_GLYPHS: u32 = 95;
+
+// a FIGlet font, only laid out at full width, characters are never smushed
+#[derive(Clone)]
+pub struct Font {
+    pub name: String,
+    pub height: usize,
+    glyphs: HashMap<char, Vec<String>>,
+}
+
+impl Font {
+    // a .flf font, the header, comment lines and then height lines per
+    // character from space on, each ending with an endmark
+    pub fn parse(name: &str, source: &str) -> Result<Font, String> {
+        let mut lines = source.lines();
+        let header: &str = lines.next().unwrap_or_default();
+        let Some(rest) = header.strip_prefix("flf2a") else {
+            return Err(format!("{} is not a FIGlet font", name));
+        };
+        let hardblank: char = rest.chars().next().unwrap_or('$');
+        let fields: Vec<usize> = rest[hardblank.len_utf8()..]
+            .split_whitespace()
+            .map(|field| field.parse::<i64>().unwrap_or(0).max(0) as usize)
+            .collect();
+        let (height, comments) = match fields.as_slice() {
+            [height, _, _, _, comments, ..] if *height > 0 => (*height, *comments),
+            _ => return Err(format!("{} has a broken header", name)),
+        };
+        let lines: Vec<&str> = lines.skip(comments).collect();
+        let mut glyphs: HashMap<char, Vec<String>> = HashMap::new();
+        for (i, rows) in lines.chunks(height).take(ASCII_GLYPHS as usize).enumerate() {
+            if rows.len() < height {
+                return Err(format!("{} ends in the middle of a character", name));
+            }
+            let rows: Vec<String> = rows
+                .iter()
+                .map(|row| {
+                    let endmark: Option<char> = row.chars().last();
+                    row.trim_end_matches(|c| Some(c) == endmark)
+                        .replace(hardblank, " ")
+                })
+                .collect();
+            let character: char = char::from_u32(' ' as u32 + i as u32).unwrap_or(' ');
+            glyphs.insert(character, rows);
+        }
+        Ok(Font {
+            name: name.to_string(),
+            height,
+            glyphs,
+        })
+    }
+
+    // rows of the text set in the font, characters it lacks are left out
+    pub fn render(&self, text: &str) -> Vec<String> {
+        let mut rows: Vec<String> = vec![String::new(); self.height];
+        for glyph in text.chars().filter_map(|c| self.glyphs.get(&c)) {
+            for (row, part) in rows.iter_mut().zip(glyph) {
+                row.push_str(part);
+            }
+        }
+        rows
+    }
+
+    // columns the text takes
+    pub fn width(&self, text: &str) -> usize {
+        text.chars()
+            .filter_map(|c| self.glyphs.get(&c))
+            .map(|glyph| {
+                glyph
+                    .iter()
+                    .map(|row| row.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .sum()
+    }
+
+    // x, y of every column of the text the font inks, a pixel each
+    pub fn pixels(&self, text: &str) -> Vec<(i32, i32)> {
+        self.render(text)
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars()
+                    .enumerate()
+                    .filter(|(_, c)| !c.is_whitespace())
+                    .map(move |(x, _)| (x as i32, y as i32))
+            })
+            .collect()
+    }
+}
+
+pub fn embedded() -> Vec<Font> {
+    EMBEDDED
+        .iter()
+        .map(|(name, source)| Font::parse(name, source).expect("embedded fonts parse"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two rows a character, `@` ends a row and `@@` the last one
+    fn font(glyphs: &str) -> String {
+        format!("flf2a$ 2 1 4 0 1\na comment line\n{}", glyphs)
+    }
+
+    #[test]
+    fn glyphs_are_read_from_space_on() {
+        let font: Font = Font::parse("tiny", &font(" $@\n $@@\n#@\n#@@\n\"\"@\n..@@\n")).unwrap();
+        assert_eq!(font.height, 2);
+        assert_eq!(font.render("! !"), vec!["#  #", "#  #"]);
+        assert_eq!(font.width("\"!"), 3);
+        assert_eq!(font.pixels("\""), vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        // characters the font lacks are left out
+        assert_eq!(font.render("!a"), vec!["#", "#"]);
+    }
+
+    #[test]
+    fn broken_fonts_are_refused() {
+        assert!(Font::parse("none", "hello").is_err());
+        assert!(Font::parse("header", "flf2a$ 0 1 4 0 0\n").is_err());
+        assert!(Font::parse("short", &font(" $@\n $@@\n#@\n")).is_err());
+    }
+
+    #[test]
+    fn embedded_fonts_have_every_ascii_glyph() {
+        for font in embedded() {
+            assert_eq!(font.glyphs.len(), ASCII_GLYPHS as usize, "{}", font.name);
+        }
+    }
+}
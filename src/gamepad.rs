@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+// stick values below the deadzone are noise, past it the cursor moves one step
+// every interval
+const GAMEPAD_DEADZONE: f32 = 0.5;
+const GAMEPAD_STEP_INTERVAL: Duration = Duration::from_millis(80);
+
+pub enum GamepadAction {
+    // displacement in cells, columns move a whole pixel at a time
+    Move(i32, i32),
+    Paint,
+    Erase,
+    NextColor,
+    PreviousColor,
+}
+
+// the left stick and the d-pad move the cursor, south paints and east erases
+// while held, west and north cycle the colors of the current palette
+pub struct Gamepad {
+    gilrs: Gilrs,
+    stick: (f32, f32),
+    last_step: Instant,
+    painting: bool,
+    erasing: bool,
+}
+
+impl Gamepad {
+    // None when no gamepad backend is available on this system
+    pub fn new() -> Option<Self> {
+        let gilrs: Gilrs = Gilrs::new().ok()?;
+        Some(Gamepad {
+            gilrs,
+            stick: (0.0, 0.0),
+            last_step: Instant::now(),
+            painting: false,
+            erasing: false,
+        })
+    }
+
+    fn step(&self, (dx, dy): (i32, i32)) -> Vec<GamepadAction> {
+        let mut actions: Vec<GamepadAction> = vec![GamepadAction::Move(2 * dx, dy)];
+        if self.painting {
+            actions.push(GamepadAction::Paint);
+        } else if self.erasing {
+            actions.push(GamepadAction::Erase);
+        }
+        actions
+    }
+
+    pub fn poll(&mut self) -> Vec<GamepadAction> {
+        let mut actions: Vec<GamepadAction> = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => self.stick.0 = value,
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => self.stick.1 = value,
+                EventType::ButtonPressed(button, _) => match button {
+                    Button::South => {
+                        self.painting = true;
+                        actions.push(GamepadAction::Paint);
+                    }
+                    Button::East => {
+                        self.erasing = true;
+                        actions.push(GamepadAction::Erase);
+                    }
+                    Button::West => actions.push(GamepadAction::PreviousColor),
+                    Button::North => actions.push(GamepadAction::NextColor),
+                    Button::DPadUp => actions.extend(self.step((0, -1))),
+                    Button::DPadDown => actions.extend(self.step((0, 1))),
+                    Button::DPadLeft => actions.extend(self.step((-1, 0))),
+                    Button::DPadRight => actions.extend(self.step((1, 0))),
+                    _ => {}
+                },
+                EventType::ButtonReleased(Button::South, _) => self.painting = false,
+                EventType::ButtonReleased(Button::East, _) => self.erasing = false,
+                _ => {}
+            }
+        }
+
+        // the stick repeats one step every interval while pushed, stick y grows upwards
+        let direction = |value: f32| match value {
+            v if v > GAMEPAD_DEADZONE => 1,
+            v if v < -GAMEPAD_DEADZONE => -1,
+            _ => 0,
+        };
+        let (dx, dy) = (direction(self.stick.0), -direction(self.stick.1));
+        if (dx, dy) != (0, 0) && self.last_step.elapsed() >= GAMEPAD_STEP_INTERVAL {
+            self.last_step = Instant::now();
+            actions.extend(self.step((dx, dy)));
+        }
+        actions
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+// pixels of a layer keyed by the layer relative cell they are anchored at, a
+// pixel takes its anchor and the cell to its right. Pixels never overlap,
+// painting over one replaces it, so dense drawings cost one entry per pixel
+// instead of an item each
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<((i32, i32), Color)>", into = "Vec<((i32, i32), Color)>")]
+pub struct Grid {
+    cells: HashMap<(i32, i32), Color>,
+}
+
+impl Grid {
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    // color of the pixel anchored exactly at anchor
+    pub fn get(&self, anchor: (i32, i32)) -> Option<Color> {
+        self.cells.get(&anchor).copied()
+    }
+
+    // anchor of the pixel covering the cell, either half of it
+    pub fn anchor_at(&self, (x, y): (i32, i32)) -> Option<(i32, i32)> {
+        [(x, y), (x - 1, y)]
+            .into_iter()
+            .find(|anchor| self.cells.contains_key(anchor))
+    }
+
+    // the pixels the new one overlaps are replaced and returned
+    pub fn paint(&mut self, (x, y): (i32, i32), color: Color) -> Vec<((i32, i32), Color)> {
+        let replaced: Vec<((i32, i32), Color)> = [(x - 1, y), (x, y), (x + 1, y)]
+            .into_iter()
+            .filter_map(|anchor| Some((anchor, self.cells.remove(&anchor)?)))
+            .collect();
+        self.cells.insert((x, y), color);
+        replaced
+    }
+
+    pub fn clear(&mut self, anchor: (i32, i32)) -> Option<Color> {
+        self.cells.remove(&anchor)
+    }
+
+    pub fn clear_all(&mut self) -> Vec<((i32, i32), Color)> {
+        let mut pixels: Vec<((i32, i32), Color)> = self.cells.drain().collect();
+        pixels.sort_by_key(|((x, y), _)| (*y, *x));
+        pixels
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((i32, i32), Color)> + '_ {
+        self.cells.iter().map(|(&anchor, &color)| (anchor, color))
+    }
+
+    pub fn colors_mut(&mut self) -> impl Iterator<Item = ((i32, i32), &mut Color)> {
+        self.cells
+            .iter_mut()
+            .map(|(&anchor, color)| (anchor, color))
+    }
+
+    // both cells of every pixel
+    pub fn filled_cells(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.cells.keys().flat_map(|&(x, y)| [(x, y), (x + 1, y)])
+    }
+}
+
+impl From<Vec<((i32, i32), Color)>> for Grid {
+    fn from(pixels: Vec<((i32, i32), Color)>) -> Self {
+        Grid {
+            cells: pixels.into_iter().collect(),
+        }
+    }
+}
+
+// saved row by row so the same drawing always serializes the same
+impl From<Grid> for Vec<((i32, i32), Color)> {
+    fn from(mut grid: Grid) -> Self {
+        grid.clear_all()
+    }
+}
@@ -0,0 +1,301 @@
+use std::io;
+
+use crossterm::style::Color;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::screen::{Item, Layer};
+
+// local edits in the order they were made, undoing one means applying its inverse
+#[derive(Serialize, Deserialize, Clone)]
+pub enum LocalOperation {
+    Add {
+        layer: usize,
+        item: Item,
+    },
+    Remove {
+        layer: usize,
+        items: Vec<Item>,
+    },
+    // a pixel painted on the grid of the layer, with the ones it replaced and,
+    // on the shared layer, their authors
+    Paint {
+        layer: usize,
+        offset: (i32, i32),
+        color: Color,
+        replaced: Vec<((i32, i32), Color)>,
+        #[serde(default)]
+        authors: Vec<((i32, i32), String)>,
+    },
+    Clear {
+        layer: usize,
+        pixels: Vec<((i32, i32), Color)>,
+        #[serde(default)]
+        authors: Vec<((i32, i32), String)>,
+    },
+    // an item dragged by the move tool, as it was dropped, and where it was
+    Move {
+        layer: usize,
+        item: Item,
+        from: (i32, i32),
+    },
+    // a layer inserted at the index, e.g. by a script, a duplicate or an
+    // import, later edits name layers by index so undo has to remove it
+    AddLayer {
+        layer: usize,
+    },
+    // the layer above the index was merged into it, as it was before. the
+    // cells the merge changed below are the operations grouped after it
+    MergeDown {
+        layer: usize,
+        upper: Box<Layer>,
+    },
+    // operations made as one edit and undone together, in the order made
+    Group {
+        operations: Vec<LocalOperation>,
+    },
+    // the shown animation frame changed, edits made before belong to the
+    // frame shown then, so undoing goes back to it first
+    Frame {
+        change: FrameChange,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum FrameChange {
+    Show { from: usize, to: usize },
+    // the shown frame at from was copied right after it
+    Duplicate { from: usize },
+    Move { from: usize, to: usize },
+    // the shown frame at index was dropped, with its document layers
+    Delete { index: usize, layers: Vec<Layer> },
+}
+
+impl LocalOperation {
+    // a group of the operations, or none for an empty one
+    pub fn group(mut operations: Vec<LocalOperation>) -> Option<LocalOperation> {
+        match operations.len() {
+            0 => None,
+            1 => operations.pop(),
+            _ => Some(LocalOperation::Group { operations }),
+        }
+    }
+
+    // the operations a group is made of, in the order made, nested groups
+    // included
+    pub fn into_operations(self) -> Vec<LocalOperation> {
+        match self {
+            LocalOperation::Group { operations } => operations
+                .into_iter()
+                .flat_map(LocalOperation::into_operations)
+                .collect(),
+            operation => vec![operation],
+        }
+    }
+
+    // the layer of a single operation, a group has none of its own and frame
+    // changes concern every document layer
+    pub fn layer(&self) -> Option<usize> {
+        match self {
+            LocalOperation::Add { layer, .. }
+            | LocalOperation::Remove { layer, .. }
+            | LocalOperation::Paint { layer, .. }
+            | LocalOperation::Clear { layer, .. }
+            | LocalOperation::Move { layer, .. }
+            | LocalOperation::AddLayer { layer }
+            | LocalOperation::MergeDown { layer, .. } => Some(*layer),
+            LocalOperation::Group { .. } | LocalOperation::Frame { .. } => None,
+        }
+    }
+
+    // cells of the layer undoing the operation writes to
+    pub fn cells(&self) -> Vec<(i32, i32)> {
+        match self {
+            LocalOperation::Add { item, .. } => vec![item.offset],
+            LocalOperation::Remove { items, .. } => items.iter().map(|item| item.offset).collect(),
+            LocalOperation::Paint {
+                offset, replaced, ..
+            } => std::iter::once(*offset)
+                .chain(replaced.iter().map(|(offset, _)| *offset))
+                .collect(),
+            LocalOperation::Clear { pixels, .. } => {
+                pixels.iter().map(|(offset, _)| *offset).collect()
+            }
+            LocalOperation::Move { item, from, .. } => vec![item.offset, *from],
+            LocalOperation::AddLayer { .. }
+            | LocalOperation::MergeDown { .. }
+            | LocalOperation::Frame { .. } => Vec::new(),
+            LocalOperation::Group { operations } => {
+                operations.iter().flat_map(LocalOperation::cells).collect()
+            }
+        }
+    }
+}
+
+pub trait History {
+    fn push(&mut self, operation: LocalOperation);
+    // the most recent operation is removed from the history and returned
+    fn pop(&mut self) -> Option<LocalOperation>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // limit operations starting at offset, oldest first, so a scrubber never
+    // needs the whole history at once
+    fn page(&self, offset: usize, limit: usize) -> Vec<LocalOperation>;
+}
+
+#[derive(Default)]
+pub struct MemoryHistory {
+    operations: Vec<LocalOperation>,
+}
+
+impl History for MemoryHistory {
+    fn push(&mut self, operation: LocalOperation) {
+        self.operations.push(operation);
+    }
+
+    fn pop(&mut self) -> Option<LocalOperation> {
+        self.operations.pop()
+    }
+
+    fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    fn page(&self, offset: usize, limit: usize) -> Vec<LocalOperation> {
+        self.operations
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+// history kept in a database next to the project so it survives across sessions,
+// failures are swallowed because losing history must never interrupt drawing
+pub struct SqliteHistory {
+    connection: Connection,
+}
+
+impl SqliteHistory {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let connection: Connection = Connection::open(path).map_err(io::Error::other)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS history (
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                    operation TEXT NOT NULL
+                );",
+            )
+            .map_err(io::Error::other)?;
+        Ok(SqliteHistory { connection })
+    }
+}
+
+impl History for SqliteHistory {
+    fn push(&mut self, operation: LocalOperation) {
+        if let Ok(serialized) = serde_json::to_string(&operation) {
+            let _ = self.connection.execute(
+                "INSERT INTO history (operation) VALUES (?1)",
+                params![serialized],
+            );
+        }
+    }
+
+    fn pop(&mut self) -> Option<LocalOperation> {
+        let (seq, serialized): (i64, String) = self
+            .connection
+            .query_row(
+                "SELECT seq, operation FROM history ORDER BY seq DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok()?;
+        let _ = self
+            .connection
+            .execute("DELETE FROM history WHERE seq = ?1", params![seq]);
+        serde_json::from_str(&serialized).ok()
+    }
+
+    fn len(&self) -> usize {
+        self.connection
+            .query_row("SELECT COUNT(*) FROM history", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map_or(0, |count| count as usize)
+    }
+
+    fn page(&self, offset: usize, limit: usize) -> Vec<LocalOperation> {
+        let Ok(mut statement) = self
+            .connection
+            .prepare("SELECT operation FROM history ORDER BY seq LIMIT ?1 OFFSET ?2")
+        else {
+            return Vec::new();
+        };
+        let rows = statement.query_map(params![limit as i64, offset as i64], |row| {
+            row.get::<_, String>(0)
+        });
+        match rows {
+            Ok(rows) => rows
+                .filter_map(|row| row.ok())
+                .filter_map(|serialized| serde_json::from_str(&serialized).ok())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(layer: usize) -> LocalOperation {
+        LocalOperation::AddLayer { layer }
+    }
+
+    fn layers(operations: Vec<LocalOperation>) -> Vec<Option<usize>> {
+        operations.iter().map(LocalOperation::layer).collect()
+    }
+
+    // pages come oldest first and pops newest first, whatever keeps them
+    fn check(history: &mut dyn History) {
+        for layer in 0..5 {
+            history.push(add(layer));
+        }
+        assert_eq!(history.len(), 5);
+        assert_eq!(layers(history.page(0, 2)), vec![Some(0), Some(1)]);
+        assert_eq!(layers(history.page(3, 10)), vec![Some(3), Some(4)]);
+        assert!(history.page(5, 10).is_empty());
+        assert_eq!(history.pop().and_then(|operation| operation.layer()), Some(4));
+        assert_eq!(layers(history.page(2, 10)), vec![Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn histories_are_paged_in_the_order_made() {
+        check(&mut MemoryHistory::default());
+        let path = std::env::temp_dir().join(format!("pixelrs-history-{}", std::process::id()));
+        let mut sqlite: SqliteHistory = SqliteHistory::new(path.to_str().unwrap()).unwrap();
+        check(&mut sqlite);
+        drop(sqlite);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn groups_flatten_in_the_order_made() {
+        assert!(LocalOperation::group(Vec::new()).is_none());
+        assert!(matches!(
+            LocalOperation::group(vec![add(1)]),
+            Some(LocalOperation::AddLayer { layer: 1 })
+        ));
+        let nested: LocalOperation = LocalOperation::Group {
+            operations: vec![add(1), LocalOperation::group(vec![add(2), add(3)]).unwrap()],
+        };
+        assert_eq!(nested.layer(), None);
+        assert_eq!(
+            layers(nested.into_operations()),
+            vec![Some(1), Some(2), Some(3)]
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use std::process::{Command, Stdio};
+use std::thread;
+
+// document events shell commands can be attached to
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum HookEvent {
+    Saved,
+    Exported,
+    SessionJoined,
+    ParticipantJoined,
+}
+
+impl HookEvent {
+    fn parse(name: &str) -> Option<HookEvent> {
+        match name {
+            "saved" => Some(HookEvent::Saved),
+            "exported" => Some(HookEvent::Exported),
+            "session-joined" => Some(HookEvent::SessionJoined),
+            "participant-joined" => Some(HookEvent::ParticipantJoined),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            HookEvent::Saved => "saved",
+            HookEvent::Exported => "exported",
+            HookEvent::SessionJoined => "session-joined",
+            HookEvent::ParticipantJoined => "participant-joined",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Hooks {
+    commands: Vec<(HookEvent, String)>,
+}
+
+impl Hooks {
+    // spec is `event=command`, e.g. `saved=notify-send saved`
+    pub fn add(&mut self, spec: &str) -> Result<(), String> {
+        let (event, command) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("hook {:?} is not event=command", spec))?;
+        let event: HookEvent =
+            HookEvent::parse(event).ok_or_else(|| format!("unknown hook event {:?}", event))?;
+        self.commands.push((event, command.to_string()));
+        Ok(())
+    }
+
+    // commands run through sh in the background with the context in PIXELRS_*
+    // env vars, their output is discarded since the terminal belongs to the canvas
+    pub fn run(&self, event: HookEvent, context: &[(&str, &str)]) {
+        for (_, command) in self.commands.iter().filter(|(e, _)| *e == event) {
+            let child = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("PIXELRS_EVENT", event.name())
+                .envs(context.iter().map(|(k, v)| (format!("PIXELRS_{}", k), v)))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            // hooks are waited on aside so they never block the session
+            if let Ok(mut child) = child {
+                thread::spawn(move || child.wait());
+            }
+        }
+    }
+}
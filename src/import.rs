@@ -0,0 +1,356 @@
+use std::fs;
+use std::io;
+
+use crossterm::style::Color;
+use image::imageops::FilterType;
+use image::RgbaImage;
+
+use crate::constants::EMPTY_TERM_CHAR;
+use crate::palette::{ansi_to_rgb, dominant_colors, nearest_ansi, Palette};
+use crate::screen::{rows_to_ansi, Item, Layer, TermChar};
+
+// pixels more transparent than this are left out of the layer
+const ALPHA_THRESHOLD: u8 = 128;
+// sobel gradient magnitude of the luminance past which a pixel is an edge
+const EDGE_THRESHOLD: f32 = 160.0;
+
+pub fn load_image(path: &str) -> io::Result<RgbaImage> {
+    let image = image::open(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(image.to_rgba8())
+}
+
+// the image is scaled to fit in width x height canvas pixels keeping its aspect
+pub fn fit(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let scale: f32 = f32::min(
+        width as f32 / image.width() as f32,
+        height as f32 / image.height() as f32,
+    );
+    let fitted_width: u32 = ((image.width() as f32 * scale) as u32).max(1);
+    let fitted_height: u32 = ((image.height() as f32 * scale) as u32).max(1);
+    image::imageops::resize(image, fitted_width, fitted_height, FilterType::Triangle)
+}
+
+// ansi code of every pixel, None for transparent ones, with dithering the
+// quantization error is spread Floyd-Steinberg style to the unvisited neighbours
+pub fn quantize(image: &RgbaImage, dither: bool) -> Vec<Vec<Option<u8>>> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut pixels: Vec<Vec<(i32, i32, i32)>> = (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let [r, g, b, _] = image.get_pixel(x as u32, y as u32).0;
+                    (r as i32, g as i32, b as i32)
+                })
+                .collect()
+        })
+        .collect();
+    let mut codes: Vec<Vec<Option<u8>>> = vec![vec![None; width]; height];
+    for y in 0..height {
+        for x in 0..width {
+            if image.get_pixel(x as u32, y as u32).0[3] < ALPHA_THRESHOLD {
+                continue;
+            }
+            let code: u8 = nearest_ansi(pixels[y][x]);
+            codes[y][x] = Some(code);
+            if !dither {
+                continue;
+            }
+            let (r, g, b) = ansi_to_rgb(code);
+            let (old_r, old_g, old_b) = pixels[y][x];
+            let error = (old_r - r as i32, old_g - g as i32, old_b - b as i32);
+            for (dx, dy, weight) in [(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)] {
+                let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                if nx < 0 || nx >= width as i32 || ny >= height as i32 {
+                    continue;
+                }
+                let neighbour = &mut pixels[ny as usize][nx as usize];
+                neighbour.0 += error.0 * weight / 16;
+                neighbour.1 += error.1 * weight / 16;
+                neighbour.2 += error.2 * weight / 16;
+            }
+        }
+    }
+    codes
+}
+
+// every quantized pixel is painted on the grid of the layer, anchored at origin
+pub fn codes_to_layer(name: &str, codes: &[Vec<Option<u8>>], origin: (i32, i32)) -> Layer {
+    let mut layer: Layer = Layer::new_empty(name.to_string(), 0, 0, (0, 0));
+    for (y, row) in codes.iter().enumerate() {
+        for (x, code) in row.iter().enumerate() {
+            let Some(code) = code else {
+                continue;
+            };
+            layer.paint(
+                (origin.0 + 2 * x as i32, origin.1 + y as i32),
+                Color::AnsiValue(*code),
+            );
+        }
+    }
+    layer
+}
+
+// two pixels stacked in a single cell, the top one through the foreground of
+// an upper half block and the bottom one through the background
+pub fn codes_to_half_blocks(codes: &[Vec<Option<u8>>]) -> Vec<Vec<TermChar>> {
+    let to_color = |code: Option<u8>| code.map_or(Color::Reset, Color::AnsiValue);
+    codes
+        .chunks(2)
+        .map(|pair| {
+            (0..pair[0].len())
+                .map(|x| {
+                    let top: Option<u8> = pair[0][x];
+                    let bottom: Option<u8> = pair.get(1).and_then(|row| row[x]);
+                    TermChar {
+                        character: '▀',
+                        foreground_color: to_color(top),
+                        background_color: to_color(bottom),
+                        empty: top.is_none() && bottom.is_none(),
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// half blocks make pixels square, so the image fits in columns x 2 * rows pixels
+pub fn ansify(path: &str, columns: u16, rows: u16, dither: bool) -> io::Result<String> {
+    let image: RgbaImage = fit(&load_image(path)?, columns as u32, 2 * rows as u32);
+    let half_blocks: Vec<Vec<TermChar>> = codes_to_half_blocks(&quantize(&image, dither));
+    Ok(rows_to_ansi(&half_blocks, "\n"))
+}
+
+// the image fits in a canvas of columns x rows terminal cells
+pub fn import_image(path: &str, columns: u16, rows: u16, dither: bool) -> io::Result<Layer> {
+    let image: RgbaImage = fit(&load_image(path)?, columns as u32 / 2, rows as u32);
+    let name: String = format!("import {}", path);
+    Ok(codes_to_layer(&name, &quantize(&image, dither), (0, 0)))
+}
+
+// edge pixels of the image painted with code, edges are where the luminance
+// changes sharply and where opaque pixels meet transparent ones, the image
+// bounds are not an edge
+pub fn trace_outline(image: &RgbaImage, code: u8) -> Vec<Vec<Option<u8>>> {
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let opaque = |x: i32, y: i32| image.get_pixel(x as u32, y as u32).0[3] >= ALPHA_THRESHOLD;
+    // samples outside the image repeat the closest pixel
+    let luminance = |x: i32, y: i32| {
+        let [r, g, b, _] = image
+            .get_pixel(x.clamp(0, width - 1) as u32, y.clamp(0, height - 1) as u32)
+            .0;
+        0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+    };
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    if !opaque(x, y) {
+                        return None;
+                    }
+                    let gx = luminance(x + 1, y - 1)
+                        + 2.0 * luminance(x + 1, y)
+                        + luminance(x + 1, y + 1)
+                        - luminance(x - 1, y - 1)
+                        - 2.0 * luminance(x - 1, y)
+                        - luminance(x - 1, y + 1);
+                    let gy = luminance(x - 1, y + 1)
+                        + 2.0 * luminance(x, y + 1)
+                        + luminance(x + 1, y + 1)
+                        - luminance(x - 1, y - 1)
+                        - 2.0 * luminance(x, y - 1)
+                        - luminance(x + 1, y - 1);
+                    let borders_transparent = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                        .iter()
+                        .map(|(dx, dy)| (x + dx, y + dy))
+                        .any(|(nx, ny)| {
+                            (0..width).contains(&nx) && (0..height).contains(&ny) && !opaque(nx, ny)
+                        });
+                    let edge: bool = borders_transparent || gx.hypot(gy) > EDGE_THRESHOLD;
+                    edge.then_some(code)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// outline of the image as imported by import_image, on a layer of its own
+pub fn import_outline(path: &str, columns: u16, rows: u16, code: u8) -> io::Result<Layer> {
+    let image: RgbaImage = fit(&load_image(path)?, columns as u32 / 2, rows as u32);
+    let name: String = format!("outline {}", path);
+    Ok(codes_to_layer(&name, &trace_outline(&image, code), (0, 0)))
+}
+
+// dominant colors of an imported layer, every pixel is counted once
+pub fn extract_palette(layer: &Layer, size: usize) -> Palette {
+    let codes = layer.pixels.iter().filter_map(|(_, color)| match color {
+        Color::AnsiValue(code) => Some(code),
+        _ => None,
+    });
+    Palette {
+        name: layer.name.clone(),
+        colors: dominant_colors(codes, size),
+    }
+}
+
+// high half of code page 437, classic ANSI art is rarely valid utf-8
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+// classic ANSI art is laid out for 80 columns terminals and relies on wrapping
+const CLASSIC_ANSI_WIDTH: usize = 80;
+// SUB marks the end of the art, a SAUCE metadata record may follow
+const END_OF_FILE: char = '\u{1a}';
+
+fn decode_cp437(bytes: &[u8]) -> String {
+    let high: Vec<char> = CP437_HIGH.chars().collect();
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            0x80..=0xff => high[(byte - 0x80) as usize],
+            _ => byte as char,
+        })
+        .collect()
+}
+
+struct SgrState {
+    foreground: Color,
+    background: Color,
+    bold: bool,
+}
+
+impl SgrState {
+    // bold turns the 8 basic foreground colors into their bright versions
+    fn foreground(&self) -> Color {
+        match (self.bold, self.foreground) {
+            (true, Color::AnsiValue(code)) if code < 8 => Color::AnsiValue(code + 8),
+            (_, color) => color,
+        }
+    }
+
+    fn apply(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => {
+                    self.foreground = Color::Reset;
+                    self.background = Color::Reset;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                30..=37 => self.foreground = Color::AnsiValue((param - 30) as u8),
+                90..=97 => self.foreground = Color::AnsiValue((param - 90 + 8) as u8),
+                39 => self.foreground = Color::Reset,
+                40..=47 => self.background = Color::AnsiValue((param - 40) as u8),
+                100..=107 => self.background = Color::AnsiValue((param - 100 + 8) as u8),
+                49 => self.background = Color::Reset,
+                38 | 48 => {
+                    let color: Option<Color> = match params.next() {
+                        Some(5) => params.next().map(|code| Color::AnsiValue(code as u8)),
+                        Some(2) => match (params.next(), params.next(), params.next()) {
+                            (Some(r), Some(g), Some(b)) => Some(Color::Rgb {
+                                r: r as u8,
+                                g: g as u8,
+                                b: b as u8,
+                            }),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match (param, color) {
+                        (38, Some(color)) => self.foreground = color,
+                        (48, Some(color)) => self.background = color,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// rows of chars as a terminal would display them, only SGR colors and cursor
+// forward are understood, every other control sequence is skipped
+pub fn parse_ansi(bytes: &[u8]) -> Vec<Vec<TermChar>> {
+    let (text, wrap): (String, Option<usize>) = match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => (decode_cp437(bytes), Some(CLASSIC_ANSI_WIDTH)),
+    };
+    let mut rows: Vec<Vec<TermChar>> = vec![Vec::new()];
+    let (mut col, mut row) = (0usize, 0usize);
+    let mut sgr = SgrState {
+        foreground: Color::Reset,
+        background: Color::Reset,
+        bold: false,
+    };
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            END_OF_FILE => break,
+            '\r' => col = 0,
+            '\n' => {
+                col = 0;
+                row += 1;
+            }
+            '\x1b' if chars.peek() == Some(&'[') => {
+                chars.next();
+                let mut sequence: String = String::new();
+                let mut command: char = 'm';
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        command = c;
+                        break;
+                    }
+                    sequence.push(c);
+                }
+                let params: Vec<u16> = sequence
+                    .split(';')
+                    .map(|param| param.parse().unwrap_or(0))
+                    .collect();
+                match command {
+                    'm' => sgr.apply(&params),
+                    'C' => col += params.first().copied().unwrap_or(1).max(1) as usize,
+                    _ => {}
+                }
+            }
+            c if c.is_control() => {}
+            c => {
+                if wrap.is_some_and(|width| col >= width) {
+                    col = 0;
+                    row += 1;
+                }
+                while rows.len() <= row {
+                    rows.push(Vec::new());
+                }
+                while rows[row].len() <= col {
+                    rows[row].push(EMPTY_TERM_CHAR);
+                }
+                rows[row][col] = TermChar {
+                    character: c,
+                    foreground_color: sgr.foreground(),
+                    background_color: sgr.background,
+                    empty: c == ' ' && sgr.background == Color::Reset,
+                };
+                col += 1;
+            }
+        }
+    }
+    rows
+}
+
+// every visible cell becomes a single cell item
+pub fn import_ansi(path: &str) -> io::Result<Layer> {
+    let rows: Vec<Vec<TermChar>> = parse_ansi(&fs::read(path)?);
+    let mut layer: Layer = Layer::new_empty(format!("import {}", path), 0, 0, (0, 0));
+    for (y, row) in rows.iter().enumerate() {
+        for (x, term_char) in row.iter().enumerate() {
+            if term_char.empty {
+                continue;
+            }
+            layer.add_item(Item {
+                name: "C".to_string(),
+                offset: (x as i32, y as i32),
+                chars: vec![vec![*term_char]],
+            });
+        }
+    }
+    Ok(layer)
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event};
+use crossterm::terminal;
+use serde::{Deserialize, Serialize};
+
+use pixelrs::error::{self, Context};
+
+// one line of an input recording, at_ms is how long after the session started
+// the event came in
+#[derive(Serialize, Deserialize)]
+struct InputRecord {
+    at_ms: u64,
+    event: Event,
+}
+
+// where the editor reads its events from, the terminal or a recording made
+// with --record-input. a replayed recording is delivered at the pace it was
+// recorded and the terminal input is ignored until it runs out
+pub struct Input {
+    start: Instant,
+    recording: Option<File>,
+    replay: VecDeque<InputRecord>,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Input {
+            start: Instant::now(),
+            recording: None,
+            replay: VecDeque::new(),
+        }
+    }
+
+    // every event read once the session starts is appended to path
+    pub fn record_to(&mut self, path: &str) -> io::Result<()> {
+        self.recording = Some(File::create(path)?);
+        Ok(())
+    }
+
+    pub fn replay_from(&mut self, path: &str) -> error::Result<()> {
+        let recording: String = fs::read_to_string(path).context(path)?;
+        for (number, line) in recording.lines().enumerate() {
+            let record: InputRecord = serde_json::from_str(line)
+                .map_err(io::Error::from)
+                .context(&format!("{} line {}", path, number + 1))?;
+            self.replay.push_back(record);
+        }
+        Ok(())
+    }
+
+    // times are counted from here, not from the setup before the session,
+    // a recording starts with the terminal size so a replay lays the screen
+    // out the same
+    pub fn start(&mut self) {
+        let (width, height): (u16, u16) = terminal::size().unwrap_or((80, 25));
+        self.start = Instant::now();
+        self.record(&Event::Resize(width, height));
+    }
+
+    // same as event::poll, true once an event can be read without blocking
+    pub fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        let Some(record) = self.replay.front() else {
+            return event::poll(timeout);
+        };
+        let due: Instant = self.start + Duration::from_millis(record.at_ms);
+        let wait: Duration = due.saturating_duration_since(Instant::now());
+        thread::sleep(wait.min(timeout));
+        Ok(wait <= timeout)
+    }
+
+    pub fn read(&mut self) -> io::Result<Event> {
+        let event: Event = match self.replay.pop_front() {
+            Some(record) => record.event,
+            None => event::read()?,
+        };
+        self.record(&event);
+        Ok(event)
+    }
+
+    // a recording that can no longer be written loses the rest of the events,
+    // the editor goes on
+    fn record(&mut self, event: &Event) {
+        let Some(file) = self.recording.as_mut() else {
+            return;
+        };
+        let record = InputRecord {
+            at_ms: self.start.elapsed().as_millis() as u64,
+            event: event.clone(),
+        };
+        if let Ok(line) = serde_json::to_string(&record) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    fn key(c: char) -> Event {
+        Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn replays_are_recorded_again_at_their_pace() {
+        let dir = std::env::temp_dir().join(format!("pixelrs-input-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (replayed, recorded) = (dir.join("replayed.jsonl"), dir.join("recorded.jsonl"));
+        let lines: Vec<String> = [(0, key('b')), (200, key('q'))]
+            .into_iter()
+            .map(|(at_ms, event)| serde_json::to_string(&InputRecord { at_ms, event }).unwrap())
+            .collect();
+        fs::write(&replayed, lines.join("\n")).unwrap();
+
+        let mut input: Input = Input::new();
+        input.replay_from(replayed.to_str().unwrap()).unwrap();
+        input.record_to(recorded.to_str().unwrap()).unwrap();
+        // the setup before the session does not count
+        thread::sleep(Duration::from_millis(300));
+        input.start();
+        assert!(input.poll(Duration::ZERO).unwrap());
+        assert!(input.read().unwrap() == key('b'));
+        assert!(!input.poll(Duration::ZERO).unwrap());
+        assert!(input.poll(Duration::from_secs(1)).unwrap());
+        assert!(input.read().unwrap() == key('q'));
+        drop(input);
+
+        let records: Vec<InputRecord> = fs::read_to_string(&recorded)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(matches!(records[0].event, Event::Resize(_, _)));
+        assert!(records[1].event == key('b') && records[1].at_ms < 200);
+        assert!(records[2].event == key('q') && records[2].at_ms >= 200);
+    }
+}
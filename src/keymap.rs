@@ -0,0 +1,551 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Quit,
+    Help,
+    CommandLine,
+    Save,
+    ExportAnsi,
+    ExportText,
+    ExportHtml,
+    ExportPng,
+    Undo,
+    SwapTool,
+    ToolSlot(usize),
+    Brush,
+    Erase,
+    Ink,
+    Shade,
+    Move,
+    Text,
+    TextMode,
+    BoxDraw,
+    Clone,
+    Sprites,
+    Grid,
+    Snap,
+    NewFrame,
+    PreviousFrame,
+    NextFrame,
+    FrameEarlier,
+    FrameLater,
+    OnionSkin,
+    CaptureBrush,
+    Toolbox,
+    Colors,
+    CyclePalette,
+    DuplicateLayer,
+    MergeDown,
+    PreviousLayer,
+    NextLayer,
+    LockLayer,
+    ScratchLayer,
+    Smoothing,
+    Overlay,
+    Fit,
+    Attribution,
+    Stats,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Park,
+    JumpToParked,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    ZoomIn,
+    ZoomOut,
+    Follow,
+    CycleStamp,
+    KeyboardDrawing,
+    Connection,
+    // only on the connection screen, where they win over the ones above
+    SelectPrevious,
+    SelectNext,
+    Kick,
+    Ban,
+    LockCanvas,
+    // only in keyboard drawing mode, where they win over the canvas ones
+    CursorLeft,
+    CursorRight,
+    CursorUp,
+    CursorDown,
+    DrawLeft,
+    DrawRight,
+    DrawUp,
+    DrawDown,
+    Stamp,
+    LeaveKeyboard,
+}
+
+// where a key is pressed, the connection screen and keyboard drawing mode
+// have actions of their own that may reuse the keys of the canvas ones
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Canvas,
+    Connection,
+    Keyboard,
+}
+
+impl Action {
+    fn mode(self) -> Mode {
+        match self {
+            Action::SelectPrevious
+            | Action::SelectNext
+            | Action::Kick
+            | Action::Ban
+            | Action::LockCanvas => Mode::Connection,
+            Action::CursorLeft
+            | Action::CursorRight
+            | Action::CursorUp
+            | Action::CursorDown
+            | Action::DrawLeft
+            | Action::DrawRight
+            | Action::DrawUp
+            | Action::DrawDown
+            | Action::Stamp
+            | Action::LeaveKeyboard => Mode::Keyboard,
+            _ => Mode::Canvas,
+        }
+    }
+}
+
+// name used in the config file, default key and what the help screen says
+const ACTIONS: [(Action, &str, &str, &str); 65] = [
+    (Action::Quit, "quit", "q", "quit"),
+    (Action::Help, "help", "?", "show the key bindings"),
+    (
+        Action::CommandLine,
+        "command_line",
+        ":",
+        "run a command, e.g. :export png",
+    ),
+    (Action::Save, "save", "ctrl+s", "save project"),
+    (
+        Action::ExportAnsi,
+        "export_ansi",
+        "ctrl+e",
+        "export ANSI art",
+    ),
+    (
+        Action::ExportText,
+        "export_text",
+        "ctrl+t",
+        "export plain text",
+    ),
+    (Action::ExportHtml, "export_html", "ctrl+w", "export HTML"),
+    (Action::ExportPng, "export_png", "ctrl+p", "export PNG"),
+    (Action::Undo, "undo", "ctrl+z", "undo"),
+    (
+        Action::SwapTool,
+        "swap_tool",
+        "tab",
+        "switch to the previous tool",
+    ),
+    (Action::ToolSlot(0), "slot1", "1", "tool bar slot 1"),
+    (Action::ToolSlot(1), "slot2", "2", "tool bar slot 2"),
+    (Action::ToolSlot(2), "slot3", "3", "tool bar slot 3"),
+    (Action::ToolSlot(3), "slot4", "4", "tool bar slot 4"),
+    (Action::ToolSlot(4), "slot5", "5", "tool bar slot 5"),
+    (Action::ToolSlot(5), "slot6", "6", "tool bar slot 6"),
+    (Action::ToolSlot(6), "slot7", "7", "tool bar slot 7"),
+    (Action::ToolSlot(7), "slot8", "8", "tool bar slot 8"),
+    (Action::Brush, "brush", "b", "brush"),
+    (Action::Erase, "erase", "e", "erase"),
+    (Action::Ink, "ink", "i", "ink tool"),
+    (Action::Shade, "shade", "v", "shade"),
+    (Action::Move, "move", "m", "move"),
+    (Action::Text, "text", "a", "text"),
+    (
+        Action::TextMode,
+        "text_mode",
+        "A",
+        "next text mode, wide, narrow or banner",
+    ),
+    (Action::BoxDraw, "box", "L", "box drawing lines"),
+    (
+        Action::Clone,
+        "clone",
+        "C",
+        "clone a region, again to sample another",
+    ),
+    (Action::Sprites, "sprites", "S", "sprite library"),
+    (Action::Grid, "grid", "#", "show/hide the grid"),
+    (
+        Action::Snap,
+        "snap",
+        "G",
+        "snap brush strokes and item moves to the grid",
+    ),
+    (
+        Action::NewFrame,
+        "new_frame",
+        "D",
+        "copy the canvas into a new animation frame",
+    ),
+    (
+        Action::PreviousFrame,
+        "previous_frame",
+        ",",
+        "show the previous frame",
+    ),
+    (Action::NextFrame, "next_frame", ".", "show the next frame"),
+    (
+        Action::FrameEarlier,
+        "frame_earlier",
+        "<",
+        "move the frame one earlier",
+    ),
+    (
+        Action::FrameLater,
+        "frame_later",
+        ">",
+        "move the frame one later",
+    ),
+    (
+        Action::OnionSkin,
+        "onion_skin",
+        "O",
+        "show the neighbouring frames dimmed",
+    ),
+    (
+        Action::CaptureBrush,
+        "capture_brush",
+        "k",
+        "shape under the cursor as brush",
+    ),
+    (
+        Action::Toolbox,
+        "toolbox",
+        "ctrl+b",
+        "show/hide the clickable toolbox",
+    ),
+    (Action::Colors, "colors", "c", "open colors"),
+    (Action::CyclePalette, "cycle_palette", "p", "next palette"),
+    (
+        Action::DuplicateLayer,
+        "duplicate_layer",
+        "d",
+        "duplicate active layer",
+    ),
+    (
+        Action::MergeDown,
+        "merge_down",
+        "j",
+        "merge active layer down",
+    ),
+    (
+        Action::PreviousLayer,
+        "previous_layer",
+        "[",
+        "select layer below",
+    ),
+    (Action::NextLayer, "next_layer", "]", "select layer above"),
+    (
+        Action::LockLayer,
+        "lock_layer",
+        "l",
+        "lock/unlock active layer",
+    ),
+    (
+        Action::ScratchLayer,
+        "scratch_layer",
+        "n",
+        "scratch layer and back",
+    ),
+    (Action::Smoothing, "smoothing", "s", "cycle drag smoothing"),
+    (Action::Overlay, "overlay", "o", "pressed keys overlay"),
+    (Action::Fit, "fit", "f", "fitted view"),
+    (Action::Attribution, "attribution", "u", "attribution view"),
+    (Action::Stats, "stats", "t", "statistics panel"),
+    (
+        Action::RotateClockwise,
+        "rotate",
+        "r",
+        "rotate the view clockwise",
+    ),
+    (
+        Action::RotateCounterClockwise,
+        "rotate_back",
+        "R",
+        "rotate the view counter-clockwise",
+    ),
+    (Action::Park, "park", "h", "park the current view"),
+    (Action::JumpToParked, "jump", "g", "jump to the parked view"),
+    (Action::PanLeft, "pan_left", "left", "pan the view left"),
+    (Action::PanRight, "pan_right", "right", "pan the view right"),
+    (Action::PanUp, "pan_up", "up", "pan the view up"),
+    (Action::PanDown, "pan_down", "down", "pan the view down"),
+    (Action::ZoomIn, "zoom_in", "+", "zoom the view in"),
+    (Action::ZoomOut, "zoom_out", "-", "zoom the view out"),
+    (Action::Follow, "follow", "w", "follow another participant"),
+    (
+        Action::CycleStamp,
+        "cycle_stamp",
+        "y",
+        "next stamp as brush",
+    ),
+    (
+        Action::KeyboardDrawing,
+        "keyboard",
+        "z",
+        "keyboard drawing mode",
+    ),
+    (Action::Connection, "connection", "x", "connection screen"),
+];
+
+// the connection screen ones are kept apart, they share keys with the others
+const CONNECTION_ACTIONS: [(Action, &str, &str, &str); 5] = [
+    (
+        Action::SelectPrevious,
+        "select_previous",
+        "up",
+        "select previous peer",
+    ),
+    (
+        Action::SelectNext,
+        "select_next",
+        "down",
+        "select next peer",
+    ),
+    (Action::Kick, "kick", "k", "kick the selected peer"),
+    (Action::Ban, "ban", "b", "ban the selected peer"),
+    (
+        Action::LockCanvas,
+        "lock_canvas",
+        "l",
+        "lock/unlock the canvas",
+    ),
+];
+
+// so are the keyboard drawing ones, the arrows move and draw as well
+const KEYBOARD_ACTIONS: [(Action, &str, &str, &str); 10] = [
+    (
+        Action::CursorLeft,
+        "cursor_left",
+        "h",
+        "move the cursor left (keyboard)",
+    ),
+    (
+        Action::CursorRight,
+        "cursor_right",
+        "l",
+        "move the cursor right (keyboard)",
+    ),
+    (
+        Action::CursorUp,
+        "cursor_up",
+        "k",
+        "move the cursor up (keyboard)",
+    ),
+    (
+        Action::CursorDown,
+        "cursor_down",
+        "j",
+        "move the cursor down (keyboard)",
+    ),
+    (Action::DrawLeft, "draw_left", "H", "draw left (keyboard)"),
+    (Action::DrawRight, "draw_right", "L", "draw right (keyboard)"),
+    (Action::DrawUp, "draw_up", "K", "draw up (keyboard)"),
+    (Action::DrawDown, "draw_down", "J", "draw down (keyboard)"),
+    (
+        Action::Stamp,
+        "stamp",
+        "space",
+        "use the tool under the cursor (keyboard)",
+    ),
+    (
+        Action::LeaveKeyboard,
+        "leave_keyboard",
+        "esc",
+        "leave keyboard drawing (keyboard)",
+    ),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    code: KeyCode,
+    ctrl: bool,
+}
+
+impl Key {
+    // a single character, or tab, space, up, down, left, right, esc, enter,
+    // backspace, optionally prefixed with ctrl+
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let (ctrl, rest) = match name.strip_prefix("ctrl+") {
+            Some(rest) => (true, rest),
+            None => (false, name),
+        };
+        let code: KeyCode = match rest {
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "esc" => KeyCode::Esc,
+            "enter" => KeyCode::Enter,
+            "backspace" => KeyCode::Backspace,
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(format!("invalid key {}", name)),
+                }
+            }
+        };
+        Ok(Key { code, ctrl })
+    }
+
+    // shift only changes the character, e.g. R is shift+r
+    fn matches(&self, event: &KeyEvent) -> bool {
+        self.code == event.code && self.ctrl == event.modifiers.contains(KeyModifiers::CONTROL)
+    }
+
+    pub fn label(&self) -> String {
+        let key: String = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) => c.to_string(),
+            code => format!("{:?}", code),
+        };
+        match self.ctrl {
+            true => format!("Ctrl+{}", key),
+            false => key,
+        }
+    }
+}
+
+pub struct Keymap {
+    bindings: Vec<(Action, Key)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings: Vec<(Action, Key)> = ACTIONS
+            .iter()
+            .chain(CONNECTION_ACTIONS.iter())
+            .chain(KEYBOARD_ACTIONS.iter())
+            .map(|(action, _, key, _)| (*action, Key::parse(key).expect("invalid default key")))
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    // replaces the key of the action named name
+    pub fn bind(&mut self, name: &str, key: &str) -> Result<(), String> {
+        let (action, _, _, _) = ACTIONS
+            .iter()
+            .chain(CONNECTION_ACTIONS.iter())
+            .chain(KEYBOARD_ACTIONS.iter())
+            .find(|(_, action_name, _, _)| *action_name == name)
+            .ok_or_else(|| format!("unknown action {}", name))?;
+        let key: Key = Key::parse(key)?;
+        for (bound, bound_key) in self.bindings.iter_mut() {
+            if bound == action {
+                *bound_key = key;
+            }
+        }
+        Ok(())
+    }
+
+    // a key does one thing per mode, connection screen and keyboard drawing
+    // actions may reuse the keys of the canvas ones
+    pub fn check(&self) -> Result<(), String> {
+        for (i, (action, key)) in self.bindings.iter().enumerate() {
+            let clash = self.bindings[i + 1..]
+                .iter()
+                .find(|(other, other_key)| other_key == key && other.mode() == action.mode());
+            if let Some((other, _)) = clash {
+                return Err(format!(
+                    "{} is bound to both {} and {}",
+                    key.label(),
+                    name(*action),
+                    name(*other)
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // the actions of the mode win, the canvas ones fill in
+    pub fn action(&self, event: &KeyEvent, mode: Mode) -> Option<Action> {
+        let bound = |mode: Mode| {
+            self.bindings
+                .iter()
+                .filter(|(action, _)| action.mode() == mode)
+                .find(|(_, key)| key.matches(event))
+                .map(|(action, _)| *action)
+        };
+        bound(mode).or_else(|| bound(Mode::Canvas))
+    }
+
+    // entries of the help screen, key, what it does and the name of the action
+    // in the config file, in the order of ACTIONS
+    pub fn help(&self) -> Vec<(String, &'static str, &'static str)> {
+        self.bindings
+            .iter()
+            .map(|(action, key)| {
+                let (_, name, _, description) = described(*action);
+                (key.label(), *description, *name)
+            })
+            .collect()
+    }
+
+    pub fn key_label(&self, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(other, _)| *other == action)
+            .map(|(_, key)| key.label())
+    }
+}
+
+fn described(action: Action) -> &'static (Action, &'static str, &'static str, &'static str) {
+    ACTIONS
+        .iter()
+        .chain(CONNECTION_ACTIONS.iter())
+        .chain(KEYBOARD_ACTIONS.iter())
+        .find(|(other, _, _, _)| *other == action)
+        .expect("every action is described")
+}
+
+fn name(action: Action) -> &'static str {
+    described(action).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn default_keys_do_one_thing_per_mode() {
+        assert!(Keymap::default().check().is_ok());
+    }
+
+    #[test]
+    fn keyboard_actions_win_in_keyboard_mode() {
+        let keymap: Keymap = Keymap::default();
+        let k: KeyEvent = press(KeyCode::Char('k'), KeyModifiers::NONE);
+        let shifted: KeyEvent = press(KeyCode::Char('K'), KeyModifiers::SHIFT);
+        let undo: KeyEvent = press(KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.action(&k, Mode::Canvas), Some(Action::CaptureBrush));
+        assert_eq!(keymap.action(&k, Mode::Keyboard), Some(Action::CursorUp));
+        assert_eq!(keymap.action(&shifted, Mode::Keyboard), Some(Action::DrawUp));
+        assert_eq!(keymap.action(&undo, Mode::Keyboard), Some(Action::Undo));
+    }
+
+    #[test]
+    fn keyboard_actions_can_be_rebound() {
+        let mut keymap: Keymap = Keymap::default();
+        keymap.bind("draw_left", "ctrl+h").unwrap();
+        let rebound: KeyEvent = press(KeyCode::Char('h'), KeyModifiers::CONTROL);
+        assert_eq!(keymap.action(&rebound, Mode::Keyboard), Some(Action::DrawLeft));
+        keymap.bind("stamp", "h").unwrap();
+        assert!(keymap.check().is_err());
+    }
+}
@@ -0,0 +1,28 @@
+pub mod archive;
+pub mod assets;
+mod backend;
+pub mod boxdraw;
+pub mod cast;
+pub mod client;
+pub mod constants;
+pub mod crdt;
+pub mod error;
+pub mod export;
+pub mod figlet;
+mod grid;
+pub mod history;
+pub mod import;
+pub mod palette;
+pub mod project;
+pub mod protocol;
+pub mod regions;
+pub mod screen;
+pub mod script;
+pub mod server;
+pub mod shade;
+pub mod sprites;
+pub mod storage;
+pub mod text;
+pub mod traffic;
+pub mod transform;
+mod websocket;
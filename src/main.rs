@@ -1,35 +1,322 @@
-use core::panic;
-use std::env;
+use std::io::{self, Write};
+use std::path::Path;
 
-use std::process::{Command, Stdio};
-mod constants;
+use clap::Parser;
+use pixelrs::{
+    archive, assets, constants, export, history, import, project, regions, screen, server, sprites,
+    storage, traffic,
+};
+
+mod cli;
+mod command_line;
 mod draw_term;
-mod screen;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod hooks;
+mod input;
+mod keymap;
+mod settings;
+
+use cli::Command;
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
+    let cli: cli::Cli = cli::Cli::parse();
+
+    // defaults of the config file, the flags win over them
+    let settings: settings::Settings =
+        settings::load(&settings::config_path()).unwrap_or_else(|e| cli::abort(&e));
+
+    let cell_aspect: (usize, usize) = cli.cell_aspect.unwrap_or(constants::DEFAULT_CELL_ASPECT);
+
+    // PIXELRS_PASSWORD is read too so the password stays out of the process list
+    let password: Option<String> = cli.password.filter(|password| !password.is_empty());
+
+    // `--render` picks how the screen is repainted, detected from $TERM otherwise
+    let render: Option<screen::RenderMode> = cli.render.or(settings.render);
+
+    // `--hook event=command` runs command on saved, exported, session-joined and
+    // participant-joined events, it can be given several times
+    let mut hooks: hooks::Hooks = hooks::Hooks::default();
+    for spec in &cli.hook {
+        hooks.add(spec).unwrap_or_else(|e| cli::fail(&e));
+    }
+
     let mut addr: Option<String> = None;
+    let mut reset: Option<(archive::ResetSchedule, String)> = None;
+    let mut regions: Vec<regions::Region> = Vec::new();
+
+    match &cli.command {
+        // `heatmap <history> <out.png>` renders where the edits of a persisted history went
+        Some(Command::Heatmap { history, out }) => {
+            let history = history::SqliteHistory::new(history)
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", history, e)));
+            let counts = export::edit_counts(&history);
+            export::export_heatmap(&counts, out, constants::DEFAULT_PNG_SCALE, cell_aspect)
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", out, e)));
+            return;
+        }
+        // `montage <dir> [--cols N] [--out file.png]` tiles every project in dir
+        Some(Command::Montage { dir, cols, out }) => {
+            export::export_montage(dir, *cols, out, constants::DEFAULT_PNG_SCALE, cell_aspect)
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", out, e)));
+            return;
+        }
+        // `assets add <url> [--sha256 <sum>]` installs a pack of palettes and stamps,
+        // `assets list` shows the installed ones
+        Some(Command::Assets(cli::Assets::Add { url, sha256 })) => {
+            let pack = assets::add(url, sha256.as_deref())
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", url, e)));
+            println!(
+                "installed {}, {} palettes and {} stamps, into {}",
+                pack.name,
+                pack.palettes.len(),
+                pack.stamps.len(),
+                assets::asset_dir().display()
+            );
+            return;
+        }
+        Some(Command::Assets(cli::Assets::List)) => {
+            for pack in assets::installed_packs() {
+                println!(
+                    "{:<16} {} palettes, {} stamps",
+                    pack.name,
+                    pack.palettes.len(),
+                    pack.stamps.len()
+                );
+            }
+            return;
+        }
+        // `ansify <image> [--dither]` prints the image in the terminal and exits
+        Some(Command::Ansify { image, dither }) => {
+            // one row is left for the prompt that follows
+            let (columns, rows) = crossterm::terminal::size().unwrap_or((80, 25));
+            let ansi: String = import::ansify(image, columns, rows.saturating_sub(1), *dither)
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", image, e)));
+            print!("{}", ansi);
+            return;
+        }
+        // `export <file.pxr> <out> [--format <format>]` renders a project without
+        // the tui, the format is told by the extension of out when not given,
+        // `export <file.pxr> <format> [out]` is kept and `export --list` shows them
+        Some(Command::Export {
+            file,
+            target,
+            out,
+            format,
+            list,
+        }) => {
+            let exporters = export::Exporters::new(cell_aspect);
+            let (Some(file), Some(target)) = (file, target) else {
+                debug_assert!(*list);
+                print!("{}", exporters.list());
+                return;
+            };
+            let lookup = |name: &str| {
+                exporters
+                    .get(name)
+                    .or_else(|| exporters.for_extension(name))
+                    .unwrap_or_else(|| {
+                        cli::fail(&format!("unknown format {}, see export --list", name))
+                    })
+            };
+            let (exporter, out) = match (format.as_deref(), out) {
+                (Some(format), None) => (lookup(format), Some(target.clone())),
+                (None, out) if exporters.get(target).is_some() => (lookup(target), out.clone()),
+                (None, None) => {
+                    let extension = Path::new(target).extension().and_then(|e| e.to_str());
+                    let Some(extension) = extension else {
+                        cli::fail(&format!(
+                            "cannot tell the format of {}, use --format",
+                            target
+                        ));
+                    };
+                    (lookup(extension), Some(target.clone()))
+                }
+                _ => cli::fail("export expects <file.pxr> <out> [--format <format>] or --list"),
+            };
+            let out: String = out.unwrap_or_else(|| {
+                Path::new(file)
+                    .with_extension(exporter.extension())
+                    .to_string_lossy()
+                    .to_string()
+            });
+            // failures exit with an error status, exports are meant to be scripted
+            let project = project::Project::load(file)
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", file, e)));
+            exporter
+                .export(&project.layers, &out)
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", out, e)));
+            return;
+        }
+        Some(Command::Analyze { traffic }) => {
+            let summary: String = traffic::analyze(traffic)
+                .unwrap_or_else(|e| cli::abort(&format!("{}: {}", traffic, e)));
+            print!("{}", summary);
+            return;
+        }
+        Some(Command::Serve {
+            reset: schedule,
+            archive,
+            region,
+            ..
+        }) => {
+            // only the hosting session resets, peers receive the wipe like any erase
+            reset = schedule.map(|schedule| (schedule, archive.clone()));
+            regions = region.clone();
+        }
+        _ => {}
+    }
+
+    let mut hosting: Option<server::Host> = None;
+    if let Some(Command::Serve { host, port, .. } | Command::Connect { host, port }) = &cli.command
+    {
+        let server: String = match (host, port, &settings.server) {
+            (Some(host), Some(port), _) => format!("{}:{}", host, port),
+            (None, _, Some(server)) => match server.rsplit_once(':') {
+                Some((_, port)) if port.parse::<u16>().is_ok() => server.clone(),
+                _ => cli::abort(&format!("invalid server {}, expected host:port", server)),
+            },
+            _ => cli::fail("expected <host> <port>, or server in the config file"),
+        };
 
-    if args.len() == 4 {
-        let host = args[2].clone();
-        let port = args[3].parse::<u16>().unwrap();
-        addr = Some(format!("{}:{}", host, port));
-
-        if args[1] == "serve" {
-            let _server_process = Command::new("../pixelrs-server/target/debug/pixelrs-server")
-                .arg(host.clone())
-                .arg(port.to_string())
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .spawn()
-                .expect("Failed to start server process");
-        } else if args[1] == "connect" {
-            println!("Connecting to {}", addr.clone().expect(""));
+        if let Some(Command::Serve { store, .. }) = &cli.command {
+            let store = store.as_ref().map(|path| {
+                storage::open(path).unwrap_or_else(|e| cli::abort(&format!("{}: {}", path, e)))
+            });
+            // the server lives as long as the hosting session
+            hosting = Some(
+                server::spawn(&server, password.clone(), store)
+                    .unwrap_or_else(|e| cli::abort(&format!("serving on {}: {}", server, e))),
+            );
         } else {
-            panic!("Unknown options");
+            println!("Connecting to {}", server);
         }
+        addr = Some(server);
     }
 
     let mut draw_term = draw_term::DrawTerm::new();
-    draw_term.run(addr);
+    // an opened project brings back its own color
+    if let Some(color) = settings.color {
+        draw_term.set_start_color(color);
+    }
+    if let Some(tool) = &settings.tool {
+        draw_term.set_start_tool(tool).unwrap_or_else(|e| {
+            cli::abort(&format!("{}: {}", settings::config_path().display(), e))
+        });
+    }
+    if let Some(canvas) = settings.canvas {
+        draw_term.set_canvas(canvas);
+    }
+    if let Some(server) = &settings.server {
+        draw_term.set_server(server);
+    }
+    if let Some(interval) = settings.autosave {
+        draw_term.set_autosave(interval);
+    }
+    draw_term.set_keymap(settings.keymap);
+    draw_term.load_assets(assets::installed_packs());
+    draw_term.load_sprites(sprites::saved());
+    if let Some(host) = hosting {
+        draw_term.host_session(host);
+    }
+    if let Some(Command::Open { file }) = &cli.command {
+        // another instance, maybe on another machine sharing the folder, may
+        // have the project open, saving over it would clobber its work
+        let lock = project::ProjectLock::acquire(file)
+            .unwrap_or_else(|e| cli::abort(&format!("locking {}: {}", file, e)));
+        match lock {
+            Ok(lock) => draw_term.hold_lock(lock),
+            Err(holder) => {
+                println!("{} is already open ({})", file, holder);
+                println!("remove {}.lock if that instance is gone", file);
+                print!("open read-only? [y/N] ");
+                let mut answer: String = String::new();
+                // a closed stdin answers no
+                let _ = io::stdout().flush();
+                let _ = io::stdin().read_line(&mut answer);
+                if !answer.trim().eq_ignore_ascii_case("y") {
+                    return;
+                }
+                draw_term.set_read_only();
+            }
+        }
+        draw_term
+            .open_project(file)
+            .unwrap_or_else(|e| cli::abort(&e.to_string()));
+    }
+    if cli.history {
+        draw_term
+            .persist_history()
+            .unwrap_or_else(|e| cli::abort(&e.to_string()));
+    }
+    draw_term.set_cell_aspect(cell_aspect);
+    draw_term.set_fit(cli.fit);
+    if let Some(render) = render {
+        draw_term.set_render_mode(render);
+    }
+    draw_term.set_hooks(hooks);
+    if let Some(password) = &password {
+        draw_term.set_password(password);
+    }
+    if cli.websocket {
+        draw_term.use_websocket();
+    }
+    if cli.replay_history {
+        draw_term.replay_history_on_join();
+    }
+    if let Some((schedule, archive_dir)) = reset {
+        draw_term.schedule_resets(schedule, &archive_dir);
+    }
+    for region in regions {
+        draw_term.assign_region(region);
+    }
+    if let Some(user) = &cli.name {
+        draw_term.set_user(user);
+    }
+    if cli.overlay {
+        draw_term.toggle_overlay();
+    }
+    if cli.keyboard {
+        draw_term.toggle_keyboard();
+    }
+    if cli.no_confirm {
+        draw_term.skip_quit_confirmation();
+    }
+    let mut input = input::Input::new();
+    if let Some(path) = &cli.replay {
+        input
+            .replay_from(path)
+            .unwrap_or_else(|e| cli::abort(&e.to_string()));
+    }
+    if let Some(path) = &cli.record_input {
+        input
+            .record_to(path)
+            .unwrap_or_else(|e| cli::abort(&format!("{}: {}", path, e)));
+    }
+    draw_term.set_input(input);
+    if let Some(path) = &cli.cast {
+        draw_term
+            .record_cast(path)
+            .unwrap_or_else(|e| cli::abort(&e.to_string()));
+    }
+    // `import <file> [--dither] [--outline <code>]` starts a session with the file
+    // on its own layer, .ans and .txt files are read as ANSI art and anything
+    // else as an image
+    if let Some(Command::Import {
+        file,
+        dither,
+        outline,
+    }) = &cli.command
+    {
+        let extension = Path::new(file).extension().and_then(|ext| ext.to_str());
+        let imported = match extension {
+            Some("ans") | Some("txt") => draw_term.import_ansi(file),
+            _ => draw_term.import_image(file, *dither, *outline),
+        };
+        imported.unwrap_or_else(|e| cli::abort(&e.to_string()));
+    }
+    // the terminal is back to normal by the time the error is printed
+    if let Err(e) = draw_term.run(addr, &cli.room, cli.record) {
+        cli::abort(&e.to_string());
+    }
 }
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+// xterm default values for the 16 system colors
+const SYSTEM_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (128, 0, 0),
+    (0, 128, 0),
+    (128, 128, 0),
+    (0, 0, 128),
+    (128, 0, 128),
+    (0, 128, 128),
+    (192, 192, 192),
+    (128, 128, 128),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (0, 0, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// levels of each channel in the 6x6x6 color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+pub fn ansi_to_rgb(code: u8) -> (u8, u8, u8) {
+    match code {
+        0..=15 => SYSTEM_COLORS[code as usize],
+        16..=231 => {
+            let i = code - 16;
+            (
+                CUBE_LEVELS[(i / 36) as usize],
+                CUBE_LEVELS[((i / 6) % 6) as usize],
+                CUBE_LEVELS[(i % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (code - 232);
+            (level, level, level)
+        }
+    }
+}
+
+// terminal defaults have no fixed value, Reset yields None
+pub fn color_to_rgb(color: Color) -> Option<(u8, u8, u8)> {
+    let code: u8 = match color {
+        Color::Reset => return None,
+        Color::Rgb { r, g, b } => return Some((r, g, b)),
+        Color::AnsiValue(code) => code,
+        Color::Black => 0,
+        Color::DarkRed => 1,
+        Color::DarkGreen => 2,
+        Color::DarkYellow => 3,
+        Color::DarkBlue => 4,
+        Color::DarkMagenta => 5,
+        Color::DarkCyan => 6,
+        Color::Grey => 7,
+        Color::DarkGrey => 8,
+        Color::Red => 9,
+        Color::Green => 10,
+        Color::Yellow => 11,
+        Color::Blue => 12,
+        Color::Magenta => 13,
+        Color::Cyan => 14,
+        Color::White => 15,
+    };
+    Some(ansi_to_rgb(code))
+}
+
+// color moved towards black by amount (0 keeps it, 1 is black), snapped back
+// to the palette, terminal defaults are left as they are
+pub fn darken(color: Color, amount: f32) -> Color {
+    let Some((r, g, b)) = color_to_rgb(color) else {
+        return color;
+    };
+    let scale = |channel: u8| (channel as f32 * (1.0 - amount.clamp(0.0, 1.0))).round() as i32;
+    Color::AnsiValue(nearest_ansi((scale(r), scale(g), scale(b))))
+}
+
+// the 16 system colors are left out, terminal themes redefine them freely
+pub const QUANTIZATION_RANGE: std::ops::RangeInclusive<u8> = 16..=255;
+
+fn distance((r0, g0, b0): (u8, u8, u8), (r1, g1, b1): (i32, i32, i32)) -> i32 {
+    let (dr, dg, db) = (r0 as i32 - r1, g0 as i32 - g1, b0 as i32 - b1);
+    dr * dr + dg * dg + db * db
+}
+
+// closest palette entry by euclidean distance in rgb space, channels may fall
+// out of 0..=255 while error diffusion is in progress
+pub fn nearest_ansi(rgb: (i32, i32, i32)) -> u8 {
+    QUANTIZATION_RANGE
+        .min_by_key(|&code| distance(ansi_to_rgb(code), rgb))
+        .unwrap_or(16)
+}
+
+// closest of the 16 system colors, the only ones every terminal has
+pub fn nearest_system(rgb: (u8, u8, u8)) -> u8 {
+    let rgb: (i32, i32, i32) = (rgb.0 as i32, rgb.1 as i32, rgb.2 as i32);
+    (0..16u8)
+        .min_by_key(|&code| distance(SYSTEM_COLORS[code as usize], rgb))
+        .unwrap_or(0)
+}
+
+// hue in degrees and saturation in 0..=1 of an rgb color, value is left out
+fn hue_saturation((r, g, b): (u8, u8, u8)) -> (f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max: f32 = r.max(g).max(b);
+    let delta: f32 = max - r.min(g).min(b);
+    if delta == 0.0 {
+        return (0.0, 0.0);
+    }
+    let hue: f32 = match max {
+        m if m == r => 60.0 * ((g - b) / delta).rem_euclid(6.0),
+        m if m == g => 60.0 * ((b - r) / delta + 2.0),
+        _ => 60.0 * ((r - g) / delta + 4.0),
+    };
+    (hue, delta / max)
+}
+
+// greys have no hue to build a harmony on
+const MIN_HARMONY_SATURATION: f32 = 0.2;
+
+// hue rotations of the companions of each color harmony
+const HARMONIES: [(&str, &[f32]); 3] = [
+    ("complementary", &[180.0]),
+    ("analogous", &[-30.0, 30.0]),
+    ("triadic", &[-120.0, 120.0]),
+];
+
+// for every harmony the palette colors closest in hue to the companions of
+// color, nothing when color or the palette have no hue to speak of
+pub fn harmony_suggestions(color: Color, palette: &[u8]) -> Vec<(&'static str, Vec<u8>)> {
+    let Some((hue, saturation)) = color_to_rgb(color).map(hue_saturation) else {
+        return Vec::new();
+    };
+    let candidates: Vec<(u8, f32)> = palette
+        .iter()
+        .filter(|&&code| Color::AnsiValue(code) != color)
+        .map(|&code| (code, hue_saturation(ansi_to_rgb(code))))
+        .filter(|(_, (_, saturation))| *saturation >= MIN_HARMONY_SATURATION)
+        .map(|(code, (hue, _))| (code, hue))
+        .collect();
+    if saturation < MIN_HARMONY_SATURATION || candidates.is_empty() {
+        return Vec::new();
+    }
+    let hue_distance = |a: f32, b: f32| {
+        let d: f32 = (a - b).rem_euclid(360.0);
+        d.min(360.0 - d)
+    };
+    HARMONIES
+        .iter()
+        .map(|(name, rotations)| {
+            let codes: Vec<u8> = rotations
+                .iter()
+                .filter_map(|rotation| {
+                    let target: f32 = hue + rotation;
+                    candidates
+                        .iter()
+                        .min_by(|(_, a), (_, b)| {
+                            hue_distance(*a, target).total_cmp(&hue_distance(*b, target))
+                        })
+                        .map(|(code, _)| *code)
+                })
+                .collect();
+            (*name, codes)
+        })
+        .collect()
+}
+
+// named set of colors offered by the color picker
+#[derive(Serialize, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<u8>,
+}
+
+impl Palette {
+    pub fn system() -> Self {
+        Palette {
+            name: "system".to_string(),
+            colors: (0..16).collect(),
+        }
+    }
+}
+
+// the n most used codes, ties are broken by the lowest code to stay stable
+pub fn dominant_colors(codes: impl Iterator<Item = u8>, n: usize) -> Vec<u8> {
+    let mut counts: HashMap<u8, usize> = HashMap::new();
+    for code in codes {
+        *counts.entry(code).or_default() += 1;
+    }
+    let mut ranked: Vec<(u8, usize)> = counts.into_iter().collect();
+    ranked.sort_by_key(|&(code, count)| (std::cmp::Reverse(count), code));
+    ranked.into_iter().take(n).map(|(code, _)| code).collect()
+}
@@ -0,0 +1,167 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::screen::Layer;
+
+// bumped whenever the layout of Project changes in a non backwards compatible way
+pub const PROJECT_VERSION: u32 = 3;
+pub const DEFAULT_PROJECT_PATH: &str = "untitled.pxr";
+// previous versions kept as <path>.bak.1 (newest) up to <path>.bak.N
+pub const PROJECT_BACKUPS: usize = 3;
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectMetadata {
+    pub name: String,
+    pub pixelrs_version: String,
+    pub created_at: u64,
+    pub saved_at: u64,
+    // seconds of active editing and strokes over every session, projects saved
+    // before they were tracked start from zero
+    #[serde(default)]
+    pub time_spent: u64,
+    #[serde(default)]
+    pub strokes: u64,
+}
+
+// everything needed to restore a drawing session, the ui layer is not part of it
+#[derive(Serialize, Deserialize)]
+pub struct Project {
+    pub version: u32,
+    pub metadata: ProjectMetadata,
+    pub layers: Vec<Layer>,
+    pub active_layer: usize,
+    pub color_selected: Color,
+    // animation frames other than the one in layers, which goes before
+    // frames[frame], projects without frames have a single one
+    #[serde(default)]
+    pub frames: Vec<Vec<Layer>>,
+    #[serde(default)]
+    pub frame: usize,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// advisory lock held while a project is open, it only guards against other
+// pixelrs instances and is removed when dropped
+pub struct ProjectLock {
+    path: String,
+}
+
+impl ProjectLock {
+    // the lock file is created exclusively and records who holds it, when it
+    // already exists the holder is returned instead
+    pub fn acquire(project_path: &str) -> io::Result<Result<ProjectLock, String>> {
+        let path: String = format!("{}.lock", project_path);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let host: String = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".into());
+                writeln!(file, "pid {} on {} since {}", process::id(), host, now())?;
+                Ok(Ok(ProjectLock { path }))
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let holder: String = fs::read_to_string(&path).unwrap_or_default();
+                Ok(Err(holder.trim().to_string()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for ProjectLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn backup_path(path: &str, n: usize) -> String {
+    format!("{}.bak.{}", path, n)
+}
+
+// the previous version is rotated into the backups and the new one is written
+// to a temporary file that replaces it with a rename once it is on disk, so an
+// interrupted save leaves either the old or the new project, never half of one
+fn write_atomic(path: &str, contents: &[u8]) -> io::Result<()> {
+    let tmp_path: String = format!("{}.tmp", path);
+    let mut tmp: File = File::create(&tmp_path)?;
+    tmp.write_all(contents)?;
+    tmp.sync_all()?;
+    drop(tmp);
+
+    if Path::new(path).exists() {
+        for n in (1..PROJECT_BACKUPS).rev() {
+            if Path::new(&backup_path(path, n)).exists() {
+                fs::rename(backup_path(path, n), backup_path(path, n + 1))?;
+            }
+        }
+        fs::copy(path, backup_path(path, 1))?;
+    }
+    fs::rename(&tmp_path, path)?;
+
+    // the rename itself is only durable once the directory is synced, not
+    // every platform allows opening a directory so this is best effort
+    let parent = Path::new(path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+impl ProjectMetadata {
+    pub fn new(path: &str) -> Self {
+        let name: String = Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "untitled".to_string());
+        ProjectMetadata {
+            name,
+            pixelrs_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: now(),
+            saved_at: now(),
+            time_spent: 0,
+            strokes: 0,
+        }
+    }
+}
+
+impl Project {
+    pub fn save(&mut self, path: &str) -> io::Result<()> {
+        self.metadata.saved_at = now();
+        let serialized: String = serde_json::to_string(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic(path, serialized.as_bytes())
+    }
+
+    pub fn load(path: &str) -> io::Result<Project> {
+        let serialized: String = fs::read_to_string(path)?;
+        let mut project: Project = serde_json::from_str(&serialized)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if project.version > PROJECT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "project version {} is newer than supported version {}",
+                    project.version, PROJECT_VERSION
+                ),
+            ));
+        }
+        // version 1 projects hold their pixels as items
+        for layer in project.layers.iter_mut() {
+            layer.absorb_pixel_items();
+        }
+        Ok(project)
+    }
+}
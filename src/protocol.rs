@@ -0,0 +1,433 @@
+use std::cmp::Ordering;
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+use serde_json::{to_string, Deserializer};
+
+use crate::constants::{BINARY_FRAME_TAG, EMPTY_TERM_CHAR, MAX_MESSAGE_LEN, PROTOCOL_VERSION};
+use crate::crdt::Stamp;
+use crate::regions::Regions;
+use crate::screen::{Item, TermChar};
+
+// every message peers of a shared session exchange
+#[derive(Serialize, Deserialize)]
+pub enum Update {
+    TermChar(SerializableTermChar),
+    Erase(SerializableErase),
+    Sync(SerializebleSync),
+    Checksum(SerializableChecksum),
+    Hello(SerializableHello),
+    Text(SerializableText),
+    Move(SerializableMove),
+    Ping(SerializablePing),
+    Pong(SerializablePing),
+    Stroke(SerializableStroke),
+    ArchiveRequest(SerializableArchiveRequest),
+    ArchiveList(SerializableArchiveList),
+    ArchiveFile(SerializableArchiveFile),
+    Regions(Regions),
+    Viewport(SerializableViewport),
+    HistoryRequest(SerializableHistoryRequest),
+    History(SerializableHistory),
+}
+
+// asks the server for every write made to the shared layer so far
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SerializableHistoryRequest {
+    pub nonce: u64,
+}
+
+// a write to the shared layer as the server journals it
+#[derive(Serialize, Deserialize, Clone)]
+pub enum SerializableWrite {
+    Pixel(SerializableTermChar),
+    Text(SerializableText),
+    Erase(SerializableErase),
+}
+
+// writes to the shared layer oldest first, only the peer that asked with the
+// nonce replays them
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableHistory {
+    pub nonce: u64,
+    pub writes: Vec<SerializableWrite>,
+}
+
+// asks the hosting session for the archived pieces of the room, or for the
+// files of one of them
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableArchiveRequest {
+    pub piece: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableArchiveList {
+    pub pieces: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableArchiveFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// pixels painted by a peer within STROKE_FLUSH_INTERVAL, in painting order
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableStroke {
+    pub cells: Vec<SerializableTermChar>,
+}
+
+// offset of the shared layer on the screen of user, followers pan along
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableViewport {
+    pub user: String,
+    pub x: i32,
+    pub y: i32,
+}
+
+// heartbeat, the server answers pings with pongs itself and the sender
+// matches them by nonce, peers of an older server that relays pings echo
+// them back instead
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SerializablePing {
+    pub nonce: u64,
+}
+
+// coordinates sent over the network are always in the canvas space of the
+// shared layer, layer 0, whatever its offset on each peer screen
+
+// any item that is not a pixel, text, glyphs, sprites and the like, whole
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableText {
+    pub abs_x: i32,
+    pub abs_y: i32,
+    // the first char of the item, all there was to it for older peers
+    pub term_char: TermChar,
+    // name of the user who drew it, empty for older peers
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub stamp: Stamp,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub chars: Vec<Vec<TermChar>>,
+}
+
+// displacement of the shared layer, no longer sent since moving the layer
+// only moves one's own view, kept so older peers still parse
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SerializableMove {
+    pub dx: i32,
+    pub dy: i32,
+}
+
+// pixels travel as term chars and anything else as text, item offsets are
+// already in canvas space. peers keep the very same item the author has
+pub fn item_update(item: &Item, author: &str) -> Update {
+    let term_char: TermChar = item.chars[0][0];
+    match term_char.background_color {
+        Color::AnsiValue(_) if term_char.character == ' ' => {
+            Update::TermChar(SerializableTermChar {
+                author: author.to_string(),
+                ..SerializableTermChar::from_pixel(item.clone(), item.offset.0, item.offset.1)
+            })
+        }
+        _ => Update::Text(SerializableText {
+            abs_x: item.offset.0,
+            abs_y: item.offset.1,
+            term_char,
+            author: author.to_string(),
+            stamp: Stamp::default(),
+            name: item.name.clone(),
+            chars: item.chars.clone(),
+        }),
+    }
+}
+
+// protocol announcement, always sent as JSON so older peers can read it,
+// replies are not answered again
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableHello {
+    pub protocol: u8,
+    pub reply: bool,
+    // crate version of the peer, empty for older peers
+    #[serde(default)]
+    pub version: String,
+    // participant id the server gave the peer, none from older servers or peers
+    #[serde(default)]
+    pub participant: Option<u64>,
+}
+
+impl SerializableHello {
+    pub fn new(reply: bool, participant: Option<u64>) -> Self {
+        SerializableHello {
+            protocol: PROTOCOL_VERSION,
+            reply,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            participant,
+        }
+    }
+
+    // what the user has to do when the peer speaks another protocol
+    pub fn mismatch(&self) -> Option<String> {
+        let version: &str = match self.version.is_empty() {
+            true => "an older pixelrs",
+            false => &self.version,
+        };
+        match self.protocol.cmp(&PROTOCOL_VERSION) {
+            Ordering::Equal => None,
+            Ordering::Greater => Some(format!("peer on {}, please upgrade", version)),
+            Ordering::Less => Some(format!("peer on {} has to upgrade", version)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SerializableChecksum {
+    pub hash: u64,
+    pub cells: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableErase {
+    pub abs_x: i32,
+    pub abs_y: i32,
+    #[serde(default)]
+    pub stamp: Stamp,
+    #[serde(default)]
+    pub author: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerializableTermChar {
+    pub abs_x: i32,
+    pub abs_y: i32,
+    pub character: char,
+    pub foreground_color: u8,
+    pub background_color: u8,
+    pub empty: bool,
+    // name of the user who drew it, empty for older peers
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub stamp: Stamp,
+}
+
+impl SerializableTermChar {
+    pub fn from_pixel(pixel: Item, x: i32, y: i32) -> Self {
+        let color = pixel.chars[0][0].background_color;
+        let mut color_code: u8 = 0;
+
+        if let Color::AnsiValue(c) = color {
+            color_code = c;
+        }
+
+        SerializableTermChar {
+            abs_x: x,
+            abs_y: y,
+            character: ' ',
+            foreground_color: color_code,
+            background_color: color_code,
+            empty: false,
+            author: String::new(),
+            stamp: Stamp::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializebleSync {
+    pub items: Vec<SerializableTermChar>,
+    // cells written and since erased, with the stamp of the erase, a pixel
+    // the receiver still has there only goes when the erase is newer
+    #[serde(default)]
+    pub erased: Vec<((i32, i32), Stamp)>,
+}
+
+// whether a relayed message draws on the shared layer, all a locked room
+// keeps its peers from sending
+pub fn draws(message: &[u8]) -> bool {
+    decode_updates(message).0.iter().any(|update| {
+        matches!(
+            update,
+            Update::TermChar(_) | Update::Stroke(_) | Update::Erase(_) | Update::Text(_)
+        )
+    })
+}
+
+// the update of a message carrying a single one, the server answers pings
+// and history requests itself instead of relaying them
+pub fn lone_update(message: &[u8]) -> Option<Update> {
+    let (mut updates, _, _) = decode_updates(message);
+    match updates.len() {
+        1 => updates.pop(),
+        _ => None,
+    }
+}
+
+// what an update writes to the shared layer
+pub fn writes(update: Update) -> Vec<SerializableWrite> {
+    match update {
+        Update::TermChar(tc) => vec![SerializableWrite::Pixel(tc)],
+        Update::Stroke(stroke) => stroke.cells.into_iter().map(SerializableWrite::Pixel).collect(),
+        Update::Text(text) => vec![SerializableWrite::Text(text)],
+        Update::Erase(erase) => vec![SerializableWrite::Erase(erase)],
+        _ => Vec::new(),
+    }
+}
+
+// JSON messages start with `{` or `"`, binary frames with BINARY_FRAME_TAG
+// followed by the little endian u32 length of the bincode payload
+pub fn encode_update(update: &Update, binary: bool) -> std::io::Result<Vec<u8>> {
+    if !binary || matches!(update, Update::Hello(_)) {
+        return Ok(to_string(update)?.into_bytes());
+    }
+    let payload: Vec<u8> = bincode::serialize(update).map_err(std::io::Error::other)?;
+    let mut frame: Vec<u8> = Vec::with_capacity(payload.len() + 5);
+    frame.push(BINARY_FRAME_TAG);
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+// terminal clients and the server exchange messages behind their little
+// endian u32 length, so the server relays whole messages only and those of
+// different peers never interleave
+pub fn encode_message(bytes: &[u8]) -> Vec<u8> {
+    let mut message: Vec<u8> = Vec::with_capacity(bytes.len() + 4);
+    message.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    message.extend_from_slice(bytes);
+    message
+}
+
+// every complete message at the start of buffer, which keeps the incomplete
+// rest, a message longer than MAX_MESSAGE_LEN is an error
+pub fn drain_messages(buffer: &mut Vec<u8>) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut messages: Vec<Vec<u8>> = Vec::new();
+    let mut consumed: usize = 0;
+    while let Some(len) = buffer.get(consumed..consumed + 4) {
+        let len: usize = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("message of {} bytes is too large", len),
+            ));
+        }
+        let Some(message) = buffer.get(consumed + 4..consumed + 4 + len) else {
+            break;
+        };
+        messages.push(message.to_vec());
+        consumed += 4 + len;
+    }
+    buffer.drain(..consumed);
+    Ok(messages)
+}
+
+// updates fully contained in bytes along with the number of bytes they took
+// and why the malformed ones were rejected, malformed JSON is consumed
+// entirely since there is no way to resynchronize
+pub fn decode_updates(bytes: &[u8]) -> (Vec<Update>, usize, Vec<String>) {
+    let mut parsed: Vec<Update> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    let mut consumed: usize = 0;
+    while consumed < bytes.len() {
+        let rest: &[u8] = &bytes[consumed..];
+        if rest[0] == BINARY_FRAME_TAG {
+            if rest.len() < 5 {
+                break;
+            }
+            let len: usize = u32::from_le_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+            if rest.len() < 5 + len {
+                break;
+            }
+            match bincode::deserialize::<Update>(&rest[5..5 + len]) {
+                Ok(update) => parsed.push(update),
+                Err(e) => errors.push(e.to_string()),
+            }
+            consumed += 5 + len;
+            continue;
+        }
+        let mut stream = Deserializer::from_slice(rest).into_iter::<Update>();
+        match stream.next() {
+            Some(Ok(update)) => {
+                parsed.push(update);
+                consumed += stream.byte_offset();
+            }
+            Some(Err(e)) if e.is_eof() => break,
+            Some(Err(e)) => {
+                errors.push(e.to_string());
+                return (parsed, bytes.len(), errors);
+            }
+            // only whitespace left
+            None => return (parsed, bytes.len(), errors),
+        }
+    }
+    (parsed, consumed, errors)
+}
+
+// older peers and journals sent items a char at a time
+pub fn text_item(text: &SerializableText) -> Item {
+    if text.chars.is_empty() {
+        return Item {
+            name: "char".to_string(),
+            offset: (text.abs_x, text.abs_y),
+            chars: vec![vec![text.term_char, EMPTY_TERM_CHAR]],
+        };
+    }
+    Item {
+        name: text.name.clone(),
+        offset: (text.abs_x, text.abs_y),
+        chars: text.chars.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_drawing_is_held_back_by_a_lock() {
+        let erase: Update = Update::Erase(SerializableErase {
+            abs_x: 1,
+            abs_y: 2,
+            stamp: Stamp::default(),
+            author: String::new(),
+        });
+        let ping: Update = Update::Ping(SerializablePing { nonce: 7 });
+        for binary in [false, true] {
+            assert!(draws(&encode_update(&erase, binary).unwrap()));
+            assert!(!draws(&encode_update(&ping, binary).unwrap()));
+        }
+    }
+
+    #[test]
+    fn lone_updates_are_told_apart() {
+        let ping: Update = Update::Ping(SerializablePing { nonce: 7 });
+        for binary in [false, true] {
+            let message: Vec<u8> = encode_update(&ping, binary).unwrap();
+            assert!(matches!(
+                lone_update(&message),
+                Some(Update::Ping(SerializablePing { nonce: 7 }))
+            ));
+        }
+        let message: Vec<u8> = [
+            encode_update(&ping, false).unwrap(),
+            encode_update(&ping, false).unwrap(),
+        ]
+        .concat();
+        assert!(lone_update(&message).is_none());
+    }
+
+    #[test]
+    fn messages_are_drained_whole() {
+        let mut buffer: Vec<u8> = [encode_message(b"first"), encode_message(b"second")].concat();
+        let tail: Vec<u8> = buffer.split_off(12);
+        assert_eq!(drain_messages(&mut buffer).unwrap(), vec![b"first".to_vec()]);
+        buffer.extend(tail);
+        assert_eq!(drain_messages(&mut buffer).unwrap(), vec![b"second".to_vec()]);
+        assert!(buffer.is_empty());
+        let mut huge: Vec<u8> = ((MAX_MESSAGE_LEN + 1) as u32).to_le_bytes().to_vec();
+        assert!(drain_messages(&mut huge).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::screen::{Item, TermChar};
+
+// home region of a workshop participant on the shared layer, in pixels like the
+// cursor info shows them, only its owner and the host may draw inside
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Region {
+    pub owner: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Region {
+    // `name=x,y,width,height`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid region {}, expected name=x,y,width,height", spec);
+        let (owner, rect) = spec.split_once('=').ok_or_else(invalid)?;
+        let values: Vec<i32> = rect
+            .split(',')
+            .map(|value| value.trim().parse::<i32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let [x, y, width, height] = values[..] else {
+            return Err(invalid());
+        };
+        if owner.is_empty() || width <= 0 || height <= 0 {
+            return Err(invalid());
+        }
+        Ok(Region {
+            owner: owner.to_string(),
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    // cell in layer coordinates, pixels are two columns wide
+    pub fn contains(&self, (col, row): (i32, i32)) -> bool {
+        let x: i32 = col.div_euclid(2);
+        x >= self.x && x < self.x + self.width && row >= self.y && row < self.y + self.height
+    }
+
+    // box drawn around the region in the color of its owner
+    pub fn border(&self, color: Color) -> Item {
+        frame(
+            &format!("region {}", self.owner),
+            (self.x, self.y, self.width, self.height),
+            color,
+        )
+    }
+}
+
+// box drawn one cell around a rectangle given in pixels, the inside is left
+// empty so the canvas shows through
+pub fn frame(name: &str, (x, y, width, height): (i32, i32, i32, i32), color: Color) -> Item {
+    let (columns, rows) = ((2 * width + 2) as usize, (height + 2) as usize);
+    let chars: Vec<Vec<TermChar>> = (0..rows)
+        .map(|row| {
+            (0..columns)
+                .map(|col| {
+                    let character: char = match (row, col) {
+                        (0, 0) => '┌',
+                        (0, c) if c == columns - 1 => '┐',
+                        (r, 0) if r == rows - 1 => '└',
+                        (r, c) if r == rows - 1 && c == columns - 1 => '┘',
+                        (0, _) => '─',
+                        (r, _) if r == rows - 1 => '─',
+                        (_, 0) => '│',
+                        (_, c) if c == columns - 1 => '│',
+                        _ => ' ',
+                    };
+                    TermChar {
+                        character,
+                        foreground_color: color,
+                        background_color: Color::Reset,
+                        empty: character == ' ',
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    Item {
+        name: name.to_string(),
+        offset: (2 * x - 1, y - 1),
+        chars,
+    }
+}
+
+// regions assigned by the session host, who is the only one drawing anywhere,
+// and the only one drawing at all while the canvas is locked
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Regions {
+    pub host: String,
+    pub regions: Vec<Region>,
+    #[serde(default)]
+    pub locked: bool,
+}
+
+impl Regions {
+    // cells outside every region are free for everyone
+    pub fn may_draw(&self, user: &str, cell: (i32, i32)) -> bool {
+        user == self.host
+            || !self.locked
+                && self
+                    .regions
+                    .iter()
+                    .filter(|region| region.contains(cell))
+                    .all(|region| region.owner == user)
+    }
+}
@@ -1,15 +1,24 @@
-use crate::constants::EMPTY_TERM_CHAR;
-use crossterm::style::{Color, Print, SetBackgroundColor, SetForegroundColor};
+use crate::backend::{Backend, CrosstermBackend, MemoryBackend};
+use crate::constants::{EMPTY_TERM_CHAR, ONION_DIMMING};
+use crate::grid::Grid;
+use crate::palette::{color_to_rgb, darken};
+use crossterm::style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor};
 use crossterm::terminal::{self as terminal};
-use crossterm::{cursor, ExecutableCommand};
-use std::io::stdout;
-use std::io::Stdout;
+use crossterm::{cursor, event, queue, Command};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::{self, stdout, Stdout, Write};
+use std::panic;
+
+// pixels are two cells wide, neighbours are one pixel away on each side
+pub const PIXEL_NEIGHBOURS: [(i32, i32); 4] = [(-2, 0), (2, 0), (0, -1), (0, 1)];
 
 pub struct Pixel {
     pub color: Color,
 }
 
-#[allow(dead_code)]
 impl Pixel {
     pub fn new(color: Color) -> Pixel {
         Pixel { color }
@@ -23,9 +32,17 @@ impl Pixel {
         };
         vec![vec![char, char]]
     }
+    // standalone item, for drawing, history and the network
+    pub fn to_item(&self, offset: (i32, i32)) -> Item {
+        Item {
+            name: "P".to_string(),
+            offset,
+            chars: self.to_chars(),
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct TermChar {
     pub character: char,
     pub foreground_color: Color,
@@ -34,7 +51,8 @@ pub struct TermChar {
 }
 
 impl TermChar {
-    pub fn draw(&self, term: &mut Stdout, col_row: (i32, i32), width: u16, height: u16) {
+    // lands in the back buffer, the terminal shows it once presented
+    pub fn draw(&self, term: &mut Terminal, col_row: (i32, i32), width: u16, height: u16) {
         let (col, row) = col_row;
         if col < 0 || row < 0 {
             return;
@@ -42,17 +60,264 @@ impl TermChar {
         if col >= width as i32 || row >= height as i32 {
             return;
         }
+        term.put((col as usize, row as usize), *self);
+    }
+}
+
+// unchanged cells shorter than this between two changed ones are written
+// again, it is cheaper than moving the cursor over them
+const DIFF_MIN_GAP: usize = 4;
+
+// cells are drawn to a back buffer and present writes the ones that differ
+// from the front buffer, what the terminal shows, to the backend. anything
+// else, cursor moves, clears or plain text, goes to the backend as it comes
+// and everything reaches the terminal when the backend is flushed
+pub struct Terminal {
+    backend: Box<dyn Backend>,
+    back: Vec<Vec<TermChar>>,
+    // empty when the terminal contents are unknown, every cell is written
+    front: Vec<Vec<TermChar>>,
+    changed: bool,
+    // first write that failed since the last present, which reports it
+    failed: Option<io::Error>,
+}
+
+impl Terminal {
+    pub fn new(backend: Box<dyn Backend>) -> Self {
+        let (width, height): (u16, u16) = backend.size().unwrap_or((80, 25));
+        Terminal {
+            backend,
+            back: vec![vec![EMPTY_TERM_CHAR; width as usize]; height as usize],
+            front: Vec::new(),
+            changed: true,
+            failed: None,
+        }
+    }
+
+    // cursor visibility, mouse capture and the like go out with the next
+    // present
+    pub fn queue(&mut self, command: impl Command) {
+        let mut ansi: String = String::new();
+        command.write_ansi(&mut ansi).unwrap();
+        self.record(|backend| backend.write_ansi(&ansi));
+    }
+
+    pub fn move_to(&mut self, col: u16, row: u16) {
+        self.record(|backend| backend.move_to((col, row)));
+    }
+
+    // plain text at the cursor
+    pub fn print(&mut self, text: &str) {
+        self.record(|backend| backend.write_ansi(text));
+    }
+
+    pub fn size(&self) -> io::Result<(u16, u16)> {
+        self.backend.size()
+    }
+
+    // the backend is replaced by whatever wraps it, e.g. a cast recording
+    // what it draws, a wrap that fails hands the backend back to be kept
+    pub fn wrap_backend(
+        &mut self,
+        wrap: impl FnOnce(
+            Box<dyn Backend>,
+        ) -> Result<Box<dyn Backend>, (io::Error, Box<dyn Backend>)>,
+    ) -> io::Result<()> {
+        let backend: Box<dyn Backend> =
+            std::mem::replace(&mut self.backend, Box::new(MemoryBackend::new(0, 0)));
+        match wrap(backend) {
+            Ok(wrapped) => {
+                self.backend = wrapped;
+                Ok(())
+            }
+            Err((e, backend)) => {
+                self.backend = backend;
+                Err(e)
+            }
+        }
+    }
+
+    fn record(&mut self, write: impl FnOnce(&mut dyn Backend) -> io::Result<()>) {
+        if let Err(e) = write(self.backend.as_mut()) {
+            self.failed.get_or_insert(e);
+        }
+    }
+
+    // whatever the terminal showed is garbled by a resize
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.back = vec![vec![EMPTY_TERM_CHAR; width as usize]; height as usize];
+        self.front = Vec::new();
+        self.changed = true;
+    }
+
+    fn put(&mut self, (col, row): (usize, usize), term_char: TermChar) {
+        if let Some(cell) = self.back.get_mut(row).and_then(|cells| cells.get_mut(col)) {
+            self.changed |= *cell != term_char;
+            *cell = term_char;
+        }
+    }
 
-        term.execute(cursor::MoveTo(col as u16, row as u16))
-            .unwrap();
-        term.execute(SetForegroundColor(self.foreground_color))
-            .unwrap();
-        term.execute(SetBackgroundColor(self.background_color))
-            .unwrap();
-        term.execute(Print(self.character.to_string())).unwrap();
+    // every cell at once, rows missing from rows are left as they are
+    pub fn fill(&mut self, rows: Vec<Vec<TermChar>>) {
+        self.fill_at((0, 0), rows);
+    }
+
+    // a block of cells with its top left corner at col,row
+    pub fn fill_at(&mut self, (col, row): (usize, usize), rows: Vec<Vec<TermChar>>) {
+        for (y, cells) in rows.into_iter().enumerate() {
+            for (x, term_char) in cells.into_iter().enumerate() {
+                self.put((col + x, row + y), term_char);
+            }
+        }
+    }
+
+    // the terminal and both buffers end up blank
+    pub fn clear(&mut self) {
+        self.record(|backend| backend.clear());
+        for row in self.back.iter_mut() {
+            row.fill(EMPTY_TERM_CHAR);
+        }
+        self.front = self.back.clone();
+        self.changed = false;
+    }
+
+    // full rewrites every line with a change from its first to its last changed
+    // cell, diff only the runs of changed cells. the cursor is put back where it
+    // was so the text and keyboard cursors stay in place. commands queued
+    // during the frame go out with it in the one flush
+    pub fn present(&mut self, mode: RenderMode) -> io::Result<()> {
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+        if !self.changed {
+            return self.backend.flush();
+        }
+        self.changed = false;
+        for (row, cells) in self.back.iter().enumerate() {
+            let front: Option<&Vec<TermChar>> = self.front.get(row);
+            let changed: Vec<usize> = (0..cells.len())
+                .filter(|&col| front.and_then(|front| front.get(col)) != Some(&cells[col]))
+                .collect();
+            let mut spans: Vec<(usize, usize)> = Vec::new();
+            for col in changed {
+                match spans.last_mut() {
+                    Some((_, end)) if mode == RenderMode::Full || col - *end < DIFF_MIN_GAP => {
+                        *end = col + 1
+                    }
+                    _ => spans.push((col, col + 1)),
+                }
+            }
+            for (start, end) in spans {
+                self.backend
+                    .write_cells((start as u16, row as u16), &cells[start..end])?;
+            }
+        }
+        self.front = self.back.clone();
+        self.backend.flush()
+    }
+}
+
+// the terminal goes back to what a shell expects whatever state it was left
+// in, errors are ignored as there is nothing left to do about them
+pub fn restore_terminal() {
+    let mut out: Stdout = stdout();
+    let _ = queue!(out, ResetColor, event::DisableMouseCapture, cursor::Show);
+    let _ = out.flush();
+    let _ = terminal::disable_raw_mode();
+}
+
+// held while the terminal is taken over, dropping it on the way out or while
+// a panic unwinds restores the terminal
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+    // a panic restores the terminal before its message is printed, otherwise
+    // it would land in raw mode with the cursor hidden
+    pub fn install() -> TerminalGuard {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            previous(info);
+        }));
+        TerminalGuard
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
     }
 }
 
+// inclusive top left and bottom right corners of a block of cells
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub min: (i32, i32),
+    pub max: (i32, i32),
+}
+
+impl Rect {
+    pub fn union(self, other: Rect) -> Rect {
+        Rect {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    pub fn shift(self, (dx, dy): (i32, i32)) -> Rect {
+        Rect {
+            min: (self.min.0 + dx, self.min.1 + dy),
+            max: (self.max.0 + dx, self.max.1 + dy),
+        }
+    }
+
+    // the part inside a width x height screen, if any
+    pub fn clip(self, width: u16, height: u16) -> Option<Rect> {
+        let min = (self.min.0.max(0), self.min.1.max(0));
+        let max = (
+            self.max.0.min(width as i32 - 1),
+            self.max.1.min(height as i32 - 1),
+        );
+        (min.0 <= max.0 && min.1 <= max.1).then_some(Rect { min, max })
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (
+            (self.max.0 - self.min.0 + 1) as usize,
+            (self.max.1 - self.min.1 + 1) as usize,
+        )
+    }
+}
+
+// what changed on a layer since it was last drawn, a layer never drawn has
+// everything to draw
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Dirty {
+    Clean,
+    Cells(Rect),
+    #[default]
+    Everything,
+}
+
+impl Dirty {
+    fn add(&mut self, rect: Rect) {
+        *self = match *self {
+            Dirty::Clean => Dirty::Cells(rect),
+            Dirty::Cells(dirty) => Dirty::Cells(dirty.union(rect)),
+            Dirty::Everything => Dirty::Everything,
+        };
+    }
+
+    fn merge(&mut self, other: Dirty) {
+        match other {
+            Dirty::Clean => {}
+            Dirty::Cells(rect) => self.add(rect),
+            Dirty::Everything => *self = Dirty::Everything,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
 pub struct Item {
     pub name: String,
     // items are contained in a layer and they have an offset with respect to it.
@@ -76,7 +341,7 @@ impl Clone for Item {
 impl Item {
     // TODO: this should improve, I need to basically return buffers, containing a "string" made
     // up of the characters that the Item is made of
-    pub fn draw(&self, term: &mut Stdout, col_row: (i32, i32), width: u16, height: u16) {
+    pub fn draw(&self, term: &mut Terminal, col_row: (i32, i32), width: u16, height: u16) {
         for (char_row, row_vec) in self.chars.iter().enumerate() {
             for (char_col, term_char) in row_vec.iter().enumerate() {
                 term_char.draw(
@@ -88,7 +353,7 @@ impl Item {
             }
         }
     }
-    pub fn redraw(&self, term: &mut Stdout, c_offset: (i32, i32), width: u16, height: u16) {
+    pub fn redraw(&self, term: &mut Terminal, c_offset: (i32, i32), width: u16, height: u16) {
         let f_offset = (self.offset.0 + c_offset.0, self.offset.1 + c_offset.1);
         for (row, row_vec) in self.chars.iter().enumerate() {
             for (col, term_char) in row_vec.iter().enumerate() {
@@ -102,24 +367,19 @@ impl Item {
         }
     }
 
-    pub fn draw_buffer(
-        &self,
-        buffer: &mut [Vec<String>],
-        c_offset: (i32, i32),
-        width: u16,
-        height: u16,
-    ) {
-        let f_offset: (i32, i32) = (self.offset.0 + c_offset.0, self.offset.1 + c_offset.1);
+    // non empty chars are written over whatever the buffer already holds,
+    // the caller is responsible of compositing in z-order
+    pub fn composite(&self, buffer: &mut [Vec<TermChar>], c_offset: (i32, i32)) {
+        let (x0, y0) = self.screen_position(vec![c_offset]);
         for (row, row_vec) in self.chars.iter().enumerate() {
             for (col, term_char) in row_vec.iter().enumerate() {
-                let x = f_offset.0 + (col as i32);
-                let y = f_offset.1 + (row as i32);
-                //println!("{}-{}", x,y);
-                if x < 0 || y < 0 || y >= height as i32 || x >= width as i32 {
+                let x = x0 + col as i32;
+                let y = y0 + row as i32;
+                if term_char.empty || x < 0 || y < 0 || y as usize >= buffer.len() {
                     continue;
                 }
-                if let Color::AnsiValue(c) = term_char.background_color {
-                    buffer[y as usize][x as usize] = format!("\x1b[48;5;{}m \x1b[49m", c)
+                if let Some(cell) = buffer[y as usize].get_mut(x as usize) {
+                    *cell = *term_char;
                 }
             }
         }
@@ -140,20 +400,43 @@ impl Item {
         (x, y)
     }
 
-    // this is draw_erase, it will draw the empty char in the position of the item
-    pub fn erase(&self, term: &mut Stdout, c_offset: (i32, i32), width: u16, height: u16) {
+    // non empty char of the item drawn at the absolute position x,y, if any
+    pub fn char_at(&self, c_offset: (i32, i32), (x, y): (i32, i32)) -> Option<&TermChar> {
         let (x0, y0) = self.screen_position(vec![c_offset]);
-        for (row, row_vec) in self.chars.iter().enumerate() {
-            for (col, _) in row_vec.iter().enumerate() {
-                EMPTY_TERM_CHAR.draw(
-                    term,
-                    // item_relative_to_container_layer + container_relative_to_screen
-                    (x0 + col as i32, y0 + row as i32),
-                    width,
-                    height,
-                );
-            }
+        let col = usize::try_from(x - x0).ok()?;
+        let row = usize::try_from(y - y0).ok()?;
+        self.chars
+            .get(row)
+            .and_then(|row_vec| row_vec.get(col))
+            .filter(|term_char| !term_char.empty)
+    }
+
+    // cells the item spans, relative to its container, None when it has no chars
+    pub fn bounds(&self) -> Option<Rect> {
+        let width: usize = self.chars.iter().map(Vec::len).max()?;
+        if width == 0 {
+            return None;
         }
+        Some(Rect {
+            min: self.offset,
+            max: (
+                self.offset.0 + width as i32 - 1,
+                self.offset.1 + self.chars.len() as i32 - 1,
+            ),
+        })
+    }
+
+    // color of an item that is a single pixel, projects saved before layers had
+    // a grid hold their pixels as such items
+    pub fn as_pixel(&self) -> Option<Color> {
+        let [row] = self.chars.as_slice() else {
+            return None;
+        };
+        let [left, right] = row.as_slice() else {
+            return None;
+        };
+        let pixel: bool = left == right && left.character == ' ' && !left.empty;
+        (pixel && left.background_color != Color::Reset).then_some(left.background_color)
     }
 
     pub fn get_filled_indexes(&self, c_offset: (i32, i32)) -> Vec<(i32, i32)> {
@@ -170,16 +453,33 @@ impl Item {
     }
 }
 
-#[allow(dead_code)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Layer {
     pub name: String,
     pub width: u16,
     pub height: u16,
     pub offset: (i32, i32), // offset with respect to container screen
+    // painted pixels, always under the items of the layer
+    #[serde(default)]
+    pub pixels: Grid,
     pub items: Vec<Item>,
+    // locked layers ignore brush, erase and move events
+    pub locked: bool,
+    // throwaway layer for construction lines, never saved, exported nor shared
+    #[serde(skip)]
+    pub scratch: bool,
+    // layer relative cell to the position of the topmost item filling it, built
+    // on compaction, kept on additions and dropped on any other change to items
+    #[serde(skip)]
+    index: Option<HashMap<(i32, i32), usize>>,
+    // layer relative cells changed since the layer was last drawn and the
+    // offset it was drawn at, a moved layer has everything to draw again
+    #[serde(skip)]
+    dirty: Dirty,
+    #[serde(skip)]
+    drawn_offset: Option<(i32, i32)>,
 }
 
-#[allow(dead_code)]
 impl Layer {
     pub fn new_empty(name: String, width: u16, height: u16, offset: (i32, i32)) -> Layer {
         Layer {
@@ -187,44 +487,177 @@ impl Layer {
             width,
             height,
             offset,
+            pixels: Grid::default(),
             items: Vec::new(),
+            locked: false,
+            scratch: false,
+            index: None,
+            dirty: Dirty::Everything,
+            drawn_offset: None,
+        }
+    }
+
+    fn touch(&mut self, item: &Item) {
+        if let Some(rect) = item.bounds() {
+            self.dirty.add(rect);
+        }
+    }
+
+    // what changed since the last call in absolute cells, the layer counts as
+    // drawn afterwards
+    fn take_dirty(&mut self) -> Dirty {
+        let dirty: Dirty = match self.drawn_offset == Some(self.offset) {
+            true => self.dirty,
+            false => Dirty::Everything,
+        };
+        self.dirty = Dirty::Clean;
+        self.drawn_offset = Some(self.offset);
+        match dirty {
+            Dirty::Cells(rect) => Dirty::Cells(rect.shift(self.offset)),
+            dirty => dirty,
         }
     }
 
-    // relative position of (col, row) to the self
-    pub fn relative_position(&self, col: u16, row: u16) -> (i32, i32) {
-        (col as i32 - self.offset.0, row as i32 - self.offset.1)
+    pub fn toggle_lock(&mut self) {
+        self.locked = !self.locked;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty() && self.items.is_empty()
+    }
+
+    // canvas position of a layer relative cell, for the ui layer the view one
+    pub fn absolute_position(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        (x + self.offset.0, y + self.offset.1)
     }
 
     pub fn add_item(&mut self, item: Item) {
+        self.touch(&item);
+        if let Some(index) = &mut self.index {
+            for cell in item.get_filled_indexes((0, 0)) {
+                index.insert(cell, self.items.len());
+            }
+        }
         self.items.push(item);
     }
 
+    // the pixels the new one overlaps are replaced and returned, items at the
+    // same offset stay on top of it
+    pub fn paint(&mut self, (x, y): (i32, i32), color: Color) -> Vec<((i32, i32), Color)> {
+        // the pixel and the halves of the neighbours it may replace
+        self.dirty.add(Rect {
+            min: (x - 1, y),
+            max: (x + 2, y),
+        });
+        self.pixels.paint((x, y), color)
+    }
+
+    pub fn clear_pixel(&mut self, (x, y): (i32, i32)) -> Option<Color> {
+        let color: Color = self.pixels.clear((x, y))?;
+        self.dirty.add(Rect {
+            min: (x, y),
+            max: (x + 1, y),
+        });
+        Some(color)
+    }
+
+    pub fn clear_pixels(&mut self) -> Vec<((i32, i32), Color)> {
+        self.dirty = Dirty::Everything;
+        self.pixels.clear_all()
+    }
+
+    // layer relative anchor of the pixel covering the absolute position x,y
+    pub fn pixel_at(&self, (abs_x, abs_y): (i32, i32)) -> Option<(i32, i32)> {
+        self.pixels
+            .anchor_at((abs_x - self.offset.0, abs_y - self.offset.1))
+    }
+
+    // single pixel items are moved into the grid, the topmost one wins
+    pub fn absorb_pixel_items(&mut self) {
+        let mut pixels: Vec<((i32, i32), Color)> = Vec::new();
+        self.retain_items(|item| match item.as_pixel() {
+            Some(color) => {
+                pixels.push((item.offset, color));
+                false
+            }
+            None => true,
+        });
+        for (offset, color) in pixels {
+            self.paint(offset, color);
+        }
+    }
+
     pub fn remove_item(&mut self, item: Option<&Item>) {
         if let Some(item) = item {
-            self.items.retain(|x| x.name != item.name);
+            self.retain_items(|x| x.name != item.name);
         }
     }
 
-    pub fn buffer_to_string(&mut self, buffer: Vec<Vec<String>>) -> String {
-        buffer.into_iter().flatten().collect()
+    // topmost item anchored at offset, used to take back the last addition there
+    pub fn remove_last_at(&mut self, offset: (i32, i32)) -> Option<Item> {
+        let position: usize = self.items.iter().rposition(|item| item.offset == offset)?;
+        self.index = None;
+        let item: Item = self.items.remove(position);
+        self.touch(&item);
+        Some(item)
+    }
+
+    // topmost item equal to item, other items anchored at the same offset are
+    // left alone
+    pub fn take_item(&mut self, item: &Item) -> Option<Item> {
+        let position: usize = self.items.iter().rposition(|other| other == item)?;
+        self.index = None;
+        let item: Item = self.items.remove(position);
+        self.touch(&item);
+        Some(item)
+    }
+
+    // items must not be removed behind the back of the spatial index nor of
+    // the dirty cells
+    pub fn retain_items<F: FnMut(&Item) -> bool>(&mut self, mut f: F) {
+        let mut dirty: Dirty = self.dirty;
+        self.items.retain(|item| {
+            let keep: bool = f(item);
+            if let (false, Some(rect)) = (keep, item.bounds()) {
+                dirty.add(rect);
+            }
+            keep
+        });
+        self.dirty = dirty;
+        self.index = None;
     }
 
-    pub fn draw_buffer(&mut self, term: &mut Stdout, width: u16, height: u16) {
-        let mut buffer: Vec<Vec<String>> =
-            vec![vec![' '.to_string(); width as usize]; height as usize];
-        for item in self.items.iter_mut() {
-            item.draw_buffer(&mut buffer, self.offset, width, height);
+    fn rebuild_index(&mut self) {
+        let mut index: HashMap<(i32, i32), usize> = HashMap::new();
+        for (i, item) in self.items.iter().enumerate() {
+            for cell in item.get_filled_indexes((0, 0)) {
+                index.insert(cell, i);
+            }
         }
-        let layer_str: String = self.buffer_to_string(buffer);
-        term.execute(cursor::MoveTo(0, 0)).unwrap();
-        term.execute(Print(layer_str)).unwrap();
+        self.index = Some(index);
     }
 
-    pub fn redraw(&mut self, term: &mut Stdout, width: u16, height: u16) {
-        for item in self.items.iter_mut() {
-            item.redraw(term, self.offset, width, height);
+    // erasing removes every item anchored at the same offset at once, so an item
+    // whose filled cells are all covered by items above it sharing its offset can
+    // never be seen again and is dropped, returns how many items were dropped
+    pub fn compact(&mut self) -> usize {
+        let before: usize = self.items.len();
+        // cells are only comparable between items anchored at the same offset
+        let mut covered: HashSet<((i32, i32), (i32, i32))> = HashSet::new();
+        let mut kept: Vec<Item> = Vec::new();
+        for item in self.items.drain(..).rev() {
+            let mut visible: bool = false;
+            for cell in item.get_filled_indexes((0, 0)) {
+                visible |= covered.insert((item.offset, cell));
+            }
+            if visible {
+                kept.push(item);
+            }
         }
+        kept.reverse();
+        self.items = kept;
+        self.rebuild_index();
+        before - self.items.len()
     }
 
     pub fn move_layer(&mut self, displacement: (i32, i32)) {
@@ -235,66 +668,1100 @@ impl Layer {
     }
 
     pub fn get_filled_indexes(&self) -> Vec<(i32, i32)> {
-        let mut indexes = Vec::new();
+        let mut indexes: Vec<(i32, i32)> = self
+            .pixels
+            .filled_cells()
+            .map(|cell| self.absolute_position(cell))
+            .collect();
         for item in self.items.iter() {
             indexes.extend(item.get_filled_indexes(self.offset));
         }
         indexes
     }
+    // pixels then items, non empty chars are written over whatever the buffer
+    // already holds
+    pub fn composite(&self, buffer: &mut [Vec<TermChar>], c_offset: (i32, i32)) {
+        for (offset, color) in self.pixels.iter() {
+            Pixel::new(color)
+                .to_item(offset)
+                .composite(buffer, c_offset);
+        }
+        for item in self.items.iter() {
+            item.composite(buffer, c_offset);
+        }
+    }
+
+    // non empty char drawn at the absolute position x,y, if any
+    pub fn char_at(&self, (x, y): (i32, i32)) -> Option<TermChar> {
+        if let Some(term_char) = self
+            .items
+            .iter()
+            .rev()
+            .find_map(|item| item.char_at(self.offset, (x, y)))
+        {
+            return Some(*term_char);
+        }
+        let anchor: (i32, i32) = self.pixel_at((x, y))?;
+        self.pixels
+            .get(anchor)
+            .map(|color| Pixel::new(color).to_chars()[0][0])
+    }
+
+    // items of the other layer are rebased into this layer coordinates and stacked
+    // on top of the existing ones, items completely covered by them are dropped,
+    // its pixels replace the ones they overlap
+    pub fn merge(&mut self, other: Layer) {
+        let covered: Vec<(i32, i32)> = other.get_filled_indexes();
+        let offset: (i32, i32) = self.offset;
+        self.retain_items(|item| {
+            !item
+                .get_filled_indexes(offset)
+                .iter()
+                .all(|index| covered.contains(index))
+        });
+        let displacement = (
+            other.offset.0 - self.offset.0,
+            other.offset.1 - self.offset.1,
+        );
+        for ((x, y), color) in other.pixels.iter() {
+            self.paint((x + displacement.0, y + displacement.1), color);
+        }
+        for mut item in other.items {
+            item.offset = (
+                item.offset.0 + displacement.0,
+                item.offset.1 + displacement.1,
+            );
+            self.add_item(item);
+        }
+    }
+
+    // color of the pixel anchored exactly at the layer relative offset
+    pub fn pixel_color(&self, offset: (i32, i32)) -> Option<Color> {
+        self.pixels.get(offset)
+    }
+
+    // layer relative offsets of the pixels connected to the one at start whose
+    // color passes matching
+    pub fn connected_pixels(
+        &self,
+        start: (i32, i32),
+        matching: impl Fn(Color) -> bool,
+    ) -> HashSet<(i32, i32)> {
+        let mut region: HashSet<(i32, i32)> = HashSet::new();
+        let mut pending: Vec<(i32, i32)> = vec![start];
+        while let Some((x, y)) = pending.pop() {
+            if region.contains(&(x, y)) || !self.pixel_color((x, y)).is_some_and(&matching) {
+                continue;
+            }
+            region.insert((x, y));
+            pending.extend(PIXEL_NEIGHBOURS.iter().map(|(dx, dy)| (x + dx, y + dy)));
+        }
+        region
+    }
+
+    // the topmost item wins, items added later are drawn on top
     pub fn get_item_at_absolute(&self, (abs_x, abs_y): (i32, i32)) -> Option<&Item> {
-        self.items.iter().find(|&item| {
+        if let Some(index) = &self.index {
+            let cell = (abs_x - self.offset.0, abs_y - self.offset.1);
+            return index.get(&cell).map(|&i| &self.items[i]);
+        }
+        self.items.iter().rev().find(|&item| {
             item.get_filled_indexes(self.offset)
                 .contains(&(abs_x, abs_y))
         })
     }
 }
 
+// layers flattened bottom to top into a width x height window with its top left
+// corner at origin, later layers and later items within a layer occlude the earlier ones
+// a single char is kept as is, several are averaged into one block of color,
+// empty ones are left out
+fn blend(chars: &[TermChar]) -> TermChar {
+    if let [term_char] = chars {
+        return *term_char;
+    }
+    let colors: Vec<(u8, u8, u8)> = chars
+        .iter()
+        .filter(|term_char| !term_char.empty)
+        .filter_map(|term_char| match term_char.background_color {
+            Color::Reset => color_to_rgb(term_char.foreground_color),
+            color => color_to_rgb(color),
+        })
+        .collect();
+    if colors.is_empty() {
+        return EMPTY_TERM_CHAR;
+    }
+    let n: u32 = colors.len() as u32;
+    let channel =
+        |f: fn(&(u8, u8, u8)) -> u8| (colors.iter().map(|c| f(c) as u32).sum::<u32>() / n) as u8;
+    TermChar {
+        character: ' ',
+        foreground_color: Color::Reset,
+        background_color: Color::Rgb {
+            r: channel(|c| c.0),
+            g: channel(|c| c.1),
+            b: channel(|c| c.2),
+        },
+        empty: false,
+    }
+}
+
+// onion skins are drawn darker
+fn dimmed(term_char: TermChar) -> TermChar {
+    TermChar {
+        foreground_color: darken(term_char.foreground_color, ONION_DIMMING),
+        background_color: darken(term_char.background_color, ONION_DIMMING),
+        ..term_char
+    }
+}
+
+pub fn composite_layers(
+    layers: &[Layer],
+    origin: (i32, i32),
+    width: usize,
+    height: usize,
+) -> Vec<Vec<TermChar>> {
+    let mut buffer: Vec<Vec<TermChar>> = vec![vec![EMPTY_TERM_CHAR; width]; height];
+    for layer in layers.iter() {
+        let c_offset = (layer.offset.0 - origin.0, layer.offset.1 - origin.1);
+        layer.composite(&mut buffer, c_offset);
+    }
+    buffer
+}
+
+// hash of what every layer shows, where relative to the first layer, its
+// name and its lock
+pub fn fingerprint(layers: &[Layer]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let base: (i32, i32) = layers.first().map_or((0, 0), |layer| layer.offset);
+    for layer in layers {
+        (&layer.name, layer.locked).hash(&mut hasher);
+        let Some(((min_x, min_y), (max_x, max_y))) = layers_bounds(std::slice::from_ref(layer))
+        else {
+            continue;
+        };
+        (min_x - base.0, min_y - base.1).hash(&mut hasher);
+        let (width, height) = ((max_x - min_x + 1) as usize, (max_y - min_y + 1) as usize);
+        let rows = composite_layers(std::slice::from_ref(layer), (min_x, min_y), width, height);
+        for term_char in rows.iter().flatten() {
+            (
+                term_char.character,
+                term_char.foreground_color,
+                term_char.background_color,
+                term_char.empty,
+            )
+                .hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+// top left and bottom right corners, inclusive, of every filled cell of the layers
+pub fn layers_bounds(layers: &[Layer]) -> Option<((i32, i32), (i32, i32))> {
+    let indexes: Vec<(i32, i32)> = layers
+        .iter()
+        .flat_map(|layer| layer.get_filled_indexes())
+        .collect();
+    let min_x = indexes.iter().map(|i| i.0).min()?;
+    let min_y = indexes.iter().map(|i| i.1).min()?;
+    let max_x = indexes.iter().map(|i| i.0).max()?;
+    let max_y = indexes.iter().map(|i| i.1).max()?;
+    Some(((min_x, min_y), (max_x, max_y)))
+}
+
+// colors are only emitted when they change, when rows are joined with a line end
+// colors are reset before it so backgrounds do not bleed to the end of the line
+pub fn rows_to_ansi(rows: &[Vec<TermChar>], line_end: &str) -> String {
+    let mut ansi: String = String::new();
+    let mut colors: Option<(Color, Color)> = None;
+    for row in rows.iter() {
+        for term_char in row.iter() {
+            let char_colors = (term_char.foreground_color, term_char.background_color);
+            if colors != Some(char_colors) {
+                SetForegroundColor(char_colors.0)
+                    .write_ansi(&mut ansi)
+                    .unwrap();
+                SetBackgroundColor(char_colors.1)
+                    .write_ansi(&mut ansi)
+                    .unwrap();
+                colors = Some(char_colors);
+            }
+            ansi.push(term_char.character);
+        }
+        if !line_end.is_empty() {
+            ResetColor.write_ansi(&mut ansi).unwrap();
+            ansi.push_str(line_end);
+            colors = None;
+        }
+    }
+    ResetColor.write_ansi(&mut ansi).unwrap();
+    ansi
+}
+
+// how much of the lines that changed since the last frame is written, full
+// writes them from the first to the last change, diff only the changed cells,
+// for terminals where escape sequences are slow like serial consoles and
+// older windows hosts
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RenderMode {
+    Full,
+    Diff,
+}
+
+impl RenderMode {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "full" => Ok(RenderMode::Full),
+            "diff" => Ok(RenderMode::Diff),
+            _ => Err(format!(
+                "unknown render mode {}, expected full or diff",
+                mode
+            )),
+        }
+    }
+
+    // PIXELRS_RENDER wins, otherwise terminals known to be slow get diff
+    pub fn detect() -> Self {
+        if let Some(mode) = std::env::var("PIXELRS_RENDER")
+            .ok()
+            .and_then(|mode| RenderMode::parse(&mode).ok())
+        {
+            return mode;
+        }
+        let term: String = std::env::var("TERM").unwrap_or_default();
+        let slow_term: bool = matches!(term.as_str(), "linux" | "dumb") || term.starts_with("vt");
+        // the legacy windows console, windows terminal sets WT_SESSION
+        let legacy_console: bool = cfg!(windows) && std::env::var_os("WT_SESSION").is_none();
+        match slow_term || legacy_console {
+            true => RenderMode::Diff,
+            false => RenderMode::Full,
+        }
+    }
+}
+
 pub struct Screen {
     pub width: u16,
     pub height: u16,
     pub layers: Vec<Layer>,
-    pub term: std::io::Stdout,
+    pub term: Terminal,
+    // the whole canvas is shrunk to the terminal instead of clipped
+    pub fit: bool,
+    // colors replacing the ones of layer 0 items, keyed by item offset
+    pub tint: Option<HashMap<(i32, i32), Color>>,
+    // view only rotation in clockwise quarter turns around the screen center,
+    // the ui layer is never rotated
+    pub rotation: u8,
+    // items drawn over the canvas and under the ui in layer 0 coordinates, never
+    // part of the document, e.g. workshop region borders
+    pub guides: Vec<Item>,
+    // canvas cell shown at the top left corner of the view, canvas layers are
+    // drawn where their offset falls relative to it, the ui layer never moves
+    pub viewport: (i32, i32),
+    // view only scale, zoomed in every pixel is drawn zoom + 1 times bigger,
+    // at -1 a pixel takes a single cell and at -2 a cell blends 2x2 pixels
+    pub zoom: i8,
+    // pixels between the lines of the grid shown where the canvas is empty,
+    // none when it is hidden
+    pub grid: Option<i32>,
+    // document layers of the animation frames other than the shown one, in
+    // order, the shown one goes before frames[frame]
+    pub frames: Vec<Vec<Layer>>,
+    pub frame: usize,
+    // the previous and next frames are drawn dimmed under the shown one
+    pub onion: bool,
+    pub render: RenderMode,
+    // fingerprint of the document when it was last saved or opened, see dirty
+    saved: u64,
+    // view of the last full redraw, refresh only redraws the changed cells
+    // while it holds
+    drawn: Option<DrawnView>,
+}
+
+// whatever changes the whole screen at once when it changes
+#[derive(PartialEq)]
+struct DrawnView {
+    layers: usize,
+    size: (u16, u16),
+    guides: usize,
+    fit: bool,
+    rotation: u8,
+    viewport: (i32, i32),
+    zoom: i8,
+    grid: Option<i32>,
+    frame: usize,
+    onion: bool,
 }
 
-#[allow(dead_code)]
 impl Screen {
     pub fn new(layers: Vec<Layer>) -> Screen {
-        let term = stdout();
-        let (width, height): (u16, u16) = terminal::size().unwrap();
+        Screen::with_backend(layers, Box::new(CrosstermBackend::new()))
+    }
+
+    // drawn somewhere else than the terminal, e.g. a MemoryBackend
+    pub fn with_backend(layers: Vec<Layer>, backend: Box<dyn Backend>) -> Screen {
+        let term: Terminal = Terminal::new(backend);
+        let (width, height): (u16, u16) = term.size().unwrap_or((80, 25));
         Screen {
             width,
             height,
             layers,
             term,
+            fit: false,
+            tint: None,
+            rotation: 0,
+            guides: Vec::new(),
+            viewport: (0, 0),
+            zoom: 0,
+            grid: None,
+            frames: Vec::new(),
+            frame: 0,
+            onion: false,
+            render: RenderMode::detect(),
+            saved: fingerprint(&[]),
+            drawn: None,
+        }
+    }
+
+    // the first document_end layers are the document, the ones above are the
+    // scratch and ui layers
+    pub fn mark_saved(&mut self, document_end: usize) {
+        self.saved = self.document_fingerprint(document_end);
+    }
+
+    // whether the document differs from what was last saved or opened, panning
+    // and items stacked differently but showing the same are not changes
+    pub fn dirty(&self, document_end: usize) -> bool {
+        self.document_fingerprint(document_end) != self.saved
+    }
+
+    // every frame in order, a document without frames hashes like its layers
+    fn document_fingerprint(&self, document_end: usize) -> u64 {
+        let shown: u64 = fingerprint(&self.layers[..document_end]);
+        if self.frames.is_empty() {
+            return shown;
+        }
+        let mut hasher = DefaultHasher::new();
+        let mut fingerprints: Vec<u64> = self.frames.iter().map(|f| fingerprint(f)).collect();
+        fingerprints.insert(self.frame, shown);
+        fingerprints.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len() + 1
+    }
+
+    // the shown frame is copied into a new one right after it, which is shown
+    pub fn duplicate_frame(&mut self, document_end: usize) {
+        self.frames
+            .insert(self.frame, self.layers[..document_end].to_vec());
+        self.frame += 1;
+    }
+
+    // the document layers of the frame at index replace the shown ones, which
+    // are kept in its place, false when there is no such other frame
+    pub fn show_frame(&mut self, index: usize, document_end: usize) -> bool {
+        if index == self.frame || index >= self.frame_count() {
+            return false;
+        }
+        let mut frames: Vec<Vec<Layer>> = std::mem::take(&mut self.frames);
+        let shown: Vec<Layer> = self.layers.drain(..document_end).collect();
+        frames.insert(self.frame, shown);
+        let next: Vec<Layer> = frames.remove(index);
+        self.layers.splice(0..0, next);
+        self.frames = frames;
+        self.frame = index;
+        true
+    }
+
+    // the shown frame is moved to index, the others keep their order
+    pub fn move_frame(&mut self, index: usize) -> bool {
+        if index == self.frame || index >= self.frame_count() {
+            return false;
+        }
+        self.frame = index;
+        true
+    }
+
+    // the shown frame is dropped and the next one, or the previous one when
+    // it was the last, is shown, the only frame is never deleted. Returns the
+    // document layers of the dropped frame
+    pub fn delete_frame(&mut self, document_end: usize) -> Option<Vec<Layer>> {
+        let index: usize = self.frame.min(self.frames.len().checked_sub(1)?);
+        self.drop_frame(index, document_end)
+    }
+
+    // the shown frame is dropped and the one at index, among the others, is
+    // shown
+    pub fn drop_frame(&mut self, index: usize, document_end: usize) -> Option<Vec<Layer>> {
+        if index >= self.frames.len() {
+            return None;
         }
+        let next: Vec<Layer> = self.frames.remove(index);
+        let dropped: Vec<Layer> = self.layers.splice(..document_end, next).collect();
+        self.frame = index;
+        Some(dropped)
     }
-    fn add_layer(&mut self, layer: Layer) {
-        self.layers.push(layer);
+
+    // layers are shown as a new frame at index, the shown one is kept in its
+    // place
+    pub fn insert_frame(&mut self, index: usize, layers: Vec<Layer>, document_end: usize) -> bool {
+        if index > self.frames.len() + 1 {
+            return false;
+        }
+        let shown: Vec<Layer> = self.layers.drain(..document_end).collect();
+        self.frames.insert(self.frame, shown);
+        self.layers.splice(0..0, layers);
+        self.frame = index;
+        true
+    }
+    // every layer is flattened bottom to top, later layers and later items
+    // within a layer occlude the earlier ones
+    pub fn composite(&self) -> Vec<Vec<TermChar>> {
+        self.composite_window((0, 0), self.width as usize, self.height as usize)
     }
-    fn redraw(&mut self) {
+
+    // width x height cells of the view with their top left corner at origin,
+    // a rotated or zoomed view is always composited whole
+    fn composite_window(
+        &self,
+        origin: (i32, i32),
+        width: usize,
+        height: usize,
+    ) -> Vec<Vec<TermChar>> {
+        let tinted: Option<Vec<Layer>> = self.tinted_layers();
+        let layers: &[Layer] = tinted.as_deref().unwrap_or(&self.layers);
+        if self.rotation != 0 || self.zoom != 0 {
+            return self.transformed_composite(layers);
+        }
+        let (canvas, ui) = layers.split_at(layers.len().saturating_sub(1));
+        let mut buffer = self.composite_canvas(canvas, self.to_canvas(origin), width, height);
+        self.fill_guides(&mut buffer, self.to_canvas(origin));
+        self.fill_grid(&mut buffer, self.to_canvas(origin));
+        for layer in ui.iter() {
+            let c_offset = (layer.offset.0 - origin.0, layer.offset.1 - origin.1);
+            layer.composite(&mut buffer, c_offset);
+        }
+        buffer
+    }
+
+    // pixels are square on screen so quarter turns happen on the pixel grid, both
+    // halves of a pixel keep their side, cells are in screen coordinates
+    fn turn(&self, (col, row): (i32, i32), turns: u8) -> (i32, i32) {
+        let (cx, cy) = ((self.width / 4) as i32, (self.height / 2) as i32);
+        let (mut dx, mut dy) = (col.div_euclid(2) - cx, row - cy);
+        for _ in 0..turns % 4 {
+            (dx, dy) = (-dy, dx);
+        }
+        (2 * (cx + dx) + col.rem_euclid(2), cy + dy)
+    }
+
+    // where a cell of the unrotated view shows up on the rotated one
+    pub fn rotate_cell(&self, cell: (i32, i32)) -> (i32, i32) {
+        self.turn(cell, self.rotation)
+    }
+
+    // cell of the unrotated view shown at a cell of the rotated one
+    pub fn unrotate_cell(&self, cell: (i32, i32)) -> (i32, i32) {
+        self.turn(cell, 4 - self.rotation)
+    }
+
+    // canvas cells, relative to the viewport, shown at a cell of the unrotated
+    // view, zoomed out to blocks each of their pixels is blended
+    fn zoom_sources(&self, (col, row): (i32, i32)) -> Vec<(i32, i32)> {
+        match self.zoom {
+            -1 => vec![(2 * col, row)],
+            zoom if zoom < -1 => vec![
+                (4 * col, 2 * row),
+                (4 * col + 2, 2 * row),
+                (4 * col, 2 * row + 1),
+                (4 * col + 2, 2 * row + 1),
+            ],
+            zoom => {
+                let factor: i32 = zoom as i32 + 1;
+                vec![(col.div_euclid(factor), row.div_euclid(factor))]
+            }
+        }
+    }
+
+    // cells of the unrotated view a pixel takes, tools work on whole pixels
+    pub fn pixel_size(&self) -> (i32, i32) {
+        match self.zoom {
+            zoom if zoom < 0 => (1, 1),
+            zoom => (2 * (zoom as i32 + 1), zoom as i32 + 1),
+        }
+    }
+
+    // canvas cell under a cell of the unrotated view
+    pub fn to_canvas(&self, cell: (i32, i32)) -> (i32, i32) {
+        let (x, y) = self.zoom_sources(cell)[0];
+        (x + self.viewport.0, y + self.viewport.1)
+    }
+
+    // cells of the rotated view a canvas cell shows up at, several when zoomed
+    // in, they may be off screen
+    pub fn to_view(&self, (x, y): (i32, i32)) -> Vec<(i32, i32)> {
+        let (x, y) = (x - self.viewport.0, y - self.viewport.1);
+        let cells: Vec<(i32, i32)> = match self.zoom {
+            -1 => vec![(x.div_euclid(2), y)],
+            zoom if zoom < -1 => vec![(x.div_euclid(4), y.div_euclid(2))],
+            zoom => {
+                let factor: i32 = zoom as i32 + 1;
+                (0..factor)
+                    .flat_map(|dy| (0..factor).map(move |dx| (x * factor + dx, y * factor + dy)))
+                    .collect()
+            }
+        };
+        cells
+            .into_iter()
+            .map(|cell| self.rotate_cell(cell))
+            .collect()
+    }
+
+    // whatever a canvas cell shows up at is drawn again
+    pub fn redraw_canvas_cell(&mut self, cell: (i32, i32)) {
+        for view_cell in self.to_view(cell) {
+            self.redraw_cell(view_cell);
+        }
+    }
+
+    // the canvas cell under the view cell anchor stays where it is, the
+    // viewport keeps pixels on even columns
+    pub fn set_zoom(&mut self, zoom: i8, anchor: (i32, i32)) {
+        let (x, y) = self.to_canvas(anchor);
+        self.zoom = zoom;
+        let (source_x, source_y) = self.zoom_sources(anchor)[0];
+        let viewport_x: i32 = x - source_x;
+        self.viewport = (viewport_x - viewport_x.rem_euclid(2), y - source_y);
+    }
+
+    // layer relative cell under a cell of the unrotated view
+    pub fn relative_position(&self, index: usize, cell: (i32, i32)) -> (i32, i32) {
+        let (x, y) = match index + 1 == self.layers.len() {
+            true => cell,
+            false => self.to_canvas(cell),
+        };
+        let offset: (i32, i32) = self.layers[index].offset;
+        (x - offset.0, y - offset.1)
+    }
+
+    // the view moves over the canvas, items keep their canvas positions
+    pub fn pan(&mut self, (dx, dy): (i32, i32)) {
+        self.viewport = (self.viewport.0 + dx, self.viewport.1 + dy);
+    }
+
+    // every cell of the view looks up the canvas cells the rotation and zoom
+    // bring it from
+    fn transformed_composite(&self, layers: &[Layer]) -> Vec<Vec<TermChar>> {
+        let (canvas, ui) = layers.split_at(layers.len().saturating_sub(1));
+        let (width, height) = (self.width as usize, self.height as usize);
+        let cells: Vec<Vec<(i32, i32)>> = (0..height as i32)
+            .flat_map(|row| (0..width as i32).map(move |col| (col, row)))
+            .map(|cell| self.zoom_sources(self.unrotate_cell(cell)))
+            .collect();
+        let sources: Vec<(i32, i32)> = cells.iter().flatten().copied().collect();
+        let mut buffer: Vec<Vec<TermChar>> = vec![vec![EMPTY_TERM_CHAR; width]; height];
+        let (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) = (
+            sources.iter().map(|c| c.0).min(),
+            sources.iter().map(|c| c.1).min(),
+            sources.iter().map(|c| c.0).max(),
+            sources.iter().map(|c| c.1).max(),
+        ) else {
+            return buffer;
+        };
+        let source_origin: (i32, i32) = (min_x + self.viewport.0, min_y + self.viewport.1);
+        let mut source = self.composite_canvas(
+            canvas,
+            source_origin,
+            (max_x - min_x + 1) as usize,
+            (max_y - min_y + 1) as usize,
+        );
+        self.fill_guides(&mut source, source_origin);
+        self.fill_grid(&mut source, source_origin);
+        for (i, cell_sources) in cells.into_iter().enumerate() {
+            let chars: Vec<TermChar> = cell_sources
+                .into_iter()
+                .map(|(x, y)| source[(y - min_y) as usize][(x - min_x) as usize])
+                .collect();
+            buffer[i / width][i % width] = blend(&chars);
+        }
+        for layer in ui.iter() {
+            for item in layer.items.iter() {
+                item.composite(&mut buffer, layer.offset);
+            }
+        }
+        buffer
+    }
+
+    // grid char at a canvas cell, a cross where lines meet and a dot along
+    // them on the left cell of every pixel, lines run from the layer 0 origin.
+    // zoomed out far enough to blend pixels there is no grid
+    fn grid_char_at(&self, (x, y): (i32, i32)) -> Option<TermChar> {
+        let spacing: i32 = self.grid.filter(|_| self.zoom >= -1)?;
+        let origin: (i32, i32) = self.layers.first().map_or((0, 0), |layer| layer.offset);
+        let (dx, dy) = (x - origin.0, y - origin.1);
+        if dx.rem_euclid(2) != 0 {
+            return None;
+        }
+        let character: char = match (
+            dx.div_euclid(2).rem_euclid(spacing) == 0,
+            dy.rem_euclid(spacing) == 0,
+        ) {
+            (true, true) => '+',
+            (true, false) | (false, true) => '·',
+            (false, false) => return None,
+        };
+        Some(TermChar {
+            character,
+            foreground_color: Color::DarkGrey,
+            background_color: Color::Reset,
+            empty: false,
+        })
+    }
+
+    // empty cells of a buffer of canvas cells starting at origin show the grid
+    fn fill_grid(&self, buffer: &mut [Vec<TermChar>], origin: (i32, i32)) {
+        if self.grid.is_none() {
+            return;
+        }
+        for (row, cells) in buffer.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate().filter(|(_, cell)| cell.empty) {
+                if let Some(term_char) =
+                    self.grid_char_at((origin.0 + col as i32, origin.1 + row as i32))
+                {
+                    *cell = term_char;
+                }
+            }
+        }
+    }
+
+    // previous and next frames when onion skinning, none without frames
+    fn onion_frames(&self) -> Vec<&Vec<Layer>> {
+        if !self.onion {
+            return Vec::new();
+        }
+        let previous: Option<&Vec<Layer>> = self.frame.checked_sub(1).map(|i| &self.frames[i]);
+        previous
+            .into_iter()
+            .chain(self.frames.get(self.frame))
+            .collect()
+    }
+
+    // canvas layers flattened over the neighbouring frames, those dimmed as
+    // they are laid down when onion skinning
+    fn composite_canvas(
+        &self,
+        canvas: &[Layer],
+        origin: (i32, i32),
+        width: usize,
+        height: usize,
+    ) -> Vec<Vec<TermChar>> {
+        let frames: Vec<&Vec<Layer>> = self.onion_frames();
+        if frames.is_empty() {
+            return composite_layers(canvas, origin, width, height);
+        }
+        let mut buffer: Vec<Vec<TermChar>> = vec![vec![EMPTY_TERM_CHAR; width]; height];
+        for layer in frames.into_iter().flatten() {
+            let c_offset = (layer.offset.0 - origin.0, layer.offset.1 - origin.1);
+            layer.composite(&mut buffer, c_offset);
+        }
+        for term_char in buffer.iter_mut().flatten().filter(|c| !c.empty) {
+            *term_char = dimmed(*term_char);
+        }
+        for layer in canvas.iter() {
+            let c_offset = (layer.offset.0 - origin.0, layer.offset.1 - origin.1);
+            layer.composite(&mut buffer, c_offset);
+        }
+        buffer
+    }
+
+    // the guides are drawn over a buffer of canvas cells starting at origin
+    fn fill_guides(&self, buffer: &mut [Vec<TermChar>], origin: (i32, i32)) {
+        let Some(base) = self.layers.first().filter(|_| self.layers.len() > 1) else {
+            return;
+        };
+        let c_offset: (i32, i32) = (base.offset.0 - origin.0, base.offset.1 - origin.1);
+        for guide in self.guides.iter() {
+            guide.composite(buffer, c_offset);
+        }
+    }
+
+    // layer 0 pixels and items recolored by the tint, None when there is no tint
+    fn tinted_layers(&self) -> Option<Vec<Layer>> {
+        let tint = self.tint.as_ref()?;
+        let mut layers: Vec<Layer> = self.layers.clone();
+        for (offset, color) in layers[0].pixels.colors_mut() {
+            if let Some(&tinted) = tint.get(&offset) {
+                *color = tinted;
+            }
+        }
+        for item in layers[0].items.iter_mut() {
+            let Some(&color) = tint.get(&item.offset) else {
+                continue;
+            };
+            for term_char in item.chars.iter_mut().flatten().filter(|c| !c.empty) {
+                term_char.foreground_color = color;
+                if term_char.background_color != Color::Reset {
+                    term_char.background_color = color;
+                }
+            }
+        }
+        Some(layers)
+    }
+
+    // canvas layers shrunk by the smallest integer factor that fits everything
+    // drawn on the screen, only every kth pixel is kept, the last layer holds
+    // the ui and is laid on top unscaled
+    pub fn fit_composite(&self) -> Vec<Vec<TermChar>> {
+        let (canvas, ui) = self.layers.split_at(self.layers.len().saturating_sub(1));
+        let Some(((min_x, min_y), (max_x, max_y))) = layers_bounds(canvas) else {
+            return self.composite();
+        };
+        // pixels are two cells wide, the window starts on a pixel boundary
+        let min_x: i32 = min_x - min_x.rem_euclid(2);
+        let (bounds_w, bounds_h) = ((max_x - min_x + 1) as usize, (max_y - min_y + 1) as usize);
+        let (width, height) = (self.width as usize, self.height as usize);
+        let k: usize = bounds_w
+            .div_ceil(width.max(1))
+            .max(bounds_h.div_ceil(height.max(1)))
+            .max(1);
+        let source = composite_layers(canvas, (min_x, min_y), bounds_w, bounds_h);
+        let mut buffer: Vec<Vec<TermChar>> = vec![vec![EMPTY_TERM_CHAR; width]; height];
+        for (y, row) in buffer.iter_mut().enumerate() {
+            let Some(source_row) = source.get(y * k) else {
+                break;
+            };
+            for (x, cell) in row.iter_mut().enumerate() {
+                if let Some(term_char) = source_row.get((x / 2) * k * 2 + x % 2) {
+                    *cell = *term_char;
+                }
+            }
+        }
+        for layer in ui.iter() {
+            for item in layer.items.iter() {
+                item.composite(&mut buffer, layer.offset);
+            }
+        }
+        buffer
+    }
+
+    // the composited screen replaces the back buffer, the next present writes
+    // what changed
+    pub fn redraw(&mut self) {
         for layer in self.layers.iter_mut() {
-            layer.redraw(&mut self.term, self.width, self.height);
+            layer.take_dirty();
+        }
+        let rows: Vec<Vec<TermChar>> = match self.fit {
+            true => self.fit_composite(),
+            false => self.composite(),
+        };
+        self.term.fill(rows);
+        self.drawn = Some(self.drawn_view());
+    }
+
+    fn drawn_view(&self) -> DrawnView {
+        DrawnView {
+            layers: self.layers.len(),
+            size: (self.width, self.height),
+            guides: self.guides.len(),
+            fit: self.fit,
+            rotation: self.rotation,
+            viewport: self.viewport,
+            zoom: self.zoom,
+            grid: self.grid,
+            frame: self.frame,
+            onion: self.onion,
+        }
+    }
+
+    // the next refresh redraws everything, e.g. after the terminal was cleared
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+
+    // like redraw but only the cells the layers changed since are composited
+    // again, a moved layer, a reshaped view, the fitted, rotated, zoomed or
+    // tinted views fall back to a full redraw
+    pub fn refresh(&mut self) {
+        let mut dirty: Dirty = Dirty::Clean;
+        let (ui, viewport) = (self.layers.len().saturating_sub(1), self.viewport);
+        for (i, layer) in self.layers.iter_mut().enumerate() {
+            dirty.merge(match (i == ui, layer.take_dirty()) {
+                (false, Dirty::Cells(rect)) => Dirty::Cells(rect.shift((-viewport.0, -viewport.1))),
+                (_, layer_dirty) => layer_dirty,
+            });
+        }
+        let partial: bool = self.drawn == Some(self.drawn_view())
+            && !self.fit
+            && self.rotation == 0
+            && self.zoom == 0
+            && self.tint.is_none();
+        match (partial, dirty) {
+            (true, Dirty::Clean) => {}
+            (true, Dirty::Cells(rect)) => {
+                let Some(rect) = rect.clip(self.width, self.height) else {
+                    return;
+                };
+                let (width, height) = rect.size();
+                let rows: Vec<Vec<TermChar>> = self.composite_window(rect.min, width, height);
+                self.term
+                    .fill_at((rect.min.0 as usize, rect.min.1 as usize), rows);
+            }
+            _ => self.redraw(),
+        }
+    }
+
+    // end of a frame, everything drawn since the last one reaches the terminal
+    pub fn present(&mut self) -> io::Result<()> {
+        self.term.present(self.render)
+    }
+
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.term.resize(width, height);
+    }
+
+    // every item anchored where the topmost item at the canvas cell x,y is gets
+    // removed, cells they covered are redrawn so that whatever is underneath
+    // shows through, the removed items are returned bottom to top
+    pub fn erase_item_at(&mut self, index: usize, (x, y): (i32, i32)) -> Vec<Item> {
+        let layer: &mut Layer = &mut self.layers[index];
+        let Some(offset) = layer.get_item_at_absolute((x, y)).map(|item| item.offset) else {
+            return Vec::new();
+        };
+        let removed_items: Vec<Item> = layer
+            .items
+            .iter()
+            .filter(|i| i.offset == offset)
+            .cloned()
+            .collect();
+        let mut cells: Vec<(i32, i32)> = Vec::new();
+        for removed in removed_items.iter() {
+            let (x0, y0) = removed.screen_position(vec![layer.offset]);
+            for (row, row_vec) in removed.chars.iter().enumerate() {
+                for col in 0..row_vec.len() {
+                    cells.push((x0 + col as i32, y0 + row as i32));
+                }
+            }
+        }
+        layer.retain_items(|i| i.offset != offset);
+        for cell in cells {
+            self.redraw_canvas_cell(cell);
+        }
+        removed_items
+    }
+
+    // the pixel covering the canvas cell x,y is cleared and both its cells
+    // redrawn, it is returned with its layer relative anchor
+    pub fn erase_pixel_at(
+        &mut self,
+        index: usize,
+        (x, y): (i32, i32),
+    ) -> Option<((i32, i32), Color)> {
+        let layer: &mut Layer = &mut self.layers[index];
+        let anchor: (i32, i32) = layer.pixel_at((x, y))?;
+        let color: Color = layer.clear_pixel(anchor)?;
+        let (x0, y0) = layer.absolute_position(anchor);
+        for col in 0..2 {
+            self.redraw_canvas_cell((x0 + col, y0));
+        }
+        Some((anchor, color))
+    }
+    // layer at index is merged into the one right below, locked layers are never merged
+    pub fn merge_down(&mut self, index: usize) -> bool {
+        if index == 0 || index >= self.layers.len() {
+            return false;
         }
+        if self.layers[index].locked || self.layers[index - 1].locked {
+            return false;
+        }
+        let upper: Layer = self.layers.remove(index);
+        self.layers[index - 1].merge(upper);
+        true
+    }
+
+    // the copy is placed right above the original
+    pub fn duplicate_layer(&mut self, index: usize) {
+        let mut copy: Layer = self.layers[index].clone();
+        copy.name = format!("{} copy", copy.name);
+        copy.locked = false;
+        self.layers.insert(index + 1, copy);
     }
-    fn first_filled_layer_at_index(&self, index: &(u16, u16)) -> Option<usize> {
-        let casted_index = (index.0 as i32, index.1 as i32);
-        for (i, layer) in self.layers.iter().enumerate() {
-            if layer.get_filled_indexes().contains(&casted_index) {
-                return Some(i);
+
+    // cells an item drawn straight on the screen, like the tool indicators,
+    // covered show the composited layers again
+    pub fn uncover(&mut self, item: &Item) {
+        for (row, row_vec) in item.chars.iter().enumerate() {
+            for col in 0..row_vec.len() {
+                self.redraw_cell((item.offset.0 + col as i32, item.offset.1 + row as i32));
             }
         }
-        None
     }
 
-    fn first_item_at_col_row(&self, (col, row): (u16, u16)) -> Option<&Item> {
-        for layer in self.layers.iter() {
-            if let Some(item) = layer.get_item_at_absolute((col as i32, row as i32)) {
-                return Some(item);
+    // redraws whatever is visible at col,row of the view, the topmost non empty
+    // char wins, canvas layers are looked up where the rotation and zoom bring
+    // them from
+    pub fn redraw_cell(&mut self, (col, row): (i32, i32)) {
+        let (x0, y0) = self.viewport;
+        let canvas_chars: Vec<TermChar> = self
+            .zoom_sources(self.unrotate_cell((col, row)))
+            .into_iter()
+            .map(|(x, y)| self.canvas_char_at((x + x0, y + y0)))
+            .collect();
+        let mut visible: TermChar = blend(&canvas_chars);
+        if let Some(ui) = self.layers.last() {
+            if let Some(term_char) = ui.char_at((col, row)) {
+                visible = term_char;
             }
         }
-        None
+        visible.draw(&mut self.term, (col, row), self.width, self.height);
     }
-    fn index_is_empty(&self, &index: &(u16, u16)) -> bool {
-        self.first_filled_layer_at_index(&index).is_none()
+
+    // topmost char of the canvas layers and the guides over them at a canvas cell
+    fn canvas_char_at(&self, cell: (i32, i32)) -> TermChar {
+        let mut visible: TermChar = EMPTY_TERM_CHAR;
+        for layer in self.onion_frames().into_iter().flatten() {
+            if let Some(term_char) = layer.char_at(cell) {
+                visible = dimmed(term_char);
+            }
+        }
+        let ui: usize = self.layers.len().saturating_sub(1);
+        for layer in self.layers[..ui].iter() {
+            if let Some(term_char) = layer.char_at(cell) {
+                visible = term_char;
+            }
+        }
+        for guide in self.guides.iter() {
+            if let Some(term_char) = guide.char_at(self.layers[0].offset, cell) {
+                visible = *term_char;
+            }
+        }
+        match visible.empty {
+            true => self.grid_char_at(cell).unwrap_or(visible),
+            false => visible,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn text_item(text: &str, offset: (i32, i32)) -> Item {
+        Item {
+            name: "text".to_string(),
+            offset,
+            chars: vec![text
+                .chars()
+                .map(|character| TermChar {
+                    character,
+                    empty: false,
+                    ..EMPTY_TERM_CHAR
+                })
+                .collect()],
+        }
+    }
+
+    // a screen drawing in memory and the memory to look at
+    fn memory_screen(layers: Vec<Layer>) -> (Screen, Rc<RefCell<MemoryBackend>>) {
+        let memory = Rc::new(RefCell::new(MemoryBackend::new(12, 3)));
+        let screen: Screen = Screen::with_backend(layers, Box::new(memory.clone()));
+        (screen, memory)
+    }
+
+    #[test]
+    fn frames_render_in_memory() {
+        let mut layer: Layer = Layer::new_empty("a".to_string(), 12, 3, (0, 0));
+        layer.add_item(text_item("hello", (1, 0)));
+        layer.add_item(text_item("pixelrs", (2, 2)));
+        let (mut screen, memory) = memory_screen(vec![layer]);
+        screen.redraw();
+        screen.present().unwrap();
+        assert_eq!(memory.borrow().text(), " hello\n\n  pixelrs");
+        assert_eq!(memory.borrow().flushes, 1);
+    }
+
+    #[test]
+    fn refreshes_match_full_redraws() {
+        for render in [RenderMode::Full, RenderMode::Diff] {
+            let layer: Layer = Layer::new_empty("a".to_string(), 12, 3, (0, 0));
+            let (mut screen, memory) = memory_screen(vec![layer]);
+            screen.render = render;
+            screen.layers[0].add_item(text_item("abc", (0, 1)));
+            screen.redraw();
+            screen.present().unwrap();
+            // only what changed is written, the rest stays on screen
+            screen.layers[0].add_item(text_item("xy", (8, 1)));
+            screen.layers[0].remove_last_at((0, 1));
+            screen.refresh();
+            screen.present().unwrap();
+            assert_eq!(memory.borrow().text(), "\n        xy\n");
+        }
+    }
+
+    #[test]
+    fn failed_wraps_keep_the_backend() {
+        let mut layer: Layer = Layer::new_empty("a".to_string(), 12, 3, (0, 0));
+        layer.add_item(text_item("kept", (0, 0)));
+        let (mut screen, memory) = memory_screen(vec![layer]);
+        let failed = screen
+            .term
+            .wrap_backend(|backend| Err((io::Error::other("no cast"), backend)));
+        assert!(failed.is_err());
+        screen.redraw();
+        screen.present().unwrap();
+        assert_eq!(memory.borrow().text(), "kept\n\n");
+    }
+
+    // frame names in order, the shown one in brackets
+    fn frames(screen: &Screen) -> Vec<String> {
+        let mut names: Vec<String> = screen.frames.iter().map(|f| f[0].name.clone()).collect();
+        names.insert(screen.frame, format!("[{}]", screen.layers[0].name));
+        names
+    }
+
+    #[test]
+    fn onion_skins_show_dimmed_under_the_frame() {
+        let layer: Layer = Layer::new_empty("a".to_string(), 12, 3, (0, 0));
+        let ui: Layer = Layer::new_empty("ui".to_string(), 12, 3, (0, 0));
+        let (mut screen, _) = memory_screen(vec![layer, ui]);
+        let red = Color::Rgb { r: 200, g: 0, b: 0 };
+        screen.layers[0].paint((0, 0), red);
+        screen.duplicate_frame(1);
+        screen.layers[0].clear_pixels();
+        screen.layers[0].paint((2, 0), red);
+        assert!(screen.composite()[0][0].empty);
+        screen.onion = true;
+        let shown: Vec<Vec<TermChar>> = screen.composite();
+        assert!(!shown[0][0].empty && shown[0][0].background_color != red);
+        assert!(shown[0][2].background_color == red);
+    }
+
+    #[test]
+    fn deleted_frames_come_back() {
+        let layer: Layer = Layer::new_empty("a".to_string(), 10, 10, (0, 0));
+        let mut screen = Screen::with_backend(vec![layer], Box::new(MemoryBackend::new(20, 10)));
+        screen.duplicate_frame(1);
+        screen.layers[0].name = "b".to_string();
+        screen.duplicate_frame(1);
+        screen.layers[0].name = "c".to_string();
+        assert_eq!(frames(&screen), vec!["a", "b", "[c]"]);
+
+        screen.show_frame(1, 1);
+        let dropped: Vec<Layer> = screen.delete_frame(1).unwrap();
+        assert_eq!(frames(&screen), vec!["a", "[c]"]);
+        assert!(screen.insert_frame(1, dropped, 1));
+        assert_eq!(frames(&screen), vec!["a", "[b]", "c"]);
+
+        screen.drop_frame(0, 1).unwrap();
+        assert_eq!(frames(&screen), vec!["[a]", "c"]);
+        assert!(screen.drop_frame(1, 1).is_none());
+        screen.drop_frame(0, 1).unwrap();
+        assert_eq!(frames(&screen), vec!["[c]"]);
+        assert!(screen.delete_frame(1).is_none());
     }
 }
@@ -0,0 +1,397 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crossterm::style::Color;
+use rhai::{Engine, EvalAltResult, Position};
+
+use crate::constants::{MAX_SCRIPT_OPERATIONS, MAX_SCRIPT_PIXELS};
+use crate::palette::{color_to_rgb, nearest_ansi};
+use crate::screen::{Layer, PIXEL_NEIGHBOURS};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// what a script drew, on copies of the document layers so it reads back its
+// own pixels, and the paints and added layers to apply to the real ones.
+// scripts count in pixels from the top left corner of the layer, colors are
+// 256 colors palette codes
+pub struct ScriptCanvas {
+    pub layers: Vec<Layer>,
+    // names of the layers the script added, they go after the existing ones
+    pub added: Vec<String>,
+    // layer, layer cell and color of every pixel painted, in order
+    pub paints: Vec<(usize, (i32, i32), Color)>,
+    layer: usize,
+    // pixels fill stays within, the canvas frame or the screen
+    size: (i32, i32),
+    color: Color,
+}
+
+impl ScriptCanvas {
+    pub fn new(layers: Vec<Layer>, layer: usize, size: (i32, i32), color: Color) -> Self {
+        ScriptCanvas {
+            layers,
+            added: Vec::new(),
+            paints: Vec::new(),
+            layer,
+            size,
+            color,
+        }
+    }
+
+    fn put_pixel(&mut self, at: (i64, i64), code: i64) -> ScriptResult<()> {
+        let color: Color = Color::AnsiValue(color_code(code)?);
+        let cell: (i32, i32) = layer_cell(at)?;
+        if self.paints.len() >= MAX_SCRIPT_PIXELS {
+            return Err(format!("more than {} pixels painted", MAX_SCRIPT_PIXELS).into());
+        }
+        self.layers[self.layer].paint(cell, color);
+        self.paints.push((self.layer, cell, color));
+        Ok(())
+    }
+
+    fn get_pixel(&self, at: (i64, i64)) -> ScriptResult<i64> {
+        Ok(match self.layers[self.layer].pixel_color(layer_cell(at)?) {
+            Some(color) => code_of(color),
+            None => -1,
+        })
+    }
+
+    // the part of the segment within the canvas, none when it misses it
+    fn clip(
+        &self,
+        (x0, y0): (i64, i64),
+        (x1, y1): (i64, i64),
+    ) -> Option<((i64, i64), (i64, i64))> {
+        let (width, height) = (self.size.0 as f64, self.size.1 as f64);
+        let (dx, dy) = ((x1 - x0) as f64, (y1 - y0) as f64);
+        let (mut enter, mut leave) = (0.0_f64, 1.0_f64);
+        for (p, q) in [
+            (-dx, x0 as f64),
+            (dx, width - 1.0 - x0 as f64),
+            (-dy, y0 as f64),
+            (dy, height - 1.0 - y0 as f64),
+        ] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    return None;
+                }
+                continue;
+            }
+            match p < 0.0 {
+                true => enter = enter.max(q / p),
+                false => leave = leave.min(q / p),
+            }
+            if enter > leave {
+                return None;
+            }
+        }
+        let at = |t: f64| {
+            (
+                (x0 as f64 + t * dx).round() as i64,
+                (y0 as f64 + t * dy).round() as i64,
+            )
+        };
+        Some((at(enter), at(leave)))
+    }
+
+    // bresenham, both ends included, only the part of it within the canvas
+    fn line(&mut self, from: (i64, i64), to: (i64, i64), code: i64) -> ScriptResult<()> {
+        layer_cell(from)?;
+        layer_cell(to)?;
+        let Some(((x0, y0), (x1, y1))) = self.clip(from, to) else {
+            return Ok(());
+        };
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        loop {
+            self.put_pixel((x, y), code)?;
+            if (x, y) == (x1, y1) {
+                return Ok(());
+            }
+            if 2 * error >= dy {
+                error += dy;
+                x += sx;
+            }
+            if 2 * error <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn rect(
+        &mut self,
+        (x, y): (i64, i64),
+        (width, height): (i64, i64),
+        code: i64,
+    ) -> ScriptResult<()> {
+        if width <= 0 || height <= 0 {
+            return Ok(());
+        }
+        let (right, bottom) = (x.saturating_add(width - 1), y.saturating_add(height - 1));
+        self.line((x, y), (right, y), code)?;
+        self.line((x, bottom), (right, bottom), code)?;
+        self.line((x, y), (x, bottom), code)?;
+        self.line((right, y), (right, bottom), code)
+    }
+
+    // only the part of the rectangle within the canvas
+    fn fill_rect(
+        &mut self,
+        (x, y): (i64, i64),
+        (width, height): (i64, i64),
+        code: i64,
+    ) -> ScriptResult<()> {
+        layer_cell((x, y))?;
+        let (right, bottom) = (x.saturating_add(width), y.saturating_add(height));
+        let columns = x.max(0)..right.min(self.size.0 as i64);
+        for row in y.max(0)..bottom.min(self.size.1 as i64) {
+            for col in columns.clone() {
+                self.put_pixel((col, row), code)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the pixels connected to x,y of the same color, or empty like it, within
+    // the canvas are painted
+    fn fill(&mut self, (x, y): (i64, i64), code: i64) -> ScriptResult<()> {
+        let fill: Color = Color::AnsiValue(color_code(code)?);
+        let start: (i32, i32) = layer_cell((x, y))?;
+        let layer: &Layer = &self.layers[self.layer];
+        let target: Option<Color> = layer.pixel_color(start);
+        if target == Some(fill) {
+            return Ok(());
+        }
+        let (width, height) = self.size;
+        let inside = |(x, y): (i32, i32)| (0..2 * width).contains(&x) && (0..height).contains(&y);
+        let mut region: Vec<(i32, i32)> = Vec::new();
+        let mut seen: HashSet<(i32, i32)> = HashSet::new();
+        let mut pending: Vec<(i32, i32)> = vec![start];
+        while let Some(cell) = pending.pop() {
+            if !inside(cell) || !seen.insert(cell) || layer.pixel_color(cell) != target {
+                continue;
+            }
+            region.push(cell);
+            pending.extend(
+                PIXEL_NEIGHBOURS
+                    .iter()
+                    .map(|(dx, dy)| (cell.0 + dx, cell.1 + dy)),
+            );
+        }
+        for (x, y) in region {
+            self.put_pixel(((x / 2) as i64, y as i64), code)?;
+        }
+        Ok(())
+    }
+
+    fn select_layer(&mut self, index: i64) -> ScriptResult<()> {
+        match usize::try_from(index)
+            .ok()
+            .filter(|&i| i < self.layers.len())
+        {
+            Some(index) => {
+                self.layer = index;
+                Ok(())
+            }
+            None => Err(format!("there is no layer {}", index).into()),
+        }
+    }
+
+    // an empty layer on top, which is selected
+    fn add_layer(&mut self, name: &str) -> i64 {
+        let (width, height) = (self.size.0 as u16, self.size.1 as u16);
+        self.layers
+            .push(Layer::new_empty(name.to_string(), width, height, (0, 0)));
+        self.added.push(name.to_string());
+        self.layer = self.layers.len() - 1;
+        self.layer as i64
+    }
+}
+
+// cell of the layer a pixel takes, pixels take two cells and layers are
+// addressed with i32, scripts going past that get an error
+fn layer_cell((x, y): (i64, i64)) -> ScriptResult<(i32, i32)> {
+    match (
+        x.checked_mul(2).and_then(|x| i32::try_from(x).ok()),
+        i32::try_from(y).ok(),
+    ) {
+        (Some(x), Some(y)) => Ok((x, y)),
+        _ => Err(format!("pixel {},{} is out of range", x, y).into()),
+    }
+}
+
+fn color_code(code: i64) -> ScriptResult<u8> {
+    u8::try_from(code).map_err(|_| {
+        Box::new(EvalAltResult::ErrorArithmetic(
+            format!("color {} is not a 0-255 palette code", code),
+            Position::NONE,
+        ))
+    })
+}
+
+// palette code of a color, the closest one for colors outside the palette
+fn code_of(color: Color) -> i64 {
+    match (color, color_to_rgb(color)) {
+        (Color::AnsiValue(code), _) => code as i64,
+        (_, Some((r, g, b))) => nearest_ansi((r as i32, g as i32, b as i32)) as i64,
+        (_, None) => -1,
+    }
+}
+
+// runs the script on the canvas, a script that fails leaves nothing drawn
+pub fn run(source: &str, canvas: ScriptCanvas) -> Result<ScriptCanvas, String> {
+    let state: Rc<RefCell<ScriptCanvas>> = Rc::new(RefCell::new(canvas));
+    let mut engine: Engine = Engine::new();
+    // scripts may loop forever, and printing would land on the canvas
+    engine.set_max_operations(MAX_SCRIPT_OPERATIONS);
+    engine.on_print(|_| {});
+    engine.on_debug(|_, _, _| {});
+
+    let s = state.clone();
+    engine.register_fn("put_pixel", move |x: i64, y: i64, color: i64| {
+        s.borrow_mut().put_pixel((x, y), color)
+    });
+    let s = state.clone();
+    engine.register_fn("get_pixel", move |x: i64, y: i64| {
+        s.borrow().get_pixel((x, y))
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "line",
+        move |x0: i64, y0: i64, x1: i64, y1: i64, color: i64| {
+            s.borrow_mut().line((x0, y0), (x1, y1), color)
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "rect",
+        move |x: i64, y: i64, width: i64, height: i64, color: i64| {
+            s.borrow_mut().rect((x, y), (width, height), color)
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "fill_rect",
+        move |x: i64, y: i64, width: i64, height: i64, color: i64| {
+            s.borrow_mut().fill_rect((x, y), (width, height), color)
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("fill", move |x: i64, y: i64, color: i64| {
+        s.borrow_mut().fill((x, y), color)
+    });
+    let s = state.clone();
+    engine.register_fn("layer", move |index: i64| {
+        s.borrow_mut().select_layer(index)
+    });
+    let s = state.clone();
+    engine.register_fn("layer", move || s.borrow().layer as i64);
+    let s = state.clone();
+    engine.register_fn("layers", move || s.borrow().layers.len() as i64);
+    let s = state.clone();
+    engine.register_fn("add_layer", move |name: &str| {
+        s.borrow_mut().add_layer(name)
+    });
+    let s = state.clone();
+    engine.register_fn("width", move || s.borrow().size.0 as i64);
+    let s = state.clone();
+    engine.register_fn("height", move || s.borrow().size.1 as i64);
+    let s = state.clone();
+    engine.register_fn("color", move || code_of(s.borrow().color));
+    engine.register_fn("rgb", |r: i64, g: i64, b: i64| {
+        nearest_ansi((r as i32, g as i32, b as i32)) as i64
+    });
+
+    let result: Result<(), Box<EvalAltResult>> = engine.run(source);
+    drop(engine);
+    result.map_err(|e| e.to_string())?;
+    Rc::try_unwrap(state)
+        .map(RefCell::into_inner)
+        .map_err(|_| "the script is still running".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canvas() -> ScriptCanvas {
+        let layer: Layer = Layer::new_empty("layer".to_string(), 20, 10, (0, 0));
+        ScriptCanvas::new(vec![layer], 0, (10, 10), Color::AnsiValue(1))
+    }
+
+    fn painted(source: &str) -> Vec<(i32, i32)> {
+        let drawn: ScriptCanvas = run(source, canvas()).unwrap();
+        drawn.paints.iter().map(|&(_, cell, _)| cell).collect()
+    }
+
+    #[test]
+    fn line_includes_both_ends() {
+        assert_eq!(painted("line(0, 0, 2, 2, 2);"), vec![(0, 0), (2, 1), (4, 2)]);
+        assert_eq!(painted("line(3, 0, 1, 0, 2);"), vec![(6, 0), (4, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn line_is_clipped_to_the_canvas() {
+        let cells: Vec<(i32, i32)> = painted("line(-1000000000, 5, 1000000000, 5, 2);");
+        assert_eq!(cells.len(), 10);
+        assert!(cells.iter().all(|&(x, y)| (0..20).contains(&x) && y == 5));
+        assert!(painted("line(-5, -5, -1, -1, 2);").is_empty());
+    }
+
+    #[test]
+    fn rect_draws_the_outline() {
+        let cells: Vec<(i32, i32)> = painted("rect(1, 1, 3, 3, 2);");
+        for cell in [(2, 1), (4, 1), (6, 1), (2, 2), (6, 2), (2, 3), (4, 3), (6, 3)] {
+            assert!(cells.contains(&cell));
+        }
+        assert!(!cells.contains(&(4, 2)));
+    }
+
+    #[test]
+    fn fill_rect_stays_within_the_canvas() {
+        assert_eq!(painted("fill_rect(8, 8, 100, 100, 2);").len(), 4);
+    }
+
+    #[test]
+    fn fill_stops_at_other_colors() {
+        let drawn: ScriptCanvas = run("rect(0, 0, 4, 4, 2); fill(1, 1, 3);", canvas()).unwrap();
+        assert_eq!(drawn.layers[0].pixel_color((2, 1)), Some(Color::AnsiValue(3)));
+        assert_eq!(drawn.layers[0].pixel_color((4, 2)), Some(Color::AnsiValue(3)));
+        assert_eq!(drawn.layers[0].pixel_color((0, 1)), Some(Color::AnsiValue(2)));
+        assert_eq!(drawn.layers[0].pixel_color((10, 5)), None);
+    }
+
+    #[test]
+    fn fill_outside_an_outline_covers_the_rest_of_the_canvas() {
+        let drawn: ScriptCanvas = run("rect(0, 0, 4, 4, 2); fill(9, 9, 3);", canvas()).unwrap();
+        assert_eq!(drawn.layers[0].pixel_color((18, 9)), Some(Color::AnsiValue(3)));
+        assert_eq!(drawn.layers[0].pixel_color((2, 1)), None);
+    }
+
+    #[test]
+    fn add_layer_selects_the_new_layer() {
+        let drawn: ScriptCanvas = run("add_layer(\"top\"); put_pixel(0, 0, 2);", canvas()).unwrap();
+        assert_eq!(drawn.added, vec!["top".to_string()]);
+        assert_eq!(drawn.paints, vec![(1, (0, 0), Color::AnsiValue(2))]);
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let error = |source: &str| run(source, canvas()).err().unwrap();
+        assert!(error("put_pixel(0, 0, 256);").contains("0-255"));
+        assert!(error("put_pixel(2000000000, 0, 1);").contains("out of range"));
+        assert!(error("get_pixel(0, 3000000000);").contains("out of range"));
+        assert!(error("line(0, 0, 9223372036854775807, 0, 1);").contains("out of range"));
+        assert!(error("layer(3);").contains("no layer 3"));
+        assert!(error("loop {}").contains("operations"));
+    }
+
+    #[test]
+    fn painted_pixels_are_capped() {
+        let source: &str = "loop { fill_rect(0, 0, 10, 10, 1); }";
+        assert!(run(source, canvas()).err().unwrap().contains("pixels painted"));
+    }
+}
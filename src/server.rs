@@ -0,0 +1,522 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+use crate::constants::{
+    AUTH_HANDSHAKE, DEFAULT_ROOM, HANDSHAKE_ACCEPTED, HANDSHAKE_REJECTED, JOURNAL_LIMIT,
+    KICK_COOLDOWN, ROOM_HANDSHAKE,
+};
+use crate::protocol::{decode_updates, draws, drain_messages, encode_message, lone_update, writes};
+use crate::protocol::{encode_update, SerializableHistory, SerializableWrite, Update};
+use crate::storage::{check_room_name, room_operations, room_stroke, RoomCell, RoomStore};
+use crate::websocket::{
+    self, Frame, OPCODE_BINARY, OPCODE_CLOSE, OPCODE_PING, OPCODE_PONG, OPCODE_TEXT,
+};
+
+// room names are short, anything longer is not a handshake
+const MAX_HANDSHAKE_LEN: u64 = 256;
+
+// websocket close code for a connection refused by policy, e.g. a wrong password
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+
+struct Peer {
+    // participant id, given by the server on the first connection along with
+    // a secret and kept by the client across reconnections, the host kicks and
+    // bans by it
+    id: u64,
+    addr: SocketAddr,
+    room: String,
+    stream: TcpStream,
+    // whoever writes to the peer holds it for a whole message
+    writer: Writer,
+    // browsers get what other peers send wrapped in websocket frames
+    websocket: bool,
+}
+
+type Writer = Arc<Mutex<TcpStream>>;
+
+// a whole message as the peer reads them, in a websocket frame or behind its
+// length
+fn send(writer: &Writer, websocket: bool, message: &[u8]) -> io::Result<()> {
+    let bytes: Vec<u8> = match websocket {
+        true => websocket::encode_frame(OPCODE_BINARY, message, false),
+        false => encode_message(message),
+    };
+    writer
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .write_all(&bytes)
+}
+
+// shared sessions are mostly a relay, every message a peer sends is written
+// as is to every other peer in its room, the server only answers pings and
+// history requests and holds back drawing in locked rooms
+type Peers = Arc<Mutex<Vec<Peer>>>;
+
+// writes made to the shared layer of each room, the last JOURNAL_LIMIT, for
+// peers joining with --replay-history
+type Journals = Arc<Mutex<HashMap<String, VecDeque<SerializableWrite>>>>;
+
+// participants turned away and their address, until when for kicks and for
+// good for bans
+type Banned = Arc<Mutex<HashMap<u64, (IpAddr, Option<Instant>)>>>;
+
+// the secret given with each participant id, a rejoining client presents it
+// to keep its id
+type Secrets = Arc<Mutex<HashMap<u64, u64>>>;
+
+// the participant id and secret presented by a rejoining client
+type Rejoin = (u64, u64);
+
+// locked rooms and the participant still drawing in each
+type Locks = Arc<Mutex<HashMap<String, u64>>>;
+
+// where rooms are kept across restarts, when the host asked for it
+type Store = Arc<Mutex<Box<dyn RoomStore + Send>>>;
+
+// a connected peer as the host sees it on the connection screen
+pub struct PeerInfo {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub room: String,
+}
+
+// what the session that started the server controls of it
+#[derive(Clone)]
+pub struct Host {
+    peers: Peers,
+    banned: Banned,
+    secrets: Secrets,
+    locks: Locks,
+    store: Option<Store>,
+    journals: Journals,
+}
+
+impl Host {
+    // every connected peer of every room, oldest first
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|peer| PeerInfo {
+                id: peer.id,
+                addr: peer.addr,
+                room: peer.room.clone(),
+            })
+            .collect()
+    }
+
+    // disconnects the peer, it is turned away for KICK_COOLDOWN so the client
+    // does not come right back when reconnecting on its own
+    pub fn kick(&self, id: u64) -> io::Result<SocketAddr> {
+        let addr: SocketAddr = self.disconnect(id)?;
+        let until: Instant = Instant::now() + KICK_COOLDOWN;
+        self.banned.lock().unwrap().insert(id, (addr.ip(), Some(until)));
+        Ok(addr)
+    }
+
+    // disconnects the peer and turns it away for good
+    pub fn ban(&self, id: u64) -> io::Result<SocketAddr> {
+        let addr: SocketAddr = self.disconnect(id)?;
+        self.banned.lock().unwrap().insert(id, (addr.ip(), None));
+        Ok(addr)
+    }
+
+    // the relay drops what the peers of room draw, but for keeper
+    pub fn lock(&self, room: &str, keeper: u64) {
+        self.locks.lock().unwrap().insert(room.to_string(), keeper);
+    }
+
+    pub fn unlock(&self, room: &str) {
+        self.locks.lock().unwrap().remove(room);
+    }
+
+    fn disconnect(&self, id: u64) -> io::Result<SocketAddr> {
+        let mut peers = self.peers.lock().unwrap();
+        let index: usize = peers
+            .iter()
+            .position(|peer| peer.id == id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no peer {}", id)))?;
+        let peer: Peer = peers.remove(index);
+        // the relay thread of the peer sees its read fail and ends
+        let _ = peer.stream.shutdown(Shutdown::Both);
+        Ok(peer.addr)
+    }
+}
+
+// a participant turned away stays away under a new id too, connections from
+// its address that don't prove an older id are refused, peers sharing the
+// address rejoin with theirs
+fn is_banned(banned: &Banned, participant: u64, from: IpAddr, fresh: bool) -> bool {
+    let mut banned = banned.lock().unwrap();
+    banned.retain(|_, (_, until)| until.is_none_or(|until| Instant::now() < until));
+    banned.contains_key(&participant) || (fresh && banned.values().any(|(addr, _)| *addr == from))
+}
+
+// a rejoining client keeps the participant id it was given, as long as it
+// presents the secret this server gave with it and nobody connected has it,
+// anyone else is known by the id of the connection
+fn claim_participant(
+    peers: &Peers,
+    secrets: &Secrets,
+    id: u64,
+    presented: Option<Rejoin>,
+) -> u64 {
+    presented
+        .filter(|(presented, secret)| secrets.lock().unwrap().get(presented) == Some(secret))
+        .map(|(presented, _)| presented)
+        .filter(|presented| !peers.lock().unwrap().iter().any(|peer| peer.id == *presented))
+        .unwrap_or(id)
+}
+
+// binds addr and serves from a background thread, returns once the listener is
+// ready so a client started right after can connect. With a password only
+// clients presenting it are let in, with a store the pixels and erases of
+// every room are kept in it and handed to whoever joins
+pub fn spawn(
+    addr: &str,
+    password: Option<String>,
+    store: Option<Box<dyn RoomStore + Send>>,
+) -> io::Result<Host> {
+    let listener: TcpListener = TcpListener::bind(addr)?;
+    let host = Host {
+        peers: Arc::new(Mutex::new(Vec::new())),
+        banned: Arc::new(Mutex::new(HashMap::new())),
+        secrets: Arc::new(Mutex::new(HashMap::new())),
+        locks: Arc::new(Mutex::new(HashMap::new())),
+        store: store.map(|store| Arc::new(Mutex::new(store))),
+        journals: Arc::new(Mutex::new(HashMap::new())),
+    };
+    let serving: Host = host.clone();
+    thread::spawn(move || serve(listener, password, serving));
+    Ok(host)
+}
+
+fn serve(listener: TcpListener, password: Option<String>, host: Host) {
+    let password: Arc<Option<String>> = Arc::new(password);
+    for (id, stream) in (1..).zip(listener.incoming()) {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let host: Host = host.clone();
+        let password: Arc<Option<String>> = Arc::clone(&password);
+        thread::spawn(move || {
+            let _ = relay(stream, id, host, &password);
+        });
+    }
+}
+
+fn read_handshake_line(reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+    let mut line: String = String::new();
+    reader
+        .by_ref()
+        .take(MAX_HANDSHAKE_LEN)
+        .read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn missing(prefix: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("missing {} handshake", prefix.trim().to_lowercase()),
+    )
+}
+
+// browsers can't send the handshake lines, they pick the room with the path,
+// e.g. `/sketches`, and send the auth line as their first text message, a
+// password in the query would end up in logs and browser history. Rejoining
+// ones add their participant id and its secret as `?peer=<id>&secret=<secret>`
+fn websocket_session(
+    path: &str,
+    reader: &mut BufReader<TcpStream>,
+) -> io::Result<(String, Option<Rejoin>, String)> {
+    let (room, query) = path.split_once('?').unwrap_or((path, ""));
+    let room: String = match room.trim_start_matches('/') {
+        "" => DEFAULT_ROOM.to_string(),
+        room => room.to_string(),
+    };
+    let param = |name: &str| -> Option<u64> {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            .and_then(|value| value.parse().ok())
+    };
+    let participant: Option<Rejoin> = param("peer").zip(param("secret"));
+    let frame: Frame = websocket::read_frame(reader, MAX_HANDSHAKE_LEN as usize)?;
+    let given: String = match frame.opcode {
+        OPCODE_TEXT => String::from_utf8_lossy(&frame.payload).to_string(),
+        _ => String::new(),
+    };
+    let given: &str = given
+        .strip_prefix(AUTH_HANDSHAKE)
+        .ok_or_else(|| missing(AUTH_HANDSHAKE))?;
+    Ok((room, participant, given.to_string()))
+}
+
+// the first line of a connection names the room to join, followed by the
+// participant id and secret of a rejoining client, the second carries the password, which
+// is only checked when the server has one. A first line upgrading to websocket
+// is followed by a frame with the password instead
+fn handshake(
+    first_line: &str,
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut TcpStream,
+    password: &Option<String>,
+) -> io::Result<(String, Option<Rejoin>)> {
+    let (room, participant, given) = match first_line.starts_with("GET ") {
+        true => websocket_session(&websocket::accept(first_line, reader, writer)?, reader)?,
+        false => {
+            let room: &str = first_line
+                .strip_prefix(ROOM_HANDSHAKE)
+                .ok_or_else(|| missing(ROOM_HANDSHAKE))?;
+            let mut words = room.split(' ');
+            let room: &str = words.next().unwrap_or_default();
+            let mut number = || words.next().and_then(|word| word.parse().ok());
+            let participant: Option<Rejoin> = number().zip(number());
+            let auth: String = read_handshake_line(reader)?;
+            let given: &str = auth
+                .strip_prefix(AUTH_HANDSHAKE)
+                .ok_or_else(|| missing(AUTH_HANDSHAKE))?;
+            (room.to_string(), participant, given.to_string())
+        }
+    };
+    check_room_name(&room)?;
+    if password.as_ref().is_some_and(|password| *password != given) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "wrong session password",
+        ));
+    }
+    Ok((room, participant))
+}
+
+// accepted peers learn their participant id and its secret, websocket peers
+// are answered in frames, a rejection closes the connection with the reason
+// so browsers can show it
+fn answer(
+    writer: &mut TcpStream,
+    websocket: bool,
+    result: &io::Result<(String, u64, u64)>,
+) -> io::Result<()> {
+    match (websocket, result) {
+        (false, Ok((_, participant, secret))) => {
+            writeln!(writer, "{} {} {}", HANDSHAKE_ACCEPTED, participant, secret)
+        }
+        (false, Err(e)) => writeln!(writer, "{}{}", HANDSHAKE_REJECTED, e),
+        (true, Ok((_, participant, secret))) => writer.write_all(&websocket::encode_frame(
+            OPCODE_TEXT,
+            format!("{} {} {}", HANDSHAKE_ACCEPTED, participant, secret).as_bytes(),
+            false,
+        )),
+        (true, Err(e)) => {
+            let mut payload: Vec<u8> = CLOSE_POLICY_VIOLATION.to_be_bytes().to_vec();
+            payload.extend_from_slice(e.to_string().as_bytes());
+            writer.write_all(&websocket::encode_frame(OPCODE_CLOSE, &payload, false))
+        }
+    }
+}
+
+fn relay(stream: TcpStream, id: u64, host: Host, password: &Option<String>) -> io::Result<()> {
+    let Host {
+        peers,
+        banned,
+        secrets,
+        locks,
+        store,
+        journals,
+    } = host;
+    let from: SocketAddr = stream.peer_addr()?;
+    let mut writer: TcpStream = stream.try_clone()?;
+    let mut reader: BufReader<TcpStream> = BufReader::new(stream);
+    let first_line: String = read_handshake_line(&mut reader)?;
+    let websocket: bool = first_line.starts_with("GET ");
+    // rejected clients are told why before being dropped, so they don't hang
+    let joined: io::Result<(String, u64, u64)> =
+        handshake(&first_line, &mut reader, &mut writer, password).and_then(
+            |(room, presented)| {
+                let participant: u64 = claim_participant(&peers, &secrets, id, presented);
+                if is_banned(&banned, participant, from.ip(), participant == id) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "turned away by the host",
+                    ));
+                }
+                let secret: u64 = *secrets
+                    .lock()
+                    .unwrap()
+                    .entry(participant)
+                    .or_insert_with(rand::random);
+                Ok((room, participant, secret))
+            },
+        );
+    let _ = answer(&mut writer, websocket, &joined);
+    let (room, id, _) = joined?;
+    let stream: TcpStream = writer.try_clone()?;
+    let writer: Writer = Arc::new(Mutex::new(writer));
+    // the store stays locked until the peer is listed, whatever is relayed
+    // meanwhile is either in the stored cells or sent to the peer
+    let stored = store
+        .as_ref()
+        .map(|store| store.lock().unwrap_or_else(PoisonError::into_inner));
+    if let Some(stored) = &stored {
+        let cells: Vec<RoomCell> = stored.load(&room)?;
+        if !cells.is_empty() {
+            send(&writer, websocket, &encode_update(&room_stroke(&cells), false)?)?;
+        }
+    }
+    peers.lock().unwrap().push(Peer {
+        id,
+        addr: from,
+        room: room.clone(),
+        stream,
+        writer: Arc::clone(&writer),
+        websocket,
+    });
+    drop(stored);
+    let control = |opcode: u8, payload: &[u8]| {
+        let frame: Vec<u8> = websocket::encode_frame(opcode, payload, false);
+        let _ = writer.lock().unwrap_or_else(PoisonError::into_inner).write_all(&frame);
+    };
+
+    let mut buff: Vec<u8> = vec![0; 4096];
+    // messages may span reads, the incomplete tail waits here
+    let mut pending: Vec<u8> = Vec::new();
+    loop {
+        let n: usize = match reader.read(&mut buff) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        pending.extend_from_slice(&buff[..n]);
+        let mut messages: Vec<Vec<u8>> = match websocket {
+            false => match drain_messages(&mut pending) {
+                Ok(messages) => messages,
+                Err(_) => break,
+            },
+            true => {
+                let frames: Vec<Frame> = match websocket::drain_frames(&mut pending) {
+                    Ok(frames) => frames,
+                    Err(e) => {
+                        control(OPCODE_CLOSE, &websocket::refusal(&e));
+                        break;
+                    }
+                };
+                if let Some(ping) = frames.iter().find(|frame| frame.opcode == OPCODE_PING) {
+                    control(OPCODE_PONG, &ping.payload);
+                }
+                if frames.iter().any(|frame| frame.opcode == OPCODE_CLOSE) {
+                    control(OPCODE_CLOSE, &[]);
+                    break;
+                }
+                frames
+                    .iter()
+                    .filter_map(Frame::data)
+                    .filter(|data| !data.is_empty())
+                    .map(<[u8]>::to_vec)
+                    .collect()
+            }
+        };
+        // answers go back as json, every peer reads it, pings measure the way
+        // to the server
+        messages.retain(|message| {
+            let answer: Update = match lone_update(message) {
+                Some(Update::Ping(ping)) => Update::Pong(ping),
+                Some(Update::HistoryRequest(request)) => {
+                    let journals = journals.lock().unwrap_or_else(PoisonError::into_inner);
+                    Update::History(SerializableHistory {
+                        nonce: request.nonce,
+                        writes: journals.get(&room).into_iter().flatten().cloned().collect(),
+                    })
+                }
+                _ => return true,
+            };
+            if let Ok(answer) = encode_update(&answer, false) {
+                let _ = send(&writer, websocket, &answer);
+            }
+            false
+        });
+        let keeper: Option<u64> = locks.lock().unwrap().get(&room).copied();
+        if keeper.is_some_and(|keeper| keeper != id) {
+            messages.retain(|message| !draws(message));
+        }
+        if messages.is_empty() {
+            continue;
+        }
+        {
+            let mut journals = journals.lock().unwrap_or_else(PoisonError::into_inner);
+            let journal: &mut VecDeque<SerializableWrite> = journals.entry(room.clone()).or_default();
+            for message in messages.iter().filter(|message| draws(message)) {
+                for write in decode_updates(message).0.into_iter().flat_map(writes) {
+                    if journal.len() == JOURNAL_LIMIT {
+                        journal.pop_front();
+                    }
+                    journal.push_back(write);
+                }
+            }
+        }
+        if let Some(store) = &store {
+            let mut store = store.lock().unwrap_or_else(PoisonError::into_inner);
+            for operation in messages.iter().flat_map(|message| room_operations(message)) {
+                // a failing store does not hold up the session
+                let _ = store.apply(&room, operation);
+            }
+        }
+        // the lock is only held to list the room, a slow peer holds up the
+        // senders writing to it and nobody else
+        let targets: Vec<(u64, Writer, bool)> = peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|peer| peer.id != id && peer.room == room)
+            .map(|peer| (peer.id, Arc::clone(&peer.writer), peer.websocket))
+            .collect();
+        let failed: Vec<u64> = targets
+            .into_iter()
+            .filter(|(_, writer, websocket)| {
+                messages
+                    .iter()
+                    .any(|message| send(writer, *websocket, message).is_err())
+            })
+            .map(|(id, _, _)| id)
+            .collect();
+        // peers failing to receive are gone, they are dropped on the spot
+        if !failed.is_empty() {
+            peers
+                .lock()
+                .unwrap()
+                .retain(|peer| !failed.contains(&peer.id));
+        }
+    }
+    peers.lock().unwrap().retain(|peer| peer.id != id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_only_kept_with_their_secret() {
+        let peers: Peers = Arc::new(Mutex::new(Vec::new()));
+        let secrets: Secrets = Arc::new(Mutex::new(HashMap::from([(2, 42)])));
+        assert_eq!(claim_participant(&peers, &secrets, 5, Some((2, 42))), 2);
+        assert_eq!(claim_participant(&peers, &secrets, 5, Some((2, 41))), 5);
+        assert_eq!(claim_participant(&peers, &secrets, 5, Some((3, 42))), 5);
+        assert_eq!(claim_participant(&peers, &secrets, 5, None), 5);
+    }
+
+    #[test]
+    fn banned_addresses_only_let_known_ids_back() {
+        let banned: Banned = Arc::new(Mutex::new(HashMap::new()));
+        let (address, other): (IpAddr, IpAddr) =
+            ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        banned.lock().unwrap().insert(2, (address, None));
+        assert!(is_banned(&banned, 2, other, false));
+        assert!(is_banned(&banned, 5, address, true));
+        assert!(!is_banned(&banned, 3, address, false));
+        assert!(!is_banned(&banned, 5, other, true));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::keymap::Keymap;
+use pixelrs::screen::RenderMode;
+
+// defaults read from the config file, flags given on the command line win
+#[derive(Default)]
+pub struct Settings {
+    // tool selected on start, `brush`, `erase`, `ink`, `move`, `text` or `shade`
+    pub tool: Option<String>,
+    // 256 colors palette code selected on start
+    pub color: Option<u8>,
+    // width and height in pixels of the frame guiding the drawing
+    pub canvas: Option<(i32, i32)>,
+    // host:port used by serve and connect when none is given
+    pub server: Option<String>,
+    // opened projects are saved this often when they changed
+    pub autosave: Option<Duration>,
+    pub render: Option<RenderMode>,
+    // `key.<action> = "<key>"` lines rebind the actions of the keymap
+    pub keymap: Keymap,
+}
+
+// $PIXELRS_CONFIG, pixelrs/config.toml in $XDG_CONFIG_HOME or in ~/.config
+pub fn config_path() -> PathBuf {
+    if let Ok(path) = env::var("PIXELRS_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let config: PathBuf = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| Path::new(&env::var("HOME").unwrap_or_default()).join(".config"));
+    config.join("pixelrs").join("config.toml")
+}
+
+// the file as written, `key.<action>` lines make up the key table
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    tool: Option<String>,
+    color: Option<u8>,
+    canvas: Option<String>,
+    server: Option<String>,
+    autosave: Option<u64>,
+    render: Option<String>,
+    #[serde(default)]
+    key: BTreeMap<String, String>,
+}
+
+pub fn parse(source: &str) -> Result<Settings, String> {
+    let file: ConfigFile = toml::from_str(source).map_err(|e| e.to_string())?;
+    let canvas: Option<(i32, i32)> = match file.canvas {
+        Some(size) => {
+            let invalid = || format!("invalid canvas {}, expected WxH", size);
+            let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+            let (width, height) = (
+                width.trim().parse::<i32>().map_err(|_| invalid())?,
+                height.trim().parse::<i32>().map_err(|_| invalid())?,
+            );
+            if width <= 0 || height <= 0 {
+                return Err(invalid());
+            }
+            Some((width, height))
+        }
+        None => None,
+    };
+    let mut keymap: Keymap = Keymap::default();
+    for (action, key) in file.key.iter() {
+        keymap
+            .bind(action, key)
+            .map_err(|e| format!("key.{}: {}", action, e))?;
+    }
+    keymap.check()?;
+    Ok(Settings {
+        tool: file.tool,
+        color: file.color,
+        canvas,
+        server: file.server,
+        // zero turns autosaving off
+        autosave: file
+            .autosave
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs),
+        render: file.render.as_deref().map(RenderMode::parse).transpose()?,
+        keymap,
+    })
+}
+
+// a missing file leaves every default as is
+pub fn load(path: &Path) -> Result<Settings, String> {
+    match fs::read_to_string(path) {
+        Ok(source) => parse(&source).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documented_config_parses() {
+        let settings: Settings = parse(
+            r#"
+# defaults
+tool = "ink"
+color = 196
+canvas = "64x32"
+server = "192.168.0.10:8080"
+autosave = 60
+render = "diff"
+key.erase = "x"
+key.connection = "ctrl+x"
+"#,
+        )
+        .unwrap();
+        assert_eq!(settings.tool.as_deref(), Some("ink"));
+        assert_eq!(settings.color, Some(196));
+        assert_eq!(settings.canvas, Some((64, 32)));
+        assert_eq!(settings.autosave, Some(Duration::from_secs(60)));
+        assert!(settings.render == Some(RenderMode::Diff));
+        assert!(parse("autosave = 0").unwrap().autosave.is_none());
+    }
+
+    #[test]
+    fn invalid_configs_are_refused() {
+        assert!(parse("color = 300").is_err());
+        assert!(parse("canvas = \"64\"").is_err());
+        assert!(parse("autosave = \"soon\"").is_err());
+        assert!(parse("brush = 1").is_err());
+        assert!(parse("key.nothing = \"x\"").is_err());
+        assert!(parse("tool = \"ink").is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::palette::darken;
+use crossterm::style::Color;
+
+use crate::screen::{Layer, PIXEL_NEIGHBOURS};
+
+// pixel steps from every cell of the region to its closest edge, cells with a
+// neighbour outside the region are 0
+pub fn edge_distances(region: &HashSet<(i32, i32)>) -> HashMap<(i32, i32), u32> {
+    let mut distances: HashMap<(i32, i32), u32> = HashMap::new();
+    let mut queue: VecDeque<(i32, i32)> = VecDeque::new();
+    for &(x, y) in region {
+        if PIXEL_NEIGHBOURS
+            .iter()
+            .any(|(dx, dy)| !region.contains(&(x + dx, y + dy)))
+        {
+            distances.insert((x, y), 0);
+            queue.push_back((x, y));
+        }
+    }
+    while let Some((x, y)) = queue.pop_front() {
+        let distance: u32 = distances[&(x, y)];
+        for (dx, dy) in PIXEL_NEIGHBOURS {
+            let next = (x + dx, y + dy);
+            if region.contains(&next) && !distances.contains_key(&next) {
+                distances.insert(next, distance + 1);
+                queue.push_back(next);
+            }
+        }
+    }
+    distances
+}
+
+// pixels shading the region around start from its edges inward, the edge gets
+// the darkest of steps shades and the last one is just short of the original
+// color, anything deeper is left untouched
+pub fn feathered_fill(
+    layer: &Layer,
+    start: (i32, i32),
+    steps: u32,
+    strength: f32,
+) -> Vec<((i32, i32), Color)> {
+    let Some(color) = layer.pixel_color(start) else {
+        return Vec::new();
+    };
+    // the contiguous pixels sharing the color of the one at start are the
+    // selection the feathered fill works on
+    let region: HashSet<(i32, i32)> = layer.connected_pixels(start, |other| other == color);
+    let mut pixels: Vec<((i32, i32), Color)> = edge_distances(&region)
+        .into_iter()
+        .filter(|&(_, distance)| distance < steps)
+        .map(|(offset, distance)| {
+            let amount: f32 = strength * (steps - distance) as f32 / steps as f32;
+            (offset, darken(color, amount))
+        })
+        .collect();
+    // stable order so the history and the network see the same sequence
+    pixels.sort_by_key(|(offset, _)| (offset.1, offset.0));
+    pixels
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::assets::asset_dir;
+use crate::constants::EMPTY_TERM_CHAR;
+use crate::screen::{Item, TermChar};
+
+// name of the items a placed sprite is stored as
+pub const SPRITE_ITEM: &str = "sprite";
+
+// shipped with pixelrs, a character per cell and spaces left see through
+const BUILTIN: [(&str, &[&str]); 9] = [
+    ("arrow right", &["───►"]),
+    ("arrow left", &["◄───"]),
+    ("arrow up", &["▲", "│", "│"]),
+    ("arrow down", &["│", "│", "▼"]),
+    ("star", &["\\ | /", "- ★ -", "/ | \\"]),
+    ("stars", &["★ ☆ ★"]),
+    ("frame", &["╔════════╗", "║        ║", "╚════════╝"]),
+    ("rule", &["═══════════"]),
+    (
+        "speech bubble",
+        &["╭──────────╮", "│          │", "╰─┬────────╯", "  │", "  ╵"],
+    ),
+];
+
+// characters placed together as an item, characters without a foreground of
+// their own take the selected color
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Sprite {
+    pub name: String,
+    pub chars: Vec<Vec<TermChar>>,
+}
+
+impl Sprite {
+    pub fn to_item(&self, color: Color) -> Item {
+        Item {
+            name: SPRITE_ITEM.to_string(),
+            offset: (0, 0),
+            chars: self
+                .chars
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&term_char| match term_char.foreground_color {
+                            Color::Reset if !term_char.empty => TermChar {
+                                foreground_color: color,
+                                ..term_char
+                            },
+                            _ => term_char,
+                        })
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    // written to the sprites directory as name.json, where load finds it
+    pub fn save(&self) -> io::Result<PathBuf> {
+        if !valid_name(&self.name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid sprite name {:?}", self.name),
+            ));
+        }
+        fs::create_dir_all(sprites_dir())?;
+        let path: PathBuf = sprites_dir().join(format!("{}.json", self.name));
+        fs::write(&path, serde_json::to_vec(self)?)?;
+        Ok(path)
+    }
+}
+
+// the name becomes a file name, anything reaching out of the sprites
+// directory is refused
+fn valid_name(name: &str) -> bool {
+    !name.contains(['/', '\\'])
+        && Path::new(name).file_name().and_then(|file| file.to_str()) == Some(name)
+}
+
+fn sprites_dir() -> PathBuf {
+    asset_dir().join("sprites")
+}
+
+pub fn builtin() -> Vec<Sprite> {
+    BUILTIN
+        .iter()
+        .map(|(name, rows)| Sprite {
+            name: name.to_string(),
+            chars: rows
+                .iter()
+                .map(|row| {
+                    row.chars()
+                        .map(|character| match character {
+                            ' ' => EMPTY_TERM_CHAR,
+                            character => TermChar {
+                                character,
+                                foreground_color: Color::Reset,
+                                background_color: Color::Reset,
+                                empty: false,
+                            },
+                        })
+                        .collect()
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+// sprites saved before, by name, broken files are skipped
+pub fn saved() -> Vec<Sprite> {
+    let mut sprites: Vec<Sprite> = fs::read_dir(sprites_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| fs::read(entry.path()).ok())
+                .filter_map(|data| serde_json::from_slice::<Sprite>(&data).ok())
+                .filter(|sprite| !sprite.chars.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    sprites.sort_by(|a, b| a.name.cmp(&b.name));
+    sprites
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stay_in_the_sprites_directory() {
+        assert!(valid_name("arrow"));
+        assert!(valid_name("big.arrow"));
+        for name in ["", ".", "..", "../arrow", "a/b", "a\\b", "/tmp/arrow"] {
+            assert!(!valid_name(name), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::Stamp;
+use crate::protocol::{
+    decode_updates, SerializableErase, SerializableStroke, SerializableTermChar, Update,
+};
+
+// room persistence for the server side of shared sessions, rooms are only ever
+// mutated through operations so every backend can keep the full history
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum RoomOperation {
+    Paint { x: i32, y: i32, color: u8 },
+    Erase { x: i32, y: i32 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RoomCell {
+    pub x: i32,
+    pub y: i32,
+    pub color: u8,
+}
+
+pub trait RoomStore {
+    // current state of the room, the result of replaying its whole history
+    fn load(&self, room: &str) -> io::Result<Vec<RoomCell>>;
+    // returns the sequence number assigned to the operation
+    fn apply(&mut self, room: &str, operation: RoomOperation) -> io::Result<u64>;
+    // at most limit operations with a sequence number greater than since
+    fn history(
+        &self,
+        room: &str,
+        since: u64,
+        limit: usize,
+    ) -> io::Result<Vec<(u64, RoomOperation)>>;
+    fn rooms(&self) -> io::Result<Vec<String>>;
+}
+
+// room names end up in file names and queries, anything unusual is rejected
+pub fn check_room_name(room: &str) -> io::Result<()> {
+    let valid = !room.is_empty()
+        && room
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid room name: {:?}", room),
+        )),
+    }
+}
+
+// a database file for .db and .sqlite paths, a directory of logs otherwise
+pub fn open(path: &str) -> io::Result<Box<dyn RoomStore + Send>> {
+    let sqlite: bool = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext == "db" || ext == "sqlite");
+    match sqlite {
+        true => Ok(Box::new(SqliteStore::new(path)?)),
+        false => Ok(Box::new(FlatFileStore::new(path)?)),
+    }
+}
+
+// what a relayed message does to the shared layer, pixels and erases, text
+// and the other items are left to the hosting session
+pub fn room_operations(message: &[u8]) -> Vec<RoomOperation> {
+    let mut operations: Vec<RoomOperation> = Vec::new();
+    let (updates, _, _) = decode_updates(message);
+    for update in updates {
+        match update {
+            Update::TermChar(tc) => operations.push(paint(&tc)),
+            Update::Stroke(stroke) => operations.extend(stroke.cells.iter().map(paint)),
+            Update::Erase(SerializableErase { abs_x, abs_y, .. }) => {
+                operations.push(RoomOperation::Erase { x: abs_x, y: abs_y })
+            }
+            _ => {}
+        }
+    }
+    operations
+}
+
+fn paint(tc: &SerializableTermChar) -> RoomOperation {
+    RoomOperation::Paint {
+        x: tc.abs_x,
+        y: tc.abs_y,
+        color: tc.background_color,
+    }
+}
+
+// the stored cells as a stroke a joining peer applies like any other,
+// unstamped so it is taken whatever the peer saw before
+pub fn room_stroke(cells: &[RoomCell]) -> Update {
+    Update::Stroke(SerializableStroke {
+        cells: cells
+            .iter()
+            .map(|cell| SerializableTermChar {
+                abs_x: cell.x,
+                abs_y: cell.y,
+                character: ' ',
+                foreground_color: cell.color,
+                background_color: cell.color,
+                empty: false,
+                author: String::new(),
+                stamp: Stamp::default(),
+            })
+            .collect(),
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct LoggedOperation {
+    seq: u64,
+    operation: RoomOperation,
+}
+
+// one append only json lines log per room inside dir
+pub struct FlatFileStore {
+    dir: PathBuf,
+    next_seq: HashMap<String, u64>,
+}
+
+impl FlatFileStore {
+    pub fn new(dir: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(FlatFileStore {
+            dir: PathBuf::from(dir),
+            next_seq: HashMap::new(),
+        })
+    }
+
+    fn room_path(&self, room: &str) -> io::Result<PathBuf> {
+        check_room_name(room)?;
+        Ok(self.dir.join(format!("{}.jsonl", room)))
+    }
+
+    fn read_log(&self, room: &str) -> io::Result<Vec<LoggedOperation>> {
+        let file: File = match File::open(self.room_path(room)?) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut log: Vec<LoggedOperation> = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let logged: LoggedOperation = serde_json::from_str(&line?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            log.push(logged);
+        }
+        Ok(log)
+    }
+}
+
+impl RoomStore for FlatFileStore {
+    fn load(&self, room: &str) -> io::Result<Vec<RoomCell>> {
+        let mut cells: BTreeMap<(i32, i32), u8> = BTreeMap::new();
+        for logged in self.read_log(room)? {
+            match logged.operation {
+                RoomOperation::Paint { x, y, color } => cells.insert((x, y), color),
+                RoomOperation::Erase { x, y } => cells.remove(&(x, y)),
+            };
+        }
+        Ok(cells
+            .into_iter()
+            .map(|((x, y), color)| RoomCell { x, y, color })
+            .collect())
+    }
+
+    fn apply(&mut self, room: &str, operation: RoomOperation) -> io::Result<u64> {
+        let seq: u64 = match self.next_seq.get(room) {
+            Some(seq) => *seq,
+            None => self
+                .read_log(room)?
+                .last()
+                .map_or(1, |logged| logged.seq + 1),
+        };
+        let line: String = serde_json::to_string(&LoggedOperation { seq, operation })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut file: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.room_path(room)?)?;
+        writeln!(file, "{}", line)?;
+        self.next_seq.insert(room.to_string(), seq + 1);
+        Ok(seq)
+    }
+
+    fn history(
+        &self,
+        room: &str,
+        since: u64,
+        limit: usize,
+    ) -> io::Result<Vec<(u64, RoomOperation)>> {
+        Ok(self
+            .read_log(room)?
+            .into_iter()
+            .filter(|logged| logged.seq > since)
+            .take(limit)
+            .map(|logged| (logged.seq, logged.operation))
+            .collect())
+    }
+
+    fn rooms(&self) -> io::Result<Vec<String>> {
+        let mut rooms: Vec<String> = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path: PathBuf = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "jsonl") {
+                if let Some(stem) = path.file_stem() {
+                    rooms.push(stem.to_string_lossy().to_string());
+                }
+            }
+        }
+        rooms.sort();
+        Ok(rooms)
+    }
+}
+
+// current cells are kept in their own table so loading a room never replays
+// the history, which can be paged from the operations table
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+fn sqlite_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl SqliteStore {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let connection: Connection = Connection::open(path).map_err(sqlite_error)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS operations (
+                    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                    room TEXT NOT NULL,
+                    operation TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS operations_room ON operations (room, seq);
+                CREATE TABLE IF NOT EXISTS cells (
+                    room TEXT NOT NULL,
+                    x INTEGER NOT NULL,
+                    y INTEGER NOT NULL,
+                    color INTEGER NOT NULL,
+                    PRIMARY KEY (room, x, y)
+                );",
+            )
+            .map_err(sqlite_error)?;
+        Ok(SqliteStore { connection })
+    }
+}
+
+impl RoomStore for SqliteStore {
+    fn load(&self, room: &str) -> io::Result<Vec<RoomCell>> {
+        check_room_name(room)?;
+        let mut statement = self
+            .connection
+            .prepare("SELECT x, y, color FROM cells WHERE room = ?1 ORDER BY x, y")
+            .map_err(sqlite_error)?;
+        let cells = statement
+            .query_map(params![room], |row| {
+                Ok(RoomCell {
+                    x: row.get(0)?,
+                    y: row.get(1)?,
+                    color: row.get(2)?,
+                })
+            })
+            .map_err(sqlite_error)?;
+        cells.map(|cell| cell.map_err(sqlite_error)).collect()
+    }
+
+    fn apply(&mut self, room: &str, operation: RoomOperation) -> io::Result<u64> {
+        check_room_name(room)?;
+        let serialized: String = serde_json::to_string(&operation)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+        transaction
+            .execute(
+                "INSERT INTO operations (room, operation) VALUES (?1, ?2)",
+                params![room, serialized],
+            )
+            .map_err(sqlite_error)?;
+        let seq: i64 = transaction.last_insert_rowid();
+        match operation {
+            RoomOperation::Paint { x, y, color } => transaction.execute(
+                "INSERT OR REPLACE INTO cells (room, x, y, color) VALUES (?1, ?2, ?3, ?4)",
+                params![room, x, y, color],
+            ),
+            RoomOperation::Erase { x, y } => transaction.execute(
+                "DELETE FROM cells WHERE room = ?1 AND x = ?2 AND y = ?3",
+                params![room, x, y],
+            ),
+        }
+        .map_err(sqlite_error)?;
+        transaction.commit().map_err(sqlite_error)?;
+        Ok(seq as u64)
+    }
+
+    fn history(
+        &self,
+        room: &str,
+        since: u64,
+        limit: usize,
+    ) -> io::Result<Vec<(u64, RoomOperation)>> {
+        check_room_name(room)?;
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT seq, operation FROM operations
+                 WHERE room = ?1 AND seq > ?2 ORDER BY seq LIMIT ?3",
+            )
+            .map_err(sqlite_error)?;
+        let rows = statement
+            .query_map(params![room, since as i64, limit as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(sqlite_error)?;
+        let mut history: Vec<(u64, RoomOperation)> = Vec::new();
+        for row in rows {
+            let (seq, serialized) = row.map_err(sqlite_error)?;
+            let operation: RoomOperation = serde_json::from_str(&serialized)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            history.push((seq as u64, operation));
+        }
+        Ok(history)
+    }
+
+    fn rooms(&self) -> io::Result<Vec<String>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT DISTINCT room FROM operations ORDER BY room")
+            .map_err(sqlite_error)?;
+        let rooms = statement
+            .query_map([], |row| row.get(0))
+            .map_err(sqlite_error)?;
+        rooms.map(|room| room.map_err(sqlite_error)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::encode_update;
+
+    fn exercise(store: &mut dyn RoomStore) {
+        store
+            .apply("wall", RoomOperation::Paint { x: 1, y: 2, color: 9 })
+            .unwrap();
+        store
+            .apply("wall", RoomOperation::Paint { x: 3, y: 2, color: 4 })
+            .unwrap();
+        let last: u64 = store.apply("wall", RoomOperation::Erase { x: 1, y: 2 }).unwrap();
+        store
+            .apply("other", RoomOperation::Paint { x: 0, y: 0, color: 1 })
+            .unwrap();
+        assert_eq!(
+            store.load("wall").unwrap(),
+            vec![RoomCell { x: 3, y: 2, color: 4 }]
+        );
+        assert_eq!(store.history("wall", 0, 10).unwrap().len(), 3);
+        assert_eq!(store.history("wall", last, 10).unwrap(), vec![]);
+        assert_eq!(store.rooms().unwrap(), vec!["other", "wall"]);
+        assert!(store.load("../wall").is_err());
+    }
+
+    #[test]
+    fn flat_files_keep_rooms() {
+        let dir = std::env::temp_dir().join(format!("pixelrs-rooms-{}", std::process::id()));
+        exercise(&mut FlatFileStore::new(dir.to_str().unwrap()).unwrap());
+        let reopened = FlatFileStore::new(dir.to_str().unwrap()).unwrap();
+        assert_eq!(reopened.load("wall").unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_keeps_rooms() {
+        exercise(&mut SqliteStore::new(":memory:").unwrap());
+    }
+
+    #[test]
+    fn stored_cells_come_back_as_operations() {
+        let cells: Vec<RoomCell> = vec![RoomCell { x: 5, y: 6, color: 7 }];
+        for binary in [false, true] {
+            let message: Vec<u8> = encode_update(&room_stroke(&cells), binary).unwrap();
+            assert_eq!(
+                room_operations(&message),
+                vec![RoomOperation::Paint { x: 5, y: 6, color: 7 }]
+            );
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crossterm::style::Color;
+
+use crate::constants::EMPTY_TERM_CHAR;
+use crate::figlet::Font;
+use crate::screen::{Item, Pixel, TermChar};
+
+// name of the items text is stored as, a whole block of lines each
+pub const TEXT_ITEM: &str = "text";
+// same for text typed a character per cell
+pub const NARROW_TEXT_ITEM: &str = "narrow_text";
+// a banner while it is typed, it becomes pixels once done
+pub const BANNER_ITEM: &str = "banner";
+// a glyph painted by the brush, it fills both cells of a pixel
+pub const GLYPH_ITEM: &str = "glyph";
+
+pub fn is_text(item: &Item) -> bool {
+    item.name == TEXT_ITEM || item.name == NARROW_TEXT_ITEM
+}
+
+// text being typed, lines of characters anchored at the layer cell of the
+// first one. characters take two cells, like pixels, the second one left
+// empty, unless narrow. with a font the text is set in it as a banner of
+// pixels instead
+#[derive(Clone)]
+pub struct TextEdit {
+    pub layer: usize,
+    pub anchor: (i32, i32),
+    pub narrow: bool,
+    pub font: Option<Font>,
+    pub lines: Vec<Vec<TermChar>>,
+    // line and character the next one is typed before
+    pub cursor: (usize, usize),
+    // the item as it was before editing it, none for new text
+    pub original: Option<Item>,
+}
+
+impl TextEdit {
+    pub fn new(layer: usize, anchor: (i32, i32), narrow: bool, font: Option<Font>) -> Self {
+        TextEdit {
+            layer,
+            anchor,
+            narrow,
+            font,
+            lines: vec![Vec::new()],
+            cursor: (0, 0),
+            original: None,
+        }
+    }
+
+    // editing an existing text item, with the cursor on the character at cell
+    pub fn open(layer: usize, item: Item, cell: (i32, i32)) -> Self {
+        let narrow: bool = item.name == NARROW_TEXT_ITEM;
+        let width: usize = if narrow { 1 } else { 2 };
+        let lines: Vec<Vec<TermChar>> = item
+            .chars
+            .iter()
+            .map(|row| row.iter().step_by(width).copied().collect())
+            .collect();
+        let line: usize = ((cell.1 - item.offset.1).max(0) as usize).min(lines.len() - 1);
+        let column: usize =
+            ((cell.0 - item.offset.0).max(0) as usize / width).min(lines[line].len());
+        TextEdit {
+            layer,
+            anchor: item.offset,
+            narrow,
+            font: None,
+            lines,
+            cursor: (line, column),
+            original: Some(item),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.font {
+            Some(_) => self.banner_pixels().is_empty(),
+            None => self.lines.iter().all(|line| line.is_empty()),
+        }
+    }
+
+    pub fn insert(&mut self, character: char, color: Color) {
+        let (line, column) = self.cursor;
+        let term_char: TermChar = TermChar {
+            character,
+            foreground_color: color,
+            background_color: Color::Reset,
+            empty: false,
+        };
+        self.lines[line].insert(column, term_char);
+        self.cursor.1 += 1;
+    }
+
+    // the rest of the line goes to a new one below
+    pub fn split_line(&mut self) {
+        let (line, column) = self.cursor;
+        let rest: Vec<TermChar> = self.lines[line].split_off(column);
+        self.lines.insert(line + 1, rest);
+        self.cursor = (line + 1, 0);
+    }
+
+    // at the start of a line it is joined to the previous one
+    pub fn backspace(&mut self) {
+        match self.cursor {
+            (0, 0) => {}
+            (line, 0) => {
+                let rest: Vec<TermChar> = self.lines.remove(line);
+                self.cursor = (line - 1, self.lines[line - 1].len());
+                self.lines[line - 1].extend(rest);
+            }
+            (line, column) => {
+                self.lines[line].remove(column - 1);
+                self.cursor.1 -= 1;
+            }
+        }
+    }
+
+    // at the end of a line the next one is joined to it
+    pub fn delete(&mut self) {
+        let (line, column) = self.cursor;
+        if column < self.lines[line].len() {
+            self.lines[line].remove(column);
+        } else if line + 1 < self.lines.len() {
+            let next: Vec<TermChar> = self.lines.remove(line + 1);
+            self.lines[line].extend(next);
+        }
+    }
+
+    // dx moves along the line, wrapping to the neighbour lines, dy between
+    // lines keeping the column when the line is long enough
+    pub fn move_cursor(&mut self, (dx, dy): (i32, i32)) {
+        let (line, column) = self.cursor;
+        self.cursor = match (dx, dy) {
+            (-1, _) if column == 0 && line > 0 => (line - 1, self.lines[line - 1].len()),
+            (-1, _) => (line, column.saturating_sub(1)),
+            (1, _) if column == self.lines[line].len() && line + 1 < self.lines.len() => {
+                (line + 1, 0)
+            }
+            (1, _) => (line, (column + 1).min(self.lines[line].len())),
+            (_, -1) if line > 0 => (line - 1, column.min(self.lines[line - 1].len())),
+            (_, 1) if line + 1 < self.lines.len() => {
+                (line + 1, column.min(self.lines[line + 1].len()))
+            }
+            _ => (line, column),
+        };
+    }
+
+    pub fn home(&mut self) {
+        self.cursor.1 = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor.1 = self.lines[self.cursor.0].len();
+    }
+
+    // cells a character takes
+    fn width(&self) -> i32 {
+        if self.narrow {
+            1
+        } else {
+            2
+        }
+    }
+
+    // layer cell the cursor is at, after the banner characters before it
+    pub fn cursor_cell(&self) -> (i32, i32) {
+        let (line, column) = self.cursor;
+        if let Some(font) = &self.font {
+            let before: String = self.lines[line][..column]
+                .iter()
+                .map(|term_char| term_char.character)
+                .collect();
+            return (
+                self.anchor.0 + 2 * font.width(&before) as i32,
+                self.anchor.1 + (line * font.height) as i32,
+            );
+        }
+        (
+            self.anchor.0 + self.width() * column as i32,
+            self.anchor.1 + line as i32,
+        )
+    }
+
+    // layer cells of the banner, every pixel in the color its character was
+    // typed with
+    pub fn banner_pixels(&self) -> Vec<((i32, i32), Color)> {
+        let Some(font) = &self.font else {
+            return Vec::new();
+        };
+        let mut pixels: Vec<((i32, i32), Color)> = Vec::new();
+        for (line, term_chars) in self.lines.iter().enumerate() {
+            let top: i32 = self.anchor.1 + (line * font.height) as i32;
+            let mut left: i32 = self.anchor.0;
+            for term_char in term_chars {
+                let glyph: String = term_char.character.to_string();
+                for (x, y) in font.pixels(&glyph) {
+                    pixels.push(((left + 2 * x, top + y), term_char.foreground_color));
+                }
+                left += 2 * font.width(&glyph) as i32;
+            }
+        }
+        pixels
+    }
+
+    fn item_name(&self) -> &str {
+        match (&self.font, self.narrow) {
+            (Some(_), _) => BANNER_ITEM,
+            (None, true) => NARROW_TEXT_ITEM,
+            (None, false) => TEXT_ITEM,
+        }
+    }
+
+    // whether item is the one to_item made
+    pub fn shown_as(&self, item: &Item) -> bool {
+        item.offset == self.anchor && item.name == self.item_name()
+    }
+
+    pub fn to_item(&self) -> Item {
+        if self.font.is_some() {
+            return self.banner_item();
+        }
+        Item {
+            name: self.item_name().to_string(),
+            offset: self.anchor,
+            chars: self
+                .lines
+                .iter()
+                .map(|line| match self.narrow {
+                    true => line.clone(),
+                    false => line
+                        .iter()
+                        .flat_map(|&term_char| [term_char, EMPTY_TERM_CHAR])
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    // the banner pixels as one item, for showing it while typed
+    fn banner_item(&self) -> Item {
+        let rows: usize = self.lines.len() * self.font.as_ref().map_or(1, |font| font.height);
+        let pixels: Vec<((i32, i32), Color)> = self.banner_pixels();
+        let width: i32 = pixels
+            .iter()
+            .map(|((x, _), _)| x - self.anchor.0 + 2)
+            .max()
+            .unwrap_or(0);
+        let mut chars: Vec<Vec<TermChar>> = vec![vec![EMPTY_TERM_CHAR; width as usize]; rows];
+        for ((x, y), color) in pixels {
+            let (x, y) = ((x - self.anchor.0) as usize, (y - self.anchor.1) as usize);
+            let pixel: Vec<TermChar> = Pixel::new(color).to_chars().remove(0);
+            chars[y][x..x + 2].copy_from_slice(&pixel);
+        }
+        Item {
+            name: BANNER_ITEM.to_string(),
+            offset: self.anchor,
+            chars,
+        }
+    }
+}
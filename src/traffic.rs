@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Deserializer, Value};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+// one line of the recording file, payload holds the raw bytes as they went
+// through the socket, so a received record may contain several messages
+#[derive(Serialize, Deserialize)]
+struct TrafficRecord {
+    timestamp_ms: u128,
+    direction: Direction,
+    size: usize,
+    payload: String,
+}
+
+pub struct TrafficRecorder {
+    file: File,
+}
+
+impl TrafficRecorder {
+    pub fn new(path: &str) -> io::Result<Self> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(TrafficRecorder { file })
+    }
+
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        let record = TrafficRecord {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            direction,
+            size: bytes.len(),
+            payload: String::from_utf8_lossy(bytes).to_string(),
+        };
+        // a record holds strings and numbers only, it always serializes
+        let line: String = serde_json::to_string(&record).unwrap_or_default();
+        // a failed write loses these bytes from the dump, the socket already
+        // sent or read them
+        let _ = writeln!(self.file, "{}", line);
+    }
+}
+
+#[derive(Default)]
+struct MessageStats {
+    count: usize,
+    bytes: usize,
+    max: usize,
+}
+
+// message type is the variant name of the serialized update, e.g. TermChar
+fn message_types(payload: &str) -> Vec<(String, usize)> {
+    let mut types: Vec<(String, usize)> = Vec::new();
+    // binary frames are recorded lossy, they are only counted as a whole
+    if payload.starts_with('\0') {
+        types.push(("Binary".to_string(), payload.len()));
+        return types;
+    }
+    let mut stream = Deserializer::from_str(payload).into_iter::<Value>();
+    let mut start: usize = 0;
+    while let Some(message) = stream.next() {
+        let end: usize = stream.byte_offset();
+        let name: String = match message {
+            Ok(Value::Object(map)) => map.keys().next().cloned().unwrap_or_default(),
+            Ok(_) => "Unknown".to_string(),
+            Err(_) => {
+                types.push(("Malformed".to_string(), payload.len() - start));
+                break;
+            }
+        };
+        types.push((name, end - start));
+        start = end;
+    }
+    types
+}
+
+// summary of a recording file produced with --record
+pub fn analyze(path: &str) -> io::Result<String> {
+    let file: File = File::open(path)?;
+    let mut stats: BTreeMap<(Direction, String), MessageStats> = BTreeMap::new();
+    let mut first_ms: Option<u128> = None;
+    let mut last_ms: u128 = 0;
+    let mut totals: BTreeMap<Direction, usize> = BTreeMap::new();
+
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        let record: TrafficRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(_) => continue,
+        };
+        first_ms.get_or_insert(record.timestamp_ms);
+        last_ms = record.timestamp_ms;
+        *totals.entry(record.direction).or_default() += record.size;
+        for (name, size) in message_types(&record.payload) {
+            let entry = stats.entry((record.direction, name)).or_default();
+            entry.count += 1;
+            entry.bytes += size;
+            entry.max = entry.max.max(size);
+        }
+    }
+
+    // records appended by sessions of other clocks may go back in time
+    let seconds: f64 = last_ms.saturating_sub(first_ms.unwrap_or(last_ms)) as f64 / 1000.0;
+    let mut summary: String = format!("duration: {:.1}s\n", seconds);
+    for (direction, bytes) in totals.iter() {
+        let rate: f64 = match seconds > 0.0 {
+            true => *bytes as f64 / seconds,
+            false => 0.0,
+        };
+        summary += &format!("{:?}: {} bytes ({:.1} B/s)\n", direction, bytes, rate);
+    }
+    summary += &format!(
+        "{:<10} {:<12} {:>8} {:>10} {:>8} {:>8}\n",
+        "direction", "type", "count", "bytes", "avg", "max"
+    );
+    for ((direction, name), s) in stats.iter() {
+        summary += &format!(
+            "{:<10} {:<12} {:>8} {:>10} {:>8} {:>8}\n",
+            format!("{:?}", direction),
+            name,
+            s.count,
+            s.bytes,
+            s.bytes / s.count.max(1),
+            s.max
+        );
+    }
+    Ok(summary)
+}
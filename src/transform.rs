@@ -0,0 +1,248 @@
+use crate::boxdraw::{self, DOWN, LEFT, RIGHT, UP};
+use crate::constants::EMPTY_TERM_CHAR;
+use crate::screen::TermChar;
+
+// flips and quarter turns, made on pixels, two cells wide and a row high so
+// the art keeps its proportions
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transform {
+    FlipHorizontal,
+    FlipVertical,
+    RotateClockwise,
+    RotateCounterClockwise,
+}
+
+impl Transform {
+    // where a line leaving through side leaves once transformed
+    pub fn side(self, side: u8) -> u8 {
+        match (self, side) {
+            (Transform::FlipHorizontal, LEFT) => RIGHT,
+            (Transform::FlipHorizontal, RIGHT) => LEFT,
+            (Transform::FlipVertical, UP) => DOWN,
+            (Transform::FlipVertical, DOWN) => UP,
+            (Transform::RotateClockwise, side) | (Transform::RotateCounterClockwise, side)
+                if side != 0 =>
+            {
+                let turns: usize = if self == Transform::RotateClockwise {
+                    1
+                } else {
+                    3
+                };
+                let index: usize = boxdraw::SIDES.iter().position(|&s| s == side).unwrap_or(0);
+                boxdraw::SIDES[(index + turns) % 4]
+            }
+            (_, side) => side,
+        }
+    }
+
+    // size of a width x height block once transformed
+    pub fn size(self, (width, height): (i32, i32)) -> (i32, i32) {
+        match self {
+            Transform::FlipHorizontal | Transform::FlipVertical => (width, height),
+            _ => (height, width),
+        }
+    }
+
+    // top left corner of the x,y,width,height rect of a block of size once
+    // both are transformed
+    pub fn origin(
+        self,
+        (x, y, width, height): (i32, i32, i32, i32),
+        size: (i32, i32),
+    ) -> (i32, i32) {
+        let (size_width, size_height) = size;
+        match self {
+            Transform::FlipHorizontal => (size_width - x - width, y),
+            Transform::FlipVertical => (x, size_height - y - height),
+            Transform::RotateClockwise => (size_height - y - height, x),
+            Transform::RotateCounterClockwise => (y, size_width - x - width),
+        }
+    }
+
+    pub fn grid<T: Copy>(self, grid: &[Vec<T>]) -> Vec<Vec<T>> {
+        let rows: usize = grid.len();
+        let columns: usize = grid.first().map_or(0, |row| row.len());
+        match self {
+            Transform::FlipHorizontal => grid
+                .iter()
+                .map(|row| row.iter().rev().copied().collect())
+                .collect(),
+            Transform::FlipVertical => grid.iter().rev().cloned().collect(),
+            Transform::RotateClockwise => (0..columns)
+                .map(|i| (0..rows).map(|j| grid[rows - 1 - j][i]).collect())
+                .collect(),
+            Transform::RotateCounterClockwise => (0..columns)
+                .map(|i| (0..rows).map(|j| grid[j][columns - 1 - i]).collect())
+                .collect(),
+        }
+    }
+
+    // characters of an item, taken two cells at a time, box drawing glyphs
+    // and arrows turn along
+    pub fn chars(self, chars: &[Vec<TermChar>]) -> Vec<Vec<TermChar>> {
+        let columns: usize = chars.iter().map(|row| row.len()).max().unwrap_or(0);
+        let pixels: Vec<Vec<[TermChar; 2]>> = chars
+            .iter()
+            .map(|row| {
+                (0..columns.div_ceil(2))
+                    .map(|i| {
+                        let cell = |x: usize| row.get(x).copied().unwrap_or(EMPTY_TERM_CHAR);
+                        [self.glyph(cell(2 * i)), self.glyph(cell(2 * i + 1))]
+                    })
+                    .collect()
+            })
+            .collect();
+        self.grid(&pixels)
+            .into_iter()
+            .map(|row| row.into_iter().flatten().collect())
+            .collect()
+    }
+
+    // characters of an item a cell at a time, for art made of single width
+    // characters that pairs would scramble
+    pub fn cells(self, chars: &[Vec<TermChar>]) -> Vec<Vec<TermChar>> {
+        let columns: usize = chars.iter().map(|row| row.len()).max().unwrap_or(0);
+        let cells: Vec<Vec<TermChar>> = chars
+            .iter()
+            .map(|row| {
+                (0..columns)
+                    .map(|x| self.glyph(row.get(x).copied().unwrap_or(EMPTY_TERM_CHAR)))
+                    .collect()
+            })
+            .collect();
+        self.grid(&cells)
+    }
+
+    // whether the characters come two cells to a pixel or wide glyph, the
+    // second cell empty or a copy of the first
+    pub fn paired(chars: &[Vec<TermChar>]) -> bool {
+        chars.iter().all(|row| {
+            row.chunks(2).all(|pair| match pair {
+                [first, second] => second.empty || second == first,
+                _ => true,
+            })
+        })
+    }
+
+    fn glyph(self, term_char: TermChar) -> TermChar {
+        let sides: u8 = boxdraw::sides(term_char.character);
+        if sides == 0 {
+            return TermChar {
+                character: self.arrow(term_char.character),
+                ..term_char
+            };
+        }
+        let turned: u8 = boxdraw::SIDES
+            .into_iter()
+            .filter(|&side| sides & side != 0)
+            .fold(0, |turned, side| turned | self.side(side));
+        match boxdraw::glyph(turned) {
+            Some(character) => TermChar {
+                character,
+                ..term_char
+            },
+            None => term_char,
+        }
+    }
+
+    // glyphs pointing somewhere point where their side went, the others are
+    // left alone
+    fn arrow(self, character: char) -> char {
+        let turns: bool = matches!(
+            self,
+            Transform::RotateClockwise | Transform::RotateCounterClockwise
+        );
+        match character {
+            '/' => '\\',
+            '\\' => '/',
+            '-' if turns => '|',
+            '|' if turns => '-',
+            character => ARROWS
+                .iter()
+                .find_map(|arrows| {
+                    let index: usize = arrows.iter().position(|&arrow| arrow == character)?;
+                    let side: u8 = self.side(boxdraw::SIDES[index]);
+                    let turned: usize = boxdraw::SIDES.iter().position(|&s| s == side)?;
+                    Some(arrows[turned])
+                })
+                .unwrap_or(character),
+        }
+    }
+}
+
+// glyphs pointing up, right, down and left, the order of boxdraw::SIDES
+const ARROWS: [[char; 4]; 6] = [
+    ['↑', '→', '↓', '←'],
+    ['▲', '►', '▼', '◄'],
+    ['△', '▷', '▽', '◁'],
+    ['▴', '▸', '▾', '◂'],
+    ['⇑', '⇒', '⇓', '⇐'],
+    ['▀', '▐', '▄', '▌'],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::style::Color;
+
+    fn chars(rows: &[&str]) -> Vec<Vec<TermChar>> {
+        rows.iter()
+            .map(|row| {
+                row.chars()
+                    .map(|character| TermChar {
+                        character,
+                        foreground_color: Color::Reset,
+                        background_color: Color::Reset,
+                        empty: character == ' ',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn text(chars: &[Vec<TermChar>]) -> Vec<String> {
+        chars
+            .iter()
+            .map(|row| row.iter().map(|term_char| term_char.character).collect())
+            .collect()
+    }
+
+    #[test]
+    fn pairs_stay_together() {
+        let art: Vec<Vec<TermChar>> = chars(&["►►◄◄", "▲▲  "]);
+        assert!(Transform::paired(&art));
+        let flipped: Vec<Vec<TermChar>> = Transform::FlipHorizontal.chars(&art);
+        assert_eq!(text(&flipped), ["►►◄◄", "  ▲▲"]);
+        let turned: Vec<Vec<TermChar>> = Transform::RotateClockwise.chars(&art);
+        assert_eq!(text(&turned), ["►►▼▼", "  ▲▲"]);
+    }
+
+    #[test]
+    fn single_width_art_turns_a_cell_at_a_time() {
+        let art: Vec<Vec<TermChar>> = chars(&["ab─┐"]);
+        assert!(!Transform::paired(&art));
+        let flipped: Vec<Vec<TermChar>> = Transform::FlipHorizontal.cells(&art);
+        assert_eq!(text(&flipped), ["┌─ba"]);
+        let turned: Vec<Vec<TermChar>> = Transform::RotateCounterClockwise.cells(&art);
+        assert_eq!(text(&turned), ["┌", "│", "b", "a"]);
+    }
+
+    #[test]
+    fn arrows_point_where_their_side_went() {
+        let arrows: Vec<Vec<TermChar>> = chars(&["►◄▲▼/"]);
+        assert_eq!(text(&Transform::FlipHorizontal.cells(&arrows)), ["\\▼▲►◄"]);
+        assert_eq!(text(&Transform::FlipVertical.cells(&arrows)), ["►◄▼▲\\"]);
+        let turned: Vec<Vec<TermChar>> = Transform::RotateClockwise.cells(&arrows);
+        assert_eq!(text(&turned), ["▼", "▲", "►", "◄", "\\"]);
+    }
+
+    #[test]
+    fn rotations_undo_each_other() {
+        let grid: Vec<Vec<u8>> = vec![vec![1, 2, 3], vec![4, 5, 6]];
+        let turned: Vec<Vec<u8>> = Transform::RotateClockwise.grid(&grid);
+        assert_eq!(turned, [[4, 1], [5, 2], [6, 3]]);
+        assert_eq!(Transform::RotateCounterClockwise.grid(&turned), grid);
+        assert_eq!(Transform::RotateClockwise.size((3, 2)), (2, 3));
+        assert_eq!(Transform::RotateClockwise.origin((0, 0, 1, 1), (3, 2)), (1, 0));
+    }
+}
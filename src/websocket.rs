@@ -0,0 +1,443 @@
+use std::io::{self, BufRead, Read, Write};
+
+// minimal RFC 6455 support, enough for browsers to join shared sessions: the
+// opening handshake and single frames, extensions, subprotocols and
+// fragmented messages are refused
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// headers of an upgrade request are short, anything longer is not one
+const MAX_REQUEST_LEN: u64 = 8192;
+// payload a frame may declare, a whole canvas sync fits well within it, so a
+// peer can't make the other end buffer without bounds
+pub const MAX_FRAME_LEN: usize = 32 << 20;
+
+pub const OPCODE_CONTINUATION: u8 = 0x0;
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xA;
+
+// close codes telling the other end why what it sent was refused
+pub const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+pub const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+pub struct Frame {
+    // last frame of its message
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    // payload of text, binary and continuation frames, control frames carry none
+    pub fn data(&self) -> Option<&[u8]> {
+        matches!(
+            self.opcode,
+            OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY
+        )
+        .then_some(&self.payload)
+    }
+}
+
+// only used to derive the handshake accept key, not for anything secret
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message: Vec<u8> = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w: [u32; 80] = [0; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp: u32 = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest: [u8; 20] = [0; 20];
+    for (i, state) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&state.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded: String = String::new();
+    for chunk in data.chunks(3) {
+        let bytes: [u8; 3] = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group: u32 = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(
+        format!("{}{}", key.trim(), WEBSOCKET_GUID).as_bytes(),
+    ))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// header lines up to the blank line ending them, read a byte at a time so the
+// frames following the handshake stay on the stream
+fn read_headers(stream: &mut impl Read) -> io::Result<Vec<String>> {
+    let mut headers: Vec<u8> = Vec::new();
+    let mut byte: [u8; 1] = [0];
+    while !headers.ends_with(b"\r\n\r\n") {
+        if headers.len() as u64 >= MAX_REQUEST_LEN {
+            return Err(invalid("websocket handshake too long"));
+        }
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "connection closed during the websocket handshake",
+            ));
+        }
+        headers.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&headers)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+fn header<'a>(headers: &'a [String], name: &str) -> Option<&'a str> {
+    headers.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+// answers the upgrade request whose first line was already read, returns the
+// requested path, e.g. `/sketches?peer=3&secret=8127`
+pub fn accept(
+    request_line: &str,
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+) -> io::Result<String> {
+    let path: &str = request_line
+        .strip_prefix("GET ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .ok_or_else(|| invalid("not a websocket upgrade request"))?;
+    let headers: Vec<String> = read_headers(reader)?;
+    if !header(&headers, "Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket")) {
+        write!(writer, "HTTP/1.1 400 Bad Request\r\n\r\n")?;
+        return Err(invalid("not a websocket upgrade request"));
+    }
+    if header(&headers, "Sec-WebSocket-Version") != Some("13") {
+        write!(
+            writer,
+            "HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )?;
+        return Err(invalid("unsupported websocket version"));
+    }
+    let key: &str = header(&headers, "Sec-WebSocket-Key")
+        .ok_or_else(|| invalid("missing Sec-WebSocket-Key header"))?;
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )?;
+    Ok(path.to_string())
+}
+
+// opening handshake from the client side for path on host
+pub fn connect(stream: &mut (impl Read + Write), host: &str, path: &str) -> io::Result<()> {
+    let key: String = base64(&rand::random::<[u8; 16]>());
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path, host, key
+    )?;
+    let headers: Vec<String> = read_headers(stream)?;
+    let status: &str = headers.first().map(String::as_str).unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(invalid(&format!("websocket upgrade refused: {}", status)));
+    }
+    if header(&headers, "Sec-WebSocket-Accept") != Some(accept_key(&key).as_str()) {
+        return Err(invalid(
+            "websocket upgrade answered with a wrong accept key",
+        ));
+    }
+    Ok(())
+}
+
+// clients must mask what they send, servers must not
+pub fn encode_frame(opcode: u8, payload: &[u8], masked: bool) -> Vec<u8> {
+    let mut frame: Vec<u8> = vec![0x80 | opcode];
+    let mask_bit: u8 = if masked { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => frame.push(mask_bit | len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    if !masked {
+        frame.extend_from_slice(payload);
+        return frame;
+    }
+    let mask: [u8; 4] = rand::random();
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+// payload length a frame declares and where its mask or payload starts, None
+// while the header is still incomplete
+fn declared_len(bytes: &[u8]) -> Option<(u64, usize)> {
+    match bytes.get(1)? & 0x7F {
+        126 => Some((
+            u16::from_be_bytes(bytes.get(2..4)?.try_into().ok()?) as u64,
+            4,
+        )),
+        127 => Some((u64::from_be_bytes(bytes.get(2..10)?.try_into().ok()?), 10)),
+        len => Some((len as u64, 2)),
+    }
+}
+
+// first complete frame of bytes and how many bytes it took, None while the
+// frame is still incomplete
+pub fn decode_frame(bytes: &[u8]) -> Option<(Frame, usize)> {
+    let (&first, &second) = (bytes.first()?, bytes.get(1)?);
+    let masked: bool = second & 0x80 != 0;
+    let (len, mut at) = declared_len(bytes)?;
+    let len: usize = usize::try_from(len).ok()?;
+    let mask: Option<&[u8]> = match masked {
+        true => {
+            at += 4;
+            Some(bytes.get(at - 4..at)?)
+        }
+        false => None,
+    };
+    let payload: &[u8] = bytes.get(at..at.checked_add(len)?)?;
+    let payload: Vec<u8> = match mask {
+        Some(mask) => payload
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask[i % 4])
+            .collect(),
+        None => payload.to_vec(),
+    };
+    let frame: Frame = Frame {
+        fin: first & 0x80 != 0,
+        opcode: first & 0x0F,
+        payload,
+    };
+    Some((frame, at + len))
+}
+
+fn too_large(len: u64, max_len: usize) -> io::Result<()> {
+    match len > max_len as u64 {
+        true => Err(invalid(&format!("websocket frame of {} bytes is too large", len))),
+        false => Ok(()),
+    }
+}
+
+// messages are only taken whole, in a single frame
+fn unfragmented(frame: Frame) -> io::Result<Frame> {
+    match frame.fin && frame.opcode != OPCODE_CONTINUATION {
+        true => Ok(frame),
+        false => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fragmented websocket messages are not supported",
+        )),
+    }
+}
+
+// the close frame payload for a refused frame, with its code and reason
+pub fn refusal(e: &io::Error) -> Vec<u8> {
+    let code: u16 = match e.kind() {
+        io::ErrorKind::Unsupported => CLOSE_UNSUPPORTED_DATA,
+        _ => CLOSE_MESSAGE_TOO_BIG,
+    };
+    [code.to_be_bytes().as_slice(), e.to_string().as_bytes()].concat()
+}
+
+// every complete frame at the start of buffer, which keeps the incomplete
+// rest, a frame declaring more than MAX_FRAME_LEN or a fragment is an error
+pub fn drain_frames(buffer: &mut Vec<u8>) -> io::Result<Vec<Frame>> {
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut consumed: usize = 0;
+    while let Some((len, _)) = declared_len(&buffer[consumed..]) {
+        too_large(len, MAX_FRAME_LEN)?;
+        let Some((frame, len)) = decode_frame(&buffer[consumed..]) else {
+            break;
+        };
+        frames.push(unfragmented(frame)?);
+        consumed += len;
+    }
+    buffer.drain(..consumed);
+    Ok(frames)
+}
+
+// a single frame read straight from the stream, nothing past it is consumed,
+// frames over max_len and fragments are an error
+pub fn read_frame(stream: &mut impl Read, max_len: usize) -> io::Result<Frame> {
+    let mut head: Vec<u8> = vec![0; 2];
+    stream.read_exact(&mut head)?;
+    let extra: usize = match head[1] & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    } + if head[1] & 0x80 != 0 { 4 } else { 0 };
+    let mut rest: Vec<u8> = vec![0; extra];
+    stream.read_exact(&mut rest)?;
+    head.extend(rest);
+    let (len, _) = declared_len(&head).ok_or_else(|| invalid("malformed websocket frame"))?;
+    too_large(len, max_len)?;
+    let mut payload: Vec<u8> = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    head.extend(payload);
+    decode_frame(&head)
+        .map(|(frame, _)| frame)
+        .ok_or_else(|| invalid("malformed websocket frame"))
+        .and_then(unfragmented)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_matches_the_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn sha1_and_base64_known_answers() {
+        let hex: String = sha1(b"abc").iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+
+    fn upgrade(headers: &str) -> (io::Result<String>, String) {
+        let mut reader: &[u8] = headers.as_bytes();
+        let mut written: Vec<u8> = Vec::new();
+        let path = accept("GET /room HTTP/1.1", &mut reader, &mut written);
+        (path, String::from_utf8(written).unwrap())
+    }
+
+    #[test]
+    fn upgrade_headers_are_checked() {
+        let key: &str = "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+        let (path, answer) = upgrade(&format!(
+            "Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+            key
+        ));
+        assert_eq!(path.unwrap(), "/room");
+        assert!(answer.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        let (path, answer) = upgrade(&format!("Sec-WebSocket-Version: 13\r\n{}\r\n", key));
+        assert!(path.is_err());
+        assert!(answer.starts_with("HTTP/1.1 400"));
+
+        let (path, answer) = upgrade(&format!(
+            "Upgrade: websocket\r\nSec-WebSocket-Version: 8\r\n{}\r\n",
+            key
+        ));
+        assert!(path.is_err());
+        assert!(answer.starts_with("HTTP/1.1 426"));
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for len in [0, 125, 126, 70_000] {
+            let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            for masked in [false, true] {
+                let mut buffer: Vec<u8> = encode_frame(OPCODE_BINARY, &payload, masked);
+                let frames: Vec<Frame> = drain_frames(&mut buffer).unwrap();
+                assert_eq!(frames.len(), 1);
+                assert_eq!(frames[0].data(), Some(payload.as_slice()));
+                assert!(buffer.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn incomplete_frames_wait_for_the_rest() {
+        let frame: Vec<u8> = encode_frame(OPCODE_TEXT, b"hello", true);
+        let mut buffer: Vec<u8> = frame[..4].to_vec();
+        assert!(drain_frames(&mut buffer).unwrap().is_empty());
+        buffer.extend_from_slice(&frame[4..]);
+        assert_eq!(drain_frames(&mut buffer).unwrap()[0].payload, b"hello");
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let mut head: Vec<u8> = vec![0x80 | OPCODE_BINARY, 127];
+        head.extend_from_slice(&(MAX_FRAME_LEN as u64 + 1).to_be_bytes());
+        assert!(drain_frames(&mut head.clone()).is_err());
+        assert!(read_frame(&mut head.as_slice(), MAX_FRAME_LEN).is_err());
+        let small: Vec<u8> = encode_frame(OPCODE_TEXT, &[b'a'; 300], false);
+        assert!(read_frame(&mut small.as_slice(), 256).is_err());
+        assert_eq!(read_frame(&mut small.as_slice(), 300).unwrap().payload.len(), 300);
+    }
+
+    #[test]
+    fn fragments_are_refused() {
+        let mut first: Vec<u8> = encode_frame(OPCODE_TEXT, b"hel", true);
+        first[0] &= 0x7F;
+        let rest: Vec<u8> = encode_frame(OPCODE_CONTINUATION, b"lo", true);
+        for frame in [first, rest] {
+            let e: io::Error = drain_frames(&mut frame.clone()).err().unwrap();
+            assert_eq!(refusal(&e)[..2], CLOSE_UNSUPPORTED_DATA.to_be_bytes());
+            assert!(read_frame(&mut frame.as_slice(), MAX_FRAME_LEN).is_err());
+        }
+    }
+}